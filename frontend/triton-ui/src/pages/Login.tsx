@@ -1,16 +1,71 @@
-import React, { useState } from 'react';
+import React, { useEffect, useState } from 'react';
 import { useNavigate } from 'react-router-dom';
 import { useAuth } from '../services/AuthContext';
+import { enrollTotp, getSsoProvider, LoginChallenge, SsoProvider, TotpEnrollment } from '../services/api';
+
+const inputClassName =
+  'block w-full rounded-md border-0 py-1.5 text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 placeholder:text-gray-400 focus:ring-2 focus:ring-inset focus:ring-indigo-600 sm:text-sm sm:leading-6';
 
 const Login = () => {
   const [username, setUsername] = useState('');
   const [password, setPassword] = useState('');
   const [isLoading, setIsLoading] = useState(false);
   const [errorMessage, setErrorMessage] = useState<string | null>(null);
+  // Second step, for accounts with two-factor authentication
+  const [challenge, setChallenge] = useState<LoginChallenge | null>(null);
+  const [enrollment, setEnrollment] = useState<TotpEnrollment | null>(null);
+  const [code, setCode] = useState('');
+
+  const [ssoProvider, setSsoProvider] = useState<SsoProvider | null>(null);
 
-  const { login } = useAuth();
+  const { login, verifyTotp, completeSsoLogin } = useAuth();
   const navigate = useNavigate();
 
+  useEffect(() => {
+    getSsoProvider().then(setSsoProvider);
+  }, []);
+
+  // The single sign-on callback sends the browser back here with the outcome
+  // in the URL fragment
+  useEffect(() => {
+    const params = new URLSearchParams(window.location.hash.slice(1));
+    if (!params.has('token') && !params.has('challenge_token') && !params.has('error')) {
+      return;
+    }
+    // Keep the tokens out of the browser history
+    window.history.replaceState(null, '', window.location.pathname);
+
+    const finishSso = async () => {
+      const token = params.get('token');
+      const challengeToken = params.get('challenge_token');
+      try {
+        setIsLoading(true);
+        if (token) {
+          await completeSsoLogin(token, params.get('refresh_token') || '');
+          navigate('/');
+        } else if (challengeToken) {
+          const pending: LoginChallenge = {
+            mfa_required: true,
+            enrollment_required: params.get('enrollment_required') === 'true',
+            challenge_token: challengeToken,
+            expires_in: Number(params.get('expires_in')),
+          };
+          if (pending.enrollment_required) {
+            setEnrollment(await enrollTotp(pending.challenge_token));
+          }
+          setChallenge(pending);
+        } else {
+          setErrorMessage(params.get('error'));
+        }
+      } catch (err: any) {
+        setErrorMessage(err.response?.data?.message || 'Single sign-on failed. Please try again.');
+      } finally {
+        setIsLoading(false);
+      }
+    };
+    finishSso();
+  }, []);
+
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
     
@@ -22,8 +77,16 @@ const Login = () => {
     try {
       setIsLoading(true);
       setErrorMessage(null);
-      await login(username, password);
-      navigate('/');
+      const pending = await login(username, password);
+      if (!pending) {
+        navigate('/');
+        return;
+      }
+      // Accounts that must use two-factor authentication enroll on first login
+      if (pending.enrollment_required) {
+        setEnrollment(await enrollTotp(pending.challenge_token));
+      }
+      setChallenge(pending);
     } catch (err: any) {
       setErrorMessage(err.response?.data?.message || 'Login failed. Please try again.');
     } finally {
@@ -31,6 +94,106 @@ const Login = () => {
     }
   };
 
+  const handleCodeSubmit = async (e: React.FormEvent) => {
+    e.preventDefault();
+    if (!challenge || !code) {
+      return;
+    }
+
+    try {
+      setIsLoading(true);
+      setErrorMessage(null);
+      await verifyTotp(challenge.challenge_token, code);
+      navigate('/');
+    } catch (err: any) {
+      setErrorMessage(err.response?.data?.message || 'Verification failed. Please try again.');
+      setCode('');
+    } finally {
+      setIsLoading(false);
+    }
+  };
+
+  const errorBanner = errorMessage && (
+    <div className="rounded-md bg-red-50 p-4">
+      <div className="flex">
+        <div className="text-sm text-red-700">
+          {errorMessage}
+        </div>
+      </div>
+    </div>
+  );
+
+  if (challenge) {
+    return (
+      <div className="flex min-h-full flex-1 flex-col justify-center px-6 py-12 lg:px-8">
+        <div className="sm:mx-auto sm:w-full sm:max-w-sm">
+          <h2 className="mt-10 text-center text-2xl font-bold leading-9 tracking-tight text-gray-900">
+            Triton Admin UI
+          </h2>
+          <h3 className="mt-3 text-center text-xl leading-9 tracking-tight text-gray-600">
+            Two-factor authentication
+          </h3>
+        </div>
+
+        <div className="mt-10 sm:mx-auto sm:w-full sm:max-w-sm">
+          <form className="space-y-6" onSubmit={handleCodeSubmit}>
+            {errorBanner}
+
+            {enrollment && (
+              <div className="space-y-3 text-sm text-gray-700">
+                <p>
+                  Your account requires two-factor authentication. Add this key to your authenticator app,
+                  then enter the code it shows.
+                </p>
+                <p className="break-all rounded-md bg-gray-50 p-3 font-mono text-gray-900">{enrollment.secret}</p>
+                <p>
+                  Or open <a className="break-all text-indigo-600" href={enrollment.otpauth_uri}>this link</a> on
+                  the device with your authenticator.
+                </p>
+                <p>Keep these recovery codes somewhere safe. Each one can be used once instead of a code:</p>
+                <ul className="grid grid-cols-2 gap-1 rounded-md bg-gray-50 p-3 font-mono text-gray-900">
+                  {enrollment.recovery_codes.map((recoveryCode) => (
+                    <li key={recoveryCode}>{recoveryCode}</li>
+                  ))}
+                </ul>
+              </div>
+            )}
+
+            <div>
+              <label htmlFor="code" className="block text-sm font-medium leading-6 text-gray-900">
+                {enrollment ? 'Authentication code' : 'Authentication code or recovery code'}
+              </label>
+              <div className="mt-2">
+                <input
+                  id="code"
+                  name="code"
+                  type="text"
+                  inputMode={enrollment ? 'numeric' : 'text'}
+                  autoComplete="one-time-code"
+                  required
+                  autoFocus
+                  value={code}
+                  onChange={(e) => setCode(e.target.value)}
+                  className={inputClassName}
+                />
+              </div>
+            </div>
+
+            <div>
+              <button
+                type="submit"
+                disabled={isLoading}
+                className="flex w-full justify-center rounded-md bg-indigo-600 px-3 py-1.5 text-sm font-semibold leading-6 text-white shadow-sm hover:bg-indigo-500 focus-visible:outline focus-visible:outline-2 focus-visible:outline-offset-2 focus-visible:outline-indigo-600 disabled:opacity-75"
+              >
+                {isLoading ? 'Verifying...' : 'Verify'}
+              </button>
+            </div>
+          </form>
+        </div>
+      </div>
+    );
+  }
+
   return (
     <div className="flex min-h-full flex-1 flex-col justify-center px-6 py-12 lg:px-8">
       <div className="sm:mx-auto sm:w-full sm:max-w-sm">
@@ -44,15 +207,7 @@ const Login = () => {
 
       <div className="mt-10 sm:mx-auto sm:w-full sm:max-w-sm">
         <form className="space-y-6" onSubmit={handleSubmit}>
-          {errorMessage && (
-            <div className="rounded-md bg-red-50 p-4">
-              <div className="flex">
-                <div className="text-sm text-red-700">
-                  {errorMessage}
-                </div>
-              </div>
-            </div>
-          )}
+          {errorBanner}
           
           <div>
             <label htmlFor="username" className="block text-sm font-medium leading-6 text-gray-900">
@@ -67,7 +222,7 @@ const Login = () => {
                 required
                 value={username}
                 onChange={(e) => setUsername(e.target.value)}
-                className="block w-full rounded-md border-0 py-1.5 text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 placeholder:text-gray-400 focus:ring-2 focus:ring-inset focus:ring-indigo-600 sm:text-sm sm:leading-6"
+                className={inputClassName}
               />
             </div>
           </div>
@@ -87,7 +242,7 @@ const Login = () => {
                 required
                 value={password}
                 onChange={(e) => setPassword(e.target.value)}
-                className="block w-full rounded-md border-0 py-1.5 text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 placeholder:text-gray-400 focus:ring-2 focus:ring-inset focus:ring-indigo-600 sm:text-sm sm:leading-6"
+                className={inputClassName}
               />
             </div>
           </div>
@@ -101,11 +256,18 @@ const Login = () => {
               {isLoading ? 'Signing in...' : 'Sign in'}
             </button>
           </div>
-          
-          <div className="text-sm text-center text-gray-500">
-            <p>Use username: admin, password: admin for testing</p>
-          </div>
         </form>
+
+        {ssoProvider && (
+          <div className="mt-6">
+            <a
+              href="/api/auth/oidc/login"
+              className="flex w-full justify-center rounded-md bg-white px-3 py-1.5 text-sm font-semibold leading-6 text-gray-900 shadow-sm ring-1 ring-inset ring-gray-300 hover:bg-gray-50"
+            >
+              Sign in with {ssoProvider.name}
+            </a>
+          </div>
+        )}
       </div>
     </div>
   );
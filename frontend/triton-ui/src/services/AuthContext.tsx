@@ -1,5 +1,11 @@
 import React, { createContext, useState, useContext, useEffect, ReactNode } from 'react';
-import { login as apiLogin, logout as apiLogout } from './api';
+import {
+  completeSsoLogin as apiCompleteSsoLogin,
+  login as apiLogin,
+  logout as apiLogout,
+  verifyTotp as apiVerifyTotp,
+  LoginChallenge,
+} from './api';
 
 interface User {
   id: string;
@@ -12,7 +18,10 @@ interface AuthContextType {
   user: User | null;
   loading: boolean;
   error: string | null;
-  login: (username: string, password: string) => Promise<void>;
+  // Resolves to a challenge when a TOTP code is needed to finish the login
+  login: (username: string, password: string) => Promise<LoginChallenge | null>;
+  verifyTotp: (challengeToken: string, code: string) => Promise<void>;
+  completeSsoLogin: (token: string, refreshToken: string) => Promise<void>;
   logout: () => Promise<void>;
   isAuthenticated: boolean;
 }
@@ -47,8 +56,12 @@ export const AuthProvider = ({ children }: AuthProviderProps) => {
       setLoading(true);
       setError(null);
       const data = await apiLogin(username, password);
-      setUser(data.user);
       setLoading(false);
+      if (data.mfa_required) {
+        return data as LoginChallenge;
+      }
+      setUser(data.user);
+      return null;
     } catch (err: any) {
       setLoading(false);
       setError(err.response?.data?.message || 'Login failed. Please try again.');
@@ -56,6 +69,34 @@ export const AuthProvider = ({ children }: AuthProviderProps) => {
     }
   };
 
+  const verifyTotp = async (challengeToken: string, code: string) => {
+    try {
+      setLoading(true);
+      setError(null);
+      const data = await apiVerifyTotp(challengeToken, code);
+      setUser(data.user);
+      setLoading(false);
+    } catch (err: any) {
+      setLoading(false);
+      setError(err.response?.data?.message || 'Verification failed. Please try again.');
+      throw err;
+    }
+  };
+
+  const completeSsoLogin = async (token: string, refreshToken: string) => {
+    try {
+      setLoading(true);
+      setError(null);
+      const data = await apiCompleteSsoLogin(token, refreshToken);
+      setUser(data);
+      setLoading(false);
+    } catch (err: any) {
+      setLoading(false);
+      setError(err.response?.data?.message || 'Single sign-on failed. Please try again.');
+      throw err;
+    }
+  };
+
   const logout = async () => {
     try {
       setLoading(true);
@@ -73,6 +114,8 @@ export const AuthProvider = ({ children }: AuthProviderProps) => {
     loading,
     error,
     login,
+    verifyTotp,
+    completeSsoLogin,
     logout,
     isAuthenticated: !!user,
   };
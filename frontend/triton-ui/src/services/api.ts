@@ -20,41 +20,158 @@ apiClient.interceptors.request.use(
   (error) => Promise.reject(error)
 );
 
+const clearSession = () => {
+  localStorage.removeItem('token');
+  localStorage.removeItem('refreshToken');
+  localStorage.removeItem('user');
+};
+
+const storeSession = (data: any) => {
+  localStorage.setItem('token', data.token);
+  localStorage.setItem('refreshToken', data.refresh_token);
+  localStorage.setItem('user', JSON.stringify(data.user));
+};
+
+// Concurrent 401s share a single refresh; refresh tokens are single-use
+let refreshing: Promise<string> | null = null;
+
+const refreshSession = (): Promise<string> => {
+  if (!refreshing) {
+    const refreshToken = localStorage.getItem('refreshToken');
+    refreshing = (refreshToken
+      ? axios.post('/api/auth/refresh', { refresh_token: refreshToken }).then((response) => {
+          storeSession(response.data);
+          return response.data.token as string;
+        })
+      : Promise.reject(new Error('No refresh token'))
+    ).finally(() => {
+      refreshing = null;
+    });
+  }
+  return refreshing;
+};
+
+// Login steps answer a wrong password or code with 401; that is not an
+// expired session
+const isLoginRequest = (request: any) =>
+  request &&
+  ((request.method === 'post' && request.url === '/auth') ||
+    request.url === '/auth/totp/verify' ||
+    request.url === '/auth/totp/enroll');
+
 // Add response interceptor to handle common responses like 401
 apiClient.interceptors.response.use(
   (response) => response,
-  (error) => {
-    if (error.response && error.response.status === 401) {
+  async (error) => {
+    const request = error.config;
+    if (error.response && error.response.status === 401 && !isLoginRequest(request)) {
+      // The access token has expired; renew it once and retry
+      if (request && !request._retried && localStorage.getItem('refreshToken')) {
+        request._retried = true;
+        try {
+          const token = await refreshSession();
+          request.headers.Authorization = `Bearer ${token}`;
+          return apiClient(request);
+        } catch (refreshError) {
+          // Fall through to the login redirect
+        }
+      }
+
       // Redirect to login on auth error
-      localStorage.removeItem('token');
-      localStorage.removeItem('user');
+      clearSession();
       window.location.href = '/login';
     }
     return Promise.reject(error);
   }
 );
 
+// Returned by login instead of a session when a TOTP code is needed
+export interface LoginChallenge {
+  mfa_required: true;
+  enrollment_required: boolean;
+  challenge_token: string;
+  expires_in: number;
+}
+
+export interface TotpEnrollment {
+  secret: string;
+  otpauth_uri: string;
+  recovery_codes: string[];
+}
+
 // Authentication
 export const login = async (username: string, password: string) => {
   const response = await apiClient.post('/auth', { username, password });
-  localStorage.setItem('token', response.data.token);
-  localStorage.setItem('user', JSON.stringify(response.data.user));
+  if (!response.data.mfa_required) {
+    storeSession(response.data);
+  }
+  return response.data;
+};
+
+// Set up an authenticator during a login that requires one
+export const enrollTotp = async (challengeToken: string): Promise<TotpEnrollment> => {
+  const response = await apiClient.post('/auth/totp/enroll', { challenge_token: challengeToken });
+  return response.data;
+};
+
+// Second login step: a code from the authenticator, or a recovery code
+export const verifyTotp = async (challengeToken: string, code: string) => {
+  const response = await apiClient.post('/auth/totp/verify', { challenge_token: challengeToken, code });
+  storeSession(response.data);
+  return response.data;
+};
+
+// Single sign-on through an OpenID Connect provider, when configured
+export interface SsoProvider {
+  name: string;
+}
+
+export const getSsoProvider = async (): Promise<SsoProvider | null> => {
+  try {
+    const response = await apiClient.get('/auth/oidc');
+    return response.data;
+  } catch (err) {
+    return null;
+  }
+};
+
+// The SSO callback hands the tokens over in the URL fragment; the user is
+// then read with the new access token
+export const completeSsoLogin = async (token: string, refreshToken: string) => {
+  localStorage.setItem('token', token);
+  localStorage.setItem('refreshToken', refreshToken);
+  const response = await apiClient.get('/auth');
+  localStorage.setItem('user', JSON.stringify(response.data));
   return response.data;
 };
 
 export const logout = async () => {
   await apiClient.delete('/auth');
-  localStorage.removeItem('token');
-  localStorage.removeItem('user');
+  clearSession();
 };
 
 export const getCurrentUser = async () => {
   return apiClient.get('/auth');
 };
 
+// List endpoints answer with { items, total, next }. Unwrap the items into
+// `data` so callers keep working with plain arrays; pass `next` back as
+// `cursor` for the following page.
+export interface ListPage<T> {
+  items: T[];
+  total: number | null;
+  next: string | null;
+}
+
+const getList = async <T = any>(url: string, params?: any) => {
+  const response = await apiClient.get<ListPage<T>>(url, { params });
+  const { items, total, next } = response.data;
+  return { ...response, data: items, total, next };
+};
+
 // VMs
-export const getVMs = async () => {
-  return apiClient.get('/vms');
+export const getVMs = async (params?: any) => {
+  return getList('/vms', params);
 };
 
 export const getVM = async (id: string) => {
@@ -62,7 +179,7 @@ export const getVM = async (id: string) => {
 };
 
 export const getVMsByServer = async (serverUuid: string) => {
-  return apiClient.get(`/vms?server_uuid=${serverUuid}`);
+  return getList('/vms', { server_uuid: serverUuid });
 };
 
 export const getVMSnapshots = async (id: string) => {
@@ -71,7 +188,7 @@ export const getVMSnapshots = async (id: string) => {
 };
 
 export const getVMJobs = async (id: string) => {
-  return apiClient.get(`/vms/${id}/jobs`);
+  return getList(`/vms/${id}/jobs`);
 };
 
 export const getVMFirewallRules = async (id: string) => {
@@ -97,7 +214,7 @@ export const vmAction = async (id: string, action: string, params?: any) => {
 
 // Servers
 export const getServers = async () => {
-  return apiClient.get('/servers');
+  return getList('/servers');
 };
 
 export const getServer = async (id: string) => {
@@ -106,12 +223,12 @@ export const getServer = async (id: string) => {
 
 // Users
 export const getUsers = async () => {
-  return apiClient.get('/users');
+  return getList('/users');
 };
 
 // Networks
 export const getNetworks = async () => {
-  return apiClient.get('/networks');
+  return getList('/networks');
 };
 
 export const getNetwork = async (id: string) => {
@@ -120,7 +237,7 @@ export const getNetwork = async (id: string) => {
 
 // Images
 export const getImages = async () => {
-  return apiClient.get('/images');
+  return getList('/images');
 };
 
 export const getImage = async (id: string) => {
@@ -129,13 +246,18 @@ export const getImage = async (id: string) => {
 
 // Packages
 export const getPackages = async () => {
-  return apiClient.get('/packages');
+  return getList('/packages');
 };
 
 export const getPackage = async (id: string) => {
   return apiClient.get(`/packages/${id}`);
 };
 
+// Global search across VMs, servers, networks, images, packages and users
+export const search = async (q: string) => {
+  return apiClient.get('/search', { params: { q } });
+};
+
 // Dashboard
 export const getDashboardStats = async () => {
   return apiClient.get('/dashboard');
@@ -143,7 +265,7 @@ export const getDashboardStats = async () => {
 
 // Jobs
 export const getJobs = async (params?: any) => {
-  return apiClient.get('/jobs', { params });
+  return getList('/jobs', params);
 };
 
 export const getJob = async (id: string) => {
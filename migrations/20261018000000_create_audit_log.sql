@@ -0,0 +1,24 @@
+-- Audit trail of every mutating request made through the admin API
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    user_uuid UUID,
+    user_name TEXT,
+    method TEXT NOT NULL,
+    route TEXT NOT NULL,
+    path TEXT NOT NULL,
+    resource_type TEXT,
+    resource_uuid TEXT,
+    action TEXT NOT NULL,
+    request_body JSONB,
+    source_ip TEXT,
+    status_code INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    job_uuid TEXT,
+    error TEXT
+);
+
+CREATE INDEX IF NOT EXISTS audit_log_occurred_at_idx ON audit_log (occurred_at DESC);
+CREATE INDEX IF NOT EXISTS audit_log_user_uuid_idx ON audit_log (user_uuid);
+CREATE INDEX IF NOT EXISTS audit_log_resource_uuid_idx ON audit_log (resource_uuid);
+CREATE INDEX IF NOT EXISTS audit_log_action_idx ON audit_log (resource_type, action);
@@ -0,0 +1,15 @@
+-- Individually revoked access tokens (logout), kept until they would have expired
+CREATE TABLE IF NOT EXISTS revoked_tokens (
+    jti TEXT PRIMARY KEY,
+    user_uuid UUID NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    revoked_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
+
+-- Every token issued to a user at or before revoked_before is rejected
+CREATE TABLE IF NOT EXISTS user_session_revocations (
+    user_uuid UUID PRIMARY KEY,
+    revoked_before TIMESTAMPTZ NOT NULL
+);
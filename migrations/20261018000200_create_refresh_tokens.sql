@@ -0,0 +1,19 @@
+-- Rotating refresh tokens; only a SHA-256 hash of each token is stored.
+-- Tokens descended from one login share a family_id, and presenting a token
+-- that has already been rotated revokes the whole family.
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    family_id UUID NOT NULL,
+    user_uuid UUID NOT NULL,
+    user_name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    roles TEXT[] NOT NULL,
+    issued_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+CREATE INDEX IF NOT EXISTS refresh_tokens_user_uuid_idx ON refresh_tokens (user_uuid);
+CREATE INDEX IF NOT EXISTS refresh_tokens_expires_at_idx ON refresh_tokens (expires_at);
@@ -0,0 +1,13 @@
+-- TOTP second factors. The shared secret is encrypted with AES-256-GCM;
+-- recovery codes are stored as SHA-256 hashes and removed once used.
+-- enabled_at stays NULL until the user has proven the authenticator works,
+-- and last_used_step stops a code from being accepted twice.
+CREATE TABLE IF NOT EXISTS totp_credentials (
+    user_uuid UUID PRIMARY KEY,
+    secret_ciphertext BYTEA NOT NULL,
+    secret_nonce BYTEA NOT NULL,
+    recovery_codes TEXT[] NOT NULL DEFAULT '{}',
+    last_used_step BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    enabled_at TIMESTAMPTZ
+);
@@ -0,0 +1,4 @@
+-- Roles granted by the OIDC provider's groups at sign-in. UFDS roles are
+-- looked up again on every refresh; these are kept with the session since
+-- the IdP is not consulted again until the next sign-in.
+ALTER TABLE refresh_tokens ADD COLUMN IF NOT EXISTS idp_roles TEXT[] NOT NULL DEFAULT '{}';
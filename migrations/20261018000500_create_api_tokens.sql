@@ -0,0 +1,19 @@
+-- Long-lived API tokens for automation; only a SHA-256 hash of each token is
+-- stored. The user's identity and roles are recorded when the token is
+-- created, and its scopes limit which routes it can call.
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id UUID PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    user_uuid UUID NOT NULL,
+    user_name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    roles TEXT[] NOT NULL,
+    name TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    last_used_at TIMESTAMPTZ,
+    UNIQUE (user_uuid, name)
+);
+
+CREATE INDEX IF NOT EXISTS api_tokens_expires_at_idx ON api_tokens (expires_at);
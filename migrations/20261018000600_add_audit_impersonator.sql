@@ -0,0 +1,6 @@
+-- The operator behind requests made with an impersonation token; user_uuid
+-- and user_name are then the impersonated user
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS impersonator_uuid UUID;
+ALTER TABLE audit_log ADD COLUMN IF NOT EXISTS impersonator_name TEXT;
+
+CREATE INDEX IF NOT EXISTS audit_log_impersonator_uuid_idx ON audit_log (impersonator_uuid) WHERE impersonator_uuid IS NOT NULL;
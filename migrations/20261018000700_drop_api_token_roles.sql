@@ -0,0 +1,3 @@
+-- API tokens act with their owner's current roles, looked up in UFDS each
+-- time a token is used, so the roles recorded at creation are not needed
+ALTER TABLE api_tokens DROP COLUMN IF EXISTS roles;
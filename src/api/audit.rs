@@ -0,0 +1,28 @@
+use actix_web::{get, web::{Data, Query}, HttpResponse};
+
+use crate::api::listing::{ListQuery, PageStart};
+use crate::audit::{self, AuditQuery, AuditStore};
+use crate::auth::AuthenticatedUser;
+use crate::error::AppError;
+
+#[get("")]
+pub async fn list_audit_entries(
+    _user: AuthenticatedUser,
+    store: Option<Data<AuditStore>>,
+    query: Query<AuditQuery>,
+    list: Query<ListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let store = store.ok_or_else(|| {
+        AppError::ServiceUnavailable("Audit logging is not enabled (DATABASE_URL is not set)".to_string())
+    })?;
+    
+    // Always most recent first
+    list.unsorted("Audit entries")?;
+    let limit = list.limit().unwrap_or(audit::DEFAULT_LIMIT);
+    let offset = list.offset()?;
+    
+    let entries = store.search(&query, limit, offset).await?;
+    let next = PageStart::after(offset, limit, entries.len());
+    
+    Ok(HttpResponse::Ok().json(list.page(entries, None, next)?))
+}
@@ -1,31 +1,281 @@
 use actix_web::{
     post, get, delete,
-    web::{self, Data, Json},
-    HttpResponse,
+    cookie::Cookie,
+    http::header,
+    web::{self, Data, Json, Query},
+    HttpRequest, HttpResponse,
 };
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-use crate::auth::{authenticate, AuthenticatedUser, LoginRequest, LoginResponse};
+use crate::auth::{
+    authenticate, AuthenticatedUser, ChallengePurpose, ChallengeRequest, Impersonation, LoginOutcome,
+    LoginRequest, LoginResponse, RefreshRequest, SessionToken,
+};
+use crate::auth::dev_users::DevUsers;
+use crate::auth::oidc::{OidcProvider, FLOW_COOKIE, FLOW_COOKIE_PATH};
+use crate::auth::refresh::RefreshTokenStore;
+use crate::auth::revocation::RevocationStore;
+use crate::auth::throttle::LoginThrottle;
+use crate::auth::totp::TotpService;
 use crate::config::Config;
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
 #[post("/auth")]
 pub async fn login(
+    req: HttpRequest,
     config: Data<Config>,
+    services: Data<ServiceClients>,
+    refresh_tokens: Data<RefreshTokenStore>,
+    throttle: Data<LoginThrottle>,
+    totp: Data<TotpService>,
     login_req: Json<LoginRequest>,
 ) -> Result<HttpResponse, AppError> {
+    let ip = crate::api::client_ip(&req);
+    
+    // Refuse clients and accounts with too many recent failures before
+    // trying the password
+    let attempt = throttle.check(ip, &login_req.username)?;
+    
     // Call our authentication function which will verify credentials against UFDS via LDAPS
-    let response = authenticate(&config, &login_req.username, &login_req.password).await?;
+    let result = authenticate(
+        &config,
+        &services.ufds,
+        // Only registered in dev_mode
+        req.app_data::<Data<DevUsers>>().map(|users| users.get_ref()),
+        &refresh_tokens,
+        &totp,
+        &login_req.username,
+        &login_req.password,
+    )
+    .await;
+    
+    // Only rejected credentials count against the limits, not UFDS outages.
+    // Failures are kept until the second factor has been passed too.
+    match &result {
+        Ok(LoginOutcome::Session(_)) => attempt.succeeded(),
+        Err(AppError::AuthError(_)) => attempt.failed(),
+        Ok(LoginOutcome::Challenge(_)) | Err(_) => drop(attempt),
+    }
+    
+    // Return either access and refresh tokens with user info, or a challenge
+    // to answer with a TOTP code
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+// Second login step: exchange a challenge token and a TOTP or recovery code
+// for access and refresh tokens
+#[post("/auth/totp/verify")]
+pub async fn verify_totp(
+    req: HttpRequest,
+    config: Data<Config>,
+    refresh_tokens: Data<RefreshTokenStore>,
+    throttle: Data<LoginThrottle>,
+    totp: Data<TotpService>,
+    challenge_req: Json<ChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let challenge = crate::auth::verify_challenge(&challenge_req.challenge_token, &config.jwt_secret)?;
+    let username = challenge.login.clone();
+    let ip = crate::api::client_ip(&req);
+    
+    // Codes are throttled like passwords
+    let attempt = throttle.check(ip, &username)?;
+    
+    let result = crate::auth::complete_challenge(&config, &refresh_tokens, &totp, challenge, &challenge_req.code).await;
+    match &result {
+        Ok(_) => attempt.succeeded(),
+        Err(AppError::AuthError(_)) => attempt.failed(),
+        Err(_) => drop(attempt),
+    }
+    
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+// Enroll an authenticator during a login that requires one. Answer the same
+// challenge with a code from the new authenticator to finish.
+#[post("/auth/totp/enroll")]
+pub async fn enroll_totp(
+    config: Data<Config>,
+    totp: Data<TotpService>,
+    challenge_req: Json<ChallengeRequest>,
+) -> Result<HttpResponse, AppError> {
+    let challenge = crate::auth::verify_challenge(&challenge_req.challenge_token, &config.jwt_secret)?;
+    if challenge.purpose != ChallengePurpose::Enroll {
+        return Err(AppError::BadRequest("This login does not need an enrollment".to_string()));
+    }
+    
+    let enrollment = totp.enroll(challenge.user_uuid()?, challenge.account()).await?;
+    
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[derive(Deserialize)]
+pub struct ActivateTotpRequest {
+    pub code: String,
+}
+
+// Enroll an authenticator for the signed-in user. Takes effect once
+// confirmed with POST /api/auth/totp/activate.
+#[post("/auth/totp")]
+pub async fn start_totp_enrollment(
+    user: AuthenticatedUser,
+    totp: Data<TotpService>,
+) -> Result<HttpResponse, AppError> {
+    let account = if user.email.is_empty() { &user.name } else { &user.email };
+    let enrollment = totp.enroll(user.id, account).await?;
+    
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+#[post("/auth/totp/activate")]
+pub async fn activate_totp(
+    user: AuthenticatedUser,
+    totp: Data<TotpService>,
+    activate_req: Json<ActivateTotpRequest>,
+) -> Result<HttpResponse, AppError> {
+    totp.activate(user.id, &activate_req.code).await?;
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Only registered when OIDC is configured
+fn oidc_provider(req: &HttpRequest) -> Result<&OidcProvider, AppError> {
+    req.app_data::<Data<OidcProvider>>()
+        .map(|provider| provider.get_ref())
+        .ok_or_else(|| AppError::NotFound("Single sign-on is not configured".to_string()))
+}
+
+// Tells the login page whether to offer single sign-on
+#[get("/auth/oidc")]
+pub async fn get_oidc_provider(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    let provider = oidc_provider(&req)?;
+    
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "name": provider.display_name() })))
+}
+
+// Send the browser to the IdP to sign in
+#[get("/auth/oidc/login")]
+pub async fn oidc_login(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    let redirect = oidc_provider(&req)?.begin().await?;
+    
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, redirect.url))
+        .cookie(redirect.flow_cookie)
+        .finish())
+}
+
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    // Set instead of a code when the IdP did not sign the user in
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+// The IdP sends the browser back here with an authorization code. The
+// browser then goes on to the login page, which picks the session, or a TOTP
+// challenge, up from the URL fragment; fragments never reach the server.
+#[get("/auth/oidc/callback")]
+pub async fn oidc_callback(
+    req: HttpRequest,
+    config: Data<Config>,
+    services: Data<ServiceClients>,
+    refresh_tokens: Data<RefreshTokenStore>,
+    totp: Data<TotpService>,
+    query: Query<OidcCallbackQuery>,
+) -> HttpResponse {
+    let result = complete_oidc_login(&req, &config, &services, &refresh_tokens, &totp, query.into_inner()).await;
+    
+    let mut fragment = url::form_urlencoded::Serializer::new(String::new());
+    match result {
+        Ok(LoginOutcome::Session(session)) => {
+            fragment
+                .append_pair("token", &session.token)
+                .append_pair("refresh_token", &session.refresh_token)
+                .append_pair("expires_in", &session.expires_in.to_string());
+        }
+        Ok(LoginOutcome::Challenge(challenge)) => {
+            fragment
+                .append_pair("challenge_token", &challenge.challenge_token)
+                .append_pair("enrollment_required", &challenge.enrollment_required.to_string())
+                .append_pair("expires_in", &challenge.expires_in.to_string());
+        }
+        Err(e) => {
+            warn!("OIDC sign-in failed: {}", e);
+            fragment.append_pair("error", &e.to_string());
+        }
+    }
+    
+    // The flow cookie is good for one attempt only
+    let mut flow_cookie = Cookie::build(FLOW_COOKIE, "").path(FLOW_COOKIE_PATH).finish();
+    flow_cookie.make_removal();
+    
+    HttpResponse::Found()
+        .insert_header((header::LOCATION, format!("/login#{}", fragment.finish())))
+        .cookie(flow_cookie)
+        .finish()
+}
+
+async fn complete_oidc_login(
+    req: &HttpRequest,
+    config: &Config,
+    services: &ServiceClients,
+    refresh_tokens: &RefreshTokenStore,
+    totp: &TotpService,
+    query: OidcCallbackQuery,
+) -> Result<LoginOutcome, AppError> {
+    let provider = oidc_provider(req)?;
+    if let Some(error) = query.error {
+        return Err(AppError::AuthError(query.error_description.unwrap_or(error)));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(AppError::BadRequest("Missing code or state".to_string()));
+    };
+    let flow_cookie = req
+        .cookie(FLOW_COOKIE)
+        .ok_or_else(|| AppError::AuthError("Sign-in attempt expired; please try again".to_string()))?;
+    
+    let identity = provider.complete(flow_cookie.value(), &code, &state).await?;
+    crate::auth::authenticate_oidc(config, &services.ufds, refresh_tokens, totp, identity).await
+}
+
+#[post("/auth/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    config: Data<Config>,
+    services: Data<ServiceClients>,
+    refresh_tokens: Data<RefreshTokenStore>,
+    refresh_req: Json<RefreshRequest>,
+) -> Result<HttpResponse, AppError> {
+    // Rotate the refresh token; the old one cannot be used again
+    let response = crate::auth::refresh(
+        &config,
+        &services.ufds,
+        // Only registered in dev_mode
+        req.app_data::<Data<DevUsers>>().map(|users| users.get_ref()),
+        &refresh_tokens,
+        &refresh_req.refresh_token,
+    )
+    .await?;
     
-    // Return success with JWT token and user info
     Ok(HttpResponse::Ok().json(response))
 }
 
 #[delete("/auth")]
-pub async fn logout() -> HttpResponse {
-    // In a stateful auth system, we would invalidate the token here
-    // Since JWTs are stateless, the client just needs to remove the token
-    HttpResponse::Ok().finish()
+pub async fn logout(
+    user: AuthenticatedUser,
+    token: SessionToken,
+    revocations: Data<RevocationStore>,
+    refresh_tokens: Data<RefreshTokenStore>,
+) -> Result<HttpResponse, AppError> {
+    // Revoke the presented token so it cannot be reused until it expires,
+    // and end the session so it cannot be refreshed
+    revocations.revoke_token(&token.jti, user.id, token.expires_at).await?;
+    refresh_tokens.revoke_family(token.session_id).await?;
+    
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[derive(Serialize)]
@@ -34,15 +284,31 @@ struct UserResponse {
     name: String,
     email: String,
     roles: Vec<String>,
+    // Set while an operator is acting as this user, so the UI can show it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    impersonator: Option<ImpersonatorResponse>,
+}
+
+#[derive(Serialize)]
+struct ImpersonatorResponse {
+    id: String,
+    name: String,
 }
 
 #[get("/auth")]
-pub async fn get_current_user(user: AuthenticatedUser) -> Result<HttpResponse, AppError> {
+pub async fn get_current_user(
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+) -> Result<HttpResponse, AppError> {
     let user_data = UserResponse {
         id: user.id.to_string(),
         name: user.name,
         email: user.email,
         roles: user.roles,
+        impersonator: impersonation.map(|i| ImpersonatorResponse {
+            id: i.impersonator_id.to_string(),
+            name: i.impersonator_name,
+        }),
     };
     
     Ok(HttpResponse::Ok().json(user_data))
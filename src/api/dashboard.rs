@@ -4,8 +4,9 @@ use tracing::info;
 use futures::try_join;
 
 use crate::auth::AuthenticatedUser;
-use crate::config::Config;
 use crate::error::AppError;
+use crate::services::ServiceClients;
+use crate::services::VmListQuery;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DashboardStats {
@@ -22,24 +23,26 @@ pub struct DashboardStats {
 #[get("")]
 pub async fn get_dashboard_stats(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
 ) -> Result<HttpResponse, AppError> {
     info!("Fetching dashboard statistics");
     
     // Create service clients
-    let vmapi_service = crate::services::VmapiService::new(config.vmapi_url.clone());
-    let ufds_service = crate::services::UfdsService::new(config.ufds_url.clone());
-    let cnapi_service = crate::services::CnapiService::new(config.cnapi_url.clone());
+    let vmapi_service = &services.vmapi;
+    let ufds_service = &services.ufds;
+    let cnapi_service = &services.cnapi;
     
     // Get data from services in parallel
-    let vms_result = vmapi_service.list_vms();
-    let users_result = ufds_service.list_users();
+    let all_vms = VmListQuery::default();
+    let vms_result = vmapi_service.count_vms(&all_vms);
+    let user_params = crate::api::users::UserListParams::default();
+    let users_result = ufds_service.list_users(&user_params);
     let servers_result = cnapi_service.list_servers();
     
-    let (vms, users, servers) = try_join!(vms_result, users_result, servers_result)?;
+    let (vms_total, users, servers) = try_join!(vms_result, users_result, servers_result)?;
     
     // Calculate statistics
-    let vms_count = vms.len();
+    let vms_count = vms_total as usize;
     let users_count = users.len();
     let servers_count = servers.len();
     
@@ -0,0 +1,27 @@
+use actix_web::{get, web::Data, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::auth::AuthenticatedUser;
+use crate::error::AppError;
+use crate::services::{ServiceClients, UpstreamStats};
+
+#[derive(Serialize)]
+struct UpstreamsResponse {
+    upstreams: Vec<UpstreamStats>,
+    time: String,
+}
+
+#[get("/upstreams")]
+pub async fn get_upstreams(
+    _user: AuthenticatedUser,
+    services: Data<ServiceClients>,
+) -> Result<HttpResponse, AppError> {
+    // Report circuit breaker state and retry/failure counters per service
+    let response = UpstreamsResponse {
+        upstreams: services.upstream_stats(),
+        time: Utc::now().to_rfc3339(),
+    };
+    
+    Ok(HttpResponse::Ok().json(response))
+}
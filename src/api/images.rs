@@ -2,9 +2,10 @@ use actix_web::{get, put, patch, web::{self, Data, Json, Path, Query}, HttpRespo
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::ListQuery;
+use crate::auth::{AuthenticatedUser, Impersonation};
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ImageListParams {
@@ -13,8 +14,6 @@ pub struct ImageListParams {
     pub state: Option<String>,
     pub owner: Option<String>,
     pub public: Option<bool>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +23,11 @@ pub struct Image {
     pub version: String,
     pub os: String,
     pub state: String,
+    // zone-dataset, lx-dataset, zvol, docker, ...
+    #[serde(rename = "type")]
+    pub image_type: String,
+    // Virtual disk size in MiB (zvol images only)
+    pub image_size: Option<u64>,
     pub owner: Option<String>,
     pub public: bool,
     pub published_at: String,
@@ -39,87 +43,53 @@ pub struct ImageFile {
     pub compression: String,
 }
 
+impl ImageListParams {
+    fn matches(&self, image: &Image) -> bool {
+        self.name.as_ref().is_none_or(|name| image.name.contains(name))
+            && self.os.as_ref().is_none_or(|os| image.os == *os)
+            && self.state.as_ref().is_none_or(|state| image.state == *state)
+            && self.owner.as_ref().is_none_or(|owner| image.owner.as_ref() == Some(owner))
+            && self.public.is_none_or(|public| image.public == public)
+    }
+}
+
 #[get("")]
 pub async fn list_images(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<ImageListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create IMGAPI service client
-    let imgapi_service = crate::services::ImgapiService::new(config.imgapi_url.clone());
-    
-    // Get images from IMGAPI
-    let images = imgapi_service.list_images().await?;
+    let imgapi_service = &services.imgapi;
     
-    // If there are filtering parameters, apply them
-    let filtered_images = if query.name.is_some() || query.os.is_some() || query.state.is_some() || query.owner.is_some() || query.public.is_some() {
-        images.into_iter().filter(|image| {
-            let name_match = match &query.name {
-                Some(name) => image.name.contains(name),
-                None => true,
-            };
-            
-            let os_match = match &query.os {
-                Some(os) => image.os == *os,
-                None => true,
-            };
-            
-            let state_match = match &query.state {
-                Some(state) => image.state == *state,
-                None => true,
-            };
-            
-            let owner_match = match &query.owner {
-                Some(owner) => image.owner.as_ref().map_or(false, |img_owner| img_owner == owner),
-                None => true,
-            };
-            
-            let public_match = match query.public {
-                Some(public) => image.public == public,
-                None => true,
-            };
-            
-            name_match && os_match && state_match && owner_match && public_match
-        }).collect()
-    } else {
-        images
-    };
+    // Get images from IMGAPI, as the impersonated account would see them
+    let account = impersonation.map(|_| user.id.to_string());
+    let mut images = imgapi_service.list_images(account.as_deref()).await?;
+    images.retain(|image| query.matches(image));
     
-    // Apply pagination if specified
-    let paginated_images = match (query.offset, query.limit) {
-        (Some(offset), Some(limit)) => {
-            let offset = offset as usize;
-            let limit = limit as usize;
-            filtered_images.into_iter().skip(offset).take(limit).collect()
-        },
-        (Some(offset), None) => {
-            let offset = offset as usize;
-            filtered_images.into_iter().skip(offset).collect()
-        },
-        (None, Some(limit)) => {
-            let limit = limit as usize;
-            filtered_images.into_iter().take(limit).collect()
-        },
-        (None, None) => filtered_images,
-    };
-    
-    Ok(HttpResponse::Ok().json(paginated_images))
+    Ok(HttpResponse::Ok().json(list.apply(images)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_image(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create IMGAPI service client
-    let imgapi_service = crate::services::ImgapiService::new(config.imgapi_url.clone());
+    let imgapi_service = &services.imgapi;
     
     // Get image from IMGAPI
     let image = imgapi_service.get_image(&uuid).await?;
     
+    // The impersonated account can only see its own and public images
+    if impersonation.is_some() && !image.public && image.owner.as_deref() != Some(&user.id.to_string()) {
+        return Err(AppError::NotFound(format!("Image with UUID {} not found", uuid)));
+    }
+    
     Ok(HttpResponse::Ok().json(image))
 }
 
@@ -134,14 +104,13 @@ pub struct UpdateImageRequest {
 #[patch("/{uuid}")]
 pub async fn update_image(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     image_req: Json<UpdateImageRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create IMGAPI service client
-    let imgapi_service = crate::services::ImgapiService::new(config.imgapi_url.clone());
+    let imgapi_service = &services.imgapi;
     
     // Update image via IMGAPI
     let image = imgapi_service.update_image(&uuid, image_req.0).await?;
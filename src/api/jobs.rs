@@ -6,18 +6,16 @@ use actix_web::{
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::{ListQuery, PageStart};
+use crate::auth::{AuthenticatedUser, Impersonation};
 use crate::error::AppError;
-use crate::services::VmapiService;
+use crate::services::ServiceClients;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JobListParams {
     pub vm_uuid: Option<String>,
     pub execution: Option<String>,
     pub name: Option<String>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -44,41 +42,59 @@ pub struct Job {
 
 #[get("")]
 pub async fn list_jobs(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<JobListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create an instance of the VMAPI service
     info!("Listing Jobs using VMAPI service");
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
     
-    // Call the service to get all jobs (filtering will be done in the service)
+    // VMAPI cannot list jobs by owner, so the impersonated customer's jobs
+    // are listed one of their VMs at a time
+    if impersonation.is_some() {
+        let vm_uuid = query.vm_uuid.as_deref().ok_or_else(|| {
+            AppError::AuthorizationError("vm_uuid is required while impersonating a user".to_string())
+        })?;
+        check_vm_owner(&services, vm_uuid, &user).await?;
+    }
+    
+    // VMAPI returns jobs newest first and does the paging
+    list.unsorted("Jobs")?;
+    let offset = list.offset()?;
+    
+    // Call the service to get the jobs (filtering will be done in the service)
     let jobs = vmapi_service.list_jobs(
         query.vm_uuid.as_deref(),
         query.execution.as_deref(),
         query.name.as_deref(),
-        query.limit,
-        query.offset,
+        list.limit(),
+        Some(offset),
     ).await?;
+    let next = list.limit().and_then(|limit| PageStart::after(offset, limit, jobs.len()));
     
     // Return the jobs as JSON
-    Ok(HttpResponse::Ok().json(jobs))
+    Ok(HttpResponse::Ok().json(list.page(jobs, None, next)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_job(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create an instance of the VMAPI service
     info!("Getting Job {} using VMAPI service", uuid);
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
     
     // Call the service to get the job
     let job = vmapi_service.get_job(&uuid).await?;
+    if impersonation.is_some() {
+        check_job_owner(&services, &job, &user).await?;
+    }
     
     // Return the job as JSON
     Ok(HttpResponse::Ok().json(job))
@@ -86,19 +102,49 @@ pub async fn get_job(
 
 #[get("/{uuid}/output")]
 pub async fn get_job_output(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create an instance of the VMAPI service
     info!("Getting Job output for {} using VMAPI service", uuid);
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
+    
+    if impersonation.is_some() {
+        let job = vmapi_service.get_job(&uuid).await?;
+        check_job_owner(&services, &job, &user).await?;
+    }
     
     // Call the service to get the job output
     let output = vmapi_service.get_job_output(&uuid).await?;
     
     // Return the output as plain text
     Ok(HttpResponse::Ok().content_type("text/plain").body(output))
-}
\ No newline at end of file
+}
+
+// While impersonating, jobs of other owners' VMs look like they don't exist
+async fn check_job_owner(services: &ServiceClients, job: &Job, user: &AuthenticatedUser) -> Result<(), AppError> {
+    let vm_uuid = job.params.as_ref().and_then(|params| params["vm_uuid"].as_str());
+    let owned = match vm_uuid {
+        Some(vm_uuid) => match check_vm_owner(services, vm_uuid, user).await {
+            Ok(()) => true,
+            Err(AppError::NotFound(_)) => false,
+            Err(e) => return Err(e),
+        },
+        None => false,
+    };
+    if !owned {
+        return Err(AppError::NotFound(format!("Job with UUID {} not found", job.uuid)));
+    }
+    Ok(())
+}
+
+async fn check_vm_owner(services: &ServiceClients, vm_uuid: &str, user: &AuthenticatedUser) -> Result<(), AppError> {
+    let vm = services.vmapi.get_vm(vm_uuid).await?;
+    if vm.owner_uuid != user.id.to_string() {
+        return Err(AppError::NotFound(format!("VM with UUID {} not found", vm_uuid)));
+    }
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+use crate::error::AppError;
+
+// Largest page any list endpoint returns
+pub const MAX_LIMIT: u32 = 1000;
+
+// Paging, sorting and field selection shared by every list endpoint. Take
+// it as a second Query extractor next to the endpoint's own filters.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    // `next` from the previous page; replaces offset
+    pub cursor: Option<String>,
+    // "field", "field:asc" or "field:desc"
+    pub sort: Option<String>,
+    // Comma-separated fields to return for each item
+    pub fields: Option<String>,
+}
+
+// Every list endpoint answers with this envelope. `total` is null when the
+// upstream service cannot count matches, and `next` is null on the last page.
+#[derive(Debug, Serialize)]
+pub struct ListResponse {
+    pub items: Vec<Value>,
+    pub total: Option<u64>,
+    pub next: Option<String>,
+}
+
+// Where a page starts: an offset, or an upstream service's marker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageStart {
+    Offset(u32),
+    Marker(String),
+}
+
+impl PageStart {
+    // Cursors are opaque to clients
+    fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(serde_json::to_string(self).unwrap_or_default().as_bytes())
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let json = BASE64URL_NOPAD.decode(cursor.as_bytes()).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    // The page after one from a service that pages by offset but does not
+    // count matches: there may be more whenever the page came back full
+    pub fn after(offset: u32, limit: u32, returned: usize) -> Option<Self> {
+        (returned >= limit as usize).then_some(PageStart::Offset(offset + returned as u32))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Sort {
+    pub field: String,
+    pub descending: bool,
+}
+
+impl ListQuery {
+    // Page size, within 1..=MAX_LIMIT; None for everything
+    pub fn limit(&self) -> Option<u32> {
+        self.limit.map(|limit| limit.clamp(1, MAX_LIMIT))
+    }
+
+    pub fn start(&self) -> Result<PageStart, AppError> {
+        match (&self.cursor, self.offset) {
+            (Some(_), Some(_)) => Err(AppError::ValidationError("Use either cursor or offset, not both".to_string())),
+            (Some(cursor), None) => {
+                PageStart::decode(cursor).ok_or_else(|| AppError::ValidationError("Invalid cursor".to_string()))
+            }
+            (None, offset) => Ok(PageStart::Offset(offset.unwrap_or(0))),
+        }
+    }
+
+    // For lists paged by offset only
+    pub fn offset(&self) -> Result<u32, AppError> {
+        match self.start()? {
+            PageStart::Offset(offset) => Ok(offset),
+            PageStart::Marker(_) => Err(AppError::ValidationError("Invalid cursor".to_string())),
+        }
+    }
+
+    pub fn sort(&self) -> Result<Option<Sort>, AppError> {
+        let sort = match self.sort.as_deref().map(str::trim).filter(|sort| !sort.is_empty()) {
+            Some(sort) => sort,
+            None => return Ok(None),
+        };
+        let (field, direction) = sort.split_once(':').unwrap_or((sort, "asc"));
+        let descending = match direction.to_ascii_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(AppError::ValidationError(format!("Sort direction must be asc or desc, not {}", direction))),
+        };
+
+        Ok(Some(Sort { field: field.to_string(), descending }))
+    }
+
+    // For lists whose upstream returns them in a fixed order
+    pub fn unsorted(&self, resource: &str) -> Result<(), AppError> {
+        match self.sort()? {
+            Some(_) => Err(AppError::ValidationError(format!("{} cannot be sorted", resource))),
+            None => Ok(()),
+        }
+    }
+
+    // Sort, page and project a complete list held in memory
+    pub fn apply<T: Serialize>(&self, items: Vec<T>) -> Result<ListResponse, AppError> {
+        let offset = self.offset()? as usize;
+        let mut items = to_values(items)?;
+
+        if let Some(sort) = self.sort()? {
+            if !items.is_empty() && !items.iter().any(|item| item.get(&sort.field).is_some()) {
+                return Err(AppError::ValidationError(format!("Cannot sort by {}", sort.field)));
+            }
+            // Stable, so equal items keep the upstream order
+            items.sort_by(|a, b| {
+                let ordering = compare(&a[&sort.field], &b[&sort.field]);
+                if sort.descending { ordering.reverse() } else { ordering }
+            });
+        }
+
+        let total = items.len();
+        let page: Vec<Value> = match self.limit() {
+            Some(limit) => items.into_iter().skip(offset).take(limit as usize).collect(),
+            None => items.into_iter().skip(offset).collect(),
+        };
+        let end = offset + page.len();
+        let next = (end < total && !page.is_empty()).then_some(PageStart::Offset(end as u32));
+
+        Ok(self.respond(page, Some(total as u64), next))
+    }
+
+    // Wrap a page fetched from a service that did the paging itself
+    pub fn page<T: Serialize>(&self, items: Vec<T>, total: Option<u64>, next: Option<PageStart>) -> Result<ListResponse, AppError> {
+        Ok(self.respond(to_values(items)?, total, next))
+    }
+
+    fn respond(&self, mut items: Vec<Value>, total: Option<u64>, next: Option<PageStart>) -> ListResponse {
+        if let Some(fields) = &self.fields {
+            let fields: Vec<&str> = fields.split(',').map(str::trim).filter(|f| !f.is_empty()).collect();
+            for item in items.iter_mut() {
+                if let Value::Object(map) = item {
+                    map.retain(|key, _| fields.contains(&key.as_str()));
+                }
+            }
+        }
+
+        ListResponse {
+            items,
+            total,
+            next: next.map(|next| next.encode()),
+        }
+    }
+}
+
+fn to_values<T: Serialize>(items: Vec<T>) -> Result<Vec<Value>, AppError> {
+    items
+        .into_iter()
+        .map(|item| serde_json::to_value(item).map_err(AppError::from))
+        .collect()
+}
+
+// Missing values sort first, then by type: booleans, numbers, strings
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => {
+            a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)).then_with(|| a.to_string().cmp(&b.to_string())),
+    }
+}
+
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Query;
+    use serde_json::json;
+
+    fn query(query: &str) -> ListQuery {
+        Query::<ListQuery>::from_query(query).unwrap().into_inner()
+    }
+
+    fn items() -> Vec<Value> {
+        vec![
+            json!({ "name": "b", "ram": 2048, "os": "linux" }),
+            json!({ "name": "a", "ram": 512, "os": "smartos" }),
+            json!({ "name": "c", "ram": 1024, "os": "linux" }),
+            json!({ "name": "d", "os": "linux" }),
+        ]
+    }
+
+    fn names(response: &ListResponse) -> Vec<&str> {
+        response.items.iter().map(|item| item["name"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn complete_lists_are_sorted_and_paged() {
+        let response = query("sort=ram:desc&limit=2").apply(items()).unwrap();
+        assert_eq!(names(&response), ["b", "c"]);
+        assert_eq!(response.total, Some(4));
+
+        // The cursor continues where the page ended
+        let next = response.next.unwrap();
+        let response = query(&format!("sort=ram:desc&limit=2&cursor={}", next)).apply(items()).unwrap();
+        assert_eq!(names(&response), ["a", "d"]);
+        assert!(response.next.is_none());
+
+        let response = query("sort=name&offset=1").apply(items()).unwrap();
+        assert_eq!(names(&response), ["b", "c", "d"]);
+        assert!(response.next.is_none());
+    }
+
+    #[test]
+    fn fields_select_what_is_returned() {
+        let response = query("fields=name,%20os&limit=1").apply(items()).unwrap();
+        assert_eq!(response.items, vec![json!({ "name": "b", "os": "linux" })]);
+    }
+
+    #[test]
+    fn bad_list_queries_are_rejected() {
+        for bad in ["sort=nope", "sort=name:sideways", "cursor=garbage", "cursor=e30&offset=2"] {
+            assert!(
+                matches!(query(bad).apply(items()), Err(AppError::ValidationError(_))),
+                "{}",
+                bad
+            );
+        }
+        assert!(query("sort=name").unsorted("Jobs").is_err());
+        assert!(query("").unsorted("Jobs").is_ok());
+
+        // Marker cursors only mean something to the list that issued them
+        let marker = PageStart::Marker("{}".to_string()).encode();
+        assert!(query(&format!("cursor={}", marker)).offset().is_err());
+        assert_eq!(query(&format!("cursor={}", marker)).start().unwrap(), PageStart::Marker("{}".to_string()));
+    }
+
+    #[test]
+    fn full_upstream_pages_may_have_more() {
+        assert_eq!(PageStart::after(10, 2, 2), Some(PageStart::Offset(12)));
+        assert_eq!(PageStart::after(10, 2, 1), None);
+        assert_eq!(query("limit=5000").limit(), Some(MAX_LIMIT));
+    }
+}
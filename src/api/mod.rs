@@ -1,7 +1,12 @@
-use actix_web::web;
+use actix_web::{web, HttpRequest};
+use std::net::IpAddr;
+use crate::audit::AuditMiddleware;
 use crate::auth::middleware::AuthMiddleware;
+use crate::auth::rbac::{Permission, RequirePermission, RequireScope};
+use crate::config::Config;
 use tracing::info;
 
+pub mod audit;
 pub mod auth;
 pub mod vms;
 pub mod users;
@@ -13,6 +18,10 @@ pub mod networks;
 pub mod jobs;
 pub mod ping;
 pub mod dashboard;
+pub mod diagnostics;
+pub mod tokens;
+pub mod listing;
+pub mod search;
 
 pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
     info!("Configuring API routes with authentication middleware");
@@ -21,54 +30,94 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
         web::scope("/api")
             // Auth endpoints (no auth required)
             .service(auth::login)
-            .service(auth::logout)
-            .service(auth::get_current_user)
+            .service(auth::refresh)
+            .service(auth::verify_totp)
+            .service(auth::enroll_totp)
+            .service(auth::get_oidc_provider)
+            .service(auth::oidc_login)
+            .service(auth::oidc_callback)
             
-            // Ping endpoint (health check)
+            // Health endpoints (no auth required)
             .service(ping::ping)
+            .service(ping::liveness)
+            .service(ping::readiness)
             
             // Protected API routes - require authentication
             .service(
                 web::scope("")
+                    // Record every authenticated mutating request in the
+                    // audit log; AuthMiddleware wraps it and runs first
+                    .wrap(AuditMiddleware)
                     .wrap(AuthMiddleware::new(jwt_secret.to_string()))
+                    // Session endpoints. Sign-out takes the session's own
+                    // token, so API tokens cannot reach it.
+                    .service(auth::get_current_user)
+                    .service(auth::logout)
+                    
+                    // API tokens for automation. Managing tokens and second
+                    // factors needs the signed-in user themselves: not an
+                    // API token, and not an operator impersonating them.
+                    .service(
+                        web::scope("/tokens")
+                            .wrap(RequireScope::session_only())
+                            .service(tokens::list_tokens)
+                            .service(tokens::create_token)
+                            .service(tokens::delete_token)
+                    )
+                    
                     // VMs endpoints
                     .service(
                         web::scope("/vms")
+                            .wrap(RequireScope::new("vms"))
                             .service(vms::list_vms)
                             .service(vms::get_vm)
-                            .service(vms::create_vm)
+                            .service(vms::get_vm_jobs)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManageVms))
+                                    .service(vms::create_vm)
                                     .service(vms::update_vm)
+                                    .service(vms::update_vm_partial)
                                     .service(vms::delete_vm)
                                     .service(vms::vm_action)
-                                    .service(vms::get_vm_jobs)
                             )
                     )
                     
                     // Users endpoints
                     .service(
                         web::scope("/users")
+                            .wrap(RequireScope::new("users"))
                             .service(users::list_users)
                             .service(users::get_user)
+                            .service(users::list_keys)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManageUsers))
                                     .service(users::create_user)
                                     .service(users::update_user)
+                                    .service(users::update_user_partial)
                                     .service(users::delete_user)
+                                    .service(users::revoke_sessions)
+                                    .service(users::unlock_user)
+                                    .service(users::reset_totp)
+                                    .service(users::impersonate_user)
+                                    .service(users::add_key)
+                                    .service(users::delete_key)
                             )
                     )
                     
                     // Packages endpoints
                     .service(
                         web::scope("/packages")
+                            .wrap(RequireScope::new("packages"))
                             .service(packages::list_packages)
                             .service(packages::get_package)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManagePackages))
                                     .service(packages::create_package)
                                     .service(packages::update_package)
                             )
@@ -77,11 +126,13 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
                     // Images endpoints
                     .service(
                         web::scope("/images")
+                            .wrap(RequireScope::new("images"))
                             .service(images::list_images)
                             .service(images::get_image)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManageImages))
                                     .service(images::update_image)
                             )
                     )
@@ -89,17 +140,20 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
                     // Platforms endpoints
                     .service(
                         web::scope("/platforms")
+                            .wrap(RequireScope::new("platforms"))
                             .service(platforms::list_platforms)
                     )
                     
                     // Servers endpoints
                     .service(
                         web::scope("/servers")
+                            .wrap(RequireScope::new("servers"))
                             .service(servers::list_servers)
                             .service(servers::get_server)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManageServers))
                                     .service(servers::update_server)
                                     .service(servers::server_action)
                             )
@@ -108,11 +162,13 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
                     // Networks endpoints
                     .service(
                         web::scope("/networks")
+                            .wrap(RequireScope::new("networks"))
                             .service(networks::list_networks)
                             .service(networks::get_network)
                             // Admin-only actions
                             .service(
                                 web::scope("")
+                                    .wrap(RequirePermission::new(Permission::ManageNetworks))
                                     .service(networks::create_network)
                                     .service(networks::update_network)
                                     .service(networks::delete_network)
@@ -122,6 +178,7 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
                     // Jobs endpoints
                     .service(
                         web::scope("/jobs")
+                            .wrap(RequireScope::new("jobs"))
                             .service(jobs::list_jobs)
                             .service(jobs::get_job)
                             .service(jobs::get_job_output)
@@ -130,8 +187,187 @@ pub fn configure_routes(cfg: &mut web::ServiceConfig, jwt_secret: &str) {
                     // Dashboard endpoint
                     .service(
                         web::scope("/dashboard")
+                            .wrap(RequireScope::new("dashboard"))
                             .service(dashboard::get_dashboard_stats)
                     )
+                    
+                    // Audit log
+                    .service(
+                        web::scope("/audit")
+                            .wrap(RequireScope::new("audit"))
+                            .wrap(RequirePermission::new(Permission::ViewAudit))
+                            .service(audit::list_audit_entries)
+                    )
+                    
+                    // Global search; each service's hits need the API token
+                    // scope for that resource
+                    .service(
+                        web::scope("/search")
+                            .service(search::search)
+                    )
+                    
+                    // Upstream diagnostics (circuit breakers, failure counters)
+                    .service(
+                        web::scope("/diagnostics")
+                            .wrap(RequireScope::new("servers"))
+                            .wrap(RequirePermission::new(Permission::ManageServers))
+                            .service(diagnostics::get_upstreams)
+                            .service(ping::service_health)
+                    )
+                    
+                    // Second factor enrollment; last, since an empty scope
+                    // takes every path that reaches it
+                    .service(
+                        web::scope("")
+                            .wrap(RequireScope::session_only())
+                            .service(auth::start_totp_enrollment)
+                            .service(auth::activate_totp)
+                    )
             )
     );
-}
\ No newline at end of file
+}
+
+// The client's address. Behind a trusted proxy this is the nearest address in
+// X-Forwarded-For that is not itself a trusted proxy; clients can prepend
+// anything to the header, so it is read from the right.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr().map(|addr| addr.ip())?;
+    let trusted = match req.app_data::<web::Data<Config>>() {
+        Some(config) => &config.trusted_proxies,
+        None => return Some(peer),
+    };
+
+    let mut client = peer;
+    if trusted.contains(&client) {
+        let forwarded = req
+            .headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+        for hop in forwarded.iter().rev() {
+            match hop.trim().parse() {
+                Ok(ip) => client = ip,
+                Err(_) => break,
+            }
+            if !trusted.contains(&client) {
+                break;
+            }
+        }
+    }
+    Some(client)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use uuid::Uuid;
+
+    use crate::auth::revocation::RevocationStore;
+    use crate::auth::{impersonate, AuthenticatedUser, UserInfo};
+    use crate::services::ServiceClients;
+
+    fn request(peer: &str, forwarded: Option<&str>) -> HttpRequest {
+        let config = Config {
+            trusted_proxies: vec![ip("10.0.0.1").unwrap(), ip("10.0.0.2").unwrap()],
+            ..Config::for_tests()
+        };
+
+        let mut req = TestRequest::default()
+            .peer_addr(format!("{}:40000", peer).parse().unwrap())
+            .app_data(web::Data::new(config));
+        if let Some(forwarded) = forwarded {
+            req = req.insert_header(("X-Forwarded-For", forwarded));
+        }
+        req.to_http_request()
+    }
+
+    fn ip(addr: &str) -> Option<IpAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    #[test]
+    fn forwarded_addresses_are_only_believed_from_trusted_proxies() {
+        // Direct clients cannot claim another address
+        assert_eq!(client_ip(&request("198.51.100.7", Some("203.0.113.9"))), ip("198.51.100.7"));
+
+        assert_eq!(client_ip(&request("10.0.0.1", Some("203.0.113.9"))), ip("203.0.113.9"));
+        // Through two trusted proxies, ignoring what the client prepended
+        assert_eq!(
+            client_ip(&request("10.0.0.1", Some("192.0.2.1, 203.0.113.9, 10.0.0.2"))),
+            ip("203.0.113.9")
+        );
+        // Without a usable header the proxy itself is the client
+        assert_eq!(client_ip(&request("10.0.0.1", None)), ip("10.0.0.1"));
+        assert_eq!(client_ip(&request("10.0.0.1", Some("unknown"))), ip("10.0.0.1"));
+    }
+
+    fn impersonation_token(config: &Config) -> String {
+        let operator = AuthenticatedUser {
+            id: Uuid::new_v4(),
+            name: "operator".to_string(),
+            email: "operator@example.com".to_string(),
+            roles: vec!["admin".to_string()],
+        };
+        let customer = UserInfo {
+            id: Uuid::new_v4().to_string(),
+            name: "customer".to_string(),
+            email: "customer@example.com".to_string(),
+            roles: vec![],
+        };
+        impersonate(config, customer, &operator).unwrap().token
+    }
+
+    #[actix_web::test]
+    async fn impersonation_cannot_mint_credentials() {
+        let config = Config::for_tests();
+        let token = impersonation_token(&config);
+
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(RevocationStore::new(None)))
+                .configure(|cfg| configure_routes(cfg, &config.jwt_secret)),
+        )
+        .await;
+
+        for (method, uri) in [
+            ("GET", "/api/tokens"),
+            ("POST", "/api/tokens"),
+            ("POST", "/api/auth/totp"),
+            ("POST", "/api/auth/totp/activate"),
+        ] {
+            let request = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri)
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            assert_eq!(call_service(&app, request).await.status(), 403, "{} {}", method, uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn impersonation_hides_servers_and_other_jobs() {
+        let config = Config::for_tests();
+        let token = impersonation_token(&config);
+
+        // The services are never reached: these are refused up front
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(RevocationStore::new(None)))
+                .app_data(web::Data::new(ServiceClients::new(&config).unwrap()))
+                .configure(|cfg| configure_routes(cfg, &config.jwt_secret)),
+        )
+        .await;
+
+        for uri in ["/api/servers", &format!("/api/servers/{}", Uuid::new_v4()), "/api/jobs"] {
+            let request = TestRequest::get()
+                .uri(uri)
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request();
+            assert_eq!(call_service(&app, request).await.status(), 403, "{}", uri);
+        }
+    }
+}
@@ -1,17 +1,15 @@
 use actix_web::{get, post, put, delete, web::{self, Data, Json, Path, Query}, HttpResponse};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::ListQuery;
+use crate::auth::{AuthenticatedUser, Impersonation};
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NetworkListParams {
     pub name: Option<String>,
     pub fabric: Option<bool>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -31,71 +29,76 @@ pub struct Network {
     pub updated_at: String,
 }
 
+// A NIC and what it is attached to: belongs_to_type is zone, server or other
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Nic {
+    pub mac: String,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub network_uuid: Option<String>,
+    pub belongs_to_uuid: String,
+    pub belongs_to_type: String,
+}
+
+// An address on one of NAPI's networks; unassigned addresses belong to
+// nothing
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NetworkIp {
+    pub ip: String,
+    pub network_uuid: String,
+    #[serde(default)]
+    pub belongs_to_uuid: Option<String>,
+    #[serde(default)]
+    pub belongs_to_type: Option<String>,
+}
+
+impl NetworkListParams {
+    fn matches(&self, network: &Network) -> bool {
+        self.name.as_ref().is_none_or(|name| network.name.contains(name))
+            && self.fabric.is_none_or(|fabric| network.fabric == fabric)
+    }
+}
+
+// While impersonating, only shared networks and the customer's own are
+// visible
+pub(crate) fn visible(network: &Network, user: &AuthenticatedUser, impersonation: Option<&Impersonation>) -> bool {
+    impersonation.is_none() || network.owner_uuid.as_ref().is_none_or(|owner| *owner == user.id.to_string())
+}
+
 #[get("")]
 pub async fn list_networks(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<NetworkListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create NAPI service client
-    let napi_service = crate::services::NapiService::new(config.napi_url.clone());
+    let napi_service = &services.napi;
     
     // Get networks from NAPI
-    let networks = napi_service.list_networks().await?;
-    
-    // If there are filtering parameters, apply them
-    let filtered_networks = if query.name.is_some() || query.fabric.is_some() {
-        networks.into_iter().filter(|network| {
-            let name_match = match &query.name {
-                Some(name) => network.name.contains(name),
-                None => true,
-            };
-            
-            let fabric_match = match query.fabric {
-                Some(fabric) => network.fabric == fabric,
-                None => true,
-            };
-            
-            name_match && fabric_match
-        }).collect()
-    } else {
-        networks
-    };
+    let mut networks = napi_service.list_networks().await?;
+    networks.retain(|network| query.matches(network) && visible(network, &user, impersonation.as_ref()));
     
-    // Apply pagination if specified
-    let paginated_networks = match (query.offset, query.limit) {
-        (Some(offset), Some(limit)) => {
-            let offset = offset as usize;
-            let limit = limit as usize;
-            filtered_networks.into_iter().skip(offset).take(limit).collect()
-        },
-        (Some(offset), None) => {
-            let offset = offset as usize;
-            filtered_networks.into_iter().skip(offset).collect()
-        },
-        (None, Some(limit)) => {
-            let limit = limit as usize;
-            filtered_networks.into_iter().take(limit).collect()
-        },
-        (None, None) => filtered_networks,
-    };
-    
-    Ok(HttpResponse::Ok().json(paginated_networks))
+    Ok(HttpResponse::Ok().json(list.apply(networks)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_network(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create NAPI service client
-    let napi_service = crate::services::NapiService::new(config.napi_url.clone());
+    let napi_service = &services.napi;
     
     // Get network from NAPI
     let network = napi_service.get_network(&uuid).await?;
+    if !visible(&network, &user, impersonation.as_ref()) {
+        return Err(AppError::NotFound(format!("Network with UUID {} not found", uuid)));
+    }
     
     Ok(HttpResponse::Ok().json(network))
 }
@@ -116,27 +119,13 @@ pub struct CreateNetworkRequest {
 #[post("")]
 pub async fn create_network(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     network_req: Json<CreateNetworkRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would call the NAPI client to create a network
-    // For now, we'll just return a placeholder
+    let napi_service = &services.napi;
     
-    let network = Network {
-        uuid: Uuid::new_v4().to_string(),
-        name: network_req.name.clone(),
-        subnet: network_req.subnet.clone(),
-        netmask: network_req.netmask.clone(),
-        gateway: network_req.gateway.clone(),
-        provision_start_ip: network_req.provision_start_ip.clone(),
-        provision_end_ip: network_req.provision_end_ip.clone(),
-        vlan_id: network_req.vlan_id,
-        fabric: false,
-        owner_uuid: network_req.owner_uuid.clone(),
-        description: network_req.description.clone(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-    };
+    // Create network in NAPI
+    let network = napi_service.create_network(network_req.into_inner()).await?;
     
     Ok(HttpResponse::Created().json(network))
 }
@@ -153,30 +142,16 @@ pub struct UpdateNetworkRequest {
 #[put("/{uuid}")]
 pub async fn update_network(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     network_req: Json<UpdateNetworkRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the NAPI client to update a network
-    // For now, we'll just return a placeholder
+    let napi_service = &services.napi;
     
-    let network = Network {
-        uuid,
-        name: network_req.name.clone().unwrap_or_else(|| "external".to_string()),
-        subnet: "192.168.1.0".to_string(),
-        netmask: "255.255.255.0".to_string(),
-        gateway: network_req.gateway.clone().unwrap_or_else(|| "192.168.1.1".to_string()),
-        provision_start_ip: network_req.provision_start_ip.clone().unwrap_or_else(|| "192.168.1.10".to_string()),
-        provision_end_ip: network_req.provision_end_ip.clone().unwrap_or_else(|| "192.168.1.250".to_string()),
-        vlan_id: 1,
-        fabric: false,
-        owner_uuid: None,
-        description: network_req.description.clone(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-    };
+    // Update network in NAPI
+    let network = napi_service.update_network(&uuid, network_req.into_inner()).await?;
     
     Ok(HttpResponse::Ok().json(network))
 }
@@ -184,13 +159,15 @@ pub async fn update_network(
 #[delete("/{uuid}")]
 pub async fn delete_network(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the NAPI client to delete a network
-    // For now, we'll just return a placeholder
+    let napi_service = &services.napi;
+    
+    // Delete network from NAPI
+    napi_service.delete_network(&uuid).await?;
     
     Ok(HttpResponse::NoContent().finish())
 }
\ No newline at end of file
@@ -2,17 +2,16 @@ use actix_web::{get, post, put, patch, web::{self, Data, Json, Path, Query}, Htt
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::api::listing::ListQuery;
 use crate::auth::AuthenticatedUser;
-use crate::config::Config;
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageListParams {
     pub name: Option<String>,
     pub memory: Option<u64>,
     pub vcpus: Option<u32>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -62,73 +61,39 @@ pub struct Package {
     pub disks: Option<Vec<serde_json::Value>>,
 }
 
+impl PackageListParams {
+    fn matches(&self, package: &Package) -> bool {
+        self.name.as_ref().is_none_or(|name| package.name.contains(name))
+            && self.memory.is_none_or(|memory| package.memory.unwrap_or(0) >= memory)
+            && self.vcpus.is_none_or(|vcpus| package.vcpus.unwrap_or(0) >= vcpus)
+    }
+}
+
 #[get("")]
 pub async fn list_packages(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     query: Query<PackageListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create PAPI service client
-    let papi_service = crate::services::PapiService::new(config.papi_url.clone());
+    let papi_service = &services.papi;
     
     // Get packages from PAPI
-    let packages = papi_service.list_packages().await?;
-    
-    // If there are filtering parameters, apply them
-    let filtered_packages = if query.name.is_some() || query.memory.is_some() || query.vcpus.is_some() {
-        packages.into_iter().filter(|package| {
-            let name_match = match &query.name {
-                Some(name) => package.name.contains(name),
-                None => true,
-            };
-            
-            let memory_match = match query.memory {
-                Some(memory) => package.memory.unwrap_or(0) >= memory,
-                None => true,
-            };
-            
-            let vcpus_match = match query.vcpus {
-                Some(vcpus) => package.vcpus.unwrap_or(0) >= vcpus,
-                None => true,
-            };
-            
-            name_match && memory_match && vcpus_match
-        }).collect()
-    } else {
-        packages
-    };
-    
-    // Apply pagination if specified
-    let paginated_packages = match (query.offset, query.limit) {
-        (Some(offset), Some(limit)) => {
-            let offset = offset as usize;
-            let limit = limit as usize;
-            filtered_packages.into_iter().skip(offset).take(limit).collect()
-        },
-        (Some(offset), None) => {
-            let offset = offset as usize;
-            filtered_packages.into_iter().skip(offset).collect()
-        },
-        (None, Some(limit)) => {
-            let limit = limit as usize;
-            filtered_packages.into_iter().take(limit).collect()
-        },
-        (None, None) => filtered_packages,
-    };
+    let mut packages = papi_service.list_packages().await?;
+    packages.retain(|package| query.matches(package));
     
-    Ok(HttpResponse::Ok().json(paginated_packages))
+    Ok(HttpResponse::Ok().json(list.apply(packages)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_package(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create PAPI service client
-    let papi_service = crate::services::PapiService::new(config.papi_url.clone());
+    let papi_service = &services.papi;
     
     // Get package from PAPI
     let package = papi_service.get_package(&uuid).await?;
@@ -150,11 +115,10 @@ pub struct CreatePackageRequest {
 #[post("")]
 pub async fn create_package(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     package_req: Json<CreatePackageRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // Create PAPI service client
-    let papi_service = crate::services::PapiService::new(config.papi_url.clone());
+    let papi_service = &services.papi;
     
     // Create package via PAPI
     let package = papi_service.create_package(package_req.0).await?;
@@ -176,14 +140,13 @@ pub struct UpdatePackageRequest {
 #[patch("/{uuid}")]
 pub async fn update_package(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     package_req: Json<UpdatePackageRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create PAPI service client
-    let papi_service = crate::services::PapiService::new(config.papi_url.clone());
+    let papi_service = &services.papi;
     
     // Update package via PAPI
     let package = papi_service.update_package(&uuid, package_req.0).await?;
@@ -1,32 +1,99 @@
-use actix_web::{get, HttpResponse, Responder};
+use actix_web::{get, web::Data, HttpResponse, Responder};
 use chrono::Utc;
 use serde::Serialize;
 
+use crate::auth::AuthenticatedUser;
+use crate::error::AppError;
+use crate::services::{HealthStatus, ServiceClients, ServiceHealth};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum OverallStatus {
+    Ok,
+    Degraded,
+    Unavailable,
+}
+
 #[derive(Serialize)]
 struct PingResponse {
-    services: ServiceStatus,
+    status: OverallStatus,
+    services: Vec<ServiceHealth>,
     time: String,
 }
 
-#[derive(Serialize)]
-struct ServiceStatus {
-    #[serde(rename = "moray")]
-    moray_connected: bool,
-    #[serde(rename = "ufds")]
-    ufds_connected: bool,
+impl PingResponse {
+    fn new(services: Vec<ServiceHealth>) -> Self {
+        let down = |critical_only: bool| {
+            services
+                .iter()
+                .any(|s| s.status == HealthStatus::Down && (s.critical || !critical_only))
+        };
+        
+        // Any critical service down makes the instance unavailable; other
+        // failures only degrade it
+        let status = if down(true) {
+            OverallStatus::Unavailable
+        } else if down(false) {
+            OverallStatus::Degraded
+        } else {
+            OverallStatus::Ok
+        };
+        
+        Self {
+            status,
+            services,
+            time: Utc::now().to_rfc3339(),
+        }
+    }
 }
 
+// Anonymous, cheap liveness check kept for clients that poll /ping. Upstream
+// detail is only reported to authenticated operators via
+// /diagnostics/services.
 #[get("/ping")]
 pub async fn ping() -> impl Responder {
-    // In a production system, these would be actual connection checks
-    // For now, we'll just return placeholders
-    let response = PingResponse {
-        services: ServiceStatus {
-            moray_connected: true,
-            ufds_connected: true,
-        },
-        time: Utc::now().to_rfc3339(),
-    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "time": Utc::now().to_rfc3339(),
+    }))
+}
+
+// Liveness: the process is up and serving requests. Deliberately does not
+// touch any upstream so a Triton outage does not get this instance restarted.
+#[get("/health/live")]
+pub async fn liveness() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "ok",
+        "time": Utc::now().to_rfc3339(),
+    }))
+}
+
+// Readiness: fails while a critical upstream (UFDS, VMAPI) is down so load
+// balancers can route around this instance. Only the overall verdict is
+// returned since the endpoint is anonymous.
+#[get("/health/ready")]
+pub async fn readiness(services: Data<ServiceClients>) -> impl Responder {
+    let health = services.health.check(&services.ufds, true).await;
+    let response = PingResponse::new(health);
+    let body = serde_json::json!({
+        "status": response.status,
+        "time": response.time,
+    });
+    
+    if response.status == OverallStatus::Unavailable {
+        HttpResponse::ServiceUnavailable().json(body)
+    } else {
+        HttpResponse::Ok().json(body)
+    }
+}
+
+// Probe every configured upstream and report status, latency and errors
+#[get("/services")]
+pub async fn service_health(
+    _user: AuthenticatedUser,
+    services: Data<ServiceClients>,
+) -> Result<HttpResponse, AppError> {
+    let health = services.health.check(&services.ufds, false).await;
     
-    HttpResponse::Ok().json(response)
-}
\ No newline at end of file
+    Ok(HttpResponse::Ok().json(PingResponse::new(health)))
+}
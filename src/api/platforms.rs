@@ -1,6 +1,7 @@
 use actix_web::{get, web::{Data, Query}, HttpResponse};
 use serde::{Deserialize, Serialize};
 
+use crate::api::listing::ListQuery;
 use crate::auth::AuthenticatedUser;
 use crate::config::Config;
 use crate::error::AppError;
@@ -8,8 +9,6 @@ use crate::error::AppError;
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlatformListParams {
     pub version: Option<String>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,11 +25,12 @@ pub async fn list_platforms(
     _user: AuthenticatedUser,
     config: Data<Config>,
     query: Query<PlatformListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
     // In a real implementation, this would call the CNAPI client to list platforms
     // For now, we'll just return a placeholder
     
-    let platforms = vec![
+    let mut platforms = vec![
         Platform {
             version: "20230101T000000Z".to_string(),
             latest: true,
@@ -46,6 +46,7 @@ pub async fn list_platforms(
             available: true,
         },
     ];
+    platforms.retain(|platform| query.version.as_ref().is_none_or(|version| platform.version == *version));
     
-    Ok(HttpResponse::Ok().json(platforms))
+    Ok(HttpResponse::Ok().json(list.apply(platforms)?))
 }
\ No newline at end of file
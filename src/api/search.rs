@@ -0,0 +1,643 @@
+use actix_web::{get, web::{Data, Query}, HttpMessage, HttpRequest, HttpResponse};
+use futures::future::{join_all, BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::api_tokens::{Access, ApiToken};
+use crate::auth::{AuthenticatedUser, Impersonation};
+use crate::config::Config;
+use crate::error::AppError;
+use crate::services::{ServiceClients, VmListQuery};
+
+// Hits returned when the request does not set a limit, and at most
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+// Matches asked of services that can page their results
+const SERVICE_LIMIT: u32 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+// What the query looks like, which decides where it is looked up
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryKind {
+    Uuid(String),
+    Ip(IpAddr),
+    // Lower case, colon separated
+    Mac(String),
+    // Alias, hostname, serial number, name, login or email
+    Text(String),
+}
+
+impl QueryKind {
+    pub fn detect(query: &str) -> Self {
+        if let Ok(uuid) = Uuid::parse_str(query) {
+            if query.len() == 36 {
+                return QueryKind::Uuid(uuid.to_string());
+            }
+        }
+        if let Ok(ip) = query.parse() {
+            return QueryKind::Ip(ip);
+        }
+        if let Some(mac) = parse_mac(query) {
+            return QueryKind::Mac(mac);
+        }
+        QueryKind::Text(query.to_string())
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            QueryKind::Uuid(_) => "uuid",
+            QueryKind::Ip(_) => "ip",
+            QueryKind::Mac(_) => "mac",
+            QueryKind::Text(_) => "text",
+        }
+    }
+}
+
+// aa:bb:cc:dd:ee:ff, aa-bb-cc-dd-ee-ff or aabbccddeeff
+fn parse_mac(query: &str) -> Option<String> {
+    let hex: String = if query.len() == 17 {
+        let parts: Vec<&str> = query.split([':', '-']).collect();
+        if parts.len() != 6 || parts.iter().any(|part| part.len() != 2) {
+            return None;
+        }
+        parts.concat()
+    } else {
+        query.to_string()
+    };
+    if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let hex = hex.to_ascii_lowercase();
+    let octets: Vec<&str> = (0..12).step_by(2).map(|i| &hex[i..i + 2]).collect();
+    Some(octets.join(":"))
+}
+
+// Declared in the order hits of equal strength are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HitType {
+    Vm,
+    Server,
+    Network,
+    Image,
+    Package,
+    User,
+}
+
+impl HitType {
+    // Where the UI shows the object
+    fn link(&self, id: &str) -> String {
+        let section = match self {
+            HitType::Vm => "vms",
+            HitType::Server => "servers",
+            HitType::Network => "networks",
+            HitType::Image => "images",
+            HitType::Package => "packages",
+            HitType::User => "users",
+        };
+        format!("/{}/{}", section, id)
+    }
+}
+
+// How well a hit matched; stronger matches rank first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strength {
+    Contains,
+    Prefix,
+    Exact,
+}
+
+// Case-insensitive match of the query against a name
+fn text_match(value: &str, query: &str) -> Option<Strength> {
+    let value = value.to_lowercase();
+    let query = query.to_lowercase();
+    if value == query {
+        Some(Strength::Exact)
+    } else if value.starts_with(&query) {
+        Some(Strength::Prefix)
+    } else if value.contains(&query) {
+        Some(Strength::Contains)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    #[serde(rename = "type")]
+    pub hit_type: HitType,
+    pub id: String,
+    pub label: String,
+    // The field the query matched: uuid, alias, ip, mac, hostname, ...
+    pub matched: &'static str,
+    #[serde(rename = "match")]
+    pub strength: Strength,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub link: String,
+}
+
+impl SearchHit {
+    fn new(hit_type: HitType, id: &str, label: &str, matched: &'static str, strength: Strength) -> Self {
+        Self {
+            hit_type,
+            id: id.to_string(),
+            label: label.to_string(),
+            matched,
+            strength,
+            detail: None,
+            link: hit_type.link(id),
+        }
+    }
+
+    fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+// Strongest matches first, each object once, at most `limit` hits
+fn rank(mut hits: Vec<SearchHit>, limit: usize) -> Vec<SearchHit> {
+    hits.sort_by(|a, b| {
+        b.strength
+            .cmp(&a.strength)
+            .then(a.hit_type.cmp(&b.hit_type))
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    let mut seen = HashSet::new();
+    hits.retain(|hit| seen.insert((hit.hit_type, hit.id.clone())));
+    hits.truncate(limit);
+    hits
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Vmapi,
+    Cnapi,
+    Napi,
+    Imgapi,
+    Papi,
+    Ufds,
+}
+
+const SOURCES: [Source; 6] = [Source::Vmapi, Source::Cnapi, Source::Napi, Source::Imgapi, Source::Papi, Source::Ufds];
+
+impl Source {
+    fn name(&self) -> &'static str {
+        match self {
+            Source::Vmapi => "vmapi",
+            Source::Cnapi => "cnapi",
+            Source::Napi => "napi",
+            Source::Imgapi => "imgapi",
+            Source::Papi => "papi",
+            Source::Ufds => "ufds",
+        }
+    }
+
+    // The API token scope needed to see its hits
+    fn resource(&self) -> &'static str {
+        match self {
+            Source::Vmapi => "vms",
+            Source::Cnapi => "servers",
+            Source::Napi => "networks",
+            Source::Imgapi => "images",
+            Source::Papi => "packages",
+            Source::Ufds => "users",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    Ok,
+    Error,
+    Timeout,
+}
+
+// How one service answered, so the UI can say which results are missing
+#[derive(Debug, Serialize)]
+pub struct SourceResult {
+    pub name: &'static str,
+    pub status: SourceStatus,
+    pub latency_ms: u64,
+    pub hits: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub query: String,
+    pub kind: &'static str,
+    pub hits: Vec<SearchHit>,
+    pub sources: Vec<SourceResult>,
+}
+
+type Lookup<'a> = BoxFuture<'a, Result<Vec<SearchHit>, AppError>>;
+
+// Run one service's lookup, giving up on it after `timeout`
+async fn timed(source: Source, timeout: Duration, lookup: Lookup<'_>) -> (SourceResult, Vec<SearchHit>) {
+    let started = Instant::now();
+    let (status, hits, error) = match tokio::time::timeout(timeout, lookup).await {
+        Ok(Ok(hits)) => (SourceStatus::Ok, hits, None),
+        Ok(Err(e)) => {
+            warn!("Search in {} failed: {}", source.name(), e);
+            (SourceStatus::Error, Vec::new(), Some(e.to_string()))
+        }
+        Err(_) => {
+            warn!("Search in {} timed out after {:?}", source.name(), timeout);
+            (SourceStatus::Timeout, Vec::new(), Some(format!("timed out after {:?}", timeout)))
+        }
+    };
+
+    let result = SourceResult {
+        name: source.name(),
+        status,
+        latency_ms: started.elapsed().as_millis() as u64,
+        hits: hits.len(),
+        error,
+    };
+    (result, hits)
+}
+
+// A lookup that found nothing is not an error
+fn found<T>(result: Result<T, AppError>) -> Result<Option<T>, AppError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(AppError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+struct Searcher<'a> {
+    services: &'a ServiceClients,
+    // Set while impersonating: results are limited to what this account
+    // can see, as in the listings. Servers are not searched at all, and
+    // other users are hidden.
+    owner: Option<String>,
+    token: Option<ApiToken>,
+}
+
+impl<'a> Searcher<'a> {
+    fn can_read(&self, resource: &str) -> bool {
+        self.token.as_ref().is_none_or(|token| token.allows(resource, Access::Read))
+    }
+
+    fn owns(&self, owner_uuid: &str) -> bool {
+        self.owner.as_deref().is_none_or(|owner| owner == owner_uuid)
+    }
+
+    // Shared networks have no owner
+    fn sees_network(&self, network: &crate::api::networks::Network) -> bool {
+        network.owner_uuid.as_deref().is_none_or(|owner| self.owns(owner))
+    }
+
+    // The lookup a service does for this kind of query, if any
+    fn lookup(&'a self, source: Source, kind: &'a QueryKind) -> Option<Lookup<'a>> {
+        let lookup = match (source, kind) {
+            (Source::Cnapi, _) if self.owner.is_some() => return None,
+            (Source::Vmapi, QueryKind::Uuid(uuid)) => self.vm_by_uuid(uuid).boxed(),
+            (Source::Vmapi, QueryKind::Text(text)) => self.vms_by_alias(text).boxed(),
+            (Source::Cnapi, QueryKind::Uuid(uuid)) => self.server_by_uuid(uuid).boxed(),
+            (Source::Cnapi, QueryKind::Text(text)) => self.servers_by_name(text).boxed(),
+            (Source::Napi, QueryKind::Uuid(uuid)) => self.network_by_uuid(uuid).boxed(),
+            (Source::Napi, QueryKind::Ip(ip)) => self.by_ip(*ip).boxed(),
+            (Source::Napi, QueryKind::Mac(mac)) => self.by_mac(mac).boxed(),
+            (Source::Napi, QueryKind::Text(text)) => self.networks_by_name(text).boxed(),
+            (Source::Imgapi, QueryKind::Uuid(uuid)) => self.image_by_uuid(uuid).boxed(),
+            (Source::Imgapi, QueryKind::Text(text)) => self.images_by_name(text).boxed(),
+            (Source::Papi, QueryKind::Uuid(uuid)) => self.package_by_uuid(uuid).boxed(),
+            (Source::Papi, QueryKind::Text(text)) => self.packages_by_name(text).boxed(),
+            (Source::Ufds, QueryKind::Uuid(uuid)) => self.user_by_uuid(uuid).boxed(),
+            (Source::Ufds, QueryKind::Text(text)) => self.users_by_login(text).boxed(),
+            _ => return None,
+        };
+        Some(lookup)
+    }
+
+    fn vm_hit(vm: &crate::api::vms::Vm, matched: &'static str, strength: Strength) -> SearchHit {
+        SearchHit::new(HitType::Vm, &vm.uuid, &vm.alias, matched, strength).detail(vm.state.clone())
+    }
+
+    fn server_hit(server: &crate::api::servers::Server, matched: &'static str, strength: Strength) -> SearchHit {
+        SearchHit::new(HitType::Server, &server.uuid, &server.hostname, matched, strength).detail(server.status.clone())
+    }
+
+    fn network_hit(network: &crate::api::networks::Network, matched: &'static str, strength: Strength) -> SearchHit {
+        SearchHit::new(HitType::Network, &network.uuid, &network.name, matched, strength).detail(network.subnet.clone())
+    }
+
+    async fn vm_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let vm = found(self.services.vmapi.get_vm(uuid).await)?;
+        Ok(vm
+            .filter(|vm| self.owns(&vm.owner_uuid))
+            .map(|vm| Self::vm_hit(&vm, "uuid", Strength::Exact))
+            .into_iter()
+            .collect())
+    }
+
+    async fn vms_by_alias(&self, alias: &str) -> Result<Vec<SearchHit>, AppError> {
+        let query = VmListQuery {
+            alias: Some(alias.to_string()),
+            owner_uuid: self.owner.clone(),
+            limit: Some(SERVICE_LIMIT),
+            ..VmListQuery::default()
+        };
+        let page = self.services.vmapi.list_vms(&query).await?;
+        Ok(page
+            .vms
+            .iter()
+            .filter_map(|vm| Some(Self::vm_hit(vm, "alias", text_match(&vm.alias, alias)?)))
+            .collect())
+    }
+
+    async fn server_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let server = found(self.services.cnapi.get_server(uuid).await)?;
+        Ok(server.map(|server| Self::server_hit(&server, "uuid", Strength::Exact)).into_iter().collect())
+    }
+
+    async fn servers_by_name(&self, text: &str) -> Result<Vec<SearchHit>, AppError> {
+        let servers = self.services.cnapi.list_servers().await?;
+        Ok(servers
+            .iter()
+            .filter_map(|server| {
+                let serial = server.sysinfo["Serial Number"].as_str().unwrap_or_default();
+                if serial.eq_ignore_ascii_case(text) {
+                    return Some(Self::server_hit(server, "serial", Strength::Exact));
+                }
+                Some(Self::server_hit(server, "hostname", text_match(&server.hostname, text)?))
+            })
+            .collect())
+    }
+
+    async fn network_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let network = found(self.services.napi.get_network(uuid).await)?;
+        Ok(network
+            .filter(|network| self.sees_network(network))
+            .map(|network| Self::network_hit(&network, "uuid", Strength::Exact))
+            .into_iter()
+            .collect())
+    }
+
+    async fn networks_by_name(&self, name: &str) -> Result<Vec<SearchHit>, AppError> {
+        let networks = self.services.napi.list_networks().await?;
+        Ok(networks
+            .iter()
+            .filter(|network| self.sees_network(network))
+            .filter_map(|network| Some(Self::network_hit(network, "name", text_match(&network.name, name)?)))
+            .collect())
+    }
+
+    // The networks holding the address, and the VMs and servers using it.
+    // Only the network's UUID is known here, so while impersonating just
+    // the customer's own VMs are listed.
+    async fn by_ip(&self, ip: IpAddr) -> Result<Vec<SearchHit>, AppError> {
+        let ip = ip.to_string();
+        let addresses = found(self.services.napi.search_ips(&ip).await)?.unwrap_or_default();
+
+        let mut hits = Vec::new();
+        for address in addresses.iter().filter(|_| self.owner.is_none()) {
+            let network = SearchHit::new(HitType::Network, &address.network_uuid, &address.network_uuid, "ip", Strength::Exact);
+            hits.push(network.detail(format!("IP {}", ip)));
+        }
+        let owners = addresses
+            .iter()
+            .filter_map(|address| Some((address.belongs_to_type.as_deref()?, address.belongs_to_uuid.as_deref()?)))
+            .map(|(owner_type, owner)| self.owner_hit(owner_type, owner, "ip", &ip));
+        hits.extend(join_all(owners).await.into_iter().flatten());
+
+        Ok(hits)
+    }
+
+    // The VM or server the NIC belongs to, and its network
+    async fn by_mac(&self, mac: &str) -> Result<Vec<SearchHit>, AppError> {
+        let nic = match found(self.services.napi.get_nic(mac).await)? {
+            Some(nic) => nic,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut hits = Vec::new();
+        if let Some(network_uuid) = nic.network_uuid.as_ref().filter(|_| self.owner.is_none()) {
+            let network = SearchHit::new(HitType::Network, network_uuid, network_uuid, "mac", Strength::Exact);
+            hits.push(network.detail(format!("NIC {}", mac)));
+        }
+        hits.extend(self.owner_hit(&nic.belongs_to_type, &nic.belongs_to_uuid, "mac", mac).await);
+
+        Ok(hits)
+    }
+
+    // What a NAPI address or NIC is attached to, if the caller may see it.
+    // Owners that cannot be looked up are still listed by UUID.
+    async fn owner_hit(&self, owner_type: &str, owner: &str, matched: &'static str, value: &str) -> Option<SearchHit> {
+        let detail = format!("{} {}", matched.to_uppercase(), value);
+        match owner_type {
+            "zone" if self.can_read("vms") => match self.services.vmapi.get_vm(owner).await {
+                Ok(vm) if self.owns(&vm.owner_uuid) => Some(Self::vm_hit(&vm, matched, Strength::Exact).detail(detail)),
+                Ok(_) | Err(AppError::NotFound(_)) => None,
+                Err(_) if self.owner.is_some() => None,
+                Err(_) => Some(SearchHit::new(HitType::Vm, owner, owner, matched, Strength::Exact).detail(detail)),
+            },
+            "server" if self.can_read("servers") && self.owner.is_none() => match self.services.cnapi.get_server(owner).await {
+                Ok(server) => Some(Self::server_hit(&server, matched, Strength::Exact).detail(detail)),
+                Err(AppError::NotFound(_)) => None,
+                Err(_) => Some(SearchHit::new(HitType::Server, owner, owner, matched, Strength::Exact).detail(detail)),
+            },
+            _ => None,
+        }
+    }
+
+    async fn image_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let image = found(self.services.imgapi.get_image(uuid).await)?;
+        Ok(image
+            .filter(|image| image.public || image.owner.as_deref().is_some_and(|owner| self.owns(owner)))
+            .map(|image| SearchHit::new(HitType::Image, &image.uuid, &image.name, "uuid", Strength::Exact).detail(image.version))
+            .into_iter()
+            .collect())
+    }
+
+    async fn images_by_name(&self, name: &str) -> Result<Vec<SearchHit>, AppError> {
+        let images = self.services.imgapi.list_images(self.owner.as_deref()).await?;
+        Ok(images
+            .iter()
+            .filter_map(|image| {
+                let hit = SearchHit::new(HitType::Image, &image.uuid, &image.name, "name", text_match(&image.name, name)?);
+                Some(hit.detail(image.version.clone()))
+            })
+            .collect())
+    }
+
+    async fn package_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let package = found(self.services.papi.get_package(uuid).await)?;
+        Ok(package
+            .map(|package| SearchHit::new(HitType::Package, &package.uuid, &package.name, "uuid", Strength::Exact))
+            .into_iter()
+            .collect())
+    }
+
+    async fn packages_by_name(&self, name: &str) -> Result<Vec<SearchHit>, AppError> {
+        let packages = self.services.papi.list_packages().await?;
+        Ok(packages
+            .iter()
+            .filter_map(|package| Some(SearchHit::new(HitType::Package, &package.uuid, &package.name, "name", text_match(&package.name, name)?)))
+            .collect())
+    }
+
+    async fn user_by_uuid(&self, uuid: &str) -> Result<Vec<SearchHit>, AppError> {
+        let user = found(self.services.ufds.get_user(uuid).await)?;
+        Ok(user
+            .filter(|user| self.owns(&user.uuid))
+            .map(|user| SearchHit::new(HitType::User, &user.uuid, &user.login, "uuid", Strength::Exact).detail(user.email))
+            .into_iter()
+            .collect())
+    }
+
+    // UFDS matches logins and emails exactly
+    async fn users_by_login(&self, text: &str) -> Result<Vec<SearchHit>, AppError> {
+        let (params, matched) = if text.contains('@') {
+            (crate::api::users::UserListParams { email: Some(text.to_string()), ..Default::default() }, "email")
+        } else {
+            (crate::api::users::UserListParams { login: Some(text.to_string()), ..Default::default() }, "login")
+        };
+        let params = crate::api::users::UserListParams { limit: Some(SERVICE_LIMIT), ..params };
+        let users = self.services.ufds.list_users(&params).await?;
+        Ok(users
+            .into_iter()
+            .filter(|user| self.owns(&user.uuid))
+            .map(|user| SearchHit::new(HitType::User, &user.uuid, &user.login, matched, Strength::Exact).detail(user.email))
+            .collect())
+    }
+}
+
+#[get("")]
+pub async fn search(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    config: Data<Config>,
+    services: Data<ServiceClients>,
+    query: Query<SearchParams>,
+) -> Result<HttpResponse, AppError> {
+    let q = query.q.trim();
+    if q.is_empty() {
+        return Err(AppError::ValidationError("q is required".to_string()));
+    }
+    let kind = QueryKind::detect(q);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    info!("Searching for {} ({})", q, kind.as_str());
+
+    let searcher = Searcher {
+        services: &services,
+        owner: impersonation.map(|_| user.id.to_string()),
+        token: req.extensions().get::<ApiToken>().cloned(),
+    };
+
+    // Ask every service that can answer this kind of query at once, each
+    // with its own timeout, and only those the API token may read
+    let timeout = Duration::from_secs(config.search_timeout_secs);
+    let lookups = SOURCES
+        .iter()
+        .filter(|source| searcher.can_read(source.resource()))
+        .filter_map(|source| Some(timed(*source, timeout, searcher.lookup(*source, &kind)?)));
+    let (sources, hits): (Vec<SourceResult>, Vec<Vec<SearchHit>>) = join_all(lookups).await.into_iter().unzip();
+
+    Ok(HttpResponse::Ok().json(SearchResponse {
+        query: q.to_string(),
+        kind: kind.as_str(),
+        hits: rank(hits.into_iter().flatten().collect(), limit),
+        sources,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(hit_type: HitType, id: &str, label: &str, strength: Strength) -> SearchHit {
+        SearchHit::new(hit_type, id, label, "name", strength)
+    }
+
+    #[test]
+    fn queries_are_recognised() {
+        assert_eq!(
+            QueryKind::detect("8F5C2A1E-0000-4000-8000-000000000001"),
+            QueryKind::Uuid("8f5c2a1e-0000-4000-8000-000000000001".to_string())
+        );
+        assert_eq!(QueryKind::detect("10.88.0.5"), QueryKind::Ip("10.88.0.5".parse().unwrap()));
+        assert_eq!(QueryKind::detect("fd00::1"), QueryKind::Ip("fd00::1".parse().unwrap()));
+        for mac in ["90:B8:D0:57:53:70", "90-b8-d0-57-53-70", "90b8d0575370"] {
+            assert_eq!(QueryKind::detect(mac), QueryKind::Mac("90:b8:d0:57:53:70".to_string()), "{}", mac);
+        }
+        for text in ["web-01", "alice@example.com", "8f5c2a1e00004000800000000000000", "90:b8:d0:57:53", "cafe"] {
+            assert_eq!(QueryKind::detect(text), QueryKind::Text(text.to_string()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn names_match_exactly_by_prefix_or_anywhere() {
+        assert_eq!(text_match("Web", "web"), Some(Strength::Exact));
+        assert_eq!(text_match("web-01", "WEB"), Some(Strength::Prefix));
+        assert_eq!(text_match("prod-web-01", "web"), Some(Strength::Contains));
+        assert_eq!(text_match("db-01", "web"), None);
+    }
+
+    #[test]
+    fn hits_are_ranked_and_deduplicated() {
+        let hits = vec![
+            hit(HitType::Image, "i-1", "web-base", Strength::Prefix),
+            hit(HitType::Vm, "v-2", "prod-web", Strength::Contains),
+            hit(HitType::User, "u-1", "web", Strength::Exact),
+            hit(HitType::Vm, "v-1", "web", Strength::Exact),
+            hit(HitType::Vm, "v-1", "web", Strength::Exact),
+            hit(HitType::Server, "s-1", "web-cn", Strength::Prefix),
+        ];
+
+        let ranked = rank(hits.clone(), 10);
+        let ids: Vec<&str> = ranked.iter().map(|hit| hit.id.as_str()).collect();
+        assert_eq!(ids, ["v-1", "u-1", "s-1", "i-1", "v-2"]);
+        assert_eq!(rank(hits, 2).len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn slow_services_time_out_without_holding_up_others() {
+        let slow: Lookup = futures::future::pending().boxed();
+        let fast: Lookup = async { Ok(vec![hit(HitType::Package, "p-1", "g4", Strength::Exact)]) }.boxed();
+        let failing: Lookup = async { Err(AppError::ServiceUnavailable("PAPI is down".to_string())) }.boxed();
+
+        let started = Instant::now();
+        let results = join_all([
+            timed(Source::Vmapi, Duration::from_millis(50), slow),
+            timed(Source::Papi, Duration::from_millis(50), fast),
+            timed(Source::Imgapi, Duration::from_millis(50), failing),
+        ])
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let statuses: Vec<(&str, SourceStatus, usize)> =
+            results.iter().map(|(source, hits)| (source.name, source.status, hits.len())).collect();
+        assert_eq!(
+            statuses,
+            [("vmapi", SourceStatus::Timeout, 0), ("papi", SourceStatus::Ok, 1), ("imgapi", SourceStatus::Error, 0)]
+        );
+    }
+
+    #[test]
+    fn hits_link_to_their_pages() {
+        let hit = SearchHit::new(HitType::Vm, "v-1", "web", "alias", Strength::Exact);
+        let json = serde_json::to_value(&hit).unwrap();
+        assert_eq!(json["type"], "vm");
+        assert_eq!(json["match"], "exact");
+        assert_eq!(json["link"], "/vms/v-1");
+    }
+}
@@ -1,18 +1,16 @@
 use actix_web::{get, post, patch, web::{self, Data, Json, Path, Query}, HttpResponse};
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::ListQuery;
+use crate::auth::{AuthenticatedUser, Impersonation};
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerListParams {
     pub hostname: Option<String>,
     pub status: Option<String>,
     pub setup: Option<bool>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -32,73 +30,43 @@ pub struct Server {
     pub updated_at: String,
 }
 
+impl ServerListParams {
+    fn matches(&self, server: &Server) -> bool {
+        self.hostname.as_ref().is_none_or(|hostname| server.hostname.contains(hostname))
+            && self.status.as_ref().is_none_or(|status| server.status == *status)
+            && self.setup.is_none_or(|setup| server.setup == setup)
+    }
+}
+
 #[get("")]
 pub async fn list_servers(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<ServerListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create CNAPI service client
-    let cnapi_service = crate::services::CnapiService::new(config.cnapi_url.clone());
+    deny_impersonation(impersonation.as_ref())?;
+    let cnapi_service = &services.cnapi;
     
     // Get servers from CNAPI
-    let servers = cnapi_service.list_servers().await?;
-    
-    // If there are filtering parameters, apply them
-    let filtered_servers = if query.hostname.is_some() || query.status.is_some() || query.setup.is_some() {
-        servers.into_iter().filter(|server| {
-            let hostname_match = match &query.hostname {
-                Some(hostname) => server.hostname.contains(hostname),
-                None => true,
-            };
-            
-            let status_match = match &query.status {
-                Some(status) => server.status == *status,
-                None => true,
-            };
-            
-            let setup_match = match query.setup {
-                Some(setup) => server.setup == setup,
-                None => true,
-            };
-            
-            hostname_match && status_match && setup_match
-        }).collect()
-    } else {
-        servers
-    };
-    
-    // Apply pagination if specified
-    let paginated_servers = match (query.offset, query.limit) {
-        (Some(offset), Some(limit)) => {
-            let offset = offset as usize;
-            let limit = limit as usize;
-            filtered_servers.into_iter().skip(offset).take(limit).collect()
-        },
-        (Some(offset), None) => {
-            let offset = offset as usize;
-            filtered_servers.into_iter().skip(offset).collect()
-        },
-        (None, Some(limit)) => {
-            let limit = limit as usize;
-            filtered_servers.into_iter().take(limit).collect()
-        },
-        (None, None) => filtered_servers,
-    };
+    let mut servers = cnapi_service.list_servers().await?;
+    servers.retain(|server| query.matches(server));
     
-    Ok(HttpResponse::Ok().json(paginated_servers))
+    Ok(HttpResponse::Ok().json(list.apply(servers)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_server(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
+    deny_impersonation(impersonation.as_ref())?;
     let uuid = path.into_inner();
     
-    // Create CNAPI service client
-    let cnapi_service = crate::services::CnapiService::new(config.cnapi_url.clone());
+    let cnapi_service = &services.cnapi;
     
     // Get server from CNAPI
     let server = cnapi_service.get_server(&uuid).await?;
@@ -106,6 +74,15 @@ pub async fn get_server(
     Ok(HttpResponse::Ok().json(server))
 }
 
+// Customers cannot see compute nodes, so neither can an operator
+// impersonating one
+fn deny_impersonation(impersonation: Option<&Impersonation>) -> Result<(), AppError> {
+    match impersonation {
+        Some(_) => Err(AppError::AuthorizationError("Servers are not visible while impersonating a user".to_string())),
+        None => Ok(()),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateServerRequest {
     pub hostname: Option<String>,
@@ -117,30 +94,16 @@ pub struct UpdateServerRequest {
 #[patch("/{uuid}")]
 pub async fn update_server(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     server_req: Json<UpdateServerRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the CNAPI client to update a server
-    // For now, we'll just return a placeholder
+    let cnapi_service = &services.cnapi;
     
-    let server = Server {
-        uuid,
-        hostname: server_req.hostname.clone().unwrap_or_else(|| "compute-01".to_string()),
-        status: "running".to_string(),
-        setup: true,
-        datacenter: server_req.datacenter.clone().unwrap_or_else(|| "us-east-1".to_string()),
-        memory_total_bytes: 128 * 1024 * 1024 * 1024, // 128 GB
-        memory_available_bytes: 64 * 1024 * 1024 * 1024, // 64 GB
-        disk_total_bytes: 2 * 1024 * 1024 * 1024 * 1024, // 2 TB
-        disk_available_bytes: 1 * 1024 * 1024 * 1024 * 1024, // 1 TB
-        platform_version: "20230101T000000Z".to_string(),
-        sysinfo: serde_json::json!({"CPU Physical Cores": 16}),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-    };
+    // Update server in CNAPI
+    let server = cnapi_service.update_server(&uuid, server_req.into_inner()).await?;
     
     Ok(HttpResponse::Ok().json(server))
 }
@@ -154,42 +117,18 @@ pub struct ServerActionRequest {
 #[post("/{uuid}")]
 pub async fn server_action(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     action_req: Json<ServerActionRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the CNAPI client to perform an action on a server
-    // For now, we'll just return a placeholder
+    let cnapi_service = &services.cnapi;
     
-    match action_req.action.as_str() {
-        "setup" => {
-            // Setup server
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "reboot" => {
-            // Reboot server
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "factory-reset" => {
-            // Factory reset server
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "update-nics" => {
-            // Update NICs
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        _ => {
-            Err(AppError::BadRequest(format!("Unsupported action: {}", action_req.action)))
-        },
-    }
+    // CNAPI rejects actions it does not support
+    let job_uuid = cnapi_service.server_action(&uuid, &action_req.action).await?;
+    
+    Ok(HttpResponse::Accepted().json(serde_json::json!({
+        "job_uuid": job_uuid
+    })))
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+use actix_web::{
+    delete, get, post,
+    web::{Data, Json, Path, Query},
+    HttpResponse,
+};
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::api::listing::ListQuery;
+use crate::auth::api_tokens::{validate_scopes, ApiToken, ApiTokenStore};
+use crate::auth::AuthenticatedUser;
+use crate::config::Config;
+use crate::error::AppError;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiTokenRequest {
+    pub name: String,
+    // e.g. ["vms:read", "vms:write"]
+    pub scopes: Vec<String>,
+    // Defaults to API_TOKEN_TTL_DAYS
+    pub expires_in_days: Option<i64>,
+}
+
+// The new token with its plain text, which is not shown again
+#[derive(Debug, Serialize)]
+pub struct CreatedApiToken {
+    #[serde(flatten)]
+    pub details: ApiToken,
+    pub token: String,
+}
+
+#[get("")]
+pub async fn list_tokens(
+    user: AuthenticatedUser,
+    api_tokens: Data<ApiTokenStore>,
+    list: Query<ListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let tokens = api_tokens.list(user.id).await?;
+
+    Ok(HttpResponse::Ok().json(list.apply(tokens)?))
+}
+
+#[post("")]
+pub async fn create_token(
+    user: AuthenticatedUser,
+    config: Data<Config>,
+    api_tokens: Data<ApiTokenStore>,
+    token_req: Json<CreateApiTokenRequest>,
+) -> Result<HttpResponse, AppError> {
+    let token_req = token_req.into_inner();
+
+    let name = token_req.name.trim();
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Token name must be 1 to {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+    let scopes = validate_scopes(&token_req.scopes)?;
+
+    // Every token expires
+    let days = token_req.expires_in_days.unwrap_or(config.api_token_ttl_days);
+    if days < 1 || days > config.api_token_max_ttl_days {
+        return Err(AppError::ValidationError(format!(
+            "expires_in_days must be between 1 and {}",
+            config.api_token_max_ttl_days
+        )));
+    }
+
+    let (details, token) = api_tokens.create(&user, name, scopes, Duration::days(days)).await?;
+
+    Ok(HttpResponse::Created().json(CreatedApiToken { details, token }))
+}
+
+#[delete("/{id}")]
+pub async fn delete_token(
+    user: AuthenticatedUser,
+    api_tokens: Data<ApiTokenStore>,
+    path: Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+
+    if !api_tokens.delete(user.id, id).await? {
+        return Err(AppError::NotFound(format!("API token {} not found", id)));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -1,20 +1,29 @@
 use actix_web::{
     get, post, put, delete, patch,
-    web::{self, Data, Json, Path, Query},
-    HttpResponse,
+    web::{Data, Json, Path, Query},
+    HttpMessage, HttpRequest, HttpResponse,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::{ListQuery, PageStart};
+use crate::auth::{self, AuthenticatedUser, Impersonation, UserInfo};
+use crate::auth::api_tokens::{ApiToken, ApiTokenStore};
+use crate::auth::refresh::RefreshTokenStore;
+use crate::auth::revocation::RevocationStore;
+use crate::auth::throttle::LoginThrottle;
+use crate::auth::totp::TotpService;
 use crate::error::AppError;
+use crate::services::ServiceClients;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UserListParams {
     pub email: Option<String>,
     pub login: Option<String>,
+    // Paging for the LDAP search, taken from the list query
+    #[serde(skip)]
     pub limit: Option<u32>,
+    #[serde(skip)]
     pub offset: Option<u32>,
 }
 
@@ -33,67 +42,67 @@ pub struct User {
 
 #[get("")]
 pub async fn list_users(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<UserListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would call the UFDS client to list users
-    // For now, we'll just return a placeholder
-    
-    let users = vec![
-        User {
-            uuid: Uuid::new_v4().to_string(),
-            login: "user1".to_string(),
-            email: "user1@example.com".to_string(),
-            first_name: Some("User".to_string()),
-            last_name: Some("One".to_string()),
-            company: None,
-            created_at: "2023-01-01T00:00:00Z".to_string(),
-            updated_at: "2023-01-01T00:00:00Z".to_string(),
-            approved_for_provisioning: true,
-        },
-        User {
-            uuid: Uuid::new_v4().to_string(),
-            login: "user2".to_string(),
-            email: "user2@example.com".to_string(),
-            first_name: Some("User".to_string()),
-            last_name: Some("Two".to_string()),
-            company: Some("Example Corp".to_string()),
-            created_at: "2023-01-02T00:00:00Z".to_string(),
-            updated_at: "2023-01-02T00:00:00Z".to_string(),
-            approved_for_provisioning: false,
-        },
-    ];
-    
-    Ok(HttpResponse::Ok().json(users))
+    let ufds_service = &services.ufds;
+    let mut params = query.into_inner();
+    
+    // The impersonated customer only sees their own account
+    if impersonation.is_some() {
+        let mut users = vec![ufds_service.get_user(&user.id.to_string()).await?];
+        users.retain(|u| {
+            params.login.as_ref().is_none_or(|login| u.login == *login)
+                && params.email.as_ref().is_none_or(|email| u.email == *email)
+        });
+        return Ok(HttpResponse::Ok().json(list.apply(users)?));
+    }
+    
+    // UFDS cannot sort, so a sorted listing fetches every match
+    if list.sort()?.is_some() {
+        let users = ufds_service.list_users(&params).await?;
+        return Ok(HttpResponse::Ok().json(list.apply(users)?));
+    }
+    
+    // Otherwise filtering and pagination are pushed down to the LDAP search
+    let offset = list.offset()?;
+    params.limit = list.limit();
+    params.offset = Some(offset);
+    let users = ufds_service.list_users(&params).await?;
+    let next = list.limit().and_then(|limit| PageStart::after(offset, limit, users.len()));
+    
+    Ok(HttpResponse::Ok().json(list.page(users, None, next)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_user(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
+    check_self(&uuid, &user, impersonation.as_ref())?;
     
-    // In a real implementation, this would call the UFDS client to get a user
-    // For now, we'll just return a placeholder
-    
-    let user = User {
-        uuid,
-        login: "user1".to_string(),
-        email: "user1@example.com".to_string(),
-        first_name: Some("User".to_string()),
-        last_name: Some("One".to_string()),
-        company: None,
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-        approved_for_provisioning: true,
-    };
+    let ufds_service = &services.ufds;
+    
+    // Get user from UFDS
+    let user = ufds_service.get_user(&uuid).await?;
     
     Ok(HttpResponse::Ok().json(user))
 }
 
+// While impersonating, other accounts look like they don't exist
+fn check_self(uuid: &str, user: &AuthenticatedUser, impersonation: Option<&Impersonation>) -> Result<(), AppError> {
+    if impersonation.is_some() && !uuid.eq_ignore_ascii_case(&user.id.to_string()) {
+        return Err(AppError::NotFound(format!("User with UUID {} not found", uuid)));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
     pub login: String,
@@ -108,23 +117,13 @@ pub struct CreateUserRequest {
 #[post("")]
 pub async fn create_user(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     user_req: Json<CreateUserRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would call the UFDS client to create a user
-    // For now, we'll just return a placeholder
-    
-    let user = User {
-        uuid: Uuid::new_v4().to_string(),
-        login: user_req.login.clone(),
-        email: user_req.email.clone(),
-        first_name: user_req.first_name.clone(),
-        last_name: user_req.last_name.clone(),
-        company: user_req.company.clone(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-        approved_for_provisioning: user_req.approved_for_provisioning.unwrap_or(false),
-    };
+    let ufds_service = &services.ufds;
+    
+    // Create user in UFDS
+    let user = ufds_service.create_user(user_req.into_inner()).await?;
     
     Ok(HttpResponse::Created().json(user))
 }
@@ -141,26 +140,16 @@ pub struct UpdateUserRequest {
 #[put("/{uuid}")]
 pub async fn update_user(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     user_req: Json<UpdateUserRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the UFDS client to update a user
-    // For now, we'll just return a placeholder
-    
-    let user = User {
-        uuid,
-        login: "user1".to_string(),
-        email: user_req.email.clone().unwrap_or_else(|| "user1@example.com".to_string()),
-        first_name: user_req.first_name.clone(),
-        last_name: user_req.last_name.clone(),
-        company: user_req.company.clone(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-        approved_for_provisioning: user_req.approved_for_provisioning.unwrap_or(true),
-    };
+    let ufds_service = &services.ufds;
+    
+    // Update user in UFDS
+    let user = ufds_service.update_user(&uuid, user_req.into_inner()).await?;
     
     Ok(HttpResponse::Ok().json(user))
 }
@@ -168,28 +157,18 @@ pub async fn update_user(
 #[patch("/{uuid}")]
 pub async fn update_user_partial(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     user_req: Json<UpdateUserRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would be handled differently from PUT
-    // For now, we'll implement the same logic as PUT
+    // UFDS modifies only the attributes that are provided, so PATCH and PUT
+    // share the same implementation
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the UFDS client to update a user
-    // For now, we'll just return a placeholder
-    
-    let user = User {
-        uuid,
-        login: "user1".to_string(),
-        email: user_req.email.clone().unwrap_or_else(|| "user1@example.com".to_string()),
-        first_name: user_req.first_name.clone(),
-        last_name: user_req.last_name.clone(),
-        company: user_req.company.clone(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        updated_at: "2023-01-01T00:00:00Z".to_string(),
-        approved_for_provisioning: user_req.approved_for_provisioning.unwrap_or(true),
-    };
+    let ufds_service = &services.ufds;
+    
+    // Update user in UFDS
+    let user = ufds_service.update_user(&uuid, user_req.into_inner()).await?;
     
     Ok(HttpResponse::Ok().json(user))
 }
@@ -197,13 +176,173 @@ pub async fn update_user_partial(
 #[delete("/{uuid}")]
 pub async fn delete_user(
     _user: AuthenticatedUser,
-    config: Data<Config>,
+    services: Data<ServiceClients>,
+    api_tokens: Data<ApiTokenStore>,
+    path: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let uuid = path.into_inner();
+    
+    let ufds_service = &services.ufds;
+    
+    // Delete user from UFDS
+    ufds_service.delete_user(&uuid).await?;
+    
+    // API tokens carry their own copy of the user, so they would outlive
+    // the account
+    if let Ok(user_uuid) = Uuid::parse_str(&uuid) {
+        api_tokens.revoke_user(user_uuid).await?;
+    }
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Sign the user out everywhere: every access token issued to them so far is
+// rejected, none of their sessions can be refreshed and their API tokens
+// are deleted
+#[delete("/{uuid}/sessions")]
+pub async fn revoke_sessions(
+    _user: AuthenticatedUser,
+    revocations: Data<RevocationStore>,
+    refresh_tokens: Data<RefreshTokenStore>,
+    api_tokens: Data<ApiTokenStore>,
+    path: Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    let uuid = path.into_inner();
+    
+    revocations.revoke_user(uuid).await?;
+    refresh_tokens.revoke_user(uuid).await?;
+    api_tokens.revoke_user(uuid).await?;
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Issue a short-lived, read-only token to act as a customer and see what
+// they see. Only a signed-in operator can do this, not an API token or
+// someone who is already impersonating.
+#[post("/{uuid}/impersonate")]
+pub async fn impersonate_user(
+    req: HttpRequest,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    config: Data<crate::config::Config>,
+    services: Data<ServiceClients>,
+    path: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let uuid = path.into_inner();
+    
+    if impersonation.is_some() || req.extensions().get::<ApiToken>().is_some() {
+        return Err(AppError::AuthorizationError("Impersonation requires a signed-in operator".to_string()));
+    }
+    if uuid == user.id.to_string() {
+        return Err(AppError::ValidationError("You cannot impersonate yourself".to_string()));
+    }
+    
+    let target = services.ufds.get_user(&uuid).await?;
+    let target = UserInfo {
+        id: target.uuid,
+        name: target.login,
+        email: target.email,
+        roles: Vec::new(),
+    };
+    
+    let response = auth::impersonate(&config, target, &user)?;
+    
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// Let a user whose account was locked by failed logins try again right away
+#[delete("/{uuid}/lockout")]
+pub async fn unlock_user(
+    _user: AuthenticatedUser,
+    services: Data<ServiceClients>,
+    throttle: Data<LoginThrottle>,
+    path: Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let uuid = path.into_inner();
+    
+    // Lockouts are kept by login name
+    let user = services.ufds.get_user(&uuid).await?;
+    throttle.unlock(&user.login);
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Remove a user's authenticator, e.g. after they lost it and their
+// recovery codes; they enroll again at their next login
+#[delete("/{uuid}/totp")]
+pub async fn reset_totp(
+    _user: AuthenticatedUser,
+    totp: Data<TotpService>,
+    path: Path<Uuid>,
+) -> Result<HttpResponse, AppError> {
+    totp.reset(path.into_inner()).await?;
+    
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SshKey {
+    pub name: String,
+    // MD5 fingerprint, as stored in UFDS
+    pub fingerprint: String,
+    pub fingerprint_sha256: String,
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddSshKeyRequest {
+    pub name: Option<String>,
+    pub key: String,
+}
+
+#[get("/{uuid}/keys")]
+pub async fn list_keys(
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
+    path: Path<String>,
+    list: Query<ListQuery>,
+) -> Result<HttpResponse, AppError> {
+    let uuid = path.into_inner();
+    check_self(&uuid, &user, impersonation.as_ref())?;
+    
+    let ufds_service = &services.ufds;
+    
+    // Get the user's SSH keys from UFDS
+    let keys = ufds_service.list_keys(&uuid).await?;
+    
+    Ok(HttpResponse::Ok().json(list.apply(keys)?))
+}
+
+#[post("/{uuid}/keys")]
+pub async fn add_key(
+    _user: AuthenticatedUser,
+    services: Data<ServiceClients>,
     path: Path<String>,
+    key_req: Json<AddSshKeyRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the UFDS client to delete a user
-    // For now, we'll just return a placeholder
+    let ufds_service = &services.ufds;
+    
+    // Add the SSH key in UFDS
+    let key = ufds_service.add_key(&uuid, key_req.into_inner()).await?;
+    
+    Ok(HttpResponse::Created().json(key))
+}
+
+#[delete("/{uuid}/keys/{fingerprint}")]
+pub async fn delete_key(
+    _user: AuthenticatedUser,
+    services: Data<ServiceClients>,
+    path: Path<(String, String)>,
+) -> Result<HttpResponse, AppError> {
+    let (uuid, fingerprint) = path.into_inner();
+    
+    let ufds_service = &services.ufds;
+    
+    // Delete the SSH key from UFDS
+    ufds_service.delete_key(&uuid, &fingerprint).await?;
     
     Ok(HttpResponse::NoContent().finish())
-}
\ No newline at end of file
+}
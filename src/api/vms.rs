@@ -4,23 +4,54 @@ use actix_web::{
     HttpResponse,
 };
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
 use tracing::info;
 
-use crate::auth::AuthenticatedUser;
-use crate::config::Config;
+use crate::api::listing::{ListQuery, PageStart};
+use crate::auth::{AuthenticatedUser, Impersonation};
+use crate::api::images::Image;
+use crate::api::packages::Package;
 use crate::error::AppError;
-use crate::services::VmapiService;
+use crate::services::ServiceClients;
+use crate::services::{VmListQuery, VmSort};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VmListParams {
     pub owner_uuid: Option<String>,
     pub state: Option<String>,
     pub alias: Option<String>,
+    // "name=value"
     pub tag: Option<String>,
     pub server_uuid: Option<String>,
-    pub limit: Option<u32>,
-    pub offset: Option<u32>,
+}
+
+impl VmListParams {
+    // Sorting and paging are done by VMAPI too
+    fn to_query(&self, list: &ListQuery) -> Result<VmListQuery, AppError> {
+        let tag = match &self.tag {
+            Some(tag) => match tag.split_once('=') {
+                Some((name, value)) if !name.is_empty() => Some((name.to_string(), value.to_string())),
+                _ => return Err(AppError::ValidationError("tag must be given as name=value".to_string())),
+            },
+            None => None,
+        };
+        let (offset, marker) = match list.start()? {
+            PageStart::Offset(0) => (None, None),
+            PageStart::Offset(offset) => (Some(offset), None),
+            PageStart::Marker(marker) => (None, Some(marker)),
+        };
+        
+        Ok(VmListQuery {
+            owner_uuid: self.owner_uuid.clone(),
+            server_uuid: self.server_uuid.clone(),
+            state: self.state.clone(),
+            alias: self.alias.clone(),
+            tag,
+            sort: list.sort.as_deref().map(VmSort::parse).transpose()?,
+            limit: list.limit(),
+            offset,
+            marker,
+        })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -63,43 +94,44 @@ pub struct Vm {
 
 #[get("")]
 pub async fn list_vms(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     query: Query<VmListParams>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
-    // Create an instance of the VMAPI service
     info!("Listing VMs using VMAPI service");
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
     
-    // Check if server_uuid filter is applied
-    if let Some(server_uuid) = &query.server_uuid {
-        info!("Filtering VMs by server_uuid: {}", server_uuid);
-        // Call the service to get the VMs for the specified server
-        let vms = vmapi_service.list_vms_by_server(server_uuid).await?;
-        return Ok(HttpResponse::Ok().json(vms));
+    let mut vm_query = query.to_query(&list)?;
+    // Only the impersonated user's VMs are visible while impersonating
+    if impersonation.is_some() {
+        vm_query.owner_uuid = Some(user.id.to_string());
     }
     
-    // Call the service to get all VMs
-    let vms = vmapi_service.list_vms().await?;
+    // Filtering and paging happen in VMAPI
+    let page = vmapi_service.list_vms(&vm_query).await?;
     
-    // Return the VMs as JSON
-    Ok(HttpResponse::Ok().json(vms))
+    // Return the VMs as JSON; later pages continue from VMAPI's marker
+    let next = page.next_marker.map(PageStart::Marker);
+    Ok(HttpResponse::Ok().json(list.page(page.vms, page.total, next)?))
 }
 
 #[get("/{uuid}")]
 pub async fn get_vm(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create an instance of the VMAPI service
     info!("Getting VM {} using VMAPI service", uuid);
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
     
     // Call the service to get the VM
     let vm = vmapi_service.get_vm(&uuid).await?;
+    check_owner(&vm, &user, impersonation.as_ref())?;
     
     // Return the VM as JSON
     Ok(HttpResponse::Ok().json(vm))
@@ -108,6 +140,8 @@ pub async fn get_vm(
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateVmRequest {
     pub alias: String,
+    // Derived from the image type when left empty
+    #[serde(default)]
     pub brand: String,
     pub image_uuid: String,
     pub package_uuid: String,
@@ -117,37 +151,174 @@ pub struct CreateVmRequest {
     pub customer_metadata: Option<serde_json::Value>,
 }
 
+// Response for VMAPI calls that start a workflow job
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VmJobResponse {
+    pub vm_uuid: String,
+    pub job_uuid: String,
+}
+
 #[post("")]
 pub async fn create_vm(
     _user: AuthenticatedUser,
-    _config: Data<Config>,
+    services: Data<ServiceClients>,
     vm_req: Json<CreateVmRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would call the VMAPI client to create a VM
-    // For now, we'll just return a placeholder
-    
-    let vm = Vm {
-        uuid: Uuid::new_v4().to_string(),
-        alias: vm_req.alias.clone(),
-        state: "provisioning".to_string(),
-        brand: vm_req.brand.clone(),
-        memory: 1024,
-        quota: 20480,
-        disk: 20480,
-        vcpus: 1,
-        ips: vec![],
-        owner_uuid: vm_req.owner_uuid.clone(),
-        image_uuid: vm_req.image_uuid.clone(),
-        package_uuid: vm_req.package_uuid.clone(),
-        server_uuid: Uuid::new_v4().to_string(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        tags: vm_req.tags.clone().unwrap_or(serde_json::json!({})),
-        customer_metadata: vm_req.customer_metadata.clone().unwrap_or(serde_json::json!({})),
-        internal_metadata: serde_json::json!({}),
-        nics: None,
+    info!("Provisioning VM {} using VMAPI service", vm_req.alias);
+    let vmapi_service = &services.vmapi;
+    let papi_service = &services.papi;
+    let imgapi_service = &services.imgapi;
+    
+    // Resolve the package and image the VM will be built from
+    let (package, image) = futures::try_join!(
+        papi_service.get_package(&vm_req.package_uuid),
+        imgapi_service.get_image(&vm_req.image_uuid),
+    )?;
+    
+    let payload = build_provision_payload(&vm_req, &package, &image)?;
+    
+    // Start the provisioning job
+    let job = vmapi_service.create_vm(&payload).await?;
+    
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+// Image types each brand can boot
+fn brand_supports_image(brand: &str, image_type: &str) -> bool {
+    match brand {
+        "joyent" | "joyent-minimal" => image_type == "zone-dataset",
+        "lx" => image_type == "lx-dataset",
+        "kvm" | "bhyve" => image_type == "zvol",
+        _ => false,
+    }
+}
+
+fn default_brand(image: &Image) -> Option<&'static str> {
+    match image.image_type.as_str() {
+        "zone-dataset" => Some("joyent"),
+        "lx-dataset" => Some("lx"),
+        "zvol" => Some("bhyve"),
+        _ => None,
+    }
+}
+
+// Build the VMAPI CreateVm payload from the request, package and image
+fn build_provision_payload(
+    req: &CreateVmRequest,
+    package: &Package,
+    image: &Image,
+) -> Result<serde_json::Value, AppError> {
+    if !package.active {
+        return Err(AppError::ValidationError(format!("Package {} is not active", package.name)));
+    }
+    if image.state != "active" {
+        return Err(AppError::ValidationError(format!("Image {} is not active", image.name)));
+    }
+    if req.networks.is_empty() {
+        return Err(AppError::ValidationError("At least one network is required".to_string()));
+    }
+    
+    // Pick the brand: request, then package, then the image's natural brand
+    let brand = if !req.brand.is_empty() {
+        req.brand.clone()
+    } else if let Some(brand) = &package.brand {
+        brand.clone()
+    } else {
+        default_brand(image)
+            .ok_or_else(|| AppError::ValidationError(format!("Cannot determine a brand for image type {}", image.image_type)))?
+            .to_string()
     };
     
-    Ok(HttpResponse::Created().json(vm))
+    if !brand_supports_image(&brand, &image.image_type) {
+        return Err(AppError::ValidationError(format!(
+            "Brand {} cannot use image {} of type {}", brand, image.name, image.image_type
+        )));
+    }
+    if let Some(required) = image.requirements["brand"].as_str() {
+        if required != brand {
+            return Err(AppError::ValidationError(format!("Image {} requires brand {}", image.name, required)));
+        }
+    }
+    if let Some(package_brand) = &package.brand {
+        if *package_brand != brand {
+            return Err(AppError::ValidationError(format!("Package {} requires brand {}", package.name, package_brand)));
+        }
+    }
+    
+    let ram = package.memory
+        .ok_or_else(|| AppError::ValidationError(format!("Package {} does not define memory", package.name)))?;
+    // PAPI quotas are in MiB
+    let quota_mib = package.quota.unwrap_or(0);
+    
+    // First network is the primary NIC
+    let networks: Vec<serde_json::Value> = req.networks.iter()
+        .enumerate()
+        .map(|(i, uuid)| serde_json::json!({ "ipv4_uuid": uuid, "primary": i == 0 }))
+        .collect();
+        
+    let mut payload = serde_json::json!({
+        "owner_uuid": req.owner_uuid,
+        "alias": req.alias,
+        "brand": brand,
+        "billing_id": package.uuid,
+        "ram": ram,
+        "max_physical_memory": ram,
+        "networks": networks,
+        "tags": req.tags.clone().unwrap_or_else(|| serde_json::json!({})),
+        "customer_metadata": req.customer_metadata.clone().unwrap_or_else(|| serde_json::json!({})),
+    });
+    
+    let optional_limits = [
+        ("cpu_cap", package.cpu_cap.map(u64::from)),
+        ("max_swap", package.swap),
+        ("max_lwps", package.max_lwps.map(u64::from)),
+        ("zfs_io_priority", package.zfs_io_priority.map(u64::from)),
+    ];
+    for (key, value) in optional_limits {
+        if let Some(value) = value {
+            payload[key] = serde_json::json!(value);
+        }
+    }
+    
+    match brand.as_str() {
+        "kvm" => {
+            // KVM boots from a copy of the image plus a data disk sized by the package
+            payload["vcpus"] = serde_json::json!(package.vcpus.unwrap_or(1));
+            payload["disks"] = serde_json::json!([
+                { "image_uuid": image.uuid, "boot": true },
+                { "size": quota_mib },
+            ]);
+        },
+        "bhyve" => {
+            payload["vcpus"] = serde_json::json!(package.vcpus.unwrap_or(1));
+            payload["quota"] = serde_json::json!(quota_mib / 1024);
+            
+            let image_size = image.image_size.unwrap_or(0);
+            let boot_disk = serde_json::json!({ "image_uuid": image.uuid, "boot": true, "image_size": image_size });
+            
+            if package.flexible_disk.unwrap_or(false) {
+                // Flexible disk packages define their own disk layout within the quota
+                let mut disks = vec![boot_disk];
+                if let Some(package_disks) = &package.disks {
+                    disks.extend(package_disks.iter().skip(1).cloned());
+                }
+                payload["flexible_disk_size"] = serde_json::json!(quota_mib);
+                payload["disks"] = serde_json::json!(disks);
+            } else {
+                payload["disks"] = serde_json::json!([
+                    boot_disk,
+                    { "size": quota_mib.saturating_sub(image_size) },
+                ]);
+            }
+        },
+        _ => {
+            // Zones: the image is the root dataset and the quota is in GiB
+            payload["image_uuid"] = serde_json::json!(image.uuid);
+            payload["quota"] = serde_json::json!(quota_mib / 1024);
+        },
+    }
+    
+    Ok(payload)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -158,92 +329,104 @@ pub struct UpdateVmRequest {
     pub customer_metadata: Option<serde_json::Value>,
 }
 
+// Build the VMAPI update payload. Tags and customer_metadata are merged into
+// the existing values; with `replace` set, keys missing from the request are
+// removed as well.
+fn build_update_payload(
+    req: &UpdateVmRequest,
+    current: Option<&Vm>,
+) -> Result<serde_json::Value, AppError> {
+    let mut payload = serde_json::Map::new();
+    
+    if let Some(alias) = &req.alias {
+        payload.insert("alias".to_string(), serde_json::json!(alias));
+    }
+    if let Some(owner_uuid) = &req.owner_uuid {
+        payload.insert("owner_uuid".to_string(), serde_json::json!(owner_uuid));
+    }
+    
+    let maps = [
+        ("tags", &req.tags, current.map(|vm| &vm.tags)),
+        ("customer_metadata", &req.customer_metadata, current.map(|vm| &vm.customer_metadata)),
+    ];
+    for (name, requested, existing) in maps {
+        let Some(requested) = requested else { continue };
+        let requested = requested.as_object()
+            .ok_or_else(|| AppError::ValidationError(format!("{} must be an object", name)))?;
+            
+        payload.insert(format!("set_{}", name), serde_json::json!(requested));
+        
+        if let Some(existing) = existing.and_then(|e| e.as_object()) {
+            let removed: Vec<&String> = existing.keys()
+                .filter(|key| !requested.contains_key(*key))
+                .collect();
+            if !removed.is_empty() {
+                payload.insert(format!("remove_{}", name), serde_json::json!(removed));
+            }
+        }
+    }
+    
+    if payload.is_empty() {
+        return Err(AppError::ValidationError("No updatable fields were provided".to_string()));
+    }
+    
+    Ok(serde_json::Value::Object(payload))
+}
+
 #[put("/{uuid}")]
 pub async fn update_vm(
     _user: AuthenticatedUser,
-    _config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     vm_req: Json<UpdateVmRequest>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the VMAPI client to update a VM
-    // For now, we'll just return a placeholder
-    
-    let vm = Vm {
-        uuid,
-        alias: vm_req.alias.clone().unwrap_or_else(|| "test-vm".to_string()),
-        state: "running".to_string(),
-        brand: "kvm".to_string(),
-        memory: 1024,
-        quota: 20480,
-        disk: 20480,
-        vcpus: 1,
-        ips: vec!["10.0.0.1".to_string()],
-        owner_uuid: vm_req.owner_uuid.clone().unwrap_or_else(|| Uuid::new_v4().to_string()),
-        image_uuid: Uuid::new_v4().to_string(),
-        package_uuid: Uuid::new_v4().to_string(),
-        server_uuid: Uuid::new_v4().to_string(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        tags: vm_req.tags.clone().unwrap_or(serde_json::json!({"environment": "development"})),
-        customer_metadata: vm_req.customer_metadata.clone().unwrap_or(serde_json::json!({})),
-        internal_metadata: serde_json::json!({}),
-        nics: None,
-    };
+    info!("Replacing attributes of VM {} using VMAPI service", uuid);
+    let vmapi_service = &services.vmapi;
     
-    Ok(HttpResponse::Ok().json(vm))
+    // PUT replaces tags and metadata, so look up what has to be removed
+    let current = vmapi_service.get_vm(&uuid).await?;
+    let payload = build_update_payload(&vm_req, Some(&current))?;
+    
+    let job = vmapi_service.update_vm(&uuid, &payload).await?;
+    
+    Ok(HttpResponse::Accepted().json(job))
 }
 
 #[patch("/{uuid}")]
 pub async fn update_vm_partial(
     _user: AuthenticatedUser,
-    _config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     vm_req: Json<UpdateVmRequest>,
 ) -> Result<HttpResponse, AppError> {
-    // In a real implementation, this would be handled differently from PUT
-    // For now, we'll implement the same logic as PUT
     let uuid = path.into_inner();
     
-    // In a real implementation, this would call the VMAPI client to update a VM
-    // For now, we'll just return a placeholder
-    
-    let vm = Vm {
-        uuid,
-        alias: vm_req.alias.clone().unwrap_or_else(|| "test-vm".to_string()),
-        state: "running".to_string(),
-        brand: "kvm".to_string(),
-        memory: 1024,
-        quota: 20480,
-        disk: 20480,
-        vcpus: 1,
-        ips: vec!["10.0.0.1".to_string()],
-        owner_uuid: vm_req.owner_uuid.clone().unwrap_or_else(|| Uuid::new_v4().to_string()),
-        image_uuid: Uuid::new_v4().to_string(),
-        package_uuid: Uuid::new_v4().to_string(),
-        server_uuid: Uuid::new_v4().to_string(),
-        created_at: "2023-01-01T00:00:00Z".to_string(),
-        tags: vm_req.tags.clone().unwrap_or(serde_json::json!({"environment": "development"})),
-        customer_metadata: vm_req.customer_metadata.clone().unwrap_or(serde_json::json!({})),
-        internal_metadata: serde_json::json!({}),
-        nics: None,
-    };
+    info!("Updating VM {} using VMAPI service", uuid);
+    let vmapi_service = &services.vmapi;
     
-    Ok(HttpResponse::Ok().json(vm))
+    let payload = build_update_payload(&vm_req, None)?;
+    
+    let job = vmapi_service.update_vm(&uuid, &payload).await?;
+    
+    Ok(HttpResponse::Accepted().json(job))
 }
 
 #[delete("/{uuid}")]
 pub async fn delete_vm(
     _user: AuthenticatedUser,
-    _config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
 ) -> Result<HttpResponse, AppError> {
-    let _uuid = path.into_inner();
+    let uuid = path.into_inner();
+    
+    info!("Destroying VM {} using VMAPI service", uuid);
+    let vmapi_service = &services.vmapi;
     
-    // In a real implementation, this would call the VMAPI client to delete a VM
-    // For now, we'll just return a placeholder
+    let job = vmapi_service.delete_vm(&uuid).await?;
     
-    Ok(HttpResponse::NoContent().finish())
+    Ok(HttpResponse::Accepted().json(job))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -252,47 +435,68 @@ pub struct VmActionRequest {
     pub params: Option<serde_json::Value>,
 }
 
+// Actions supported by VMAPI's POST /vms/:uuid?action=..., with the
+// parameters each one requires
+const VM_ACTIONS: &[(&str, &[&str])] = &[
+    ("start", &[]),
+    ("stop", &[]),
+    ("kill", &[]),
+    ("reboot", &[]),
+    ("reprovision", &["image_uuid"]),
+    ("update", &[]),
+    ("add_nics", &["networks"]),
+    ("update_nics", &["nics"]),
+    ("remove_nics", &["macs"]),
+    ("create_snapshot", &[]),
+    ("rollback_snapshot", &["snapshot_name"]),
+    ("delete_snapshot", &["snapshot_name"]),
+    ("create_disk", &["size"]),
+    ("resize_disk", &["path", "size"]),
+    ("delete_disk", &["path"]),
+    ("migrate", &["migration_action"]),
+];
+
+fn validate_vm_action(req: &VmActionRequest) -> Result<(), AppError> {
+    let (_, required) = VM_ACTIONS.iter()
+        .find(|(name, _)| *name == req.action)
+        .ok_or_else(|| AppError::ValidationError(format!("Unsupported action: {}", req.action)))?;
+        
+    let params = match &req.params {
+        Some(serde_json::Value::Object(params)) => Some(params),
+        Some(serde_json::Value::Null) | None => None,
+        Some(_) => return Err(AppError::ValidationError("params must be an object".to_string())),
+    };
+    
+    let missing: Vec<&str> = required.iter()
+        .filter(|param| !params.is_some_and(|p| p.contains_key(**param)))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        return Err(AppError::ValidationError(format!(
+            "Action {} requires parameters: {}", req.action, missing.join(", ")
+        )));
+    }
+    
+    Ok(())
+}
+
 #[post("/{uuid}")]
 pub async fn vm_action(
     _user: AuthenticatedUser,
-    _config: Data<Config>,
+    services: Data<ServiceClients>,
     path: Path<String>,
     action_req: Json<VmActionRequest>,
 ) -> Result<HttpResponse, AppError> {
-    let _uuid = path.into_inner();
+    let uuid = path.into_inner();
     
-    // In a real implementation, this would call the VMAPI client to perform an action on a VM
-    // For now, we'll just return a placeholder
+    validate_vm_action(&action_req)?;
     
-    match action_req.action.as_str() {
-        "start" => {
-            // Start VM
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "stop" => {
-            // Stop VM
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "reboot" => {
-            // Reboot VM
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        "resize" => {
-            // Resize VM
-            Ok(HttpResponse::Accepted().json(serde_json::json!({
-                "job_uuid": Uuid::new_v4().to_string()
-            })))
-        },
-        _ => {
-            Err(AppError::BadRequest(format!("Unsupported action: {}", action_req.action)))
-        },
-    }
+    info!("Running action {} on VM {} using VMAPI service", action_req.action, uuid);
+    let vmapi_service = &services.vmapi;
+    
+    let job = vmapi_service.vm_action(&uuid, &action_req.action, action_req.params.as_ref()).await?;
+    
+    Ok(HttpResponse::Accepted().json(job))
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -319,19 +523,310 @@ pub struct VmJob {
 
 #[get("/{uuid}/jobs")]
 pub async fn get_vm_jobs(
-    _user: AuthenticatedUser,
-    config: Data<Config>,
+    user: AuthenticatedUser,
+    impersonation: Option<Impersonation>,
+    services: Data<ServiceClients>,
     path: Path<String>,
+    list: Query<ListQuery>,
 ) -> Result<HttpResponse, AppError> {
     let uuid = path.into_inner();
     
-    // Create an instance of the VMAPI service
     info!("Getting jobs for VM {} using VMAPI service", uuid);
-    let vmapi_service = VmapiService::new(config.vmapi_url.clone());
+    let vmapi_service = &services.vmapi;
+    
+    if impersonation.is_some() {
+        let vm = vmapi_service.get_vm(&uuid).await?;
+        check_owner(&vm, &user, impersonation.as_ref())?;
+    }
     
     // Call the service to get the VM jobs
     let jobs = vmapi_service.get_vm_jobs(&uuid).await?;
     
     // Return the jobs as JSON
-    Ok(HttpResponse::Ok().json(jobs))
-}
\ No newline at end of file
+    Ok(HttpResponse::Ok().json(list.apply(jobs)?))
+}
+// While impersonating, VMs of other owners look like they don't exist
+fn check_owner(vm: &Vm, user: &AuthenticatedUser, impersonation: Option<&Impersonation>) -> Result<(), AppError> {
+    if impersonation.is_some() && vm.owner_uuid != user.id.to_string() {
+        return Err(AppError::NotFound(format!("VM with UUID {} not found", vm.uuid)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn package(extra: serde_json::Value) -> Package {
+        let mut value = json!({
+            "uuid": "p-1", "name": "g4-highcpu-1G", "max_physical_memory": 1024,
+            "quota": 25600, "vcpus": 2, "active": true,
+        });
+        value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn image(image_type: &str, extra: serde_json::Value) -> Image {
+        let mut value = json!({
+            "uuid": "i-1", "name": "base-64", "version": "1.0", "os": "smartos", "state": "active",
+            "type": image_type, "public": true, "published_at": "", "files": [],
+            "requirements": {}, "tags": {},
+        });
+        value.as_object_mut().unwrap().extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn request(brand: &str) -> CreateVmRequest {
+        CreateVmRequest {
+            alias: "web-1".to_string(),
+            brand: brand.to_string(),
+            image_uuid: "i-1".to_string(),
+            package_uuid: "p-1".to_string(),
+            owner_uuid: "o-1".to_string(),
+            networks: vec!["n-1".to_string(), "n-2".to_string()],
+            tags: None,
+            customer_metadata: None,
+        }
+    }
+
+    fn rejected(result: Result<serde_json::Value, AppError>) -> bool {
+        matches!(result, Err(AppError::ValidationError(_)))
+    }
+
+    fn params(query: &str) -> VmListParams {
+        Query::<VmListParams>::from_query(query).unwrap().into_inner()
+    }
+
+    #[test]
+    fn list_params_are_passed_down() {
+        let query = params("owner_uuid=o-1&state=running&alias=web&tag=role%3Ddb&server_uuid=s-1")
+            .to_query(&list("sort=alias:desc&limit=5000&offset=10"))
+            .unwrap();
+        assert_eq!(
+            query,
+            VmListQuery {
+                owner_uuid: Some("o-1".to_string()),
+                server_uuid: Some("s-1".to_string()),
+                state: Some("running".to_string()),
+                alias: Some("web".to_string()),
+                tag: Some(("role".to_string(), "db".to_string())),
+                sort: Some(VmSort { field: "alias", descending: true }),
+                limit: Some(crate::api::listing::MAX_LIMIT),
+                offset: Some(10),
+                marker: None,
+            }
+        );
+
+        // A cursor from the previous page carries VMAPI's marker
+        let marker = PageStart::Marker("{\"uuid\":\"v-1\"}".to_string());
+        let page = ListQuery::default().page(Vec::<Vm>::new(), Some(3), Some(marker)).unwrap();
+        let cursor = page.next.unwrap();
+        let query = params("").to_query(&list(&format!("cursor={}&limit=2", cursor))).unwrap();
+        assert_eq!(query.marker.as_deref(), Some("{\"uuid\":\"v-1\"}"));
+        assert_eq!((query.offset, query.limit), (None, Some(2)));
+
+        assert!(rejected_query("tag=role", ""));
+        assert!(rejected_query("tag=%3Ddb", ""));
+        assert!(rejected_query("", "sort=nics"));
+        assert!(rejected_query("", &format!("cursor={}&offset=10", cursor)));
+    }
+
+    fn list(query: &str) -> ListQuery {
+        Query::<ListQuery>::from_query(query).unwrap().into_inner()
+    }
+
+    fn rejected_query(query: &str, list_query: &str) -> bool {
+        matches!(params(query).to_query(&list(list_query)), Err(AppError::ValidationError(_)))
+    }
+
+    #[test]
+    fn zones_use_the_image_as_root_dataset() {
+        let payload = build_provision_payload(&request(""), &package(json!({})), &image("zone-dataset", json!({}))).unwrap();
+        assert_eq!(payload["brand"], "joyent");
+        assert_eq!(payload["image_uuid"], "i-1");
+        assert_eq!(payload["quota"], 25);
+        assert_eq!(payload["ram"], 1024);
+        assert!(payload.get("disks").is_none());
+        assert_eq!(payload["networks"], json!([
+            { "ipv4_uuid": "n-1", "primary": true },
+            { "ipv4_uuid": "n-2", "primary": false },
+        ]));
+    }
+
+    #[test]
+    fn hardware_vms_get_boot_and_data_disks() {
+        let payload = build_provision_payload(&request("kvm"), &package(json!({})), &image("zvol", json!({}))).unwrap();
+        assert_eq!(payload["vcpus"], 2);
+        assert_eq!(payload["disks"], json!([{ "image_uuid": "i-1", "boot": true }, { "size": 25600 }]));
+        assert!(payload.get("image_uuid").is_none());
+
+        let payload = build_provision_payload(&request(""), &package(json!({})), &image("zvol", json!({ "image_size": 10240 }))).unwrap();
+        assert_eq!(payload["brand"], "bhyve");
+        assert_eq!(payload["disks"], json!([
+            { "image_uuid": "i-1", "boot": true, "image_size": 10240 },
+            { "size": 15360 },
+        ]));
+    }
+
+    #[test]
+    fn flexible_disk_packages_keep_their_disk_layout() {
+        let flexible = package(json!({ "flexible_disk": true, "disks": [{}, { "size": 2048 }] }));
+        let payload = build_provision_payload(&request("bhyve"), &flexible, &image("zvol", json!({ "image_size": 10240 }))).unwrap();
+        assert_eq!(payload["flexible_disk_size"], 25600);
+        assert_eq!(payload["disks"], json!([
+            { "image_uuid": "i-1", "boot": true, "image_size": 10240 },
+            { "size": 2048 },
+        ]));
+    }
+
+    #[test]
+    fn rejects_brands_that_cannot_boot_the_image() {
+        assert!(rejected(build_provision_payload(&request("kvm"), &package(json!({})), &image("zone-dataset", json!({})))));
+        assert!(rejected(build_provision_payload(&request("joyent"), &package(json!({})), &image("zvol", json!({})))));
+        assert!(rejected(build_provision_payload(&request("lx"), &package(json!({})), &image("zone-dataset", json!({})))));
+        assert!(rejected(build_provision_payload(&request(""), &package(json!({})), &image("docker", json!({})))));
+    }
+
+    #[test]
+    fn rejects_brands_the_image_or_package_forbid() {
+        let bhyve_only = image("zvol", json!({ "requirements": { "brand": "bhyve" } }));
+        assert!(rejected(build_provision_payload(&request("kvm"), &package(json!({})), &bhyve_only)));
+
+        let kvm_package = package(json!({ "brand": "kvm" }));
+        assert!(rejected(build_provision_payload(&request("bhyve"), &kvm_package, &image("zvol", json!({})))));
+        assert_eq!(build_provision_payload(&request(""), &kvm_package, &image("zvol", json!({}))).unwrap()["brand"], "kvm");
+    }
+
+    #[test]
+    fn rejects_inactive_inputs_and_missing_networks() {
+        assert!(rejected(build_provision_payload(&request(""), &package(json!({ "active": false })), &image("zone-dataset", json!({})))));
+        assert!(rejected(build_provision_payload(&request(""), &package(json!({})), &image("zone-dataset", json!({ "state": "disabled" })))));
+
+        let mut no_networks = request("");
+        no_networks.networks.clear();
+        assert!(rejected(build_provision_payload(&no_networks, &package(json!({})), &image("zone-dataset", json!({})))));
+    }
+
+    fn action(action: &str, params: Option<serde_json::Value>) -> VmActionRequest {
+        VmActionRequest { action: action.to_string(), params }
+    }
+
+    #[test]
+    fn validates_actions_and_their_parameters() {
+        assert!(validate_vm_action(&action("start", None)).is_ok());
+        assert!(validate_vm_action(&action("stop", Some(serde_json::Value::Null))).is_ok());
+        assert!(validate_vm_action(&action("reprovision", Some(json!({ "image_uuid": "i-2" })))).is_ok());
+        assert!(validate_vm_action(&action("resize_disk", Some(json!({ "path": "/dev/a", "size": 10 })))).is_ok());
+
+        let unknown = validate_vm_action(&action("explode", None)).unwrap_err();
+        assert!(unknown.to_string().contains("Unsupported action"));
+
+        let missing = validate_vm_action(&action("resize_disk", Some(json!({ "size": 10 })))).unwrap_err();
+        assert!(missing.to_string().contains("path"));
+        assert!(matches!(validate_vm_action(&action("add_nics", None)), Err(AppError::ValidationError(_))));
+        assert!(matches!(validate_vm_action(&action("start", Some(json!([1])))), Err(AppError::ValidationError(_))));
+    }
+
+    fn vm(tags: serde_json::Value, customer_metadata: serde_json::Value) -> Vm {
+        serde_json::from_value(json!({
+            "uuid": "v-1", "alias": "web-1", "state": "running", "brand": "joyent", "ram": 1024,
+            "owner_uuid": "o-1", "tags": tags, "customer_metadata": customer_metadata,
+        }))
+        .unwrap()
+    }
+
+    fn update(tags: Option<serde_json::Value>, customer_metadata: Option<serde_json::Value>) -> UpdateVmRequest {
+        UpdateVmRequest { alias: None, owner_uuid: None, tags, customer_metadata }
+    }
+
+    #[test]
+    fn replacing_removes_keys_missing_from_the_request() {
+        let current = vm(json!({ "role": "web", "env": "prod" }), json!({ "user-script": "x", "motd": "hi" }));
+        let payload = build_update_payload(
+            &update(Some(json!({ "role": "db" })), Some(json!({ "motd": "hello" }))),
+            Some(&current),
+        )
+        .unwrap();
+
+        assert_eq!(payload["set_tags"], json!({ "role": "db" }));
+        assert_eq!(payload["remove_tags"], json!(["env"]));
+        assert_eq!(payload["set_customer_metadata"], json!({ "motd": "hello" }));
+        assert_eq!(payload["remove_customer_metadata"], json!(["user-script"]));
+    }
+
+    #[test]
+    fn merging_removes_nothing() {
+        let payload = build_update_payload(&update(Some(json!({ "role": "db" })), None), None).unwrap();
+        assert_eq!(payload, json!({ "set_tags": { "role": "db" } }));
+
+        // Nothing to remove when every existing key is kept
+        let current = vm(json!({ "role": "web" }), json!({}));
+        let payload = build_update_payload(&update(Some(json!({ "role": "db", "env": "dev" })), None), Some(&current)).unwrap();
+        assert!(payload.get("remove_tags").is_none());
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_updates() {
+        assert!(matches!(build_update_payload(&update(None, None), None), Err(AppError::ValidationError(_))));
+        assert!(matches!(build_update_payload(&update(Some(json!(["a"])), None), None), Err(AppError::ValidationError(_))));
+
+        let rename = UpdateVmRequest { alias: Some("web-2".to_string()), owner_uuid: None, tags: None, customer_metadata: None };
+        assert_eq!(build_update_payload(&rename, None).unwrap(), json!({ "alias": "web-2" }));
+    }
+
+    // Stub VMAPI with more VMs than fit on one default-size page: the first
+    // page is as long as the limit asked for, the next one holds the rest
+    #[get("/vms")]
+    async fn stub_list_vms(query: Query<std::collections::HashMap<String, String>>) -> HttpResponse {
+        // Like VMAPI, 1000 VMs when no limit is given
+        let limit: usize = query.get("limit").and_then(|limit| limit.parse().ok()).unwrap_or(1000);
+        let count = if query.contains_key("marker") { 3 } else { limit };
+        let vms: Vec<serde_json::Value> = (0..count)
+            .map(|i| json!({ "uuid": format!("v-{}", i), "alias": format!("web-{}", i), "owner_uuid": "o-1" }))
+            .collect();
+        HttpResponse::Ok().json(vms)
+    }
+
+    #[actix_web::test]
+    async fn full_default_pages_have_a_next_cursor() {
+        use actix_web::dev::Service;
+        use actix_web::test::{call_and_read_body_json, init_service, TestRequest};
+        use actix_web::{App, HttpMessage, HttpServer};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = crate::config::Config {
+            vmapi_url: format!("http://{}", listener.local_addr().unwrap()),
+            ..crate::config::Config::for_tests()
+        };
+        let stub = HttpServer::new(|| App::new().service(stub_list_vms)).workers(1).listen(listener).unwrap().run();
+        actix_web::rt::spawn(stub);
+
+        let user = AuthenticatedUser {
+            id: uuid::Uuid::new_v4(),
+            name: "operator".to_string(),
+            email: "operator@example.com".to_string(),
+            roles: vec!["admin".to_string()],
+        };
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(ServiceClients::new(&config).unwrap()))
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(user.clone());
+                    srv.call(req)
+                })
+                .service(web::scope("/vms").service(list_vms)),
+        )
+        .await;
+
+        // No limit: VMAPI's default page is full, so there may be more
+        let page: serde_json::Value = call_and_read_body_json(&app, TestRequest::get().uri("/vms").to_request()).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 1000);
+        let cursor = page["next"].as_str().expect("a full default page has a next cursor");
+
+        let uri = format!("/vms?cursor={}", cursor);
+        let page: serde_json::Value = call_and_read_body_json(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(page["items"].as_array().unwrap().len(), 3);
+        assert!(page["next"].is_null());
+    }
+}
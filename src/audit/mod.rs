@@ -0,0 +1,294 @@
+use actix_web::{
+    body::{self, BodySize, BoxBody, MessageBody},
+    dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    web::{Bytes, BytesMut, Data, Query},
+    Error, HttpMessage, HttpRequest,
+};
+use futures::future::{ok, Ready};
+use futures::{stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::auth::{AuthenticatedUser, Impersonation};
+use crate::models::entities::NewAuditEntry;
+
+mod store;
+
+pub use store::{AuditQuery, AuditStore, DEFAULT_LIMIT};
+
+// Field names whose values never reach the audit log
+const REDACTED_FIELDS: &[&str] = &["password", "secret", "token", "private", "otp", "credential", "code"];
+const REDACTED: &str = "[REDACTED]";
+// Plain-text error bodies are truncated to this many characters
+const MAX_ERROR_LENGTH: usize = 1000;
+// Request and response bodies larger than this are passed through without
+// being recorded
+const MAX_RECORDED_BODY: usize = 64 * 1024;
+
+// Middleware that queues an audit record for every POST/PUT/PATCH/DELETE.
+// It is a no-op unless an AuditStore is registered as app data. Wrap it
+// inside AuthMiddleware so anonymous requests never reach it: the
+// authenticated user is read from the request extensions.
+pub struct AuditMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for AuditMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditMiddlewareService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditMiddlewareService {
+            service: Rc::new(service),
+        })
+    }
+}
+
+pub struct AuditMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for AuditMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let store = req.app_data::<Data<AuditStore>>().cloned();
+        let mutating = matches!(*req.method(), Method::POST | Method::PUT | Method::PATCH | Method::DELETE);
+
+        let store = match store {
+            Some(store) if mutating => store,
+            _ => {
+                let fut = self.service.call(req);
+                return Box::pin(async move { Ok(fut.await?.map_into_boxed_body()) });
+            }
+        };
+
+        let service = Rc::clone(&self.service);
+
+        Box::pin(async move {
+            // Buffer the request body, up to a limit, so it can be both
+            // recorded and handed on to the handler
+            let mut payload = req.take_payload();
+            let mut request_body = BytesMut::new();
+            let mut complete = true;
+            while let Some(chunk) = payload.next().await {
+                request_body.extend_from_slice(&chunk?);
+                if request_body.len() > MAX_RECORDED_BODY {
+                    complete = false;
+                    break;
+                }
+            }
+            let request_body = request_body.freeze();
+            
+            let recorded_body = if complete {
+                req.set_payload(bytes_to_payload(request_body.clone()));
+                Some(request_body)
+            } else {
+                // Replay what was read, then stream the rest straight through
+                let rest = stream::once(async move { Ok(request_body) }).chain(payload);
+                req.set_payload(Payload::from(Box::pin(rest) as Pin<Box<_>>));
+                None
+            };
+
+            let res = service.call(req).await?;
+
+            // Only small, already sized responses are captured, for the job
+            // UUID or error message; anything else streams through untouched
+            let (request, response) = res.into_parts();
+            let capture = matches!(response.body().size(), BodySize::Sized(n) if n <= MAX_RECORDED_BODY as u64);
+            let (response, response_body) = if capture {
+                let (response, response_body) = response.into_parts();
+                let response_body = body::to_bytes(response_body)
+                    .await
+                    .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+                (response.set_body(BoxBody::new(response_body.clone())), response_body)
+            } else {
+                (response.map_into_boxed_body(), Bytes::new())
+            };
+
+            let entry = build_entry(&request, response.status().as_u16(), recorded_body.as_ref(), &response_body);
+            store.submit(entry);
+
+            Ok(ServiceResponse::new(request, response))
+        })
+    }
+}
+
+fn bytes_to_payload(buf: Bytes) -> Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(buf);
+    Payload::from(payload)
+}
+
+// A request body of None was too large to record
+fn build_entry(request: &HttpRequest, status: u16, request_body: Option<&Bytes>, response_body: &Bytes) -> NewAuditEntry {
+    let user = request.extensions().get::<AuthenticatedUser>().cloned();
+    let impersonation = request.extensions().get::<Impersonation>().cloned();
+    let route = request
+        .match_pattern()
+        .unwrap_or_else(|| request.path().to_string());
+
+    let mut body: Option<Value> = match request_body {
+        Some(request_body) => serde_json::from_slice(request_body).ok(),
+        None => Some(json!({ "truncated": true })),
+    };
+    if let Some(body) = body.as_mut() {
+        redact(body);
+    }
+    let response: Option<Value> = serde_json::from_slice(response_body).ok();
+
+    // Resource type is the first segment after /api, e.g. "vms"
+    let resource_type = route
+        .trim_start_matches("/api")
+        .trim_start_matches('/')
+        .split('/')
+        .next()
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_string);
+
+    // Explicit actions (?action=start, {"action": "reboot"}) take precedence
+    // over the HTTP method
+    let action = Query::<HashMap<String, String>>::from_query(request.query_string())
+        .ok()
+        .and_then(|query| query.get("action").cloned())
+        .or_else(|| {
+            body.as_ref()
+                .and_then(|b| b["action"].as_str())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| {
+            match *request.method() {
+                Method::POST => "create",
+                Method::PUT | Method::PATCH => "update",
+                Method::DELETE => "delete",
+                _ => "other",
+            }
+            .to_string()
+        });
+
+    let success = (200..400).contains(&status);
+    let field = |name: &str| {
+        response
+            .as_ref()
+            .and_then(|r| r[name].as_str())
+            .map(str::to_string)
+    };
+
+    NewAuditEntry {
+        user_uuid: user.as_ref().map(|u| u.id),
+        user_name: user.as_ref().map(|u| u.name.clone()),
+        impersonator_uuid: impersonation.as_ref().map(|i| i.impersonator_id),
+        impersonator_name: impersonation.map(|i| i.impersonator_name),
+        method: request.method().to_string(),
+        route,
+        path: request.path().to_string(),
+        resource_type,
+        resource_uuid: request
+            .match_info()
+            .get("uuid")
+            .map(str::to_string)
+            .or_else(|| field("vm_uuid"))
+            .or_else(|| field("uuid")),
+        action,
+        request_body: body,
+        source_ip: crate::api::client_ip(request).map(|ip| ip.to_string()),
+        status_code: status as i32,
+        outcome: if success { "success" } else { "failure" }.to_string(),
+        job_uuid: field("job_uuid"),
+        error: if success {
+            None
+        } else {
+            field("message").or_else(|| {
+                let text = String::from_utf8_lossy(response_body);
+                let text = text.trim();
+                (!text.is_empty()).then(|| text.chars().take(MAX_ERROR_LENGTH).collect())
+            })
+        },
+    }
+}
+
+// Replace the values of secret-looking fields, at any depth
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, field) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if REDACTED_FIELDS.iter().any(|secret| key.contains(secret)) {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_replaces_secret_fields_at_any_depth() {
+        let mut body = json!({
+            "login": "alice",
+            "password": "hunter2",
+            "nested": {
+                "api_token": "abc",
+                "otp": "123456",
+                "email": "alice@example.com",
+                "keys": [{"name": "laptop", "Private_Key": "-----BEGIN"}]
+            },
+            "auth": [{"code": "xyz", "ram": 1024}]
+        });
+
+        redact(&mut body);
+
+        assert_eq!(
+            body,
+            json!({
+                "login": "alice",
+                "password": REDACTED,
+                "nested": {
+                    "api_token": REDACTED,
+                    "otp": REDACTED,
+                    "email": "alice@example.com",
+                    "keys": [{"name": "laptop", "Private_Key": REDACTED}]
+                },
+                "auth": [{"code": REDACTED, "ram": 1024}]
+            })
+        );
+    }
+
+    #[test]
+    fn redact_replaces_whole_secret_objects() {
+        let mut body = json!({"credentials": {"user": "a", "pass": "b"}, "alias": "web-1"});
+
+        redact(&mut body);
+
+        assert_eq!(body, json!({"credentials": REDACTED, "alias": "web-1"}));
+    }
+}
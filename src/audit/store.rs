@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use sqlx::{Postgres, QueryBuilder};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::error;
+
+use crate::error::AppError;
+use crate::models::entities::{AuditEntry, NewAuditEntry};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    // User UUID or login/name; also matches what an operator did while
+    // impersonating someone
+    pub user: Option<String>,
+    // Resource UUID or resource type (vms, users, ...)
+    pub resource: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+// Entries per page when the request does not set a limit
+pub const DEFAULT_LIMIT: u32 = 100;
+// Records waiting to be written; beyond this new records are dropped rather
+// than holding up requests while Postgres is slow
+const QUEUE_CAPACITY: usize = 1024;
+
+// Postgres-backed audit log. Records are queued and written by a background
+// task so requests never wait on the insert.
+#[derive(Clone)]
+pub struct AuditStore {
+    pool: PgPool,
+    queue: mpsc::Sender<NewAuditEntry>,
+}
+
+impl AuditStore {
+    pub fn new(pool: PgPool) -> Self {
+        let (queue, mut pending) = mpsc::channel::<NewAuditEntry>(QUEUE_CAPACITY);
+        
+        let writer = pool.clone();
+        tokio::spawn(async move {
+            while let Some(entry) = pending.recv().await {
+                if let Err(e) = record(&writer, &entry).await {
+                    error!("Failed to write audit record for {} {}: {}", entry.method, entry.path, e);
+                }
+            }
+        });
+        
+        Self { pool, queue }
+    }
+    
+    // Queue a record for writing without waiting for it
+    pub fn submit(&self, entry: NewAuditEntry) {
+        match self.queue.try_send(entry) {
+            Ok(()) => {}
+            Err(TrySendError::Full(entry)) => {
+                error!("Audit queue full, dropping record for {} {}", entry.method, entry.path);
+            }
+            Err(TrySendError::Closed(entry)) => {
+                error!("Audit writer stopped, dropping record for {} {}", entry.method, entry.path);
+            }
+        }
+    }
+
+    
+    // Most recent entries first
+    pub async fn search(&self, query: &AuditQuery, limit: u32, offset: u32) -> Result<Vec<AuditEntry>, AppError> {
+        let mut builder: QueryBuilder<Postgres> = QueryBuilder::new("SELECT * FROM audit_log WHERE TRUE");
+        
+        if let Some(user) = &query.user {
+            builder.push(" AND (user_uuid::text = ").push_bind(user.clone())
+                .push(" OR user_name = ").push_bind(user.clone())
+                .push(" OR impersonator_uuid::text = ").push_bind(user.clone())
+                .push(" OR impersonator_name = ").push_bind(user.clone())
+                .push(")");
+        }
+        
+        if let Some(resource) = &query.resource {
+            builder.push(" AND (resource_uuid = ").push_bind(resource.clone())
+                .push(" OR resource_type = ").push_bind(resource.clone())
+                .push(")");
+        }
+        
+        if let Some(action) = &query.action {
+            builder.push(" AND action = ").push_bind(action.clone());
+        }
+        
+        if let Some(since) = query.since {
+            builder.push(" AND occurred_at >= ").push_bind(since);
+        }
+        
+        if let Some(until) = query.until {
+            builder.push(" AND occurred_at < ").push_bind(until);
+        }
+        
+        builder.push(" ORDER BY occurred_at DESC, id DESC LIMIT ").push_bind(i64::from(limit))
+            .push(" OFFSET ").push_bind(i64::from(offset));
+            
+        let entries = builder
+            .build_query_as::<AuditEntry>()
+            .fetch_all(&self.pool)
+            .await?;
+            
+        Ok(entries)
+    }
+}
+
+async fn record(pool: &PgPool, entry: &NewAuditEntry) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT INTO audit_log (user_uuid, user_name, impersonator_uuid, impersonator_name, method, \
+         route, path, resource_type, resource_uuid, action, request_body, source_ip, status_code, \
+         outcome, job_uuid, error) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+    )
+    .bind(entry.user_uuid)
+    .bind(&entry.user_name)
+    .bind(entry.impersonator_uuid)
+    .bind(&entry.impersonator_name)
+    .bind(&entry.method)
+    .bind(&entry.route)
+    .bind(&entry.path)
+    .bind(&entry.resource_type)
+    .bind(&entry.resource_uuid)
+    .bind(&entry.action)
+    .bind(&entry.request_body)
+    .bind(&entry.source_ip)
+    .bind(entry.status_code)
+    .bind(&entry.outcome)
+    .bind(&entry.job_uuid)
+    .bind(&entry.error)
+    .execute(pool)
+    .await?;
+    
+    Ok(())
+}
@@ -0,0 +1,634 @@
+use actix_web::web::Data;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::dev_users::DevUsers;
+use crate::auth::AuthenticatedUser;
+use crate::error::AppError;
+use crate::services::ServiceClients;
+
+// API tokens start with this, so AuthMiddleware can tell them from JWTs
+pub const TOKEN_PREFIX: &str = "tat_";
+// Bytes of randomness in each token (hex encoded after the prefix)
+const TOKEN_BYTES: usize = 32;
+// last_used_at is only written this often, not on every request
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+// Resources that scopes refer to, one per API route group
+pub const RESOURCES: &[&str] = &[
+    "vms", "users", "packages", "images", "platforms", "servers", "networks", "jobs", "dashboard", "audit",
+];
+
+// Access levels in a scope. Each level includes the ones below it: a token
+// with vms:write can also read VMs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Read,
+    Write,
+    Admin,
+}
+
+impl Access {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Admin => "admin",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "read" => Some(Access::Read),
+            "write" => Some(Access::Write),
+            "admin" => Some(Access::Admin),
+            _ => None,
+        }
+    }
+}
+
+// Parse a scope such as "vms:read" into its resource and access level
+fn parse_scope(scope: &str) -> Option<(&str, Access)> {
+    let (resource, access) = scope.split_once(':')?;
+    if !RESOURCES.contains(&resource) {
+        return None;
+    }
+    Some((resource, Access::parse(access)?))
+}
+
+// Check requested scopes, returning them without duplicates
+pub fn validate_scopes(scopes: &[String]) -> Result<Vec<String>, AppError> {
+    if scopes.is_empty() {
+        return Err(AppError::ValidationError("At least one scope is required".to_string()));
+    }
+
+    let mut valid: Vec<String> = Vec::new();
+    for scope in scopes {
+        let scope = scope.trim();
+        if parse_scope(scope).is_none() {
+            return Err(AppError::ValidationError(format!(
+                "Unknown scope {:?}; scopes are <resource>:<read|write|admin> with resource one of {}",
+                scope,
+                RESOURCES.join(", ")
+            )));
+        }
+        if !valid.iter().any(|s| s == scope) {
+            valid.push(scope.to_string());
+        }
+    }
+    Ok(valid)
+}
+
+// A token as shown to its owner. The token itself is only returned once,
+// when it is created.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    // Whether the token's scopes allow this access to a resource
+    pub fn allows(&self, resource: &str, access: Access) -> bool {
+        self.scopes
+            .iter()
+            .filter_map(|scope| parse_scope(scope))
+            .any(|(r, granted)| r == resource && granted >= access)
+    }
+}
+
+#[derive(Clone, sqlx::FromRow)]
+struct ApiTokenRow {
+    #[sqlx(flatten)]
+    token: ApiToken,
+    user_uuid: Uuid,
+    user_name: String,
+    email: String,
+}
+
+// Named, scoped, expiring tokens that scripts send instead of a JWT. A token
+// acts as the user who created it, with the roles they have in UFDS when it
+// is used, further limited by its scopes. Only SHA-256 hashes of tokens are
+// kept.
+#[derive(Clone)]
+pub enum ApiTokenStore {
+    Postgres(PgPool),
+    Memory(Arc<MemoryApiTokens>),
+}
+
+#[derive(Default)]
+pub struct MemoryApiTokens {
+    // token hash -> token
+    tokens: Mutex<HashMap<String, ApiTokenRow>>,
+}
+
+impl ApiTokenStore {
+    pub fn new(pool: Option<PgPool>) -> Self {
+        match pool {
+            Some(pool) => ApiTokenStore::Postgres(pool),
+            None => {
+                info!("No database configured; API tokens are kept in memory");
+                ApiTokenStore::Memory(Arc::new(MemoryApiTokens::default()))
+            }
+        }
+    }
+
+    // Create a token for the user and return it with its plain text
+    pub async fn create(
+        &self,
+        user: &AuthenticatedUser,
+        name: &str,
+        scopes: Vec<String>,
+        ttl: Duration,
+    ) -> Result<(ApiToken, String), AppError> {
+        let secret = generate_token();
+        let now = Utc::now();
+        let row = ApiTokenRow {
+            token: ApiToken {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+                scopes,
+                created_at: now,
+                expires_at: now + ttl,
+                last_used_at: None,
+            },
+            user_uuid: user.id,
+            user_name: user.name.clone(),
+            email: user.email.clone(),
+        };
+
+        let created = match self {
+            ApiTokenStore::Postgres(pool) => {
+                // Expired tokens can never be used again; keep the table small
+                sqlx::query("DELETE FROM api_tokens WHERE expires_at < now()")
+                    .execute(pool)
+                    .await?;
+
+                let result = sqlx::query(
+                    "INSERT INTO api_tokens \
+                     (id, token_hash, user_uuid, user_name, email, name, scopes, created_at, expires_at) \
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+                     ON CONFLICT (user_uuid, name) DO NOTHING",
+                )
+                .bind(row.token.id)
+                .bind(hash_token(&secret))
+                .bind(row.user_uuid)
+                .bind(&row.user_name)
+                .bind(&row.email)
+                .bind(&row.token.name)
+                .bind(&row.token.scopes)
+                .bind(row.token.created_at)
+                .bind(row.token.expires_at)
+                .execute(pool)
+                .await?;
+
+                result.rows_affected() > 0
+            }
+            ApiTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                tokens.retain(|_, existing| existing.token.expires_at >= now);
+                let taken = tokens
+                    .values()
+                    .any(|existing| existing.user_uuid == user.id && existing.token.name == name);
+                if !taken {
+                    tokens.insert(hash_token(&secret), row.clone());
+                }
+                !taken
+            }
+        };
+
+        if !created {
+            return Err(AppError::Conflict(format!("You already have an API token named {:?}", name)));
+        }
+
+        info!("Created API token {} ({}) for user {}", row.token.id, name, user.id);
+        Ok((row.token, secret))
+    }
+
+    // The user's tokens that have not expired, newest first
+    pub async fn list(&self, user_uuid: Uuid) -> Result<Vec<ApiToken>, AppError> {
+        match self {
+            ApiTokenStore::Postgres(pool) => Ok(sqlx::query_as(
+                "SELECT id, name, scopes, created_at, expires_at, last_used_at FROM api_tokens \
+                 WHERE user_uuid = $1 AND expires_at > now() ORDER BY created_at DESC",
+            )
+            .bind(user_uuid)
+            .fetch_all(pool)
+            .await?),
+            ApiTokenStore::Memory(memory) => {
+                let now = Utc::now();
+                let mut list: Vec<ApiToken> = memory
+                    .tokens
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter(|row| row.user_uuid == user_uuid && row.token.expires_at > now)
+                    .map(|row| row.token.clone())
+                    .collect();
+                list.sort_by_key(|token| std::cmp::Reverse(token.created_at));
+                Ok(list)
+            }
+        }
+    }
+
+    // The token and the UUID of the user it acts as, if it exists and has
+    // not expired. See token_user for the user themselves.
+    pub async fn authenticate(&self, secret: &str) -> Result<Option<(ApiToken, Uuid)>, AppError> {
+        let hash = hash_token(secret);
+        let now = Utc::now();
+        let stale = |token: &ApiToken| {
+            token
+                .last_used_at
+                .is_none_or(|at| now - at >= Duration::seconds(LAST_USED_RESOLUTION_SECS))
+        };
+
+        match self {
+            ApiTokenStore::Postgres(pool) => {
+                let row: Option<ApiTokenRow> = sqlx::query_as(
+                    "SELECT id, name, scopes, created_at, expires_at, last_used_at, \
+                     user_uuid, user_name, email \
+                     FROM api_tokens WHERE token_hash = $1 AND expires_at > now()",
+                )
+                .bind(&hash)
+                .fetch_optional(pool)
+                .await?;
+                let Some(row) = row else {
+                    return Ok(None);
+                };
+
+                if stale(&row.token) {
+                    sqlx::query("UPDATE api_tokens SET last_used_at = now() WHERE id = $1")
+                        .bind(row.token.id)
+                        .execute(pool)
+                        .await?;
+                }
+                Ok(Some((row.token.clone(), row.user_uuid)))
+            }
+            ApiTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                let Some(row) = tokens.get_mut(&hash).filter(|row| row.token.expires_at > now) else {
+                    return Ok(None);
+                };
+
+                if stale(&row.token) {
+                    row.token.last_used_at = Some(now);
+                }
+                Ok(Some((row.token.clone(), row.user_uuid)))
+            }
+        }
+    }
+
+    // Revoke one of the user's tokens. Returns false if they have no such token.
+    pub async fn delete(&self, user_uuid: Uuid, id: Uuid) -> Result<bool, AppError> {
+        let deleted = match self {
+            ApiTokenStore::Postgres(pool) => {
+                sqlx::query("DELETE FROM api_tokens WHERE id = $1 AND user_uuid = $2")
+                    .bind(id)
+                    .bind(user_uuid)
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+                    > 0
+            }
+            ApiTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                let before = tokens.len();
+                tokens.retain(|_, row| !(row.user_uuid == user_uuid && row.token.id == id));
+                tokens.len() < before
+            }
+        };
+
+        if deleted {
+            info!("Revoked API token {} of user {}", id, user_uuid);
+        }
+        Ok(deleted)
+    }
+
+    // Revoke every token of a user, e.g. when their sessions are revoked or
+    // the account is deleted
+    pub async fn revoke_user(&self, user_uuid: Uuid) -> Result<u64, AppError> {
+        let revoked = match self {
+            ApiTokenStore::Postgres(pool) => {
+                sqlx::query("DELETE FROM api_tokens WHERE user_uuid = $1")
+                    .bind(user_uuid)
+                    .execute(pool)
+                    .await?
+                    .rows_affected()
+            }
+            ApiTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                let before = tokens.len();
+                tokens.retain(|_, row| row.user_uuid != user_uuid);
+                (before - tokens.len()) as u64
+            }
+        };
+
+        if revoked > 0 {
+            info!("Revoked {} API tokens of user {}", revoked, user_uuid);
+        }
+        Ok(revoked)
+    }
+}
+
+// The token's owner as UFDS (or, in dev_mode, the dev users file) has them
+// now, the way a session refresh looks them up, so role changes and removed
+// accounts take effect at once. None if the account no longer exists.
+pub async fn token_user(request: &HttpRequest, user_uuid: Uuid) -> Result<Option<AuthenticatedUser>, AppError> {
+    let uuid = user_uuid.to_string();
+    if let Some(user) = request.app_data::<Data<DevUsers>>().and_then(|users| users.find(&uuid)) {
+        return Ok(Some(AuthenticatedUser {
+            id: user_uuid,
+            name: user.name,
+            email: user.email,
+            roles: user.roles,
+        }));
+    }
+
+    // Without UFDS only the dev users exist
+    let Some(services) = request.app_data::<Data<ServiceClients>>() else {
+        return Ok(None);
+    };
+    let user = services.ufds.find_user_by_uuid(&uuid).await?;
+    if user.is_none() {
+        warn!("API token owner {} no longer exists", user_uuid);
+    }
+    Ok(user.map(|user| AuthenticatedUser {
+        id: user_uuid,
+        name: user.name,
+        email: user.email,
+        roles: user.roles,
+    }))
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", TOKEN_PREFIX, hex::encode(bytes))
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{get, post, web, web::Data, App, HttpResponse};
+
+    use crate::auth::dev_users::DevUser;
+    use crate::auth::middleware::AuthMiddleware;
+    use crate::auth::rbac::{Permission, RequirePermission, RequireScope};
+
+    fn user(roles: &[&str]) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id: Uuid::new_v4(),
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    // Token owners as they are now; tokens are resolved against these
+    fn dev_users(users: &[&AuthenticatedUser]) -> Data<DevUsers> {
+        Data::new(DevUsers::new(
+            users
+                .iter()
+                .map(|user| DevUser {
+                    username: user.name.clone(),
+                    password: "secret".to_string(),
+                    uuid: user.id,
+                    name: user.name.clone(),
+                    email: user.email.clone(),
+                    roles: user.roles.clone(),
+                })
+                .collect(),
+        ))
+    }
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn scopes_are_validated() {
+        assert_eq!(
+            validate_scopes(&scopes(&["vms:read", " vms:read", "servers:admin"])).unwrap(),
+            scopes(&["vms:read", "servers:admin"])
+        );
+
+        for bad in [&[][..], &["vms"][..], &["vms:delete"][..], &["mail:read"][..], &["vms:read", "*:read"][..]] {
+            assert!(matches!(validate_scopes(&scopes(bad)), Err(AppError::ValidationError(_))), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn higher_access_includes_lower() {
+        let token = ApiToken {
+            id: Uuid::new_v4(),
+            name: "ci".to_string(),
+            scopes: scopes(&["vms:write", "servers:read"]),
+            created_at: Utc::now(),
+            expires_at: Utc::now() + Duration::days(1),
+            last_used_at: None,
+        };
+
+        assert!(token.allows("vms", Access::Read));
+        assert!(token.allows("vms", Access::Write));
+        assert!(!token.allows("vms", Access::Admin));
+        assert!(token.allows("servers", Access::Read));
+        assert!(!token.allows("servers", Access::Write));
+        assert!(!token.allows("networks", Access::Read));
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_stored_hashed_and_shown_once() {
+        let store = ApiTokenStore::new(None);
+        let alice = user(&["operator"]);
+
+        let (token, secret) = store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(30)).await.unwrap();
+        assert!(secret.starts_with(TOKEN_PREFIX));
+
+        let ApiTokenStore::Memory(memory) = &store else {
+            panic!("expected the in-memory store");
+        };
+        assert!(!memory.tokens.lock().unwrap().contains_key(&secret));
+
+        let (found, owner) = store.authenticate(&secret).await.unwrap().unwrap();
+        assert_eq!(found.id, token.id);
+        assert!(found.last_used_at.is_some());
+        assert_eq!(owner, alice.id);
+
+        assert!(store.authenticate("tat_unknown").await.unwrap().is_none());
+
+        // Listing never includes the token itself
+        let listed = serde_json::to_value(store.list(alice.id).await.unwrap()).unwrap();
+        assert_eq!(listed[0]["name"], "ci");
+        assert!(!listed.to_string().contains(&secret));
+    }
+
+    #[actix_web::test]
+    async fn names_are_unique_per_user() {
+        let store = ApiTokenStore::new(None);
+        let (alice, bob) = (user(&[]), user(&[]));
+
+        store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+        assert!(matches!(
+            store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(1)).await,
+            Err(AppError::Conflict(_))
+        ));
+        store.create(&bob, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn expired_and_revoked_tokens_stop_working() {
+        let store = ApiTokenStore::new(None);
+        let (alice, bob) = (user(&[]), user(&[]));
+
+        let (_, expired) = store.create(&alice, "old", scopes(&["vms:read"]), Duration::seconds(-1)).await.unwrap();
+        assert!(store.authenticate(&expired).await.unwrap().is_none());
+        assert!(store.list(alice.id).await.unwrap().is_empty());
+
+        let (token, secret) = store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+        // Only the owner can revoke it
+        assert!(!store.delete(bob.id, token.id).await.unwrap());
+        assert!(store.delete(alice.id, token.id).await.unwrap());
+        assert!(store.authenticate(&secret).await.unwrap().is_none());
+
+        let (_, secret) = store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+        assert_eq!(store.revoke_user(alice.id).await.unwrap(), 1);
+        assert!(store.authenticate(&secret).await.unwrap().is_none());
+    }
+
+    #[get("")]
+    async fn list_vms() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[post("")]
+    async fn create_vm() -> HttpResponse {
+        HttpResponse::Created().finish()
+    }
+
+    #[post("/servers/reboot")]
+    async fn reboot_server() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[post("/tokens")]
+    async fn create_api_token() -> HttpResponse {
+        HttpResponse::Created().finish()
+    }
+
+    #[actix_web::test]
+    async fn middleware_enforces_token_scopes() {
+        let store = ApiTokenStore::new(None);
+        let admin = user(&["admin"]);
+        let app = init_service(
+            App::new().app_data(Data::new(store.clone())).app_data(dev_users(&[&admin])).service(
+                web::scope("")
+                    .wrap(AuthMiddleware::new("test-secret".to_string()))
+                    .service(web::scope("/vms").wrap(RequireScope::new("vms")).service(list_vms).service(create_vm))
+                    .service(
+                        web::scope("")
+                            .wrap(RequireScope::session_only())
+                            .service(create_api_token),
+                    ),
+            ),
+        )
+        .await;
+        let call = |secret: &str, request: TestRequest| {
+            request.insert_header(("Authorization", format!("Bearer {}", secret))).to_request()
+        };
+
+        let (_, reader) = store.create(&admin, "reader", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+        let (_, writer) = store.create(&admin, "writer", scopes(&["vms:write"]), Duration::days(1)).await.unwrap();
+
+        let get = || TestRequest::get().uri("/vms");
+        let post = || TestRequest::post().uri("/vms");
+        assert_eq!(call_service(&app, call(&reader, get())).await.status(), 200);
+        assert_eq!(call_service(&app, call(&reader, post())).await.status(), 403);
+        assert_eq!(call_service(&app, call(&writer, post())).await.status(), 201);
+
+        // Tokens cannot mint other tokens
+        let mint = || TestRequest::post().uri("/tokens");
+        assert_eq!(call_service(&app, call(&writer, mint())).await.status(), 403);
+
+        assert_eq!(call_service(&app, call("tat_0000", get())).await.status(), 401);
+    }
+
+    #[actix_web::test]
+    async fn tokens_are_limited_by_roles_and_scopes() {
+        let store = ApiTokenStore::new(None);
+        let admin = user(&["admin"]);
+        let operator = user(&["operator"]);
+        // Demoted since creating their tokens
+        let demoted = AuthenticatedUser { roles: vec!["operator".to_string()], ..user(&[]) };
+        let app = init_service(
+            App::new().app_data(Data::new(store.clone())).app_data(dev_users(&[&admin, &operator, &demoted])).service(
+                web::scope("")
+                    .wrap(RequirePermission::new(Permission::ManageServers))
+                    .wrap(AuthMiddleware::new("test-secret".to_string()))
+                    .service(reboot_server),
+            ),
+        )
+        .await;
+        let reboot = |secret: &str| {
+            TestRequest::post()
+                .uri("/servers/reboot")
+                .insert_header(("Authorization", format!("Bearer {}", secret)))
+                .to_request()
+        };
+
+        let (_, write) = store.create(&admin, "write", scopes(&["servers:write"]), Duration::days(1)).await.unwrap();
+        let (_, full) = store.create(&admin, "full", scopes(&["servers:admin"]), Duration::days(1)).await.unwrap();
+        let (_, escalated) = store.create(&operator, "full", scopes(&["servers:admin"]), Duration::days(1)).await.unwrap();
+        let as_admin = AuthenticatedUser { roles: vec!["admin".to_string()], ..demoted.clone() };
+        let (_, stale) = store.create(&as_admin, "full", scopes(&["servers:admin"]), Duration::days(1)).await.unwrap();
+
+        // Server changes need the admin scope, and a scope never grants more
+        // than the user's current roles
+        assert_eq!(call_service(&app, reboot(&write)).await.status(), 403);
+        assert_eq!(call_service(&app, reboot(&full)).await.status(), 200);
+        assert_eq!(call_service(&app, reboot(&escalated)).await.status(), 403);
+        assert_eq!(call_service(&app, reboot(&stale)).await.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn tokens_of_removed_users_are_rejected() {
+        let store = ApiTokenStore::new(None);
+        let (alice, removed) = (user(&["admin"]), user(&["admin"]));
+        let app = init_service(
+            App::new().app_data(Data::new(store.clone())).app_data(dev_users(&[&alice])).service(
+                web::scope("")
+                    .wrap(AuthMiddleware::new("test-secret".to_string()))
+                    .service(web::scope("/vms").wrap(RequireScope::new("vms")).service(list_vms)),
+            ),
+        )
+        .await;
+        let list = |secret: &str| {
+            TestRequest::get()
+                .uri("/vms")
+                .insert_header(("Authorization", format!("Bearer {}", secret)))
+                .to_request()
+        };
+
+        let (_, kept) = store.create(&alice, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+        let (_, orphaned) = store.create(&removed, "ci", scopes(&["vms:read"]), Duration::days(1)).await.unwrap();
+
+        assert_eq!(call_service(&app, list(&kept)).await.status(), 200);
+        assert_eq!(call_service(&app, list(&orphaned)).await.status(), 401);
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+use crate::auth::UserInfo;
+
+// A login accepted without UFDS while dev_mode is on. Fixtures live in a
+// JSON file (see dev-users.json.example), never in code.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DevUser {
+    pub username: String,
+    pub password: String,
+    pub uuid: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl DevUser {
+    fn user_info(&self) -> UserInfo {
+        UserInfo {
+            id: self.uuid.to_string(),
+            name: self.name.clone(),
+            email: self.email.clone(),
+            roles: self.roles.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DevUsers {
+    users: Vec<DevUser>,
+}
+
+impl DevUsers {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read dev users file {}", path.display()))?;
+        let users: Vec<DevUser> = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse dev users file {}", path.display()))?;
+
+        for (i, user) in users.iter().enumerate() {
+            if user.username.is_empty() || user.password.is_empty() {
+                bail!("Dev user #{} in {} needs a username and password", i + 1, path.display());
+            }
+            if users[..i].iter().any(|other| other.username == user.username) {
+                bail!("Dev user {} is defined more than once in {}", user.username, path.display());
+            }
+        }
+
+        Ok(Self { users })
+    }
+
+    pub fn usernames(&self) -> Vec<&str> {
+        self.users.iter().map(|user| user.username.as_str()).collect()
+    }
+
+    // The matching dev user, if the credentials belong to one
+    pub fn authenticate(&self, username: &str, password: &str) -> Option<UserInfo> {
+        self.users
+            .iter()
+            .find(|user| user.username == username && user.password == password)
+            .map(DevUser::user_info)
+    }
+
+    pub fn find(&self, uuid: &str) -> Option<UserInfo> {
+        self.users
+            .iter()
+            .find(|user| user.uuid.to_string() == uuid)
+            .map(DevUser::user_info)
+    }
+}
+
+#[cfg(test)]
+impl DevUsers {
+    pub fn new(users: Vec<DevUser>) -> Self {
+        Self { users }
+    }
+}
@@ -0,0 +1,424 @@
+use actix_web::{web::Data, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use data_encoding::BASE64;
+use p256::ecdsa::signature::Verifier;
+use sha2::{Sha256, Sha512};
+use ssh_key::public::{EcdsaPublicKey, KeyData};
+use ssh_key::PublicKey;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::services::ServiceClients;
+
+// Authorization scheme of signed requests, as sent by CloudAPI clients:
+// Signature keyId="/<login>/keys/<fingerprint>",algorithm="rsa-sha256",headers="date",signature="..."
+pub const SCHEME: &str = "Signature ";
+
+// Headers covered by the signature when the client does not say
+const DEFAULT_HEADERS: &str = "date";
+
+// The parameters of a Signature authorization header
+#[derive(Debug, PartialEq)]
+pub struct SignatureParams {
+    pub login: String,
+    pub fingerprint: String,
+    pub algorithm: String,
+    // Lower-cased names, in signing order
+    pub headers: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+impl SignatureParams {
+    // Parse the value of the Authorization header, including the scheme
+    pub fn parse(header: &str) -> Result<Self, AppError> {
+        let params = header
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| invalid("not a Signature authorization header"))?;
+
+        let mut key_id = None;
+        let mut algorithm = None;
+        let mut headers = None;
+        let mut signature = None;
+        for (name, value) in split_params(params)? {
+            match name {
+                "keyId" => key_id = Some(value),
+                "algorithm" => algorithm = Some(value.to_ascii_lowercase()),
+                "headers" => headers = Some(value),
+                "signature" => signature = Some(value),
+                // Unknown parameters are ignored, as the draft requires
+                _ => {}
+            }
+        }
+
+        let key_id = key_id.ok_or_else(|| invalid("keyId is missing"))?;
+        let (login, fingerprint) = parse_key_id(key_id)?;
+        let algorithm = algorithm.ok_or_else(|| invalid("algorithm is missing"))?;
+        let signature = signature.ok_or_else(|| invalid("signature is missing"))?;
+        let signature = BASE64
+            .decode(signature.as_bytes())
+            .map_err(|_| invalid("signature is not valid base64"))?;
+        let headers = headers
+            .unwrap_or(DEFAULT_HEADERS)
+            .split_whitespace()
+            .map(str::to_ascii_lowercase)
+            .collect();
+
+        Ok(Self {
+            login: login.to_string(),
+            fingerprint: fingerprint.to_string(),
+            algorithm,
+            headers,
+            signature,
+        })
+    }
+}
+
+// Authenticate a signed request: look up the key in UFDS, check the Date
+// header against our clock and verify the signature. The user gets the same
+// roles from their UFDS groups as at a password login.
+pub async fn authenticate(request: &HttpRequest, header: &str) -> Result<AuthenticatedUser, AppError> {
+    let (services, config) = match (request.app_data::<Data<ServiceClients>>(), request.app_data::<Data<Config>>()) {
+        (Some(services), Some(config)) => (services, config),
+        _ => return Err(AppError::AuthError("Signature authentication is not available".to_string())),
+    };
+
+    let params = SignatureParams::parse(header)?;
+    check_date(request, &params.headers, Utc::now(), Duration::seconds(config.signature_max_skew_secs))?;
+    let signing_string = signing_string(request, &params.headers)?;
+
+    let (user, openssh) = services
+        .ufds
+        .find_signing_key(&params.login, &params.fingerprint)
+        .await?
+        .ok_or_else(|| {
+            info!("No SSH key {} for login {}", params.fingerprint, params.login);
+            AppError::AuthError("Unknown signing key".to_string())
+        })?;
+    let key = PublicKey::from_openssh(openssh.trim()).map_err(|e| {
+        warn!("Cannot parse SSH key {} of {}: {}", params.fingerprint, params.login, e);
+        AppError::AuthError("Unknown signing key".to_string())
+    })?;
+
+    verify(key.key_data(), &params.algorithm, signing_string.as_bytes(), &params.signature)?;
+
+    Ok(AuthenticatedUser {
+        id: Uuid::parse_str(&user.uuid).unwrap_or_else(|_| Uuid::nil()),
+        name: user.name,
+        email: user.email,
+        roles: user.roles,
+    })
+}
+
+// The string the client signed: one "name: value" line per signed header
+pub fn signing_string(request: &HttpRequest, headers: &[String]) -> Result<String, AppError> {
+    let target = request
+        .uri()
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or_else(|| request.path());
+
+    let lines = headers
+        .iter()
+        .map(|name| match name.as_str() {
+            "(request-target)" => Ok(format!("(request-target): {} {}", request.method().as_str().to_ascii_lowercase(), target)),
+            // Older clients sign the request line instead
+            "request-line" => Ok(format!("{} {} {:?}", request.method(), target, request.version())),
+            _ => {
+                let values = request
+                    .headers()
+                    .get_all(name.as_str())
+                    .map(|value| value.to_str().map(str::trim))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| invalid(&format!("{} header is not valid text", name)))?;
+                if values.is_empty() {
+                    return Err(invalid(&format!("signed header {} is missing", name)));
+                }
+                Ok(format!("{}: {}", name, values.join(", ")))
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(lines.join("\n"))
+}
+
+// The signature must cover a Date (or x-date) header close to now, so a
+// captured request cannot be replayed later
+fn check_date(request: &HttpRequest, headers: &[String], now: DateTime<Utc>, max_skew: Duration) -> Result<(), AppError> {
+    let name = ["date", "x-date"]
+        .into_iter()
+        .find(|name| headers.iter().any(|h| h == name))
+        .ok_or_else(|| invalid("the Date header must be signed"))?;
+
+    let date = request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .ok_or_else(|| invalid(&format!("{} header is missing or malformed", name)))?;
+
+    if (now - date.with_timezone(&Utc)).abs() > max_skew {
+        info!("Rejected signed request dated {}", date);
+        return Err(AppError::AuthError(format!(
+            "Date header is more than {} seconds from the server clock",
+            max_skew.num_seconds()
+        )));
+    }
+
+    Ok(())
+}
+
+// Check a signature made with the private half of an SSH key. The hash in
+// the algorithm must suit the key: ECDSA keys use the hash of their curve.
+pub fn verify(key: &KeyData, algorithm: &str, data: &[u8], signature: &[u8]) -> Result<(), AppError> {
+    let valid = match (algorithm, key) {
+        ("rsa-sha256" | "rsa-sha512", KeyData::Rsa(rsa_key)) => {
+            let public_key = match (rsa_key.n.as_positive_bytes(), rsa_key.e.as_positive_bytes()) {
+                (Some(n), Some(e)) => {
+                    rsa::RsaPublicKey::new(rsa::BigUint::from_bytes_be(n), rsa::BigUint::from_bytes_be(e))
+                        .map_err(|_| unusable_key())?
+                }
+                _ => return Err(unusable_key()),
+            };
+            let signature = rsa::pkcs1v15::Signature::try_from(signature).map_err(|_| bad_signature())?;
+            if algorithm == "rsa-sha256" {
+                rsa::pkcs1v15::VerifyingKey::<Sha256>::new(public_key).verify(data, &signature).is_ok()
+            } else {
+                rsa::pkcs1v15::VerifyingKey::<Sha512>::new(public_key).verify(data, &signature).is_ok()
+            }
+        }
+        ("ecdsa-sha256", KeyData::Ecdsa(EcdsaPublicKey::NistP256(point))) => {
+            let public_key = p256::ecdsa::VerifyingKey::from_sec1_bytes(point.as_bytes()).map_err(|_| unusable_key())?;
+            // Clients send DER; accept the fixed-size form as well
+            let signature = p256::ecdsa::Signature::from_der(signature)
+                .or_else(|_| p256::ecdsa::Signature::from_slice(signature))
+                .map_err(|_| bad_signature())?;
+            public_key.verify(data, &signature).is_ok()
+        }
+        ("ecdsa-sha384", KeyData::Ecdsa(EcdsaPublicKey::NistP384(point))) => {
+            let public_key = p384::ecdsa::VerifyingKey::from_sec1_bytes(point.as_bytes()).map_err(|_| unusable_key())?;
+            let signature = p384::ecdsa::Signature::from_der(signature)
+                .or_else(|_| p384::ecdsa::Signature::from_slice(signature))
+                .map_err(|_| bad_signature())?;
+            public_key.verify(data, &signature).is_ok()
+        }
+        ("ecdsa-sha512", KeyData::Ecdsa(EcdsaPublicKey::NistP521(point))) => {
+            let public_key = p521::ecdsa::VerifyingKey::from_sec1_bytes(point.as_bytes()).map_err(|_| unusable_key())?;
+            let signature = p521::ecdsa::Signature::from_der(signature)
+                .or_else(|_| p521::ecdsa::Signature::from_slice(signature))
+                .map_err(|_| bad_signature())?;
+            public_key.verify(data, &signature).is_ok()
+        }
+        ("ed25519" | "ed25519-sha512", KeyData::Ed25519(ed25519_key)) => {
+            let public_key = ed25519_dalek::VerifyingKey::from_bytes(&ed25519_key.0).map_err(|_| unusable_key())?;
+            let signature = ed25519_dalek::Signature::from_slice(signature).map_err(|_| bad_signature())?;
+            public_key.verify(data, &signature).is_ok()
+        }
+        _ => {
+            return Err(AppError::AuthError(format!(
+                "Algorithm {} is not supported for {} keys",
+                algorithm,
+                key.algorithm()
+            )))
+        }
+    };
+
+    if !valid {
+        return Err(bad_signature());
+    }
+    Ok(())
+}
+
+// keyId is "/<login>/keys/<fingerprint>"
+fn parse_key_id(key_id: &str) -> Result<(&str, &str), AppError> {
+    match key_id.strip_prefix('/').map(|rest| rest.splitn(3, '/').collect::<Vec<_>>()).as_deref() {
+        Some([login, "keys", fingerprint]) if !login.is_empty() && !fingerprint.is_empty() => Ok((login, fingerprint)),
+        _ => Err(invalid("keyId must be /<login>/keys/<fingerprint>")),
+    }
+}
+
+// Split `name="value",name="value"` into pairs
+fn split_params(params: &str) -> Result<Vec<(&str, &str)>, AppError> {
+    let mut pairs = Vec::new();
+    let mut rest = params.trim();
+
+    while !rest.is_empty() {
+        let (name, after) = rest.split_once('=').ok_or_else(|| invalid("malformed parameters"))?;
+        let after = after.strip_prefix('"').ok_or_else(|| invalid("parameter values must be quoted"))?;
+        let (value, after) = after.split_once('"').ok_or_else(|| invalid("unterminated parameter value"))?;
+        pairs.push((name.trim(), value));
+
+        rest = after.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+
+    Ok(pairs)
+}
+
+fn invalid(reason: &str) -> AppError {
+    AppError::AuthError(format!("Invalid Signature authorization: {}", reason))
+}
+
+fn bad_signature() -> AppError {
+    AppError::AuthError("Invalid request signature".to_string())
+}
+
+fn unusable_key() -> AppError {
+    AppError::AuthError("Signing key cannot be used".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+    use p256::ecdsa::signature::Signer;
+    use ssh_key::public::{Ed25519PublicKey, RsaPublicKey};
+    use ssh_key::Mpint;
+
+    const DATE: &str = "Sun, 18 Oct 2026 12:00:00 GMT";
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc2822(DATE).unwrap().with_timezone(&Utc)
+    }
+
+    fn header(algorithm: &str, headers: &str, signature: &[u8]) -> String {
+        format!(
+            "Signature keyId=\"/alice/keys/SHA256:abc/def\",algorithm=\"{}\",headers=\"{}\",signature=\"{}\"",
+            algorithm,
+            headers,
+            BASE64.encode(signature)
+        )
+    }
+
+    #[test]
+    fn parses_authorization_headers() {
+        let params = SignatureParams::parse(&header("RSA-SHA256", "(request-target) Date", b"sig")).unwrap();
+        assert_eq!(
+            params,
+            SignatureParams {
+                login: "alice".to_string(),
+                fingerprint: "SHA256:abc/def".to_string(),
+                algorithm: "rsa-sha256".to_string(),
+                headers: vec!["(request-target)".to_string(), "date".to_string()],
+                signature: b"sig".to_vec(),
+            }
+        );
+
+        // Only the date is signed by default
+        let params = SignatureParams::parse(
+            "Signature keyId=\"/bob/keys/aa:bb\", algorithm=\"ed25519\", signature=\"c2ln\"",
+        )
+        .unwrap();
+        assert_eq!(params.fingerprint, "aa:bb");
+        assert_eq!(params.headers, vec!["date"]);
+    }
+
+    #[test]
+    fn malformed_authorization_headers_are_rejected() {
+        for bad in [
+            "Bearer abc",
+            "Signature keyId=\"alice/keys/aa\",algorithm=\"rsa-sha256\",signature=\"c2ln\"",
+            "Signature keyId=\"/alice/users/bob/keys/aa\",algorithm=\"rsa-sha256\",signature=\"c2ln\"",
+            "Signature keyId=\"/alice/keys/aa\",signature=\"c2ln\"",
+            "Signature keyId=\"/alice/keys/aa\",algorithm=\"rsa-sha256\"",
+            "Signature keyId=\"/alice/keys/aa\",algorithm=\"rsa-sha256\",signature=\"not base64!\"",
+            "Signature keyId=/alice/keys/aa,algorithm=\"rsa-sha256\",signature=\"c2ln\"",
+            "Signature keyId=\"/alice/keys/aa",
+        ] {
+            assert!(SignatureParams::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn signing_string_covers_the_listed_headers() {
+        let request = TestRequest::post()
+            .uri("/api/vms?limit=5")
+            .insert_header(("Date", DATE))
+            .insert_header(("Host", "adminui.example.com"))
+            .to_http_request();
+        let headers = ["(request-target)", "host", "date"].map(String::from);
+
+        assert_eq!(
+            signing_string(&request, &headers).unwrap(),
+            format!("(request-target): post /api/vms?limit=5\nhost: adminui.example.com\ndate: {}", DATE)
+        );
+        assert_eq!(
+            signing_string(&request, &["request-line".to_string()]).unwrap(),
+            "POST /api/vms?limit=5 HTTP/1.1"
+        );
+        assert!(signing_string(&request, &["x-missing".to_string()]).is_err());
+    }
+
+    #[test]
+    fn the_date_must_be_signed_and_recent() {
+        let skew = Duration::seconds(300);
+        let request = TestRequest::get().insert_header(("Date", DATE)).to_http_request();
+        let date = ["date".to_string()];
+
+        assert!(check_date(&request, &date, now(), skew).is_ok());
+        assert!(check_date(&request, &date, now() - Duration::seconds(299), skew).is_ok());
+        assert!(check_date(&request, &date, now() + Duration::seconds(301), skew).is_err());
+        assert!(check_date(&request, &date, now() - Duration::seconds(301), skew).is_err());
+        assert!(check_date(&request, &["host".to_string()], now(), skew).is_err());
+
+        let undated = TestRequest::get().to_http_request();
+        assert!(check_date(&undated, &date, now(), skew).is_err());
+    }
+
+    #[test]
+    fn rsa_signatures_are_verified() {
+        let private_key = rsa::RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let public_key = private_key.to_public_key();
+        let key = KeyData::Rsa(RsaPublicKey {
+            e: Mpint::from_positive_bytes(&rsa::traits::PublicKeyParts::e(&public_key).to_bytes_be()).unwrap(),
+            n: Mpint::from_positive_bytes(&rsa::traits::PublicKeyParts::n(&public_key).to_bytes_be()).unwrap(),
+        });
+
+        let signer = rsa::pkcs1v15::SigningKey::<Sha256>::new(private_key.clone());
+        let signature: Box<[u8]> = signer.sign(b"date: now").into();
+        assert!(verify(&key, "rsa-sha256", b"date: now", &signature).is_ok());
+        assert!(verify(&key, "rsa-sha256", b"date: later", &signature).is_err());
+        assert!(verify(&key, "rsa-sha512", b"date: now", &signature).is_err());
+
+        let signer = rsa::pkcs1v15::SigningKey::<Sha512>::new(private_key);
+        let signature: Box<[u8]> = signer.sign(b"date: now").into();
+        assert!(verify(&key, "rsa-sha512", b"date: now", &signature).is_ok());
+
+        // SHA-1 is not accepted
+        assert!(verify(&key, "rsa-sha1", b"date: now", &signature).is_err());
+    }
+
+    #[test]
+    fn ecdsa_signatures_are_verified() {
+        let signing_key = p256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let key = KeyData::Ecdsa(EcdsaPublicKey::from_sec1_bytes(point.as_bytes()).unwrap());
+
+        let signature: p256::ecdsa::Signature = signing_key.sign(b"date: now");
+        assert!(verify(&key, "ecdsa-sha256", b"date: now", signature.to_der().as_bytes()).is_ok());
+        assert!(verify(&key, "ecdsa-sha256", b"date: now", &signature.to_bytes()).is_ok());
+        assert!(verify(&key, "ecdsa-sha256", b"date: later", signature.to_der().as_bytes()).is_err());
+        // The hash must match the curve
+        assert!(verify(&key, "ecdsa-sha384", b"date: now", signature.to_der().as_bytes()).is_err());
+
+        let signing_key = p384::ecdsa::SigningKey::from_slice(&[7u8; 48]).unwrap();
+        let point = signing_key.verifying_key().to_encoded_point(false);
+        let key = KeyData::Ecdsa(EcdsaPublicKey::from_sec1_bytes(point.as_bytes()).unwrap());
+        let signature: p384::ecdsa::Signature = signing_key.sign(b"date: now");
+        assert!(verify(&key, "ecdsa-sha384", b"date: now", signature.to_der().as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn ed25519_signatures_are_verified() {
+        let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+        let key = KeyData::Ed25519(Ed25519PublicKey(signing_key.verifying_key().to_bytes()));
+
+        let signature = signing_key.sign(b"date: now").to_bytes();
+        assert!(verify(&key, "ed25519-sha512", b"date: now", &signature).is_ok());
+        assert!(verify(&key, "ed25519", b"date: now", &signature).is_ok());
+        assert!(verify(&key, "ed25519", b"date: later", &signature).is_err());
+        assert!(verify(&key, "rsa-sha256", b"date: now", &signature).is_err());
+    }
+}
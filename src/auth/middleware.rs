@@ -3,15 +3,23 @@ use actix_web::{
     dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
     Error, HttpMessage, HttpRequest, HttpResponse,
 };
+use actix_web::{web::Data, ResponseError};
+use chrono::{DateTime, Utc};
 use futures::future::{ok, Ready};
 use std::future::{Future};
 use std::pin::Pin;
+use std::rc::Rc;
 use std::task::{Context, Poll};
 use uuid::Uuid;
-use crate::auth::{AuthenticatedUser, verify_token};
-use tracing::info;
+use crate::auth::{AuthenticatedUser, Impersonation, SessionToken, verify_token};
+use crate::auth::api_tokens::{self, ApiToken, ApiTokenStore, TOKEN_PREFIX};
+use crate::auth::http_signature;
+use crate::error::AppError;
+use crate::auth::revocation::RevocationStore;
+use tracing::{error, info, info_span, Instrument, Span};
 
-// JWT authentication middleware
+// Authentication middleware: accepts JWT access tokens, API tokens and
+// requests signed with the user's SSH keys
 pub struct AuthMiddleware {
     pub jwt_secret: String,
 }
@@ -36,14 +44,14 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(AuthMiddlewareService { 
-            service,
+            service: Rc::new(service),
             jwt_secret: self.jwt_secret.clone(),
         })
     }
 }
 
 pub struct AuthMiddlewareService<S> {
-    service: S,
+    service: Rc<S>,
     jwt_secret: String,
 }
 
@@ -77,9 +85,67 @@ where
         // Extract authorization header
         if let Some(auth_header) = req.headers().get("Authorization") {
             if let Ok(auth_str) = auth_header.to_str() {
+                if auth_str.starts_with(http_signature::SCHEME) {
+                    // Requests signed with one of the user's SSH keys, as
+                    // CloudAPI clients do
+                    let header = auth_str.to_string();
+                    let service = Rc::clone(&self.service);
+                    
+                    return Box::pin(async move {
+                        match http_signature::authenticate(req.request(), &header).await {
+                            Ok(user) => {
+                                req.extensions_mut().insert(user);
+                                
+                                let res = service.call(req).await?;
+                                Ok(res.map_into_left_body())
+                            }
+                            Err(e) => {
+                                info!("Rejected signed request: {}", e);
+                                let response = e.error_response();
+                                Ok(req.into_response(response).map_into_right_body())
+                            }
+                        }
+                    });
+                }
+                
                 if auth_str.starts_with("Bearer ") {
                     let token = &auth_str[7..]; // Skip "Bearer " prefix
                     
+                    // API tokens for automation are looked up in the store
+                    if token.starts_with(TOKEN_PREFIX) {
+                        let token = token.to_string();
+                        let api_tokens = req.app_data::<Data<ApiTokenStore>>().cloned();
+                        let service = Rc::clone(&self.service);
+                        
+                        return Box::pin(async move {
+                            let found = match api_tokens {
+                                Some(api_tokens) => authenticate_api_token(req.request(), &api_tokens, &token).await,
+                                None => Ok(None),
+                            };
+                            match found {
+                                Ok(Some((api_token, user))) => {
+                                    req.extensions_mut().insert(user);
+                                    req.extensions_mut().insert(api_token);
+                                    
+                                    let res = service.call(req).await?;
+                                    Ok(res.map_into_left_body())
+                                }
+                                Ok(None) => {
+                                    info!("Unknown or expired API token");
+                                    let (request, _) = req.into_parts();
+                                    let response = HttpResponse::Unauthorized()
+                                        .json(serde_json::json!({ "error": "Invalid or expired API token" }));
+                                    Ok(ServiceResponse::new(request, response).map_into_right_body())
+                                }
+                                Err(e) => {
+                                    error!("Failed to check API token: {}", e);
+                                    let response = e.error_response();
+                                    Ok(req.into_response(response).map_into_right_body())
+                                }
+                            }
+                        });
+                    }
+                    
                     // Verify token
                     match verify_token(token, &jwt_secret) {
                         Ok(claims) => {
@@ -91,13 +157,72 @@ where
                                 roles: claims.roles,
                             };
                             
-                            // Add user to request extensions
-                            req.extensions_mut().insert(user);
+                            let session = SessionToken {
+                                jti: claims.jti,
+                                session_id: Uuid::parse_str(&claims.sid).unwrap_or_else(|_| Uuid::nil()),
+                                issued_at: DateTime::<Utc>::from_timestamp(claims.iat, 0).unwrap_or_default(),
+                                expires_at: DateTime::<Utc>::from_timestamp(claims.exp, 0).unwrap_or_default(),
+                            };
+                            
+                            // An operator acting as this user
+                            let impersonation = claims.act.map(|act| Impersonation {
+                                impersonator_id: Uuid::parse_str(&act.sub).unwrap_or_else(|_| Uuid::nil()),
+                                impersonator_name: claims.impersonator.unwrap_or_default(),
+                            });
+                            
+                            let revocations = req.app_data::<Data<RevocationStore>>().cloned();
+                            let service = Rc::clone(&self.service);
                             
-                            // Continue with the request
-                            let fut = self.service.call(req);
                             return Box::pin(async move {
-                                let res = fut.await?;
+                                // Reject tokens revoked by logout or by an administrator
+                                if let Some(revocations) = revocations {
+                                    // Revoking the operator's sessions also ends their impersonation
+                                    let subject = impersonation.as_ref().map_or(user.id, |i| i.impersonator_id);
+                                    match revocations.is_revoked(&session.jti, subject, session.issued_at).await {
+                                        Ok(false) => {}
+                                        Ok(true) => {
+                                            info!("Rejected revoked token for user {}", user.id);
+                                            let (request, _) = req.into_parts();
+                                            let response = HttpResponse::Unauthorized()
+                                                .json(serde_json::json!({ "error": "Token has been revoked" }));
+                                            return Ok(ServiceResponse::new(request, response).map_into_right_body());
+                                        }
+                                        Err(e) => {
+                                            // Fail closed: without the store we cannot tell
+                                            // whether the token is still valid
+                                            error!("Failed to check token revocation: {}", e);
+                                            let response = e.error_response();
+                                            return Ok(req.into_response(response).map_into_right_body());
+                                        }
+                                    }
+                                }
+                                
+                                // Tag everything logged for this request with who is
+                                // really behind it
+                                let span = match &impersonation {
+                                    Some(i) => {
+                                        let span = info_span!(
+                                            "impersonation",
+                                            impersonator = %i.impersonator_name,
+                                            impersonator_id = %i.impersonator_id,
+                                            user = %user.name,
+                                            user_id = %user.id,
+                                        );
+                                        span.in_scope(|| info!("Impersonated request: {} {}", req.method(), req.path()));
+                                        span
+                                    }
+                                    None => Span::none(),
+                                };
+                                
+                                // Add user and token to request extensions
+                                req.extensions_mut().insert(user);
+                                req.extensions_mut().insert(session);
+                                if let Some(impersonation) = impersonation {
+                                    req.extensions_mut().insert(impersonation);
+                                }
+                                
+                                // Continue with the request
+                                let res = service.call(req).instrument(span).await?;
                                 Ok(res.map_into_left_body())
                             });
                         },
@@ -146,4 +271,16 @@ where
 }
 
 // For backward compatibility
-pub type DummyMiddleware = AuthMiddleware;
\ No newline at end of file
+pub type DummyMiddleware = AuthMiddleware;
+// An API token and the user it acts as; None as well if that user no
+// longer exists
+async fn authenticate_api_token(
+    req: &HttpRequest,
+    api_tokens: &ApiTokenStore,
+    token: &str,
+) -> Result<Option<(ApiToken, AuthenticatedUser)>, AppError> {
+    let Some((api_token, user_uuid)) = api_tokens.authenticate(token).await? else {
+        return Ok(None);
+    };
+    Ok(api_tokens::token_user(req, user_uuid).await?.map(|user| (api_token, user)))
+}
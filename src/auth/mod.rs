@@ -5,15 +5,30 @@ use actix_web::{
 use futures::future::{ready, Ready};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::future::Future;
 use std::pin::Pin;
 use chrono::{DateTime, Duration, Utc};
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::config::Config;
 use crate::error::AppError;
+use self::dev_users::DevUsers;
+use self::oidc::OidcIdentity;
+use self::refresh::{RefreshGrant, RefreshTokenStore};
+use self::totp::TotpService;
 
+pub mod api_tokens;
+pub mod dev_users;
+pub mod http_signature;
 pub mod middleware;
+pub mod oidc;
+pub mod rbac;
+pub mod refresh;
+pub mod revocation;
+pub mod throttle;
+pub mod totp;
 
 pub use middleware::DummyMiddleware;
 
@@ -25,6 +40,19 @@ pub struct Claims {
     pub roles: Vec<String>,     // User's roles
     pub exp: i64,               // Expiration time (standard claim)
     pub iat: i64,               // Issued at (standard claim)
+    pub jti: String,            // Token ID, used for revocation (standard claim)
+    pub sid: String,            // Session ID (refresh token family)
+    // Only on impersonation tokens: the operator acting as the subject
+    // (RFC 8693 actor claim), and their name for display and logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<Actor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Actor {
+    pub sub: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,13 +61,99 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
+    // Access token lifetime in seconds
+    pub expires_in: i64,
     pub user: UserInfo,
 }
 
+// First step of a login that needs a second factor. The challenge token is
+// exchanged, together with a TOTP or recovery code, for a LoginResponse.
+#[derive(Debug, Serialize)]
+pub struct LoginChallenge {
+    pub mfa_required: bool,
+    // The user must enroll an authenticator (POST /api/auth/totp/enroll)
+    // before the challenge can be answered
+    pub enrollment_required: bool,
+    pub challenge_token: String,
+    // Challenge lifetime in seconds
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Session(LoginResponse),
+    Challenge(LoginChallenge),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ChallengeRequest {
+    pub challenge_token: String,
+    // A code from the authenticator, or a recovery code
+    #[serde(default)]
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengePurpose {
+    // Answer with a code from an enrolled authenticator
+    Verify,
+    // Enroll an authenticator, then answer with a code from it
+    Enroll,
+}
+
+// Claims of a challenge token. They are signed with a key of their own so a
+// challenge token can never pass as an access token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeClaims {
+    pub sub: String,
+    // The username given at login, for throttling the second step
+    pub login: String,
+    pub name: String,
+    pub email: String,
+    pub roles: Vec<String>,
+    // Roles granted by OIDC provider groups, also included in roles
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub idp_roles: Vec<String>,
+    pub purpose: ChallengePurpose,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+}
+
+const CHALLENGE_AUDIENCE: &str = "totp-challenge";
+
+impl ChallengeClaims {
+    pub fn user_uuid(&self) -> Result<Uuid, AppError> {
+        Uuid::parse_str(&self.sub).map_err(|_| AppError::AuthError("Invalid challenge token".to_string()))
+    }
+
+    // Label for the user's entry in an authenticator app
+    pub fn account(&self) -> &str {
+        if self.email.is_empty() { &self.login } else { &self.email }
+    }
+
+    fn user(self) -> UserInfo {
+        UserInfo {
+            id: self.sub,
+            name: self.name,
+            email: self.email,
+            roles: self.roles,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfo {
     pub id: String,
     pub name: String,
@@ -55,6 +169,62 @@ pub struct AuthenticatedUser {
     pub roles: Vec<String>,
 }
 
+// Set in the request extensions by AuthMiddleware when an operator is acting
+// as another user with an impersonation token. The AuthenticatedUser is then
+// the impersonated user.
+#[derive(Debug, Clone)]
+pub struct Impersonation {
+    pub impersonator_id: Uuid,
+    pub impersonator_name: String,
+}
+
+impl FromRequest for Impersonation {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    // Take Option<Impersonation> in handlers; it is None for normal sessions
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Impersonation>() {
+            Some(impersonation) => ready(Ok(impersonation.clone())),
+            None => ready(Err(ActixError::from(AppError::AuthError("Not impersonating".to_string())))),
+        }
+    }
+}
+
+// An impersonation token and whom it acts as. There is no refresh token; the
+// operator asks for a new one when it expires.
+#[derive(Debug, Serialize)]
+pub struct ImpersonationResponse {
+    pub token: String,
+    pub expires_in: i64,
+    pub user: UserInfo,
+    pub impersonator: UserInfo,
+}
+
+// The access token presented with the current request, inserted into the
+// request extensions by AuthMiddleware alongside the AuthenticatedUser
+#[derive(Debug, Clone)]
+pub struct SessionToken {
+    pub jti: String,
+    pub session_id: Uuid,
+    pub issued_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl FromRequest for SessionToken {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<SessionToken>() {
+            Some(token) => ready(Ok(token.clone())),
+            None => ready(Err(ActixError::from(AppError::AuthError(
+                "User not authenticated".to_string(),
+            )))),
+        }
+    }
+}
+
 impl FromRequest for AuthenticatedUser {
     type Error = ActixError;
     type Future = Ready<Result<Self, Self::Error>>;
@@ -72,61 +242,308 @@ impl FromRequest for AuthenticatedUser {
 
 pub async fn authenticate(
     config: &Config,
+    ufds_service: &crate::services::UfdsService,
+    dev_users: Option<&DevUsers>,
+    refresh_tokens: &RefreshTokenStore,
+    totp: &TotpService,
     username: &str,
     password: &str,
-) -> Result<LoginResponse, AppError> {
-    // Use UFDS service for authentication
-    let ufds_service = crate::services::UfdsService::new(config.ufds_url.clone());
+) -> Result<LoginOutcome, AppError> {
+    // Development users (dev_mode only) are checked before UFDS
+    let result = match dev_users.and_then(|users| users.authenticate(username, password)) {
+        Some(user) => {
+            warn!("Development user {} logged in without UFDS", username);
+            Ok(user)
+        }
+        None => ufds_service
+            .authenticate(username, password)
+            .await
+            .map(|(id, name, email, roles)| UserInfo { id, name, email, roles }),
+    };
+    crate::metrics::record_login(&result);
     
-    // Authenticate against UFDS
-    let (user_id, name, email, roles) = ufds_service.authenticate(username, password).await?;
+    finish_login(config, refresh_tokens, totp, result?, Vec::new(), username).await
+}
+
+// Sign in a user whose identity an OIDC provider has vouched for. The ID
+// token is matched to a UFDS user, whose roles are extended with those the
+// IdP groups grant.
+pub async fn authenticate_oidc(
+    config: &Config,
+    ufds_service: &crate::services::UfdsService,
+    refresh_tokens: &RefreshTokenStore,
+    totp: &TotpService,
+    identity: OidcIdentity,
+) -> Result<LoginOutcome, AppError> {
+    let result = ufds_service
+        .find_login_user(identity.login.as_deref(), identity.email.as_deref())
+        .await
+        .and_then(|user| {
+            user.ok_or_else(|| {
+                warn!(
+                    "No UFDS user for OIDC subject {} (login {:?}, email {:?})",
+                    identity.subject, identity.login, identity.email
+                );
+                AppError::AuthError("No Triton account matches this sign-in".to_string())
+            })
+        });
+    crate::metrics::record_login(&result);
+    let user = result?;
     
     let user_info = UserInfo {
-        id: user_id.clone(),
-        name,
-        email,
-        roles,
+        id: user.uuid,
+        name: user.name,
+        email: user.email,
+        roles: merge_roles(user.roles, &identity.roles),
+    };
+    
+    finish_login(config, refresh_tokens, totp, user_info, identity.roles, &user.login).await
+}
+
+fn merge_roles(mut roles: Vec<String>, extra: &[String]) -> Vec<String> {
+    for role in extra {
+        if !roles.contains(role) {
+            roles.push(role.clone());
+        }
+    }
+    roles
+}
+
+// Start a session for an authenticated user, or a challenge if they still
+// need to present a second factor
+async fn finish_login(
+    config: &Config,
+    refresh_tokens: &RefreshTokenStore,
+    totp: &TotpService,
+    user_info: UserInfo,
+    idp_roles: Vec<String>,
+    login: &str,
+) -> Result<LoginOutcome, AppError> {
+    // Users with an authenticator, or whose roles require one, only get a
+    // challenge until they present a code
+    let user_uuid = Uuid::parse_str(&user_info.id)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid user UUID {}: {}", user_info.id, e)))?;
+    let purpose = if totp.is_enabled(user_uuid).await? {
+        Some(ChallengePurpose::Verify)
+    } else if totp.is_required(&user_info.roles) {
+        Some(ChallengePurpose::Enroll)
+    } else {
+        None
+    };
+    
+    match purpose {
+        Some(purpose) => Ok(LoginOutcome::Challenge(create_challenge(config, user_info, idp_roles, login, purpose)?)),
+        None => Ok(LoginOutcome::Session(start_session(config, refresh_tokens, user_info, idp_roles).await?)),
+    }
+}
+
+// Second login step: check the code against the challenge and start the session
+pub async fn complete_challenge(
+    config: &Config,
+    refresh_tokens: &RefreshTokenStore,
+    totp: &TotpService,
+    challenge: ChallengeClaims,
+    code: &str,
+) -> Result<LoginResponse, AppError> {
+    let user_uuid = challenge.user_uuid()?;
+    
+    match challenge.purpose {
+        ChallengePurpose::Verify => totp.verify(user_uuid, code).await?,
+        ChallengePurpose::Enroll => totp.activate(user_uuid, code).await?,
+    }
+    
+    let idp_roles = challenge.idp_roles.clone();
+    start_session(config, refresh_tokens, challenge.user(), idp_roles).await
+}
+
+async fn start_session(
+    config: &Config,
+    refresh_tokens: &RefreshTokenStore,
+    user_info: UserInfo,
+    idp_roles: Vec<String>,
+) -> Result<LoginResponse, AppError> {
+    // Each login starts a new session (refresh token family)
+    let grant = RefreshGrant {
+        family_id: Uuid::new_v4(),
+        user: user_info,
+        idp_roles,
+    };
+    let refresh_token = refresh_tokens
+        .issue(&grant, Duration::seconds(config.refresh_token_ttl_secs))
+        .await?;
+    
+    issue_tokens(config, grant, refresh_token)
+}
+
+fn create_challenge(
+    config: &Config,
+    user: UserInfo,
+    idp_roles: Vec<String>,
+    login: &str,
+    purpose: ChallengePurpose,
+) -> Result<LoginChallenge, AppError> {
+    let now = Utc::now();
+    let claims = ChallengeClaims {
+        sub: user.id,
+        login: login.to_string(),
+        name: user.name,
+        email: user.email,
+        roles: user.roles,
+        idp_roles,
+        purpose,
+        aud: CHALLENGE_AUDIENCE.to_string(),
+        iat: now.timestamp(),
+        exp: (now + Duration::seconds(config.totp_challenge_ttl_secs)).timestamp(),
+    };
+    
+    let challenge_token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(&challenge_key(&config.jwt_secret)),
+    )
+    .map_err(|e| AppError::AuthError(format!("Error creating challenge: {}", e)))?;
+    
+    Ok(LoginChallenge {
+        mfa_required: true,
+        enrollment_required: purpose == ChallengePurpose::Enroll,
+        challenge_token,
+        expires_in: config.totp_challenge_ttl_secs,
+    })
+}
+
+pub fn verify_challenge(token: &str, secret: &str) -> Result<ChallengeClaims, AppError> {
+    let mut validation = Validation::default();
+    validation.set_audience(&[CHALLENGE_AUDIENCE]);
+    
+    let token_data = decode::<ChallengeClaims>(
+        token,
+        &DecodingKey::from_secret(&challenge_key(secret)),
+        &validation,
+    )
+    .map_err(|e| AppError::AuthError(format!("Invalid challenge token: {}", e)))?;
+    
+    Ok(token_data.claims)
+}
+
+fn challenge_key(secret: &str) -> Vec<u8> {
+    Sha256::digest(format!("{}:{}", CHALLENGE_AUDIENCE, secret).as_bytes()).to_vec()
+}
+
+// Exchange a refresh token for a new access token and a replacement refresh
+// token. The user is looked up again so role changes take effect and deleted
+// accounts lose their sessions.
+pub async fn refresh(
+    config: &Config,
+    ufds_service: &crate::services::UfdsService,
+    dev_users: Option<&DevUsers>,
+    refresh_tokens: &RefreshTokenStore,
+    refresh_token: &str,
+) -> Result<LoginResponse, AppError> {
+    let grant = refresh_tokens.find(refresh_token).await?;
+    
+    let user = match dev_users.and_then(|users| users.find(&grant.user.id)) {
+        Some(user) => Some(user),
+        None => ufds_service
+            .find_user_by_uuid(&grant.user.id)
+            .await?
+            .map(|user| UserInfo {
+                id: user.uuid,
+                name: user.name,
+                email: user.email,
+                roles: user.roles,
+            }),
+    };
+    
+    let Some(mut user) = user else {
+        warn!("User {} no longer exists; ending session {}", grant.user.id, grant.family_id);
+        refresh_tokens.revoke_family(grant.family_id).await?;
+        return Err(AppError::AuthError("Invalid refresh token".to_string()));
     };
+    user.roles = merge_roles(user.roles, &grant.idp_roles);
     
-    // Create JWT token
+    let (refresh_token, grant) = refresh_tokens
+        .rotate(refresh_token, Duration::seconds(config.refresh_token_ttl_secs), &user)
+        .await?;
+    
+    issue_tokens(config, grant, refresh_token)
+}
+
+fn issue_tokens(config: &Config, grant: RefreshGrant, refresh_token: String) -> Result<LoginResponse, AppError> {
     let token = create_token(
         &config.jwt_secret,
-        &user_id,
-        &user_info.name,
-        &user_info.email,
-        &user_info.roles,
-        config.jwt_expiration,
+        &grant.user,
+        grant.family_id,
+        Duration::seconds(config.access_token_ttl_secs),
     )?;
     
     Ok(LoginResponse {
         token,
-        user: user_info,
+        refresh_token,
+        expires_in: config.access_token_ttl_secs,
+        user: grant.user,
     })
 }
 
-fn create_token(
-    secret: &str,
-    user_id: &str,
-    name: &str,
-    email: &str,
-    roles: &[String],
-    expiration_hours: i64,
-) -> Result<String, AppError> {
+// Issue a short-lived token that lets an operator see the admin UI as
+// another user sees it. The token carries no roles, so it is read-only, and
+// names the operator in its act and impersonator claims.
+pub fn impersonate(config: &Config, target: UserInfo, impersonator: &AuthenticatedUser) -> Result<ImpersonationResponse, AppError> {
+    let user = UserInfo { roles: Vec::new(), ..target };
     let now = Utc::now();
-    let expires_at = now + Duration::hours(expiration_hours);
     
     let claims = Claims {
-        sub: user_id.to_string(),
-        name: name.to_string(),
-        email: email.to_string(),
-        roles: roles.to_vec(),
-        iat: now.timestamp(),
-        exp: expires_at.timestamp(),
+        act: Some(Actor { sub: impersonator.id.to_string() }),
+        impersonator: Some(impersonator.name.clone()),
+        ..new_claims(&user, Uuid::new_v4(), now, Duration::seconds(config.impersonation_ttl_secs))
     };
+    let token = sign(&config.jwt_secret, &claims)?;
     
+    warn!(
+        "User {} ({}) is impersonating {} ({}) until {}",
+        impersonator.name, impersonator.id, user.name, user.id, now + Duration::seconds(config.impersonation_ttl_secs)
+    );
+    
+    Ok(ImpersonationResponse {
+        token,
+        expires_in: config.impersonation_ttl_secs,
+        user,
+        impersonator: UserInfo {
+            id: impersonator.id.to_string(),
+            name: impersonator.name.clone(),
+            email: impersonator.email.clone(),
+            roles: impersonator.roles.clone(),
+        },
+    })
+}
+
+fn create_token(
+    secret: &str,
+    user: &UserInfo,
+    session_id: Uuid,
+    ttl: Duration,
+) -> Result<String, AppError> {
+    sign(secret, &new_claims(user, session_id, Utc::now(), ttl))
+}
+
+fn new_claims(user: &UserInfo, session_id: Uuid, now: DateTime<Utc>, ttl: Duration) -> Claims {
+    Claims {
+        sub: user.id.clone(),
+        name: user.name.clone(),
+        email: user.email.clone(),
+        roles: user.roles.clone(),
+        iat: now.timestamp(),
+        exp: (now + ttl).timestamp(),
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id.to_string(),
+        act: None,
+        impersonator: None,
+    }
+}
+
+fn sign(secret: &str, claims: &Claims) -> Result<String, AppError> {
     encode(
         &Header::default(),
-        &claims,
+        claims,
         &EncodingKey::from_secret(secret.as_bytes()),
     )
     .map_err(|e| AppError::AuthError(format!("Error creating token: {}", e)))
@@ -141,4 +558,98 @@ pub fn verify_token(token: &str, secret: &str) -> Result<Claims, AppError> {
     .map_err(|e| AppError::AuthError(format!("Invalid token: {}", e)))?;
     
     Ok(token_data.claims)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, read_body, TestRequest};
+    use actix_web::{get, App, HttpResponse};
+    use crate::auth::middleware::AuthMiddleware;
+    use crate::auth::revocation::RevocationStore;
+
+    fn operator() -> AuthenticatedUser {
+        AuthenticatedUser {
+            id: Uuid::new_v4(),
+            name: "operator".to_string(),
+            email: "operator@example.com".to_string(),
+            roles: vec!["admin".to_string()],
+        }
+    }
+
+    fn customer() -> UserInfo {
+        UserInfo {
+            id: Uuid::new_v4().to_string(),
+            name: "customer".to_string(),
+            email: "customer@example.com".to_string(),
+            roles: vec!["admin".to_string()],
+        }
+    }
+
+    #[get("/whoami")]
+    async fn whoami(user: AuthenticatedUser, impersonation: Option<Impersonation>) -> HttpResponse {
+        match impersonation {
+            Some(i) => HttpResponse::Ok().body(format!("{} as {}", i.impersonator_name, user.name)),
+            None => HttpResponse::Ok().body(user.name),
+        }
+    }
+
+    #[test]
+    fn impersonation_tokens_are_marked_and_read_only() {
+        let config = Config::for_tests();
+        let operator = operator();
+        let customer = customer();
+
+        let response = impersonate(&config, customer.clone(), &operator).unwrap();
+        assert!(response.user.roles.is_empty());
+        assert_eq!(response.impersonator.id, operator.id.to_string());
+        assert_eq!(response.expires_in, config.impersonation_ttl_secs);
+
+        let claims = verify_token(&response.token, &config.jwt_secret).unwrap();
+        assert_eq!(claims.sub, customer.id);
+        assert!(claims.roles.is_empty());
+        assert_eq!(claims.act.unwrap().sub, operator.id.to_string());
+        assert_eq!(claims.impersonator.as_deref(), Some("operator"));
+        assert_eq!(claims.exp - claims.iat, config.impersonation_ttl_secs);
+    }
+
+    #[test]
+    fn access_tokens_carry_no_impersonation_claims() {
+        let token = create_token("secret", &customer(), Uuid::new_v4(), Duration::minutes(5)).unwrap();
+        let claims = verify_token(&token, "secret").unwrap();
+        assert!(claims.act.is_none());
+        assert!(claims.impersonator.is_none());
+    }
+
+    #[actix_web::test]
+    async fn revoking_the_operator_ends_impersonation() {
+        let config = Config::for_tests();
+        let operator = operator();
+        let revocations = Data::new(RevocationStore::new(None));
+        let token = impersonate(&config, customer(), &operator).unwrap().token;
+
+        let app = init_service(
+            App::new()
+                .app_data(revocations.clone())
+                .service(
+                    web::scope("")
+                        .wrap(AuthMiddleware::new(config.jwt_secret.clone()))
+                        .service(whoami),
+                ),
+        )
+        .await;
+        let request = || {
+            TestRequest::get()
+                .uri("/whoami")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let response = call_service(&app, request()).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(read_body(response).await, "operator as customer");
+
+        revocations.revoke_user(operator.id).await.unwrap();
+        let response = call_service(&app, request()).await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,403 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpMessage, ResponseError,
+};
+use futures::future::{ok, Ready};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tracing::info;
+
+use crate::auth::api_tokens::{Access, ApiToken};
+use crate::auth::{AuthenticatedUser, Impersonation};
+use crate::error::AppError;
+
+// Actions that are restricted to particular roles. Read-only endpoints are
+// available to every authenticated user; anything that changes datacenter
+// state requires one of these permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ManageVms,
+    ManageUsers,
+    ManagePackages,
+    ManageImages,
+    ManageServers,
+    ManageNetworks,
+    ViewAudit,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::ManageVms => "manage_vms",
+            Permission::ManageUsers => "manage_users",
+            Permission::ManagePackages => "manage_packages",
+            Permission::ManageImages => "manage_images",
+            Permission::ManageServers => "manage_servers",
+            Permission::ManageNetworks => "manage_networks",
+            Permission::ViewAudit => "view_audit",
+        }
+    }
+
+    // The API token scope needed on top of the role's permission. Managing
+    // infrastructure and accounts needs admin; other changes need write.
+    pub fn token_scope(&self) -> (&'static str, Access) {
+        match self {
+            Permission::ManageVms => ("vms", Access::Write),
+            Permission::ManageUsers => ("users", Access::Admin),
+            Permission::ManagePackages => ("packages", Access::Write),
+            Permission::ManageImages => ("images", Access::Write),
+            Permission::ManageServers => ("servers", Access::Admin),
+            Permission::ManageNetworks => ("networks", Access::Admin),
+            Permission::ViewAudit => ("audit", Access::Read),
+        }
+    }
+}
+
+// Permission matrix: role name -> permissions granted to that role.
+// Roles not listed here (including "readonly") grant no permissions.
+const PERMISSION_MATRIX: &[(&str, &[Permission])] = &[
+    (
+        "admin",
+        &[
+            Permission::ManageVms,
+            Permission::ManageUsers,
+            Permission::ManagePackages,
+            Permission::ManageImages,
+            Permission::ManageServers,
+            Permission::ManageNetworks,
+            Permission::ViewAudit,
+        ],
+    ),
+    (
+        "operator",
+        &[
+            Permission::ManageVms,
+            Permission::ManageImages,
+        ],
+    ),
+    ("readonly", &[]),
+];
+
+pub fn role_has_permission(role: &str, permission: Permission) -> bool {
+    PERMISSION_MATRIX
+        .iter()
+        .filter(|(name, _)| *name == role)
+        .any(|(_, permissions)| permissions.contains(&permission))
+}
+
+impl AuthenticatedUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        self.roles
+            .iter()
+            .any(|role| role_has_permission(role, permission))
+    }
+}
+
+// Middleware that rejects requests from users lacking a permission.
+// Must be nested inside AuthMiddleware so the user is already in the
+// request extensions.
+pub struct RequirePermission {
+    permission: Permission,
+}
+
+impl RequirePermission {
+    pub fn new(permission: Permission) -> Self {
+        Self { permission }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequirePermission
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequirePermissionService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequirePermissionService {
+            service,
+            permission: self.permission,
+        })
+    }
+}
+
+pub struct RequirePermissionService<S> {
+    service: S,
+    permission: Permission,
+}
+
+impl<S, B> Service<ServiceRequest> for RequirePermissionService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Allow OPTIONS requests for CORS
+        if req.method() == actix_web::http::Method::OPTIONS {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                let res = fut.await?;
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        let permitted = req
+            .extensions()
+            .get::<AuthenticatedUser>()
+            .map(|user| user.has_permission(self.permission))
+            .unwrap_or(false);
+        // Requests made with an API token also need the matching scope
+        let (resource, access) = self.permission.token_scope();
+        let scoped = req
+            .extensions()
+            .get::<ApiToken>()
+            .is_none_or(|token| token.allows(resource, access));
+
+        if permitted && scoped {
+            let fut = self.service.call(req);
+            return Box::pin(async move {
+                let res = fut.await?;
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        let message = if permitted {
+            format!("API token scope {}:{} is required for this action", resource, access.as_str())
+        } else {
+            format!("Permission {} is required for this action", self.permission.as_str())
+        };
+        info!("Denied {} {}: {}", req.method(), req.path(), message);
+        let response = req.into_response(AppError::AuthorizationError(message).error_response());
+
+        Box::pin(async move { Ok(response.map_into_right_body()) })
+    }
+}
+
+// Middleware that limits requests made with an API token to the resources
+// its scopes cover: reads need <resource>:read, anything else
+// <resource>:write. Requests with a session token pass through. Routes that
+// only the signed-in user themselves may use (not API tokens, and not an
+// operator impersonating them) use RequireScope::session_only().
+pub struct RequireScope {
+    resource: Option<&'static str>,
+}
+
+impl RequireScope {
+    pub fn new(resource: &'static str) -> Self {
+        Self { resource: Some(resource) }
+    }
+
+    pub fn session_only() -> Self {
+        Self { resource: None }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireScopeService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireScopeService {
+            service,
+            resource: self.resource,
+        })
+    }
+}
+
+pub struct RequireScopeService<S> {
+    service: S,
+    resource: Option<&'static str>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let access = match *req.method() {
+            Method::GET | Method::HEAD | Method::OPTIONS => Access::Read,
+            _ => Access::Write,
+        };
+
+        let denied = req.extensions().get::<ApiToken>().and_then(|token| match self.resource {
+            Some(resource) if token.allows(resource, access) => None,
+            Some(resource) => Some(format!(
+                "API token scope {}:{} is required for this action",
+                resource,
+                access.as_str()
+            )),
+            None => Some("API tokens cannot be used for this action".to_string()),
+        });
+        // Impersonation is read-only and must not leave credentials or
+        // second factors behind on the customer's account
+        let denied = denied.or_else(|| {
+            (self.resource.is_none() && req.extensions().get::<Impersonation>().is_some())
+                .then(|| "Not available while impersonating a user".to_string())
+        });
+
+        match denied {
+            None => {
+                let fut = self.service.call(req);
+                Box::pin(async move {
+                    let res = fut.await?;
+                    Ok(res.map_into_left_body())
+                })
+            }
+            Some(message) => {
+                info!("Denied {} {}: {}", req.method(), req.path(), message);
+                let response = req.into_response(AppError::AuthorizationError(message).error_response());
+                Box::pin(async move { Ok(response.map_into_right_body()) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{delete, get, http::StatusCode, test::{self as atest, TestRequest}, web, App, HttpResponse};
+    use uuid::Uuid;
+
+    #[get("/things")]
+    async fn list_things() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[delete("/things/{id}")]
+    async fn delete_thing() -> HttpResponse {
+        HttpResponse::NoContent().finish()
+    }
+
+    fn user_with_roles(roles: &[&str]) -> AuthenticatedUser {
+        AuthenticatedUser {
+            id: Uuid::nil(),
+            name: "Test User".to_string(),
+            email: "test@example.com".to_string(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+
+    async fn delete_as(roles: &'static [&'static str], permission: Permission) -> StatusCode {
+        let app = atest::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| {
+                    req.extensions_mut().insert(user_with_roles(roles));
+                    srv.call(req)
+                })
+                .service(list_things)
+                .service(
+                    web::scope("")
+                        .wrap(RequirePermission::new(permission))
+                        .service(delete_thing),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::delete().uri("/things/1").to_request();
+        atest::call_service(&app, req).await.status()
+    }
+
+    #[test]
+    fn admin_has_every_permission() {
+        let admin = user_with_roles(&["admin"]);
+        assert!(admin.has_permission(Permission::ManageVms));
+        assert!(admin.has_permission(Permission::ManageServers));
+        assert!(admin.has_permission(Permission::ManageNetworks));
+        assert!(admin.has_permission(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn operator_can_manage_vms_but_not_infrastructure() {
+        let operator = user_with_roles(&["operator"]);
+        assert!(operator.has_permission(Permission::ManageVms));
+        assert!(operator.has_permission(Permission::ManageImages));
+        assert!(!operator.has_permission(Permission::ManageServers));
+        assert!(!operator.has_permission(Permission::ManageNetworks));
+        assert!(!operator.has_permission(Permission::ManageUsers));
+    }
+
+    #[test]
+    fn readonly_and_unknown_roles_have_no_permissions() {
+        for roles in [&["readonly"][..], &["something-else"][..], &[][..]] {
+            let user = user_with_roles(roles);
+            assert!(!user.has_permission(Permission::ManageVms));
+            assert!(!user.has_permission(Permission::ManageServers));
+        }
+    }
+
+    #[actix_web::test]
+    async fn middleware_allows_admin() {
+        assert_eq!(delete_as(&["admin"], Permission::ManageServers).await, StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn middleware_allows_operator_for_granted_permission() {
+        assert_eq!(delete_as(&["operator"], Permission::ManageVms).await, StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn middleware_forbids_operator_for_missing_permission() {
+        assert_eq!(delete_as(&["operator"], Permission::ManageNetworks).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn middleware_forbids_readonly() {
+        assert_eq!(delete_as(&["readonly"], Permission::ManageVms).await, StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn readonly_can_still_read() {
+        let app = atest::init_service(
+            App::new()
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(user_with_roles(&["readonly"]));
+                    srv.call(req)
+                })
+                .service(list_things)
+                .service(
+                    web::scope("")
+                        .wrap(RequirePermission::new(Permission::ManageVms))
+                        .service(delete_thing),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get().uri("/things").to_request();
+        assert_eq!(atest::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+}
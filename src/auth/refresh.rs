@@ -0,0 +1,445 @@
+use chrono::{DateTime, Duration, Utc};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::{PgExecutor, PgPool};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::UserInfo;
+use crate::error::AppError;
+
+// Bytes of randomness in each refresh token (hex encoded on the wire)
+const TOKEN_BYTES: usize = 32;
+
+// Rotating refresh tokens. Each login starts a token family; every refresh
+// marks the presented token used and issues its replacement in the same
+// family. A used token presented again means it was copied, so the whole
+// family is revoked. Only SHA-256 hashes of tokens are kept.
+#[derive(Clone)]
+pub enum RefreshTokenStore {
+    Postgres(PgPool),
+    Memory(Arc<MemoryRefreshTokens>),
+}
+
+#[derive(Default)]
+pub struct MemoryRefreshTokens {
+    // token hash -> record
+    tokens: Mutex<HashMap<String, RefreshRecord>>,
+}
+
+struct RefreshRecord {
+    grant: RefreshGrant,
+    expires_at: DateTime<Utc>,
+    used: bool,
+    revoked: bool,
+}
+
+// What a refresh token entitles its holder to: new access tokens for this
+// user within this session
+#[derive(Debug, Clone)]
+pub struct RefreshGrant {
+    pub family_id: Uuid,
+    pub user: UserInfo,
+    // Roles granted by OIDC provider groups at sign-in, included in
+    // user.roles and re-applied whenever the user is looked up again
+    pub idp_roles: Vec<String>,
+}
+
+#[derive(sqlx::FromRow)]
+struct RefreshRow {
+    family_id: Uuid,
+    user_uuid: Uuid,
+    user_name: String,
+    email: String,
+    roles: Vec<String>,
+    idp_roles: Vec<String>,
+    expires_at: DateTime<Utc>,
+    used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+impl RefreshTokenStore {
+    pub fn new(pool: Option<PgPool>) -> Self {
+        match pool {
+            Some(pool) => RefreshTokenStore::Postgres(pool),
+            None => {
+                info!("No database configured; refresh tokens are kept in memory");
+                RefreshTokenStore::Memory(Arc::new(MemoryRefreshTokens::default()))
+            }
+        }
+    }
+
+    // Issue a refresh token in the given family and return it in plain text
+    pub async fn issue(&self, grant: &RefreshGrant, ttl: Duration) -> Result<String, AppError> {
+        let token = generate_token();
+        let expires_at = Utc::now() + ttl;
+
+        match self {
+            RefreshTokenStore::Postgres(pool) => {
+                insert(pool, &hash_token(&token), grant, expires_at).await?;
+
+                // Expired tokens can never be used again; keep the table small
+                sqlx::query("DELETE FROM refresh_tokens WHERE expires_at < now()")
+                    .execute(pool)
+                    .await?;
+            }
+            RefreshTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                let now = Utc::now();
+                tokens.retain(|_, record| record.expires_at >= now);
+                tokens.insert(
+                    hash_token(&token),
+                    RefreshRecord {
+                        grant: grant.clone(),
+                        expires_at,
+                        used: false,
+                        revoked: false,
+                    },
+                );
+            }
+        }
+
+        Ok(token)
+    }
+
+    // The grant a refresh token was issued with, without using it up. Used to
+    // look the user up again before rotating.
+    pub async fn find(&self, token: &str) -> Result<RefreshGrant, AppError> {
+        let hash = hash_token(token);
+
+        match self {
+            RefreshTokenStore::Postgres(pool) => {
+                let row: Option<RefreshRow> = sqlx::query_as(
+                    "SELECT family_id, user_uuid, user_name, email, roles, idp_roles, expires_at, used_at, revoked_at \
+                     FROM refresh_tokens WHERE token_hash = $1",
+                )
+                .bind(&hash)
+                .fetch_optional(pool)
+                .await?;
+
+                let row = row.ok_or_else(invalid_token)?;
+                Ok(RefreshGrant {
+                    family_id: row.family_id,
+                    user: UserInfo {
+                        id: row.user_uuid.to_string(),
+                        name: row.user_name,
+                        email: row.email,
+                        roles: row.roles,
+                    },
+                    idp_roles: row.idp_roles,
+                })
+            }
+            RefreshTokenStore::Memory(memory) => memory
+                .tokens
+                .lock()
+                .unwrap()
+                .get(&hash)
+                .map(|record| record.grant.clone())
+                .ok_or_else(invalid_token),
+        }
+    }
+
+    // Exchange a refresh token for its replacement, carrying the user's
+    // current details (including any IdP roles). Returns the new token and
+    // the grant it carries.
+    pub async fn rotate(&self, token: &str, ttl: Duration, user: &UserInfo) -> Result<(String, RefreshGrant), AppError> {
+        let hash = hash_token(token);
+        let replacement = generate_token();
+        let expires_at = Utc::now() + ttl;
+
+        let grant = match self {
+            RefreshTokenStore::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+
+                let row: Option<RefreshRow> = sqlx::query_as(
+                    "SELECT family_id, user_uuid, user_name, email, roles, idp_roles, expires_at, used_at, revoked_at \
+                     FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE",
+                )
+                .bind(&hash)
+                .fetch_optional(&mut *tx)
+                .await?;
+
+                let row = row.ok_or_else(invalid_token)?;
+                if row.revoked_at.is_some() {
+                    return Err(invalid_token());
+                }
+                if row.used_at.is_some() {
+                    sqlx::query(
+                        "UPDATE refresh_tokens SET revoked_at = now() \
+                         WHERE family_id = $1 AND revoked_at IS NULL",
+                    )
+                    .bind(row.family_id)
+                    .execute(&mut *tx)
+                    .await?;
+                    tx.commit().await?;
+
+                    return Err(reused_token(row.family_id, &row.user_uuid.to_string()));
+                }
+                if row.expires_at <= Utc::now() {
+                    return Err(expired_token());
+                }
+                if row.user_uuid.to_string() != user.id {
+                    return Err(invalid_token());
+                }
+
+                let grant = RefreshGrant {
+                    family_id: row.family_id,
+                    user: user.clone(),
+                    idp_roles: row.idp_roles,
+                };
+
+                sqlx::query("UPDATE refresh_tokens SET used_at = now() WHERE token_hash = $1")
+                    .bind(&hash)
+                    .execute(&mut *tx)
+                    .await?;
+                insert(&mut *tx, &hash_token(&replacement), &grant, expires_at).await?;
+                tx.commit().await?;
+
+                grant
+            }
+            RefreshTokenStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+
+                let record = tokens.get_mut(&hash).ok_or_else(invalid_token)?;
+                if record.revoked {
+                    return Err(invalid_token());
+                }
+                if record.used {
+                    let family_id = record.grant.family_id;
+                    let user_id = record.grant.user.id.clone();
+                    tokens
+                        .values_mut()
+                        .filter(|r| r.grant.family_id == family_id)
+                        .for_each(|r| r.revoked = true);
+
+                    return Err(reused_token(family_id, &user_id));
+                }
+                if record.expires_at <= Utc::now() {
+                    return Err(expired_token());
+                }
+                if record.grant.user.id != user.id {
+                    return Err(invalid_token());
+                }
+
+                record.used = true;
+                let grant = RefreshGrant {
+                    family_id: record.grant.family_id,
+                    user: user.clone(),
+                    idp_roles: record.grant.idp_roles.clone(),
+                };
+                tokens.insert(
+                    hash_token(&replacement),
+                    RefreshRecord {
+                        grant: grant.clone(),
+                        expires_at,
+                        used: false,
+                        revoked: false,
+                    },
+                );
+
+                grant
+            }
+        };
+
+        Ok((replacement, grant))
+    }
+
+    // End a session: no token in the family can be refreshed again
+    pub async fn revoke_family(&self, family_id: Uuid) -> Result<(), AppError> {
+        match self {
+            RefreshTokenStore::Postgres(pool) => {
+                sqlx::query(
+                    "UPDATE refresh_tokens SET revoked_at = now() \
+                     WHERE family_id = $1 AND revoked_at IS NULL",
+                )
+                .bind(family_id)
+                .execute(pool)
+                .await?;
+            }
+            RefreshTokenStore::Memory(memory) => {
+                memory
+                    .tokens
+                    .lock()
+                    .unwrap()
+                    .values_mut()
+                    .filter(|r| r.grant.family_id == family_id)
+                    .for_each(|r| r.revoked = true);
+            }
+        }
+
+        Ok(())
+    }
+
+    // End every session of a user
+    pub async fn revoke_user(&self, user_uuid: Uuid) -> Result<(), AppError> {
+        match self {
+            RefreshTokenStore::Postgres(pool) => {
+                sqlx::query(
+                    "UPDATE refresh_tokens SET revoked_at = now() \
+                     WHERE user_uuid = $1 AND revoked_at IS NULL",
+                )
+                .bind(user_uuid)
+                .execute(pool)
+                .await?;
+            }
+            RefreshTokenStore::Memory(memory) => {
+                let user_id = user_uuid.to_string();
+                memory
+                    .tokens
+                    .lock()
+                    .unwrap()
+                    .values_mut()
+                    .filter(|r| r.grant.user.id == user_id)
+                    .for_each(|r| r.revoked = true);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn insert<'c>(
+    executor: impl PgExecutor<'c>,
+    token_hash: &str,
+    grant: &RefreshGrant,
+    expires_at: DateTime<Utc>,
+) -> Result<(), AppError> {
+    let user_uuid = Uuid::parse_str(&grant.user.id)
+        .map_err(|e| AppError::InternalServerError(format!("Invalid user UUID {}: {}", grant.user.id, e)))?;
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (token_hash, family_id, user_uuid, user_name, email, roles, idp_roles, expires_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(token_hash)
+    .bind(grant.family_id)
+    .bind(user_uuid)
+    .bind(&grant.user.name)
+    .bind(&grant.user.email)
+    .bind(&grant.user.roles)
+    .bind(&grant.idp_roles)
+    .bind(expires_at)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn invalid_token() -> AppError {
+    AppError::AuthError("Invalid refresh token".to_string())
+}
+
+fn expired_token() -> AppError {
+    AppError::AuthError("Refresh token has expired".to_string())
+}
+
+fn reused_token(family_id: Uuid, user_id: &str) -> AppError {
+    warn!("Refresh token reuse detected for user {}; revoked session {}", user_id, family_id);
+    AppError::AuthError("Refresh token has already been used".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant() -> RefreshGrant {
+        RefreshGrant {
+            family_id: Uuid::new_v4(),
+            user: UserInfo {
+                id: Uuid::new_v4().to_string(),
+                name: "alice".to_string(),
+                email: "alice@example.com".to_string(),
+                roles: vec!["operator".to_string()],
+            },
+            idp_roles: vec![],
+        }
+    }
+
+    #[actix_web::test]
+    async fn rotation_returns_a_new_token_with_the_current_user() {
+        let store = RefreshTokenStore::new(None);
+        let grant = grant();
+        let token = store.issue(&grant, Duration::hours(1)).await.unwrap();
+
+        let mut user = store.find(&token).await.unwrap().user;
+        assert_eq!(user.roles, vec!["operator"]);
+        user.roles = vec!["admin".to_string()];
+
+        let (replacement, rotated) = store.rotate(&token, Duration::hours(1), &user).await.unwrap();
+
+        assert_ne!(replacement, token);
+        assert_eq!(rotated.family_id, grant.family_id);
+        assert_eq!(rotated.user.roles, vec!["admin"]);
+        // The replacement carries the updated roles forward
+        assert_eq!(store.find(&replacement).await.unwrap().user.roles, vec!["admin"]);
+        assert!(store.rotate(&replacement, Duration::hours(1), &user).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn reusing_a_rotated_token_revokes_the_family() {
+        let store = RefreshTokenStore::new(None);
+        let grant = grant();
+        let token = store.issue(&grant, Duration::hours(1)).await.unwrap();
+        let (replacement, _) = store.rotate(&token, Duration::hours(1), &grant.user).await.unwrap();
+
+        let reuse = store.rotate(&token, Duration::hours(1), &grant.user).await;
+        assert!(matches!(reuse, Err(AppError::AuthError(ref m)) if m == "Refresh token has already been used"));
+
+        // The legitimate holder's replacement no longer works either
+        let next = store.rotate(&replacement, Duration::hours(1), &grant.user).await;
+        assert!(matches!(next, Err(AppError::AuthError(ref m)) if m == "Invalid refresh token"));
+    }
+
+    #[actix_web::test]
+    async fn other_families_survive_a_revocation() {
+        let store = RefreshTokenStore::new(None);
+        let first = grant();
+        let second = RefreshGrant {
+            family_id: Uuid::new_v4(),
+            ..first.clone()
+        };
+        let first_token = store.issue(&first, Duration::hours(1)).await.unwrap();
+        let second_token = store.issue(&second, Duration::hours(1)).await.unwrap();
+
+        store.revoke_family(first.family_id).await.unwrap();
+
+        assert!(store.rotate(&first_token, Duration::hours(1), &first.user).await.is_err());
+        assert!(store.rotate(&second_token, Duration::hours(1), &second.user).await.is_ok());
+    }
+
+    #[actix_web::test]
+    async fn expired_and_unknown_tokens_are_rejected() {
+        let store = RefreshTokenStore::new(None);
+        let grant = grant();
+        let token = store.issue(&grant, Duration::seconds(-1)).await.unwrap();
+
+        let expired = store.rotate(&token, Duration::hours(1), &grant.user).await;
+        assert!(matches!(expired, Err(AppError::AuthError(ref m)) if m == "Refresh token has expired"));
+        assert!(store.find("not-a-token").await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn a_token_cannot_be_rotated_for_another_user() {
+        let store = RefreshTokenStore::new(None);
+        let grant = grant();
+        let token = store.issue(&grant, Duration::hours(1)).await.unwrap();
+        let other = UserInfo {
+            id: Uuid::new_v4().to_string(),
+            ..grant.user.clone()
+        };
+
+        assert!(store.rotate(&token, Duration::hours(1), &other).await.is_err());
+    }
+}
@@ -0,0 +1,274 @@
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+// Revoked tokens and per-user session cut-offs. Backed by Postgres when a
+// database is configured so revocations survive restarts and are shared
+// between instances; otherwise kept in memory for this process only.
+#[derive(Clone)]
+pub enum RevocationStore {
+    Postgres(PgPool),
+    Memory(Arc<MemoryRevocations>),
+}
+
+#[derive(Default)]
+pub struct MemoryRevocations {
+    // jti -> token expiry
+    tokens: Mutex<HashMap<String, DateTime<Utc>>>,
+    // user -> tokens issued at or before this instant are revoked
+    users: Mutex<HashMap<Uuid, DateTime<Utc>>>,
+}
+
+impl RevocationStore {
+    pub fn new(pool: Option<PgPool>) -> Self {
+        match pool {
+            Some(pool) => RevocationStore::Postgres(pool),
+            None => {
+                info!("No database configured; token revocations are kept in memory");
+                RevocationStore::Memory(Arc::new(MemoryRevocations::default()))
+            }
+        }
+    }
+    
+    // Revoke a single token until it expires
+    pub async fn revoke_token(&self, jti: &str, user_uuid: Uuid, expires_at: DateTime<Utc>) -> Result<(), AppError> {
+        match self {
+            RevocationStore::Postgres(pool) => {
+                sqlx::query(
+                    "INSERT INTO revoked_tokens (jti, user_uuid, expires_at) VALUES ($1, $2, $3) \
+                     ON CONFLICT (jti) DO NOTHING",
+                )
+                .bind(jti)
+                .bind(user_uuid)
+                .bind(expires_at)
+                .execute(pool)
+                .await?;
+                
+                // Expired tokens are rejected anyway; keep the table small
+                sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < now()")
+                    .execute(pool)
+                    .await?;
+            }
+            RevocationStore::Memory(memory) => {
+                let mut tokens = memory.tokens.lock().unwrap();
+                let now = Utc::now();
+                tokens.retain(|_, expires| *expires >= now);
+                tokens.insert(jti.to_string(), expires_at);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    // Revoke every token issued to a user up to now
+    pub async fn revoke_user(&self, user_uuid: Uuid) -> Result<(), AppError> {
+        let now = Utc::now();
+        
+        match self {
+            RevocationStore::Postgres(pool) => {
+                sqlx::query(
+                    "INSERT INTO user_session_revocations (user_uuid, revoked_before) VALUES ($1, $2) \
+                     ON CONFLICT (user_uuid) DO UPDATE SET revoked_before = EXCLUDED.revoked_before",
+                )
+                .bind(user_uuid)
+                .bind(now)
+                .execute(pool)
+                .await?;
+            }
+            RevocationStore::Memory(memory) => {
+                memory.users.lock().unwrap().insert(user_uuid, now);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn is_revoked(&self, jti: &str, user_uuid: Uuid, issued_at: DateTime<Utc>) -> Result<bool, AppError> {
+        match self {
+            RevocationStore::Postgres(pool) => {
+                let revoked: bool = sqlx::query_scalar(
+                    "SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1) \
+                     OR EXISTS (SELECT 1 FROM user_session_revocations \
+                                WHERE user_uuid = $2 AND revoked_before >= $3)",
+                )
+                .bind(jti)
+                .bind(user_uuid)
+                .bind(issued_at)
+                .fetch_one(pool)
+                .await?;
+                
+                Ok(revoked)
+            }
+            RevocationStore::Memory(memory) => {
+                if memory.tokens.lock().unwrap().contains_key(jti) {
+                    return Ok(true);
+                }
+                
+                Ok(memory
+                    .users
+                    .lock()
+                    .unwrap()
+                    .get(&user_uuid)
+                    .map(|revoked_before| issued_at <= *revoked_before)
+                    .unwrap_or(false))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{get, test, web::Data, App, HttpResponse};
+    use chrono::Duration;
+
+    use crate::auth::middleware::AuthMiddleware;
+    use crate::auth::{create_token, verify_token, UserInfo};
+
+    const SECRET: &str = "test-secret";
+
+    #[get("/whoami")]
+    async fn whoami(user: crate::auth::AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.name)
+    }
+
+    fn user() -> UserInfo {
+        UserInfo {
+            id: Uuid::new_v4().to_string(),
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            roles: vec!["operator".to_string()],
+        }
+    }
+
+    #[actix_web::test]
+    async fn revoked_token_is_reported_until_it_expires() {
+        let store = RevocationStore::new(None);
+        let user_uuid = Uuid::new_v4();
+        let issued_at = Utc::now();
+
+        assert!(!store.is_revoked("jti-1", user_uuid, issued_at).await.unwrap());
+
+        store
+            .revoke_token("jti-1", user_uuid, Utc::now() + Duration::minutes(5))
+            .await
+            .unwrap();
+
+        assert!(store.is_revoked("jti-1", user_uuid, issued_at).await.unwrap());
+        assert!(!store.is_revoked("jti-2", user_uuid, issued_at).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn expired_revocations_are_pruned() {
+        let store = RevocationStore::new(None);
+        let user_uuid = Uuid::new_v4();
+
+        store
+            .revoke_token("old", user_uuid, Utc::now() - Duration::minutes(1))
+            .await
+            .unwrap();
+        store
+            .revoke_token("new", user_uuid, Utc::now() + Duration::minutes(1))
+            .await
+            .unwrap();
+
+        let RevocationStore::Memory(memory) = &store else {
+            panic!("expected the in-memory store");
+        };
+        let tokens = memory.tokens.lock().unwrap();
+        assert!(!tokens.contains_key("old"));
+        assert!(tokens.contains_key("new"));
+    }
+
+    #[actix_web::test]
+    async fn revoking_a_user_revokes_tokens_issued_before() {
+        let store = RevocationStore::new(None);
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let earlier = Utc::now() - Duration::seconds(10);
+
+        store.revoke_user(alice).await.unwrap();
+
+        assert!(store.is_revoked("a", alice, earlier).await.unwrap());
+        assert!(!store.is_revoked("b", bob, earlier).await.unwrap());
+        // Logging in again afterwards yields a working session
+        let later = Utc::now() + Duration::seconds(10);
+        assert!(!store.is_revoked("c", alice, later).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn middleware_rejects_revoked_tokens() {
+        let store = RevocationStore::new(None);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(store.clone()))
+                .service(
+                    actix_web::web::scope("")
+                        .wrap(AuthMiddleware::new(SECRET.to_string()))
+                        .service(whoami),
+                ),
+        )
+        .await;
+
+        let user = user();
+        let token = create_token(SECRET, &user, Uuid::new_v4(), Duration::minutes(5)).unwrap();
+        let request = || {
+            test::TestRequest::get()
+                .uri("/whoami")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, request()).await;
+        assert_eq!(response.status(), 200);
+
+        let claims = verify_token(&token, SECRET).unwrap();
+        store
+            .revoke_token(
+                &claims.jti,
+                Uuid::parse_str(&claims.sub).unwrap(),
+                DateTime::<Utc>::from_timestamp(claims.exp, 0).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let response = test::call_service(&app, request()).await;
+        assert_eq!(response.status(), 401);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["error"], "Token has been revoked");
+    }
+
+    #[actix_web::test]
+    async fn middleware_rejects_tokens_of_a_revoked_user() {
+        let store = RevocationStore::new(None);
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(store.clone()))
+                .service(
+                    actix_web::web::scope("")
+                        .wrap(AuthMiddleware::new(SECRET.to_string()))
+                        .service(whoami),
+                ),
+        )
+        .await;
+
+        let user = user();
+        let token = create_token(SECRET, &user, Uuid::new_v4(), Duration::minutes(5)).unwrap();
+        store.revoke_user(Uuid::parse_str(&user.id).unwrap()).await.unwrap();
+
+        let response = test::call_service(
+            &app,
+            test::TestRequest::get()
+                .uri("/whoami")
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,472 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::error::AppError;
+
+// Tracked clients and usernames with no recent failures are dropped once
+// this many are held
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct LoginThrottleConfig {
+    pub window: Duration,
+    // Failures allowed within the window; 0 means unlimited
+    pub max_failures_per_ip: u32,
+    pub max_failures_per_user: u32,
+    // Consecutive failures that lock a username; 0 disables lockout
+    pub lockout_threshold: u32,
+    pub lockout: Duration,
+}
+
+impl LoginThrottleConfig {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            window: Duration::from_secs(config.login_window_secs),
+            max_failures_per_ip: config.login_max_failures_per_ip,
+            max_failures_per_user: config.login_max_failures_per_user,
+            lockout_threshold: config.login_lockout_threshold,
+            lockout: Duration::from_secs(config.login_lockout_secs),
+        }
+    }
+}
+
+// Brute-force protection for logins. Failed attempts are counted per client
+// IP and per username over a sliding window; once either limit is reached,
+// further attempts are refused with 429 until the oldest failure leaves the
+// window. A username that keeps failing is locked for a fixed time, which
+// only an admin unlock clears. Failures in a row are forgotten after a
+// success or after a quiet period as long as the lockout itself.
+//
+// check() counts an attempt as a failure up front, under the same lock as
+// the limit check, so concurrent requests cannot all pass it before any of
+// them has failed. The returned LoginAttempt settles it: failed() keeps the
+// count, succeeded() clears it, and dropping it unsettled (an upstream error,
+// a pending second factor) takes the attempt back.
+//
+// State is kept in memory, so each instance counts the attempts it sees.
+pub struct LoginThrottle {
+    config: LoginThrottleConfig,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    ips: HashMap<IpAddr, VecDeque<Instant>>,
+    users: HashMap<String, UserFailures>,
+}
+
+#[derive(Default)]
+struct UserFailures {
+    recent: VecDeque<Instant>,
+    consecutive: u32,
+    // Attempts checked but not settled yet
+    pending: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+impl UserFailures {
+    fn is_idle(&self, now: Instant, lockout: Duration) -> bool {
+        let failing = self.last_failure.is_some_and(|at| now.duration_since(at) < lockout);
+        let locked = self.locked_until.is_some_and(|until| until > now);
+        self.recent.is_empty() && self.pending == 0 && !failing && !locked
+    }
+}
+
+// An attempt let through by LoginThrottle::check and counted as a failure
+// until it is settled; dropping it unsettled takes it back
+#[must_use]
+pub struct LoginAttempt<'a> {
+    throttle: &'a LoginThrottle,
+    ip: Option<IpAddr>,
+    username: String,
+    at: Instant,
+    settled: bool,
+}
+
+impl LoginAttempt<'_> {
+    // The credentials or code were rejected
+    pub fn failed(mut self) {
+        self.settled = true;
+        self.throttle.record_failure(&self);
+    }
+
+    // The user got a session. The username's failures are reset; the
+    // client's are kept so one valid account cannot be used to reset an
+    // attacker's count.
+    pub fn succeeded(mut self) {
+        self.settled = true;
+        self.throttle.release(&self);
+        self.throttle.state.lock().unwrap().users.remove(&normalize(&self.username));
+    }
+}
+
+impl Drop for LoginAttempt<'_> {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.throttle.release(self);
+        }
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(config: LoginThrottleConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(ThrottleState::default()),
+        }
+    }
+
+    // Refuse the attempt if the client or the username is over its limit,
+    // otherwise count it until the returned attempt is settled
+    pub fn check(&self, ip: Option<IpAddr>, username: &str) -> Result<LoginAttempt<'_>, AppError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        if state.ips.len() + state.users.len() > PRUNE_THRESHOLD {
+            self.prune_idle(&mut state, now);
+        }
+
+        if let Some(user) = state.users.get_mut(&normalize(username)) {
+            if let Some(until) = user.locked_until.filter(|until| *until > now) {
+                crate::metrics::record_login_blocked("locked");
+                warn!("Refused login for locked account {} from {}", username, describe(ip));
+                return Err(too_many("Account is temporarily locked", until - now));
+            }
+
+            prune(&mut user.recent, now, self.config.window);
+            if let Some(wait) = self.over_limit(&user.recent, self.config.max_failures_per_user, now) {
+                crate::metrics::record_login_blocked("throttled");
+                warn!("Throttled login for {} from {}", username, describe(ip));
+                return Err(too_many("Too many failed login attempts", wait));
+            }
+
+            // Attempts still in flight could take the username to the
+            // lockout; wait for them to settle
+            let threshold = self.config.lockout_threshold;
+            if threshold > 0 && user.consecutive + user.pending >= threshold {
+                crate::metrics::record_login_blocked("throttled");
+                warn!("Throttled login for {} from {}", username, describe(ip));
+                return Err(too_many("Too many failed login attempts", Duration::from_secs(1)));
+            }
+        }
+
+        if let Some(failures) = ip.and_then(|ip| state.ips.get_mut(&ip)) {
+            prune(failures, now, self.config.window);
+            if let Some(wait) = self.over_limit(failures, self.config.max_failures_per_ip, now) {
+                crate::metrics::record_login_blocked("throttled");
+                warn!("Throttled login for {} from {}", username, describe(ip));
+                return Err(too_many("Too many failed login attempts", wait));
+            }
+        }
+
+        if let Some(ip) = ip {
+            state.ips.entry(ip).or_default().push_back(now);
+        }
+        let user = state.users.entry(normalize(username)).or_default();
+        user.recent.push_back(now);
+        user.pending += 1;
+
+        Ok(LoginAttempt {
+            throttle: self,
+            ip,
+            username: username.to_string(),
+            at: now,
+            settled: false,
+        })
+    }
+
+    // Keep the failure counted by check
+    fn record_failure(&self, attempt: &LoginAttempt) {
+        let (ip, username) = (attempt.ip, attempt.username.as_str());
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        let lockout = self.config.lockout;
+        let user = state.users.entry(normalize(username)).or_default();
+        user.pending = user.pending.saturating_sub(1);
+        if user.last_failure.is_some_and(|at| now.duration_since(at) >= lockout) {
+            user.consecutive = 0;
+        }
+        user.consecutive += 1;
+        user.last_failure = Some(now);
+
+        warn!(
+            "Failed login for {} from {} ({} in a row)",
+            username, describe(ip), user.consecutive
+        );
+
+        if self.config.lockout_threshold > 0 && user.consecutive >= self.config.lockout_threshold {
+            user.locked_until = Some(now + self.config.lockout);
+            user.consecutive = 0;
+            crate::metrics::record_lockout();
+            warn!(
+                "Locked account {} for {}s after {} consecutive failed logins",
+                username, self.config.lockout.as_secs(), self.config.lockout_threshold
+            );
+        }
+    }
+
+    // Take back the failure counted by check
+    fn release(&self, attempt: &LoginAttempt) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(failures) = attempt.ip.and_then(|ip| state.ips.get_mut(&ip)) {
+            forget(failures, attempt.at);
+            if failures.is_empty() {
+                state.ips.remove(&attempt.ip.unwrap());
+            }
+        }
+
+        let key = normalize(&attempt.username);
+        if let Some(user) = state.users.get_mut(&key) {
+            forget(&mut user.recent, attempt.at);
+            user.pending = user.pending.saturating_sub(1);
+            if user.is_idle(Instant::now(), self.config.lockout) {
+                state.users.remove(&key);
+            }
+        }
+    }
+
+    // Clear a username's lock and failures
+    pub fn unlock(&self, username: &str) {
+        let now = Instant::now();
+        let removed = self.state.lock().unwrap().users.remove(&normalize(username));
+        let was_locked = removed
+            .and_then(|user| user.locked_until)
+            .map(|until| until > now)
+            .unwrap_or(false);
+
+        info!("Cleared login failures for {} (was locked: {})", username, was_locked);
+    }
+
+    // Time until another attempt is allowed, if the limit has been reached
+    fn over_limit(&self, failures: &VecDeque<Instant>, limit: u32, now: Instant) -> Option<Duration> {
+        if limit == 0 || failures.len() < limit as usize {
+            return None;
+        }
+
+        // Attempts open up again as the oldest failures leave the window
+        let reopens = failures[failures.len() - limit as usize] + self.config.window;
+        Some(reopens.saturating_duration_since(now))
+    }
+
+    fn prune_idle(&self, state: &mut ThrottleState, now: Instant) {
+        let (window, lockout) = (self.config.window, self.config.lockout);
+        state.ips.retain(|_, failures| {
+            prune(failures, now, window);
+            !failures.is_empty()
+        });
+        state.users.retain(|_, user| {
+            prune(&mut user.recent, now, window);
+            !user.is_idle(now, lockout)
+        });
+    }
+}
+
+// Drop failures that have left the window
+fn prune(failures: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while failures.front().is_some_and(|at| now.duration_since(*at) >= window) {
+        failures.pop_front();
+    }
+}
+
+// Remove one attempt, unless it has already left the window
+fn forget(failures: &mut VecDeque<Instant>, at: Instant) {
+    if let Some(index) = failures.iter().position(|t| *t == at) {
+        failures.remove(index);
+    }
+}
+
+// Logins are matched case-insensitively so case variants share one count
+fn normalize(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+fn describe(ip: Option<IpAddr>) -> String {
+    ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown address".to_string())
+}
+
+fn too_many(message: &str, wait: Duration) -> AppError {
+    // Round up so clients never retry a moment too early
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+
+    AppError::TooManyRequests {
+        message: message.to_string(),
+        retry_after: retry_after.max(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(per_ip: u32, per_user: u32, lockout_threshold: u32) -> LoginThrottle {
+        LoginThrottle::new(LoginThrottleConfig {
+            window: Duration::from_secs(60),
+            max_failures_per_ip: per_ip,
+            max_failures_per_user: per_user,
+            lockout_threshold,
+            lockout: Duration::from_secs(900),
+        })
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::from([192, 0, 2, last]))
+    }
+
+    fn fail(throttle: &LoginThrottle, ip: Option<IpAddr>, username: &str) {
+        throttle.check(ip, username).unwrap().failed();
+    }
+
+    // Seconds until the next attempt, or None if it is allowed
+    fn retry_after(throttle: &LoginThrottle, ip: Option<IpAddr>, username: &str) -> Option<u64> {
+        match throttle.check(ip, username) {
+            Ok(_) => None,
+            Err(AppError::TooManyRequests { retry_after, .. }) => Some(retry_after),
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    // Move every recorded time into the past, as if `by` had elapsed
+    fn rewind(throttle: &LoginThrottle, by: Duration) {
+        let mut state = throttle.state.lock().unwrap();
+        for failures in state.ips.values_mut() {
+            failures.iter_mut().for_each(|at| *at -= by);
+        }
+        for user in state.users.values_mut() {
+            user.recent.iter_mut().for_each(|at| *at -= by);
+            user.last_failure = user.last_failure.map(|at| at - by);
+            user.locked_until = user.locked_until.map(|at| at - by);
+        }
+    }
+
+    #[test]
+    fn failures_slide_out_of_the_window() {
+        let throttle = throttle(0, 3, 0);
+
+        fail(&throttle, ip(1), "alice");
+        rewind(&throttle, Duration::from_secs(30));
+        fail(&throttle, ip(2), "alice");
+        fail(&throttle, ip(3), "Alice");
+
+        // The oldest failure leaves the window in 30 seconds
+        let wait = retry_after(&throttle, ip(4), "alice").unwrap();
+        assert!((29..=30).contains(&wait), "{}", wait);
+        assert_eq!(retry_after(&throttle, ip(4), "bob"), None);
+
+        rewind(&throttle, Duration::from_secs(31));
+        fail(&throttle, ip(4), "alice");
+        assert!(retry_after(&throttle, ip(5), "alice").is_some());
+    }
+
+    #[test]
+    fn client_failures_are_limited_across_usernames() {
+        let throttle = throttle(2, 0, 0);
+
+        fail(&throttle, ip(1), "alice");
+        fail(&throttle, ip(1), "bob");
+
+        assert!(retry_after(&throttle, ip(1), "carol").is_some());
+        assert_eq!(retry_after(&throttle, ip(2), "carol"), None);
+    }
+
+    #[test]
+    fn repeated_failures_lock_the_account() {
+        let throttle = throttle(0, 0, 3);
+
+        for _ in 0..3 {
+            fail(&throttle, ip(1), "alice");
+        }
+
+        match throttle.check(ip(2), "alice") {
+            Err(AppError::TooManyRequests { message, retry_after }) => {
+                assert_eq!(message, "Account is temporarily locked");
+                assert!((899..=900).contains(&retry_after), "{}", retry_after);
+            }
+            _ => panic!("account was not locked"),
+        }
+
+        rewind(&throttle, Duration::from_secs(901));
+        assert_eq!(retry_after(&throttle, ip(2), "alice"), None);
+    }
+
+    #[test]
+    fn success_resets_the_username_but_not_the_client() {
+        let throttle = throttle(3, 3, 3);
+
+        fail(&throttle, ip(1), "alice");
+        fail(&throttle, ip(1), "alice");
+        throttle.check(ip(1), "alice").unwrap().succeeded();
+
+        // The username starts over, so two more failures neither throttle
+        // nor lock it
+        fail(&throttle, ip(2), "alice");
+        fail(&throttle, ip(2), "alice");
+        assert_eq!(retry_after(&throttle, ip(3), "alice"), None);
+
+        // The client's two failures still count
+        fail(&throttle, ip(1), "bob");
+        assert!(retry_after(&throttle, ip(1), "carol").is_some());
+    }
+
+    #[test]
+    fn admin_unlock_clears_the_lock() {
+        let throttle = throttle(0, 0, 2);
+
+        fail(&throttle, ip(1), "alice");
+        fail(&throttle, ip(1), "alice");
+        assert!(retry_after(&throttle, ip(1), "alice").is_some());
+
+        throttle.unlock("ALICE");
+        assert_eq!(retry_after(&throttle, ip(1), "alice"), None);
+    }
+
+    #[test]
+    fn concurrent_attempts_are_counted_before_they_fail() {
+        let throttle = throttle(0, 2, 0);
+
+        let first = throttle.check(ip(1), "alice").unwrap();
+        let second = throttle.check(ip(2), "alice").unwrap();
+        assert!(retry_after(&throttle, ip(3), "alice").is_some());
+
+        // An attempt that ends without a verdict is taken back
+        drop(first);
+        let third = throttle.check(ip(3), "alice").unwrap();
+        second.failed();
+        third.failed();
+        assert!(retry_after(&throttle, ip(4), "alice").is_some());
+    }
+
+    #[test]
+    fn attempts_in_flight_cannot_overshoot_the_lockout() {
+        let throttle = throttle(0, 0, 2);
+
+        fail(&throttle, ip(1), "alice");
+        let pending = throttle.check(ip(1), "alice").unwrap();
+        assert_eq!(retry_after(&throttle, ip(2), "alice"), Some(1));
+
+        pending.failed();
+        match throttle.check(ip(2), "alice") {
+            Err(AppError::TooManyRequests { message, .. }) => assert_eq!(message, "Account is temporarily locked"),
+            _ => panic!("account was not locked"),
+        };
+    }
+
+    #[test]
+    fn released_attempts_leave_no_state() {
+        let throttle = throttle(1, 1, 1);
+
+        for _ in 0..5 {
+            drop(throttle.check(ip(1), "alice").unwrap());
+        }
+
+        let state = throttle.state.lock().unwrap();
+        assert!(state.ips.is_empty());
+        assert!(state.users.is_empty());
+    }
+}
@@ -0,0 +1,424 @@
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+mod store;
+
+pub use store::TotpStore;
+use store::TotpCredential;
+
+// RFC 6238 parameters understood by every authenticator app
+const STEP_SECS: i64 = 30;
+const DIGITS: u32 = 6;
+// 160-bit secrets, as RFC 4226 recommends
+const SECRET_BYTES: usize = 20;
+// Codes from one step either side of now are accepted, for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+const NONCE_BYTES: usize = 12;
+
+// What a user needs to set up an authenticator. Shown once; only the
+// encrypted secret and hashed recovery codes are kept.
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    // Base32, for typing into an authenticator by hand
+    pub secret: String,
+    pub otpauth_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+// TOTP enrollment and verification. Secrets are encrypted with AES-256-GCM
+// before they reach the store.
+pub struct TotpService {
+    store: TotpStore,
+    cipher: Aes256Gcm,
+    issuer: String,
+    required_roles: Vec<String>,
+}
+
+impl TotpService {
+    pub fn new(config: &Config, store: TotpStore) -> Result<Self> {
+        let key = config
+            .totp_encryption_key
+            .as_deref()
+            .ok_or_else(|| anyhow!("TOTP_ENCRYPTION_KEY is not set"))?;
+        let key = hex::decode(key.trim()).map_err(|e| anyhow!("Invalid TOTP_ENCRYPTION_KEY: {}", e))?;
+        if key.len() != 32 {
+            return Err(anyhow!("TOTP_ENCRYPTION_KEY must be 32 bytes (64 hex characters)"));
+        }
+
+        Ok(Self {
+            store,
+            cipher: Aes256Gcm::new_from_slice(&key).map_err(|e| anyhow!("Invalid TOTP encryption key: {}", e))?,
+            issuer: config.totp_issuer.clone(),
+            required_roles: config.totp_required_roles.clone(),
+        })
+    }
+
+    // Whether any of these roles must use a second factor
+    pub fn is_required(&self, roles: &[String]) -> bool {
+        roles.iter().any(|role| self.required_roles.contains(role))
+    }
+
+    pub async fn is_enabled(&self, user_uuid: Uuid) -> Result<bool, AppError> {
+        Ok(self.store.get(user_uuid).await?.is_some_and(|credential| credential.enabled))
+    }
+
+    // Generate a new secret and recovery codes. The enrollment only takes
+    // effect once `activate` sees a code made from the new secret.
+    pub async fn enroll(&self, user_uuid: Uuid, account: &str) -> Result<TotpEnrollment, AppError> {
+        let mut secret = [0u8; SECRET_BYTES];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| generate_recovery_code()).collect();
+
+        let mut nonce = [0u8; NONCE_BYTES];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let secret_ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), secret.as_ref())
+            .map_err(|_| AppError::InternalServerError("Failed to encrypt TOTP secret".to_string()))?;
+
+        let credential = TotpCredential {
+            secret_ciphertext,
+            secret_nonce: nonce.to_vec(),
+            recovery_codes: recovery_codes.iter().map(|code| hash_recovery_code(code)).collect(),
+            last_used_step: 0,
+            enabled: false,
+        };
+        if !self.store.save_pending(user_uuid, &credential).await? {
+            return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string()));
+        }
+
+        info!("Started TOTP enrollment for user {}", user_uuid);
+
+        let secret = BASE32_NOPAD.encode(&secret);
+        Ok(TotpEnrollment {
+            otpauth_uri: otpauth_uri(&self.issuer, account, &secret),
+            secret,
+            recovery_codes,
+        })
+    }
+
+    // Finish an enrollment with a code from the authenticator
+    pub async fn activate(&self, user_uuid: Uuid, code: &str) -> Result<(), AppError> {
+        let credential = self
+            .store
+            .get(user_uuid)
+            .await?
+            .ok_or_else(|| AppError::BadRequest("No two-factor enrollment in progress".to_string()))?;
+        if credential.enabled {
+            return Err(AppError::Conflict("Two-factor authentication is already enabled".to_string()));
+        }
+
+        self.check_code(user_uuid, &credential, code).await?;
+        self.store.enable(user_uuid).await?;
+
+        info!("Enabled TOTP for user {}", user_uuid);
+        Ok(())
+    }
+
+    // Check the second factor of an enrolled user: a current code from the
+    // authenticator, or one of the recovery codes
+    pub async fn verify(&self, user_uuid: Uuid, code: &str) -> Result<(), AppError> {
+        let credential = self
+            .store
+            .get(user_uuid)
+            .await?
+            .filter(|credential| credential.enabled)
+            .ok_or_else(invalid_code)?;
+
+        if is_totp_code(code) {
+            return self.check_code(user_uuid, &credential, code).await;
+        }
+
+        if self.store.use_recovery_code(user_uuid, &hash_recovery_code(code)).await? {
+            warn!(
+                "User {} signed in with a recovery code; {} left",
+                user_uuid,
+                credential.recovery_codes.len().saturating_sub(1)
+            );
+            return Ok(());
+        }
+
+        Err(invalid_code())
+    }
+
+    // Remove a user's second factor so they enroll again at next login
+    pub async fn reset(&self, user_uuid: Uuid) -> Result<(), AppError> {
+        if !self.store.delete(user_uuid).await? {
+            return Err(AppError::NotFound(format!("User {} has no two-factor enrollment", user_uuid)));
+        }
+
+        info!("Reset TOTP for user {}", user_uuid);
+        Ok(())
+    }
+
+    async fn check_code(&self, user_uuid: Uuid, credential: &TotpCredential, code: &str) -> Result<(), AppError> {
+        if !is_totp_code(code) {
+            return Err(invalid_code());
+        }
+
+        let secret = self
+            .cipher
+            .decrypt(Nonce::from_slice(&credential.secret_nonce), credential.secret_ciphertext.as_ref())
+            .map_err(|_| AppError::InternalServerError("Failed to decrypt TOTP secret".to_string()))?;
+
+        let current = Utc::now().timestamp().div_euclid(STEP_SECS);
+        match matching_step(&secret, code, current) {
+            // A code is only good once, even within its time step
+            Some(step) if self.store.use_step(user_uuid, step).await? => Ok(()),
+            _ => Err(invalid_code()),
+        }
+    }
+}
+
+// RFC 4226 HOTP value for a counter (here the RFC 6238 time step)
+fn hotp(secret: &[u8], counter: i64) -> u32 {
+    let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([digest[offset], digest[offset + 1], digest[offset + 2], digest[offset + 3]]);
+    (value & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+// The time step within the allowed drift of `current` that produced the code
+fn matching_step(secret: &[u8], code: &str, current: i64) -> Option<i64> {
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .find(|step| format_code(hotp(secret, *step)) == code.trim())
+}
+
+fn format_code(value: u32) -> String {
+    format!("{:0width$}", value, width = DIGITS as usize)
+}
+
+fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == DIGITS as usize && code.chars().all(|c| c.is_ascii_digit())
+}
+
+// Key URI understood by authenticator apps, usually shown as a QR code
+fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        secret,
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// 40 random bits as eight base32 characters, grouped for reading: "abcd-efgh"
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+    format!("{}-{}", &code[..4], &code[4..])
+}
+
+// Recovery codes are compared ignoring case, spaces and dashes
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+fn invalid_code() -> AppError {
+    AppError::AuthError("Invalid authentication code".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::{get, web::Data, App, HttpResponse};
+
+    use crate::auth::middleware::AuthMiddleware;
+    use crate::auth::revocation::RevocationStore;
+    use crate::auth::{create_challenge, verify_challenge, ChallengePurpose, UserInfo};
+
+    // The RFC 6238 SHA-1 test secret
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    fn config() -> Config {
+        Config {
+            totp_encryption_key: Some("00".repeat(32)),
+            ..Config::for_tests()
+        }
+    }
+
+    fn service() -> TotpService {
+        TotpService::new(&config(), TotpStore::new(None)).unwrap()
+    }
+
+    fn current_step() -> i64 {
+        Utc::now().timestamp().div_euclid(STEP_SECS)
+    }
+
+    // Enroll and activate a user, returning their secret and recovery codes
+    async fn enrolled(totp: &TotpService, user_uuid: Uuid) -> (Vec<u8>, Vec<String>) {
+        let enrollment = totp.enroll(user_uuid, "alice").await.unwrap();
+        let secret = BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap();
+        totp.activate(user_uuid, &format_code(hotp(&secret, current_step() - 1)))
+            .await
+            .unwrap();
+        (secret, enrollment.recovery_codes)
+    }
+
+    #[test]
+    fn codes_match_the_rfc_6238_vectors() {
+        // Appendix B, truncated from eight digits to six
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+
+        for (time, code) in vectors {
+            assert_eq!(format_code(hotp(RFC_SECRET, time / STEP_SECS)), code, "T = {}", time);
+        }
+    }
+
+    #[test]
+    fn codes_from_adjacent_steps_are_accepted() {
+        let current = 1111111111 / STEP_SECS;
+        let code = |step: i64| format_code(hotp(RFC_SECRET, step));
+
+        assert_eq!(matching_step(RFC_SECRET, &code(current), current), Some(current));
+        assert_eq!(matching_step(RFC_SECRET, &code(current - 1), current), Some(current - 1));
+        assert_eq!(matching_step(RFC_SECRET, &code(current + 1), current), Some(current + 1));
+        assert_eq!(matching_step(RFC_SECRET, &code(current - 2), current), None);
+        assert_eq!(matching_step(RFC_SECRET, &code(current + 2), current), None);
+    }
+
+    #[actix_web::test]
+    async fn codes_are_accepted_once() {
+        let totp = service();
+        let user_uuid = Uuid::new_v4();
+        let (secret, _) = enrolled(&totp, user_uuid).await;
+
+        let code = format_code(hotp(&secret, current_step() + 1));
+        totp.verify(user_uuid, &code).await.unwrap();
+        assert!(matches!(totp.verify(user_uuid, &code).await, Err(AppError::AuthError(_))));
+
+        // Nor is an older code once a later one has been used
+        let earlier = format_code(hotp(&secret, current_step() - 1));
+        assert!(matches!(totp.verify(user_uuid, &earlier).await, Err(AppError::AuthError(_))));
+    }
+
+    #[actix_web::test]
+    async fn recovery_codes_are_accepted_once() {
+        let totp = service();
+        let user_uuid = Uuid::new_v4();
+        let (_, recovery_codes) = enrolled(&totp, user_uuid).await;
+        assert_eq!(recovery_codes.len(), RECOVERY_CODES);
+
+        totp.verify(user_uuid, &recovery_codes[0]).await.unwrap();
+        assert!(matches!(
+            totp.verify(user_uuid, &recovery_codes[0]).await,
+            Err(AppError::AuthError(_))
+        ));
+
+        // Case and dashes do not matter
+        let typed = recovery_codes[1].replace('-', " ").to_uppercase();
+        totp.verify(user_uuid, &typed).await.unwrap();
+
+        assert!(matches!(totp.verify(user_uuid, "abcd-efgh").await, Err(AppError::AuthError(_))));
+    }
+
+    #[actix_web::test]
+    async fn secrets_are_stored_encrypted() {
+        let totp = service();
+        let user_uuid = Uuid::new_v4();
+        let (secret, _) = enrolled(&totp, user_uuid).await;
+
+        let credential = totp.store.get(user_uuid).await.unwrap().unwrap();
+        assert_ne!(credential.secret_ciphertext, secret);
+        let nonce = Nonce::from_slice(&credential.secret_nonce);
+        assert_eq!(totp.cipher.decrypt(nonce, credential.secret_ciphertext.as_ref()).unwrap(), secret);
+
+        // Another key cannot read it
+        let other = Aes256Gcm::new_from_slice(&[1u8; 32]).unwrap();
+        assert!(other.decrypt(nonce, credential.secret_ciphertext.as_ref()).is_err());
+    }
+
+    #[test]
+    fn encryption_key_is_required() {
+        let missing = TotpService::new(&Config::for_tests(), TotpStore::new(None));
+        assert!(missing.is_err());
+
+        let short = Config {
+            totp_encryption_key: Some("00".repeat(16)),
+            ..Config::for_tests()
+        };
+        assert!(TotpService::new(&short, TotpStore::new(None)).is_err());
+    }
+
+    #[get("/whoami")]
+    async fn whoami(user: crate::auth::AuthenticatedUser) -> HttpResponse {
+        HttpResponse::Ok().body(user.name)
+    }
+
+    #[actix_web::test]
+    async fn challenge_tokens_are_not_access_tokens() {
+        let config = config();
+        let user = UserInfo {
+            id: Uuid::new_v4().to_string(),
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+            roles: vec!["admin".to_string()],
+        };
+        let challenge = create_challenge(&config, user, vec![], "alice", ChallengePurpose::Verify).unwrap();
+        assert!(verify_challenge(&challenge.challenge_token, &config.jwt_secret).is_ok());
+        assert!(crate::auth::verify_token(&challenge.challenge_token, &config.jwt_secret).is_err());
+
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(RevocationStore::new(None)))
+                .service(
+                    actix_web::web::scope("")
+                        .wrap(AuthMiddleware::new(config.jwt_secret.clone()))
+                        .service(whoami),
+                ),
+        )
+        .await;
+        let request = TestRequest::get()
+            .uri("/whoami")
+            .insert_header(("Authorization", format!("Bearer {}", challenge.challenge_token)))
+            .to_request();
+
+        let response = call_service(&app, request).await;
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,185 @@
+use sqlx::postgres::PgPool;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+// Where TOTP credentials are kept. The secret arrives here already
+// encrypted, and recovery codes already hashed.
+#[derive(Clone)]
+pub enum TotpStore {
+    Postgres(PgPool),
+    Memory(Arc<Mutex<HashMap<Uuid, TotpCredential>>>),
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct TotpCredential {
+    pub secret_ciphertext: Vec<u8>,
+    pub secret_nonce: Vec<u8>,
+    pub recovery_codes: Vec<String>,
+    pub last_used_step: i64,
+    pub enabled: bool,
+}
+
+impl TotpStore {
+    pub fn new(pool: Option<PgPool>) -> Self {
+        match pool {
+            Some(pool) => TotpStore::Postgres(pool),
+            None => {
+                info!("No database configured; TOTP enrollments are kept in memory");
+                TotpStore::Memory(Arc::new(Mutex::new(HashMap::new())))
+            }
+        }
+    }
+
+    pub async fn get(&self, user_uuid: Uuid) -> Result<Option<TotpCredential>, AppError> {
+        match self {
+            TotpStore::Postgres(pool) => Ok(sqlx::query_as(
+                "SELECT secret_ciphertext, secret_nonce, recovery_codes, last_used_step, \
+                 enabled_at IS NOT NULL AS enabled \
+                 FROM totp_credentials WHERE user_uuid = $1",
+            )
+            .bind(user_uuid)
+            .fetch_optional(pool)
+            .await?),
+            TotpStore::Memory(credentials) => Ok(credentials.lock().unwrap().get(&user_uuid).cloned()),
+        }
+    }
+
+    // Start an enrollment, replacing any earlier one that was never confirmed.
+    // Returns false if the user already has TOTP enabled.
+    pub async fn save_pending(&self, user_uuid: Uuid, credential: &TotpCredential) -> Result<bool, AppError> {
+        match self {
+            TotpStore::Postgres(pool) => {
+                let result = sqlx::query(
+                    "INSERT INTO totp_credentials (user_uuid, secret_ciphertext, secret_nonce, recovery_codes) \
+                     VALUES ($1, $2, $3, $4) \
+                     ON CONFLICT (user_uuid) DO UPDATE SET \
+                         secret_ciphertext = EXCLUDED.secret_ciphertext, \
+                         secret_nonce = EXCLUDED.secret_nonce, \
+                         recovery_codes = EXCLUDED.recovery_codes, \
+                         last_used_step = 0, \
+                         created_at = now() \
+                     WHERE totp_credentials.enabled_at IS NULL",
+                )
+                .bind(user_uuid)
+                .bind(&credential.secret_ciphertext)
+                .bind(&credential.secret_nonce)
+                .bind(&credential.recovery_codes)
+                .execute(pool)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            TotpStore::Memory(credentials) => {
+                let mut credentials = credentials.lock().unwrap();
+                if credentials.get(&user_uuid).is_some_and(|existing| existing.enabled) {
+                    return Ok(false);
+                }
+                credentials.insert(
+                    user_uuid,
+                    TotpCredential {
+                        last_used_step: 0,
+                        enabled: false,
+                        ..credential.clone()
+                    },
+                );
+                Ok(true)
+            }
+        }
+    }
+
+    // Mark a code's time step as used. Returns false if that step, or a later
+    // one, was used before: each code is accepted once.
+    pub async fn use_step(&self, user_uuid: Uuid, step: i64) -> Result<bool, AppError> {
+        match self {
+            TotpStore::Postgres(pool) => {
+                let result = sqlx::query(
+                    "UPDATE totp_credentials SET last_used_step = $2 \
+                     WHERE user_uuid = $1 AND last_used_step < $2",
+                )
+                .bind(user_uuid)
+                .bind(step)
+                .execute(pool)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            TotpStore::Memory(credentials) => {
+                let mut credentials = credentials.lock().unwrap();
+                match credentials.get_mut(&user_uuid) {
+                    Some(credential) if credential.last_used_step < step => {
+                        credential.last_used_step = step;
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+        }
+    }
+
+    // Remove a recovery code by its hash. Returns false if it was not there.
+    pub async fn use_recovery_code(&self, user_uuid: Uuid, code_hash: &str) -> Result<bool, AppError> {
+        match self {
+            TotpStore::Postgres(pool) => {
+                let result = sqlx::query(
+                    "UPDATE totp_credentials SET recovery_codes = array_remove(recovery_codes, $2) \
+                     WHERE user_uuid = $1 AND enabled_at IS NOT NULL AND $2 = ANY(recovery_codes)",
+                )
+                .bind(user_uuid)
+                .bind(code_hash)
+                .execute(pool)
+                .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            TotpStore::Memory(credentials) => {
+                let mut credentials = credentials.lock().unwrap();
+                let Some(credential) = credentials.get_mut(&user_uuid).filter(|c| c.enabled) else {
+                    return Ok(false);
+                };
+                let before = credential.recovery_codes.len();
+                credential.recovery_codes.retain(|hash| hash != code_hash);
+                Ok(credential.recovery_codes.len() < before)
+            }
+        }
+    }
+
+    pub async fn enable(&self, user_uuid: Uuid) -> Result<(), AppError> {
+        match self {
+            TotpStore::Postgres(pool) => {
+                sqlx::query(
+                    "UPDATE totp_credentials SET enabled_at = now() \
+                     WHERE user_uuid = $1 AND enabled_at IS NULL",
+                )
+                .bind(user_uuid)
+                .execute(pool)
+                .await?;
+            }
+            TotpStore::Memory(credentials) => {
+                if let Some(credential) = credentials.lock().unwrap().get_mut(&user_uuid) {
+                    credential.enabled = true;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Remove a user's TOTP credential. Returns false if there was none.
+    pub async fn delete(&self, user_uuid: Uuid) -> Result<bool, AppError> {
+        match self {
+            TotpStore::Postgres(pool) => {
+                let result = sqlx::query("DELETE FROM totp_credentials WHERE user_uuid = $1")
+                    .bind(user_uuid)
+                    .execute(pool)
+                    .await?;
+
+                Ok(result.rows_affected() > 0)
+            }
+            TotpStore::Memory(credentials) => Ok(credentials.lock().unwrap().remove(&user_uuid).is_some()),
+        }
+    }
+}
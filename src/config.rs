@@ -1,8 +1,11 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
+use std::net::IpAddr;
 use std::path::Path;
-use anyhow::{Result, Context};
+use std::str::FromStr;
+use anyhow::{anyhow, Result, Context};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
@@ -10,21 +13,478 @@ pub struct Config {
     pub port: u16,
     pub database_url: String,
     pub jwt_secret: String,
-    pub jwt_expiration: i64,
+    // Lifetime of access tokens (JWTs) and of refresh tokens, in seconds
+    #[serde(default = "default_access_token_ttl_secs")]
+    pub access_token_ttl_secs: i64,
+    #[serde(default = "default_refresh_token_ttl_secs")]
+    pub refresh_token_ttl_secs: i64,
+    // API tokens for automation: lifetime when none is asked for, and the
+    // longest a user may ask for, in days
+    #[serde(default = "default_api_token_ttl_days")]
+    pub api_token_ttl_days: i64,
+    #[serde(default = "default_api_token_max_ttl_days")]
+    pub api_token_max_ttl_days: i64,
+    // Lifetime of the tokens operators get to act as another user
+    #[serde(default = "default_impersonation_ttl_secs")]
+    pub impersonation_ttl_secs: i64,
+    // How far the Date header of a signed request may be from our clock
+    #[serde(default = "default_signature_max_skew_secs")]
+    pub signature_max_skew_secs: i64,
     pub log_level: String,
     pub triton_datacenter: String,
+    #[serde(default)]
+    pub datacenter_environment: DatacenterEnvironment,
+    
+    // Development users, loaded from dev_users_file, log in without UFDS.
+    // Release builds refuse to start with dev_mode on in a production datacenter.
+    #[serde(default)]
+    pub dev_mode: bool,
+    #[serde(default = "default_dev_users_file")]
+    pub dev_users_file: String,
+    
+    // Login throttling: failed logins are counted per client IP and per
+    // username over a sliding window, and a username is locked after too
+    // many failures in a row. A limit of 0 turns that check off.
+    #[serde(default = "default_login_window_secs")]
+    pub login_window_secs: u64,
+    #[serde(default = "default_login_max_failures_per_ip")]
+    pub login_max_failures_per_ip: u32,
+    #[serde(default = "default_login_max_failures_per_user")]
+    pub login_max_failures_per_user: u32,
+    #[serde(default = "default_login_lockout_threshold")]
+    pub login_lockout_threshold: u32,
+    #[serde(default = "default_login_lockout_secs")]
+    pub login_lockout_secs: u64,
+    // Reverse proxies and load balancers in front of the admin UI. Requests
+    // from these addresses are attributed to the client named in
+    // X-Forwarded-For; otherwise the connecting address is the client, and
+    // every request through an untrusted proxy shares the proxy's limits.
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    
+    // TOTP two-factor authentication. Users with a required role must enroll
+    // before they get a session, which needs the database. Secrets are
+    // encrypted with totp_encryption_key (32 bytes, hex encoded), which must
+    // be set.
+    #[serde(default = "default_totp_required_roles")]
+    pub totp_required_roles: Vec<String>,
+    #[serde(default)]
+    pub totp_encryption_key: Option<String>,
+    #[serde(default = "default_totp_issuer")]
+    pub totp_issuer: String,
+    // Lifetime of the challenge token handed out between the two login steps
+    #[serde(default = "default_totp_challenge_ttl_secs")]
+    pub totp_challenge_ttl_secs: i64,
     
     // URLs for Triton services
     pub vmapi_url: String,
     pub cnapi_url: String,
     pub napi_url: String,
     pub imgapi_url: String,
-    pub amon_url: String,
     pub ufds_url: String,
+    pub papi_url: String,
+    
+    // Optional services; an empty URL means the service is not configured
+    #[serde(default)]
+    pub amon_url: String,
+    #[serde(default)]
     pub sapi_url: String,
+    #[serde(default)]
     pub fwapi_url: String,
-    pub papi_url: String,
+    #[serde(default)]
     pub mahi_url: String,
+    
+    // Shared HTTP client settings for Triton service calls
+    #[serde(default = "default_http_timeout_secs")]
+    pub http_timeout_secs: u64,
+    #[serde(default = "default_http_connect_timeout_secs")]
+    pub http_connect_timeout_secs: u64,
+    #[serde(default = "default_http_pool_idle_timeout_secs")]
+    pub http_pool_idle_timeout_secs: u64,
+    #[serde(default = "default_http_keepalive_secs")]
+    pub http_keepalive_secs: u64,
+    #[serde(default = "default_http_user_agent")]
+    pub http_user_agent: String,
+    
+    // UFDS directory access: credentials, search settings, TLS, group to
+    // role mapping and connection pooling
+    #[serde(default)]
+    pub ldap: LdapConfig,
+    
+    // OpenID Connect single sign-on, offered next to UFDS passwords when set
+    #[serde(default)]
+    pub oidc: Option<OidcConfig>,
+    
+    // Timeout for each upstream probe made by the health endpoints
+    #[serde(default = "default_health_check_timeout_secs")]
+    pub health_check_timeout_secs: u64,
+    
+    // How long probe results are reused, so health endpoints polled by load
+    // balancers or dashboards do not hammer every upstream
+    #[serde(default = "default_health_check_cache_secs")]
+    pub health_check_cache_secs: u64,
+    
+    // How long global search waits for each service before returning
+    // without its results
+    #[serde(default = "default_search_timeout_secs")]
+    pub search_timeout_secs: u64,
+    
+    // Prometheus metrics: when metrics_bind is set, /metrics is served on that
+    // address only. The main listener only serves it when metrics_token is
+    // set; the token, if set, must be sent as a bearer token on either.
+    #[serde(default)]
+    pub metrics_bind: Option<String>,
+    #[serde(default)]
+    pub metrics_token: Option<String>,
+}
+
+// Whether the datacenter serves real customers. Anything other than
+// production has to be set explicitly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DatacenterEnvironment {
+    #[default]
+    Production,
+    Staging,
+    Development,
+}
+
+impl FromStr for DatacenterEnvironment {
+    type Err = anyhow::Error;
+    
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "production" => Ok(DatacenterEnvironment::Production),
+            "staging" => Ok(DatacenterEnvironment::Staging),
+            "development" => Ok(DatacenterEnvironment::Development),
+            _ => Err(anyhow!("Unknown datacenter environment: {}", s)),
+        }
+    }
+}
+
+fn default_dev_users_file() -> String {
+    "dev-users.json".to_string()
+}
+
+fn default_login_window_secs() -> u64 {
+    5 * 60
+}
+
+fn default_login_max_failures_per_ip() -> u32 {
+    20
+}
+
+fn default_login_max_failures_per_user() -> u32 {
+    5
+}
+
+fn default_login_lockout_threshold() -> u32 {
+    10
+}
+
+fn default_login_lockout_secs() -> u64 {
+    15 * 60
+}
+
+fn default_totp_required_roles() -> Vec<String> {
+    vec!["admin".to_string(), "operator".to_string()]
+}
+
+fn default_totp_issuer() -> String {
+    "Triton AdminUI".to_string()
+}
+
+fn default_totp_challenge_ttl_secs() -> i64 {
+    5 * 60
+}
+
+fn default_access_token_ttl_secs() -> i64 {
+    15 * 60
+}
+
+fn default_refresh_token_ttl_secs() -> i64 {
+    7 * 24 * 60 * 60
+}
+
+fn default_api_token_ttl_days() -> i64 {
+    90
+}
+
+fn default_api_token_max_ttl_days() -> i64 {
+    365
+}
+
+fn default_impersonation_ttl_secs() -> i64 {
+    15 * 60
+}
+
+fn default_signature_max_skew_secs() -> i64 {
+    300
+}
+
+fn default_http_timeout_secs() -> u64 {
+    30
+}
+
+fn default_http_connect_timeout_secs() -> u64 {
+    5
+}
+
+fn default_http_pool_idle_timeout_secs() -> u64 {
+    90
+}
+
+fn default_http_keepalive_secs() -> u64 {
+    60
+}
+
+fn default_health_check_timeout_secs() -> u64 {
+    3
+}
+
+fn default_health_check_cache_secs() -> u64 {
+    5
+}
+
+fn default_search_timeout_secs() -> u64 {
+    3
+}
+
+fn default_http_user_agent() -> String {
+    format!("triton-rustadminui/{}", env!("CARGO_PKG_VERSION"))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LdapConfig {
+    // Service account used for login lookups and user management
+    pub bind_dn: String,
+    pub bind_password: String,
+    // Directory root; when unset, a DN in UFDS_URL is used, then o=smartdc
+    pub base_dn: Option<String>,
+    // Where and how to find the entry for a login. `{login}` in the filter is
+    // replaced with the escaped username. The base defaults to ou=users under
+    // the base DN.
+    pub user_search_base: Option<String>,
+    pub user_search_filter: String,
+    // PEM bundle of CAs trusted for UFDS, in addition to the system roots.
+    // Prefer this over turning verification off for private CAs.
+    pub ca_file: Option<String>,
+    pub verify_certificates: bool,
+    // Upgrade plain ldap:// connections with StartTLS
+    pub starttls: bool,
+    // UFDS group -> admin UI role. Keys are group DNs (cn=operators, ou=groups,
+    // o=smartdc) or just their first RDN (cn=operators). Groups not listed
+    // grant no role. By default every member of the UFDS operators group gets
+    // the admin role, i.e. full read/write access to the admin UI, and
+    // cn=readers gets readonly. LDAP_GROUP_ROLES replaces these defaults.
+    pub group_roles: HashMap<String, String>,
+    
+    // Service-account connections kept for searches and user management
+    pub pool_size: usize,
+    // Short-lived connections used to check user passwords at the same time
+    pub max_concurrent_binds: usize,
+    pub connect_timeout_secs: u64,
+    pub operation_timeout_secs: u64,
+    // How long a request waits for a pooled connection before giving up
+    pub pool_timeout_secs: u64,
+    // Idle connections older than this are probed before being reused
+    pub health_check_interval_secs: u64,
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            bind_dn: "cn=root".to_string(),
+            bind_password: String::new(),
+            base_dn: None,
+            user_search_base: None,
+            user_search_filter: "(&(objectclass=sdcperson)(login={login}))".to_string(),
+            ca_file: None,
+            verify_certificates: true,
+            starttls: false,
+            group_roles: HashMap::from([
+                ("cn=operators".to_string(), "admin".to_string()),
+                ("cn=readers".to_string(), "readonly".to_string()),
+            ]),
+            pool_size: 8,
+            max_concurrent_binds: 16,
+            connect_timeout_secs: 5,
+            operation_timeout_secs: 10,
+            pool_timeout_secs: 5,
+            health_check_interval_secs: 30,
+        }
+    }
+}
+
+impl LdapConfig {
+    fn from_env() -> Result<Self> {
+        let defaults = Self::default();
+        
+        Ok(Self {
+            bind_dn: env::var("LDAP_BIND_DN")
+                .or_else(|_| env::var("UFDS_BIND_DN"))
+                .unwrap_or(defaults.bind_dn),
+            bind_password: env::var("LDAP_BIND_PASSWORD")
+                .or_else(|_| env::var("UFDS_BIND_PASSWORD"))
+                .unwrap_or(defaults.bind_password),
+            base_dn: env::var("LDAP_BASE_DN").ok().filter(|v| !v.is_empty()),
+            user_search_base: env::var("LDAP_USER_SEARCH_BASE").ok().filter(|v| !v.is_empty()),
+            user_search_filter: env::var("LDAP_USER_SEARCH_FILTER").unwrap_or(defaults.user_search_filter),
+            ca_file: env::var("LDAP_CA_FILE").ok().filter(|v| !v.is_empty()),
+            verify_certificates: match env::var("LDAP_VERIFY_CERTIFICATES") {
+                Ok(v) => v.to_lowercase() != "false",
+                Err(_) => defaults.verify_certificates,
+            },
+            starttls: match env::var("LDAP_STARTTLS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.starttls,
+            },
+            // LDAP_GROUP_ROLES="cn=operators:admin;cn=readers:readonly"
+            group_roles: match env::var("LDAP_GROUP_ROLES") {
+                Ok(v) => parse_group_roles("LDAP_GROUP_ROLES", &v)?,
+                Err(_) => defaults.group_roles,
+            },
+            pool_size: match env::var("UFDS_POOL_SIZE") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.pool_size,
+            },
+            max_concurrent_binds: match env::var("UFDS_MAX_CONCURRENT_BINDS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.max_concurrent_binds,
+            },
+            connect_timeout_secs: match env::var("UFDS_CONNECT_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.connect_timeout_secs,
+            },
+            operation_timeout_secs: match env::var("UFDS_OPERATION_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.operation_timeout_secs,
+            },
+            pool_timeout_secs: match env::var("UFDS_POOL_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.pool_timeout_secs,
+            },
+            health_check_interval_secs: match env::var("UFDS_HEALTH_CHECK_INTERVAL_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => defaults.health_check_interval_secs,
+            },
+        })
+    }
+    
+    fn validate(&self) -> Result<()> {
+        // A simple bind with an empty password is an anonymous bind, which
+        // many servers accept while granting none of the service account's
+        // access
+        if self.bind_password.is_empty() {
+            return Err(anyhow!(
+                "LDAP bind password for {} is empty; set LDAP_BIND_PASSWORD (ldap.bind_password)",
+                self.bind_dn
+            ));
+        }
+        
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    // The discovery document is read from {issuer_url}/.well-known/openid-configuration
+    pub issuer_url: String,
+    pub client_id: String,
+    // Leave unset for a public client, which relies on PKCE alone
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    // Registered with the IdP; the admin UI's /api/auth/oidc/callback
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+    // Label of the sign-in button on the login page
+    #[serde(default = "default_oidc_display_name")]
+    pub display_name: String,
+    // ID token claims used to find the UFDS user: the login claim first, then
+    // the email address if the IdP has verified it
+    #[serde(default = "default_oidc_login_claim")]
+    pub login_claim: String,
+    #[serde(default = "default_oidc_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default = "default_oidc_require_verified_email")]
+    pub require_verified_email: bool,
+    // IdP group -> admin UI role, granted on top of the roles from UFDS groups
+    #[serde(default)]
+    pub group_roles: HashMap<String, String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".to_string(), "profile".to_string(), "email".to_string()]
+}
+
+fn default_oidc_display_name() -> String {
+    "Single sign-on".to_string()
+}
+
+fn default_oidc_login_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_oidc_groups_claim() -> String {
+    "groups".to_string()
+}
+
+fn default_oidc_require_verified_email() -> bool {
+    true
+}
+
+impl OidcConfig {
+    // OIDC is enabled by setting OIDC_ISSUER_URL
+    fn from_env() -> Result<Option<Self>> {
+        let issuer_url = match env::var("OIDC_ISSUER_URL") {
+            Ok(v) if !v.is_empty() => v,
+            _ => return Ok(None),
+        };
+        
+        Ok(Some(Self {
+            issuer_url,
+            client_id: env::var("OIDC_CLIENT_ID").context("OIDC_CLIENT_ID is required with OIDC_ISSUER_URL")?,
+            client_secret: env::var("OIDC_CLIENT_SECRET").ok().filter(|v| !v.is_empty()),
+            redirect_url: env::var("OIDC_REDIRECT_URL").context("OIDC_REDIRECT_URL is required with OIDC_ISSUER_URL")?,
+            // Separated by commas or spaces
+            scopes: match env::var("OIDC_SCOPES") {
+                Ok(v) => v
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|scope| !scope.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                Err(_) => default_oidc_scopes(),
+            },
+            display_name: env::var("OIDC_DISPLAY_NAME").unwrap_or_else(|_| default_oidc_display_name()),
+            login_claim: env::var("OIDC_LOGIN_CLAIM").unwrap_or_else(|_| default_oidc_login_claim()),
+            groups_claim: env::var("OIDC_GROUPS_CLAIM").unwrap_or_else(|_| default_oidc_groups_claim()),
+            require_verified_email: match env::var("OIDC_REQUIRE_VERIFIED_EMAIL") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_oidc_require_verified_email(),
+            },
+            // OIDC_GROUP_ROLES="triton-admins:admin;triton-operators:operator"
+            group_roles: match env::var("OIDC_GROUP_ROLES") {
+                Ok(v) => parse_group_roles("OIDC_GROUP_ROLES", &v)?,
+                Err(_) => HashMap::new(),
+            },
+        }))
+    }
+}
+
+fn parse_group_roles(name: &str, value: &str) -> Result<HashMap<String, String>> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.rsplit_once(':') {
+            Some((group, role)) if !group.trim().is_empty() && !role.trim().is_empty() => {
+                Ok((group.trim().to_string(), role.trim().to_string()))
+            }
+            _ => Err(anyhow!("Invalid {} entry {:?}; expected group:role", name, pair)),
+        })
+        .collect()
 }
 
 impl Config {
@@ -36,23 +496,133 @@ impl Config {
                 .parse()?,
             database_url: env::var("DATABASE_URL").unwrap_or_else(|_| "".to_string()),
             jwt_secret: env::var("JWT_SECRET")?,
-            jwt_expiration: env::var("JWT_EXPIRATION")
-                .unwrap_or_else(|_| "60".to_string())
-                .parse()?,
+            access_token_ttl_secs: match env::var("ACCESS_TOKEN_TTL_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_access_token_ttl_secs(),
+            },
+            refresh_token_ttl_secs: match env::var("REFRESH_TOKEN_TTL_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_refresh_token_ttl_secs(),
+            },
+            api_token_ttl_days: match env::var("API_TOKEN_TTL_DAYS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_api_token_ttl_days(),
+            },
+            api_token_max_ttl_days: match env::var("API_TOKEN_MAX_TTL_DAYS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_api_token_max_ttl_days(),
+            },
+            impersonation_ttl_secs: match env::var("IMPERSONATION_TTL_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_impersonation_ttl_secs(),
+            },
+            signature_max_skew_secs: match env::var("HTTP_SIGNATURE_MAX_SKEW_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_signature_max_skew_secs(),
+            },
             log_level: env::var("LOG_LEVEL").unwrap_or_else(|_| "info".to_string()),
             triton_datacenter: env::var("TRITON_DATACENTER")?,
+            datacenter_environment: match env::var("TRITON_DATACENTER_ENVIRONMENT") {
+                Ok(v) => v.parse()?,
+                Err(_) => DatacenterEnvironment::default(),
+            },
+            dev_mode: match env::var("DEV_MODE") {
+                Ok(v) => v.parse()?,
+                Err(_) => false,
+            },
+            dev_users_file: env::var("DEV_USERS_FILE").unwrap_or_else(|_| default_dev_users_file()),
+            login_window_secs: match env::var("LOGIN_WINDOW_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_login_window_secs(),
+            },
+            login_max_failures_per_ip: match env::var("LOGIN_MAX_FAILURES_PER_IP") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_login_max_failures_per_ip(),
+            },
+            login_max_failures_per_user: match env::var("LOGIN_MAX_FAILURES_PER_USER") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_login_max_failures_per_user(),
+            },
+            login_lockout_threshold: match env::var("LOGIN_LOCKOUT_THRESHOLD") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_login_lockout_threshold(),
+            },
+            login_lockout_secs: match env::var("LOGIN_LOCKOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_login_lockout_secs(),
+            },
+            // Comma separated IP addresses
+            trusted_proxies: match env::var("TRUSTED_PROXIES") {
+                Ok(v) => v
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|ip| !ip.is_empty())
+                    .map(|ip| ip.parse().map_err(|_| anyhow!("Invalid TRUSTED_PROXIES address {:?}", ip)))
+                    .collect::<Result<_>>()?,
+                Err(_) => Vec::new(),
+            },
+            // Comma separated; empty means no role requires TOTP
+            totp_required_roles: match env::var("TOTP_REQUIRED_ROLES") {
+                Ok(v) => v
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|role| !role.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+                Err(_) => default_totp_required_roles(),
+            },
+            totp_encryption_key: env::var("TOTP_ENCRYPTION_KEY").ok().filter(|v| !v.is_empty()),
+            totp_issuer: env::var("TOTP_ISSUER").unwrap_or_else(|_| default_totp_issuer()),
+            totp_challenge_ttl_secs: match env::var("TOTP_CHALLENGE_TTL_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_totp_challenge_ttl_secs(),
+            },
             
             // Triton service URLs - in production these would be provided by service discovery
             vmapi_url: env::var("VMAPI_URL")?,
             cnapi_url: env::var("CNAPI_URL")?,
             napi_url: env::var("NAPI_URL")?,
             imgapi_url: env::var("IMGAPI_URL")?,
-            amon_url: env::var("AMON_URL")?,
             ufds_url: env::var("UFDS_URL")?,
-            sapi_url: env::var("SAPI_URL")?,
-            fwapi_url: env::var("FWAPI_URL")?,
             papi_url: env::var("PAPI_URL")?,
-            mahi_url: env::var("MAHI_URL")?,
+            amon_url: env::var("AMON_URL").unwrap_or_default(),
+            sapi_url: env::var("SAPI_URL").unwrap_or_default(),
+            fwapi_url: env::var("FWAPI_URL").unwrap_or_default(),
+            mahi_url: env::var("MAHI_URL").unwrap_or_default(),
+            
+            http_timeout_secs: match env::var("HTTP_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_http_timeout_secs(),
+            },
+            http_connect_timeout_secs: match env::var("HTTP_CONNECT_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_http_connect_timeout_secs(),
+            },
+            http_pool_idle_timeout_secs: match env::var("HTTP_POOL_IDLE_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_http_pool_idle_timeout_secs(),
+            },
+            http_keepalive_secs: match env::var("HTTP_KEEPALIVE_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_http_keepalive_secs(),
+            },
+            http_user_agent: env::var("HTTP_USER_AGENT").unwrap_or_else(|_| default_http_user_agent()),
+            ldap: LdapConfig::from_env()?,
+            oidc: OidcConfig::from_env()?,
+            health_check_timeout_secs: match env::var("HEALTH_CHECK_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_health_check_timeout_secs(),
+            },
+            health_check_cache_secs: match env::var("HEALTH_CHECK_CACHE_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_health_check_cache_secs(),
+            },
+            search_timeout_secs: match env::var("SEARCH_TIMEOUT_SECS") {
+                Ok(v) => v.parse()?,
+                Err(_) => default_search_timeout_secs(),
+            },
+            metrics_bind: env::var("METRICS_BIND").ok().filter(|v| !v.is_empty()),
+            metrics_token: env::var("METRICS_TOKEN").ok().filter(|v| !v.is_empty()),
         })
     }
     
@@ -65,4 +635,127 @@ impl Config {
             
         Ok(config)
     }
-}
\ No newline at end of file
+    
+    // Checks that cannot be expressed through defaults, run at startup
+    // whichever way the configuration was loaded
+    pub fn validate(&self) -> Result<()> {
+        // Without its own key, rotating JWT_SECRET would leave every enrolled
+        // authenticator unreadable
+        if self.totp_encryption_key.is_none() {
+            return Err(anyhow!(
+                "TOTP_ENCRYPTION_KEY is not set; generate one with `openssl rand -hex 32`"
+            ));
+        }
+        // Enrollments in memory are lost on restart, and users of a required
+        // role would then be asked to enroll again by whoever logs in first
+        if !self.totp_required_roles.is_empty() && self.database_url.is_empty() {
+            return Err(anyhow!(
+                "TOTP is required for {} but DATABASE_URL is not set; set it, or set TOTP_REQUIRED_ROLES= to make TOTP optional",
+                self.totp_required_roles.join(", ")
+            ));
+        }
+
+        self.ldap.validate()
+    }
+}
+
+// Minimal configuration for unit tests: defaults for everything optional
+#[cfg(test)]
+impl Config {
+    pub fn for_tests() -> Self {
+        serde_json::from_value(serde_json::json!({
+            "host": "127.0.0.1",
+            "port": 8080,
+            "database_url": "",
+            "jwt_secret": "test-secret",
+            "log_level": "info",
+            "triton_datacenter": "test",
+            "vmapi_url": "", "cnapi_url": "", "napi_url": "",
+            "imgapi_url": "", "ufds_url": "", "papi_url": "",
+        }))
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_bind_password_is_rejected() {
+        let ldap = LdapConfig::default();
+        assert!(ldap.validate().is_err());
+
+        let ldap = LdapConfig {
+            bind_password: "secret".to_string(),
+            ..LdapConfig::default()
+        };
+        assert!(ldap.validate().is_ok());
+    }
+
+    #[test]
+    fn totp_needs_a_key_and_a_database() {
+        let valid = Config {
+            database_url: "postgres://localhost/adminui".to_string(),
+            totp_encryption_key: Some("00".repeat(32)),
+            ldap: LdapConfig {
+                bind_password: "secret".to_string(),
+                ..LdapConfig::default()
+            },
+            ..Config::for_tests()
+        };
+        assert!(valid.validate().is_ok());
+
+        let no_key = Config {
+            totp_encryption_key: None,
+            ..valid.clone()
+        };
+        assert!(no_key.validate().unwrap_err().to_string().starts_with("TOTP_ENCRYPTION_KEY is not set"));
+
+        let no_database = Config {
+            database_url: String::new(),
+            ..valid.clone()
+        };
+        assert!(no_database.validate().is_err());
+
+        // Opt-in TOTP works without a database
+        let optional = Config {
+            totp_required_roles: vec![],
+            ..no_database
+        };
+        assert!(optional.validate().is_ok());
+    }
+
+    #[test]
+    fn operators_are_admins_by_default() {
+        let roles = LdapConfig::default().group_roles;
+        assert_eq!(roles.len(), 2);
+        assert_eq!(roles["cn=operators"], "admin");
+        assert_eq!(roles["cn=readers"], "readonly");
+    }
+
+    #[test]
+    fn group_roles_are_parsed() {
+        let roles = parse_group_roles(
+            "LDAP_GROUP_ROLES",
+            " cn=operators, ou=groups, o=smartdc : admin ;cn=support:operator;; urn:example:team:readonly ;",
+        )
+        .unwrap();
+
+        assert_eq!(roles.len(), 3);
+        assert_eq!(roles["cn=operators, ou=groups, o=smartdc"], "admin");
+        assert_eq!(roles["cn=support"], "operator");
+        // Only the last colon separates the role, so groups may contain colons
+        assert_eq!(roles["urn:example:team"], "readonly");
+
+        assert!(parse_group_roles("LDAP_GROUP_ROLES", "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn malformed_group_roles_are_rejected() {
+        for value in ["cn=operators", "cn=operators:", ":admin", "cn=readers:readonly;cn=operators"] {
+            let err = parse_group_roles("OIDC_GROUP_ROLES", value).unwrap_err().to_string();
+            assert!(err.starts_with("Invalid OIDC_GROUP_ROLES entry"), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use tracing::info;
+
+// Create the Postgres connection pool and bring the schema up to date
+pub async fn create_pool(database_url: &str) -> Result<PgPool, sqlx::Error> {
+    info!("Connecting to database");
+    
+    let pool = PgPoolOptions::new()
+        .max_connections(10)
+        .connect(database_url)
+        .await?;
+        
+    // Migrations are embedded from ./migrations at compile time
+    sqlx::migrate!().run(&pool).await?;
+    info!("Database migrations applied");
+    
+    Ok(pool)
+}
@@ -1,5 +1,5 @@
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
-use serde::Serialize;
+use actix_web::{http::{header, StatusCode}, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use std::fmt;
 use tracing::error;
@@ -23,6 +23,25 @@ pub enum AppError {
 
     #[error("Service unavailable: {0}")]
     ServiceUnavailable(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Timeout: {0}")]
+    Timeout(String),
+
+    #[error("Too many requests: {message}")]
+    TooManyRequests {
+        message: String,
+        // Seconds until the client may try again, sent as Retry-After
+        retry_after: u64,
+    },
+
+    #[error("Validation error: {message}")]
+    UpstreamValidation {
+        message: String,
+        errors: Vec<FieldError>,
+    },
     
     #[error("Database error: {0}")]
     DatabaseError(#[from] sqlx::Error),
@@ -34,10 +53,24 @@ pub enum AppError {
     SerializationError(#[from] serde_json::Error),
 }
 
+// Field-level validation error as reported by Triton services in the
+// `errors` array of a restify error body
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldError {
+    #[serde(default)]
+    pub field: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
+
 #[derive(Serialize)]
 struct ErrorResponse {
     code: String,
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
 }
 
 impl ResponseError for AppError {
@@ -55,6 +88,10 @@ impl ResponseError for AppError {
             AppError::BadRequest(_) => "BadRequest",
             AppError::InternalServerError(_) => "InternalServerError",
             AppError::ServiceUnavailable(_) => "ServiceUnavailable",
+            AppError::Conflict(_) => "Conflict",
+            AppError::Timeout(_) => "Timeout",
+            AppError::TooManyRequests { .. } => "TooManyRequests",
+            AppError::UpstreamValidation { .. } => "ValidationError",
             AppError::DatabaseError(_) => "DatabaseError",
             AppError::ValidationError(_) => "ValidationError",
             AppError::SerializationError(_) => "SerializationError",
@@ -63,10 +100,18 @@ impl ResponseError for AppError {
         let response = ErrorResponse {
             code: code.to_string(),
             message: self.to_string(),
+            errors: match self {
+                AppError::UpstreamValidation { errors, .. } => errors.clone(),
+                _ => Vec::new(),
+            },
         };
         
-        HttpResponse::build(status)
-            .json(response)
+        let mut builder = HttpResponse::build(status);
+        if let AppError::TooManyRequests { retry_after, .. } = self {
+            builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        
+        builder.json(response)
     }
 
     fn status_code(&self) -> StatusCode {
@@ -78,6 +123,10 @@ impl ResponseError for AppError {
             AppError::ValidationError(_) => StatusCode::BAD_REQUEST,
             AppError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::UpstreamValidation { .. } => StatusCode::UNPROCESSABLE_ENTITY,
             AppError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::SerializationError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -3,13 +3,16 @@ use actix_web::{middleware, web, App, HttpResponse, HttpServer};
 use dotenv::dotenv;
 use rust_embed::RustEmbed;
 use std::env;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod api;
+mod audit;
 mod auth;
 mod config;
+mod db;
 mod error;
+mod metrics;
 mod models;
 mod services;
 
@@ -115,13 +118,98 @@ async fn main() -> std::io::Result<()> {
         }
     };
     
+    if let Err(e) = config.validate() {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    }
+    
     let app_config = web::Data::new(config.clone());
     
-    // Database connection pool will be initialized here
-    // let db_pool = db::create_pool(&config.database_url).await?;
+    // Development users bypass UFDS, so they must be switched on explicitly
+    // and never reach a production datacenter in a release build
+    let dev_users = if config.dev_mode {
+        if !cfg!(debug_assertions) && config.datacenter_environment == config::DatacenterEnvironment::Production {
+            eprintln!("DEV_MODE is enabled in a production datacenter; refusing to start.");
+            eprintln!("Set TRITON_DATACENTER_ENVIRONMENT to staging or development to use development users.");
+            std::process::exit(1);
+        }
+        
+        match auth::dev_users::DevUsers::load(&config.dev_users_file) {
+            Ok(users) => {
+                warn!("************************************************************");
+                warn!("DEV MODE: development users can log in WITHOUT UFDS: {}", users.usernames().join(", "));
+                warn!("Loaded from {}. Never enable DEV_MODE in production.", config.dev_users_file);
+                warn!("************************************************************");
+                Some(web::Data::new(users))
+            }
+            Err(e) => {
+                eprintln!("Failed to load development users: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    
+    // Triton service clients are shared by all workers so connections are pooled
+    let service_clients = match services::ServiceClients::new(&config) {
+        Ok(clients) => web::Data::new(clients),
+        Err(e) => {
+            eprintln!("Failed to initialize Triton service clients: {}", e);
+            std::process::exit(1);
+        }
+    };
+    
+    // Postgres backs the audit log, token revocations, refresh tokens, API
+    // tokens and TOTP enrollments
+    let db_pool = if config.database_url.is_empty() {
+        warn!("DATABASE_URL is not set; audit logging is disabled and sessions are not persisted");
+        None
+    } else {
+        match db::create_pool(&config.database_url).await {
+            Ok(pool) => Some(pool),
+            Err(e) => {
+                eprintln!("Failed to initialize database: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let audit_store = db_pool.clone().map(|pool| web::Data::new(audit::AuditStore::new(pool)));
+    let revocations = web::Data::new(auth::revocation::RevocationStore::new(db_pool.clone()));
+    let refresh_tokens = web::Data::new(auth::refresh::RefreshTokenStore::new(db_pool.clone()));
+    let api_tokens = web::Data::new(auth::api_tokens::ApiTokenStore::new(db_pool.clone()));
+    let totp = match auth::totp::TotpService::new(&config, auth::totp::TotpStore::new(db_pool)) {
+        Ok(totp) => web::Data::new(totp),
+        Err(e) => {
+            eprintln!("Failed to configure TOTP: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let oidc = match &config.oidc {
+        Some(oidc) => match auth::oidc::OidcProvider::from_config(oidc, &config) {
+            Ok(provider) => Some(web::Data::new(provider)),
+            Err(e) => {
+                eprintln!("Failed to configure OIDC: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let login_throttle = web::Data::new(auth::throttle::LoginThrottle::new(
+        auth::throttle::LoginThrottleConfig::from_config(&config),
+    ));
+
+    // Metrics are served on the main listener only when no separate bind
+    // address is configured and scrapers must present a token
+    let serve_metrics = config.metrics_bind.is_none()
+        && config.metrics_token.as_deref().is_some_and(|t| !t.is_empty());
+    if config.metrics_bind.is_none() && !serve_metrics {
+        warn!("Metrics disabled: set METRICS_BIND or METRICS_TOKEN to expose /metrics");
+    }
+    let metrics_config = app_config.clone();
 
     // Start HTTP server
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         // Configure CORS
         let cors = Cors::default()
             .allow_any_origin()
@@ -130,19 +218,59 @@ async fn main() -> std::io::Result<()> {
             .max_age(3600);
 
         App::new()
+            .wrap(metrics::HttpMetrics)
             .wrap(middleware::Logger::default())
             .wrap(middleware::Compress::default())
             .wrap(cors)
             // Add application state
-            // .app_data(web::Data::new(db_pool.clone()))
             .app_data(app_config.clone())
+            .app_data(service_clients.clone())
+            .app_data(revocations.clone())
+            .app_data(refresh_tokens.clone())
+            .app_data(api_tokens.clone())
+            .app_data(login_throttle.clone())
+            .app_data(totp.clone())
+            .configure(|cfg| {
+                if let Some(store) = &audit_store {
+                    cfg.app_data(store.clone());
+                }
+                if let Some(users) = &dev_users {
+                    cfg.app_data(users.clone());
+                }
+                if let Some(provider) = &oidc {
+                    cfg.app_data(provider.clone());
+                }
+            })
             // API routes with JWT authentication
             .configure(|cfg| api::configure_routes(cfg, &config.jwt_secret))
+            // Prometheus metrics
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(metrics::metrics_handler));
+                }
+            })
             // Static files (for SPA frontend) - embedded in the binary
             .route("/", web::get().to(serve_index))
             .route("/{path:.*}", web::get().to(serve_static_file))
     })
     .bind(format!("{0}:{1}", &config.host, config.port))?
-    .run()
-    .await
-}
\ No newline at end of file
+    .run();
+
+    match &config.metrics_bind {
+        Some(metrics_bind) => {
+            info!("Serving metrics on {}", metrics_bind);
+            let metrics_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(metrics_config.clone())
+                    .route("/metrics", web::get().to(metrics::metrics_handler))
+            })
+            .workers(1)
+            .bind(metrics_bind)?
+            .run();
+
+            futures::try_join!(server, metrics_server)?;
+            Ok(())
+        }
+        None => server.await,
+    }
+}
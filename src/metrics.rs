@@ -0,0 +1,200 @@
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web::Data,
+    Error, HttpRequest, HttpResponse,
+};
+use futures::future::{ok, Ready};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec_with_registry, register_int_counter_vec_with_registry,
+    register_int_counter_with_registry, Encoder, HistogramVec, IntCounter, IntCounterVec, Registry,
+    TextEncoder,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use subtle::ConstantTimeEq;
+use tracing::error;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+// Buckets in seconds, from fast cached reads up to slow upstream jobs
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new_custom(Some("adminui".to_string()), None)
+        .expect("metrics registry");
+
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec_with_registry!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["method", "route", "status"],
+        REGISTRY
+    )
+    .expect("http_requests_total metric");
+
+    pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec_with_registry!(
+        "http_request_duration_seconds",
+        "HTTP request latency, by route and status",
+        &["method", "route", "status"],
+        LATENCY_BUCKETS.to_vec(),
+        REGISTRY
+    )
+    .expect("http_request_duration_seconds metric");
+
+    pub static ref UPSTREAM_REQUEST_DURATION: HistogramVec = register_histogram_vec_with_registry!(
+        "upstream_request_duration_seconds",
+        "Latency of calls to Triton services, by service and outcome",
+        &["service", "method", "outcome"],
+        LATENCY_BUCKETS.to_vec(),
+        REGISTRY
+    )
+    .expect("upstream_request_duration_seconds metric");
+
+    pub static ref UPSTREAM_ERRORS: IntCounterVec = register_int_counter_vec_with_registry!(
+        "upstream_errors_total",
+        "Failed calls to Triton services, by service and kind",
+        &["service", "kind"],
+        REGISTRY
+    )
+    .expect("upstream_errors_total metric");
+
+    pub static ref LOGIN_ATTEMPTS: IntCounterVec = register_int_counter_vec_with_registry!(
+        "login_attempts_total",
+        "Login attempts, by result (success, failure, error, throttled, locked)",
+        &["result"],
+        REGISTRY
+    )
+    .expect("login_attempts_total metric");
+
+    pub static ref LOGIN_LOCKOUTS: IntCounter = register_int_counter_with_registry!(
+        "login_lockouts_total",
+        "Accounts locked after repeated failed logins",
+        REGISTRY
+    )
+    .expect("login_lockouts_total metric");
+}
+
+// Rejected credentials count as "failure"; logins that could not be checked
+// at all (UFDS down, timeouts) count as "error" so outages do not look like
+// a password-guessing attack
+pub fn record_login<T>(result: &Result<T, AppError>) {
+    let result = match result {
+        Ok(_) => "success",
+        Err(AppError::AuthError(_) | AppError::AuthorizationError(_)) => "failure",
+        Err(_) => "error",
+    };
+    LOGIN_ATTEMPTS.with_label_values(&[result]).inc();
+}
+
+// Logins refused before the password was checked ("throttled" or "locked")
+pub fn record_login_blocked(reason: &str) {
+    LOGIN_ATTEMPTS.with_label_values(&[reason]).inc();
+}
+
+pub fn record_lockout() {
+    LOGIN_LOCKOUTS.inc();
+}
+
+// Serve the registry in the Prometheus text format. When a metrics token is
+// configured, scrapers must send it as a bearer token.
+pub async fn metrics_handler(req: HttpRequest, config: Data<Config>) -> HttpResponse {
+    if let Some(token) = config.metrics_token.as_deref().filter(|t| !t.is_empty()) {
+        let authorized = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|presented| bool::from(presented.as_bytes().ct_eq(token.as_bytes())))
+            .unwrap_or(false);
+
+        if !authorized {
+            return HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish();
+        }
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        error!("Failed to encode metrics: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(buffer)
+}
+
+// Middleware recording request counts and latency. Requests are labelled with
+// the matched route pattern (e.g. /api/vms/{uuid}) rather than the raw path
+// to keep label cardinality bounded.
+pub struct HttpMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for HttpMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = HttpMetricsService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HttpMetricsService { service })
+    }
+}
+
+pub struct HttpMetricsService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for HttpMetricsService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let result = fut.await;
+
+            let (route, status) = match &result {
+                Ok(res) => (
+                    res.request().match_pattern().unwrap_or_else(|| "unmatched".to_string()),
+                    res.status().as_u16().to_string(),
+                ),
+                Err(e) => (
+                    "unmatched".to_string(),
+                    e.as_response_error().status_code().as_u16().to_string(),
+                ),
+            };
+
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            HTTP_REQUESTS.with_label_values(&labels).inc();
+            HTTP_REQUEST_DURATION
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+
+            result
+        })
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+// A row of the audit_log table
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub user_uuid: Option<Uuid>,
+    pub user_name: Option<String>,
+    // Set when an operator made the request as user_uuid
+    pub impersonator_uuid: Option<Uuid>,
+    pub impersonator_name: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub resource_type: Option<String>,
+    pub resource_uuid: Option<String>,
+    pub action: String,
+    pub request_body: Option<serde_json::Value>,
+    pub source_ip: Option<String>,
+    pub status_code: i32,
+    pub outcome: String,
+    pub job_uuid: Option<String>,
+    pub error: Option<String>,
+}
+
+// An audit record about to be written
+#[derive(Debug)]
+pub struct NewAuditEntry {
+    pub user_uuid: Option<Uuid>,
+    pub user_name: Option<String>,
+    // Set when an operator made the request as user_uuid
+    pub impersonator_uuid: Option<Uuid>,
+    pub impersonator_name: Option<String>,
+    pub method: String,
+    pub route: String,
+    pub path: String,
+    pub resource_type: Option<String>,
+    pub resource_uuid: Option<String>,
+    pub action: String,
+    pub request_body: Option<serde_json::Value>,
+    pub source_ip: Option<String>,
+    pub status_code: i32,
+    pub outcome: String,
+    pub job_uuid: Option<String>,
+    pub error: Option<String>,
+}
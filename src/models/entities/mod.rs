@@ -1,2 +1,5 @@
 // This module will contain database entity definitions
-// For now, this is just a placeholder
\ No newline at end of file
+
+pub mod audit;
+
+pub use audit::{AuditEntry, NewAuditEntry};
@@ -1,4 +1,3 @@
-// Database models will go here when we implement persistent storage
-// For now, this is just a placeholder
+// Database models for persistent storage
 
-pub mod entities; // Database entities
\ No newline at end of file
+pub mod entities; // Database entities
@@ -1,22 +0,0 @@
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-use anyhow::Result;
-
-use crate::error::AppError;
-
-pub struct AmonService {
-    client: reqwest::Client,
-    base_url: String,
-}
-
-impl AmonService {
-    pub fn new(base_url: String) -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            base_url,
-        }
-    }
-    
-    // Placeholder for AMON service methods
-    // In a real implementation, this would include methods for interacting with the AMON API
-}
\ No newline at end of file
@@ -0,0 +1,66 @@
+use std::time::Duration;
+use tracing::info;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+use super::health::HealthChecker;
+use super::triton::UpstreamStats;
+use super::{CnapiService, ImgapiService, NapiService, PapiService, UfdsService, VmapiService};
+
+// Registry of Triton service clients. Built once at startup and shared with
+// every handler through web::Data, so all requests reuse the same pooled
+// HTTP connections and the UFDS user cache.
+pub struct ServiceClients {
+    pub vmapi: VmapiService,
+    pub cnapi: CnapiService,
+    pub napi: NapiService,
+    pub imgapi: ImgapiService,
+    pub papi: PapiService,
+    pub ufds: UfdsService,
+    pub health: HealthChecker,
+}
+
+impl ServiceClients {
+    pub fn new(config: &Config) -> Result<Self, AppError> {
+        info!(
+            "Building shared HTTP client: timeout={}s, connect_timeout={}s, pool_idle_timeout={}s, keepalive={}s, user_agent={}",
+            config.http_timeout_secs,
+            config.http_connect_timeout_secs,
+            config.http_pool_idle_timeout_secs,
+            config.http_keepalive_secs,
+            config.http_user_agent,
+        );
+        
+        // reqwest::Client is reference counted internally, so every service
+        // shares one connection pool
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.http_timeout_secs))
+            .connect_timeout(Duration::from_secs(config.http_connect_timeout_secs))
+            .pool_idle_timeout(Duration::from_secs(config.http_pool_idle_timeout_secs))
+            .tcp_keepalive(Duration::from_secs(config.http_keepalive_secs))
+            .user_agent(config.http_user_agent.clone())
+            .build()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to build HTTP client: {}", e)))?;
+            
+        Ok(Self {
+            vmapi: VmapiService::new(client.clone(), config.vmapi_url.clone()),
+            cnapi: CnapiService::new(client.clone(), config.cnapi_url.clone()),
+            napi: NapiService::new(client.clone(), config.napi_url.clone()),
+            imgapi: ImgapiService::new(client.clone(), config.imgapi_url.clone()),
+            papi: PapiService::new(client.clone(), config.papi_url.clone()),
+            ufds: UfdsService::new(client.clone(), config.ufds_url.clone(), &config.ldap)?,
+            health: HealthChecker::new(client, config),
+        })
+    }    
+    // Circuit breaker state and failure counters for each HTTP upstream
+    pub fn upstream_stats(&self) -> Vec<UpstreamStats> {
+        vec![
+            self.vmapi.client.stats(),
+            self.cnapi.client.stats(),
+            self.napi.client.stats(),
+            self.imgapi.client.stats(),
+            self.papi.client.stats(),
+        ]
+    }
+}
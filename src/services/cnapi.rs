@@ -3,41 +3,33 @@ use uuid::Uuid;
 use anyhow::Result;
 
 use crate::error::AppError;
+use super::triton::TritonClient;
 
 pub struct CnapiService {
-    client: reqwest::Client,
-    base_url: String,
+    pub(super) client: TritonClient,
 }
 
 impl CnapiService {
-    pub fn new(base_url: String) -> Self {
+    pub fn new(client: reqwest::Client, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client: TritonClient::new("CNAPI", client, base_url),
         }
     }
     
     pub async fn list_servers(&self) -> Result<Vec<crate::api::servers::Server>, AppError> {
         // Make a real HTTP request to CNAPI
-        let servers_url = format!("{}/servers", self.base_url);
+        let servers_path = "/servers".to_string();
         
         let response = self.client
-            .get(&servers_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("CNAPI request failed: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("CNAPI returned error: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&servers_path),
+                "fetch servers from CNAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response as a vector of server objects
-        let servers_data: Vec<serde_json::Value> = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse CNAPI response: {}", e)))?;
+        let servers_data: Vec<serde_json::Value> = self.client.json(response).await?;
             
         // Convert the JSON to our Server type
         let servers = servers_data.into_iter().map(|server_json| {
@@ -120,27 +112,18 @@ impl CnapiService {
     
     pub async fn get_server(&self, uuid: &str) -> Result<crate::api::servers::Server, AppError> {
         // Make a real HTTP request to CNAPI to get a specific server
-        let server_url = format!("{}/servers/{}", self.base_url, uuid);
+        let server_path = format!("/servers/{}", uuid);
         
         let response = self.client
-            .get(&server_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("CNAPI request failed: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Server with UUID {} not found", uuid)));
-        } else if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("CNAPI returned error: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&server_path),
+                "fetch server from CNAPI",
+                Some(format!("Server with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response as a server object
-        let server_json: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse CNAPI response: {}", e)))?;
+        let server_json: serde_json::Value = self.client.json(response).await?;
             
         // Convert the JSON to our Server type
         let uuid = server_json["uuid"]
@@ -223,7 +206,7 @@ impl CnapiService {
         server: crate::api::servers::UpdateServerRequest
     ) -> Result<crate::api::servers::Server, AppError> {
         // Implement server update functionality
-        let server_url = format!("{}/servers/{}", self.base_url, uuid);
+        let server_path = format!("/servers/{}", uuid);
         
         // Build the payload for the update
         let mut payload = serde_json::Map::new();
@@ -245,22 +228,13 @@ impl CnapiService {
         }
         
         // Make the request to CNAPI
-        let response = self.client
-            .post(&server_url)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update server with CNAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Server with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to update server with CNAPI: {} - {}", status, error_text)));
-        }
+        self.client
+            .send(
+                self.client.post(&server_path).json(&payload),
+                "update server with CNAPI",
+                Some(format!("Server with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // After a successful update, fetch the updated server
         self.get_server(uuid).await
@@ -268,7 +242,7 @@ impl CnapiService {
     
     pub async fn server_action(&self, uuid: &str, action: &str) -> Result<String, AppError> {
         // Implement server actions like reboot, setup, etc.
-        let action_url = format!("{}/servers/{}", self.base_url, uuid);
+        let action_path = format!("/servers/{}", uuid);
         
         let payload = match action {
             "reboot" => serde_json::json!({ "action": "reboot" }),
@@ -279,27 +253,15 @@ impl CnapiService {
         
         // Make the request to CNAPI
         let response = self.client
-            .post(&action_url)
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to perform server action with CNAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Server with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to perform server action with CNAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.post(&action_path).json(&payload),
+                "perform server action with CNAPI",
+                Some(format!("Server with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON to get the job UUID
-        let job_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse CNAPI response: {}", e)))?;
+        let job_data: serde_json::Value = self.client.json(response).await?;
             
         let job_uuid = job_data["job_uuid"]
             .as_str()
@@ -0,0 +1,161 @@
+use futures::future::{join_all, BoxFuture, FutureExt};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config::Config;
+
+use super::UfdsService;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+// Result of probing a single upstream
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceHealth {
+    pub name: &'static str,
+    pub status: HealthStatus,
+    pub critical: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Probe results and when they were taken
+type CachedHealth = Mutex<Option<(Instant, Vec<ServiceHealth>)>>;
+
+struct HttpTarget {
+    name: &'static str,
+    ping_url: String,
+    critical: bool,
+}
+
+// Probes the configured Triton services concurrently, each with its own
+// timeout. HTTP services are checked through their /ping endpoint and UFDS
+// through a service account bind. Results are cached briefly; the lock is
+// held while probing so concurrent callers share a single round of probes.
+pub struct HealthChecker {
+    http: reqwest::Client,
+    timeout: Duration,
+    cache_ttl: Duration,
+    targets: Vec<HttpTarget>,
+    // Indexed by critical_only
+    cache: [CachedHealth; 2],
+}
+
+impl HealthChecker {
+    pub fn new(http: reqwest::Client, config: &Config) -> Self {
+        // VMAPI and UFDS are critical: without them the UI cannot log anyone
+        // in or show instances, so readiness fails while they are down
+        let services: [(&'static str, &str, bool); 9] = [
+            ("vmapi", &config.vmapi_url, true),
+            ("cnapi", &config.cnapi_url, false),
+            ("napi", &config.napi_url, false),
+            ("imgapi", &config.imgapi_url, false),
+            ("papi", &config.papi_url, false),
+            ("sapi", &config.sapi_url, false),
+            ("fwapi", &config.fwapi_url, false),
+            ("amon", &config.amon_url, false),
+            ("mahi", &config.mahi_url, false),
+        ];
+        
+        let targets = services
+            .iter()
+            .filter(|(_, url, _)| !url.is_empty())
+            .map(|(name, url, critical)| HttpTarget {
+                name,
+                ping_url: format!("{}/ping", url.trim_end_matches('/')),
+                critical: *critical,
+            })
+            .collect();
+            
+        Self {
+            http,
+            timeout: Duration::from_secs(config.health_check_timeout_secs),
+            cache_ttl: Duration::from_secs(config.health_check_cache_secs),
+            targets,
+            cache: [Mutex::new(None), Mutex::new(None)],
+        }
+    }
+    
+    // Probe every configured service, or only the critical ones, reusing
+    // results younger than the cache TTL
+    pub async fn check(&self, ufds: &UfdsService, critical_only: bool) -> Vec<ServiceHealth> {
+        let mut cached = self.cache[critical_only as usize].lock().await;
+        
+        if let Some((checked_at, health)) = cached.as_ref() {
+            if checked_at.elapsed() < self.cache_ttl {
+                return health.clone();
+            }
+        }
+        
+        let health = self.probe_all(ufds, critical_only).await;
+        *cached = Some((Instant::now(), health.clone()));
+        health
+    }
+    
+    async fn probe_all(&self, ufds: &UfdsService, critical_only: bool) -> Vec<ServiceHealth> {
+        let mut probes: Vec<BoxFuture<'_, ServiceHealth>> = self
+            .targets
+            .iter()
+            .filter(|target| target.critical || !critical_only)
+            .map(|target| self.probe_http(target).boxed())
+            .collect();
+            
+        probes.push(self.probe_ufds(ufds).boxed());
+        
+        join_all(probes).await
+    }
+    
+    async fn probe_http(&self, target: &HttpTarget) -> ServiceHealth {
+        let started = Instant::now();
+        let result = match self.http.get(&target.ping_url).timeout(self.timeout).send().await {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => Err(format!("ping returned {}", response.status())),
+            Err(e) if e.is_timeout() => Err(format!("timed out after {:?}", self.timeout)),
+            Err(e) => Err(e.to_string()),
+        };
+        
+        Self::report(target.name, target.critical, started, result)
+    }
+    
+    async fn probe_ufds(&self, ufds: &UfdsService) -> ServiceHealth {
+        let started = Instant::now();
+        let result = match tokio::time::timeout(self.timeout, ufds.ping()).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("timed out after {:?}", self.timeout)),
+        };
+        
+        Self::report("ufds", true, started, result)
+    }
+    
+    fn report(name: &'static str, critical: bool, started: Instant, result: Result<(), String>) -> ServiceHealth {
+        let latency_ms = started.elapsed().as_millis() as u64;
+        
+        match result {
+            Ok(()) => ServiceHealth {
+                name,
+                status: HealthStatus::Up,
+                critical,
+                latency_ms,
+                error: None,
+            },
+            Err(error) => {
+                warn!("Health check for {} failed: {}", name, error);
+                ServiceHealth {
+                    name,
+                    status: HealthStatus::Down,
+                    critical,
+                    latency_ms,
+                    error: Some(error),
+                }
+            }
+        }
+    }
+}
@@ -4,45 +4,40 @@ use anyhow::Result;
 use tracing::info;
 
 use crate::error::AppError;
+use super::triton::TritonClient;
 
 pub struct ImgapiService {
-    client: reqwest::Client,
-    base_url: String,
+    pub(super) client: TritonClient,
 }
 
 impl ImgapiService {
-    pub fn new(base_url: String) -> Self {
-        info!("Initializing IMGAPI service with URL: {}", base_url);
+    pub fn new(client: reqwest::Client, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client: TritonClient::new("IMGAPI", client, base_url),
         }
     }
     
-    pub async fn list_images(&self) -> Result<Vec<crate::api::images::Image>, AppError> {
+    // All images, or only those an account can use: its own and public ones
+    pub async fn list_images(&self, account: Option<&str>) -> Result<Vec<crate::api::images::Image>, AppError> {
         info!("Fetching image list from IMGAPI");
         
         // Construct the URL for the IMGAPI images endpoint
-        let images_url = format!("{}/images", self.base_url);
+        let images_path = match account {
+            Some(account) => format!("/images?account={}", account),
+            None => "/images".to_string(),
+        };
         
         // Make the request to IMGAPI
         let response = self.client
-            .get(&images_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch images from IMGAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch images from IMGAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&images_path),
+                "fetch images from IMGAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON
-        let images_data: Vec<serde_json::Value> = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse IMGAPI response: {}", e)))?;
+        let images_data: Vec<serde_json::Value> = self.client.json(response).await?;
             
         // Convert the response data to our Image model
         let images: Vec<crate::api::images::Image> = images_data
@@ -53,6 +48,8 @@ impl ImgapiService {
                 let version = image_data["version"].as_str()?;
                 let os = image_data["os"].as_str()?;
                 let state = image_data["state"].as_str()?;
+                let image_type = image_data["type"].as_str().unwrap_or("").to_string();
+                let image_size = image_data["image_size"].as_u64();
                 let public = image_data["public"].as_bool().unwrap_or(false);
                 let published_at = image_data["published_at"].as_str().unwrap_or("");
                 
@@ -84,6 +81,8 @@ impl ImgapiService {
                     version: version.to_string(),
                     os: os.to_string(),
                     state: state.to_string(),
+                    image_type,
+                    image_size,
                     owner,
                     public,
                     published_at: published_at.to_string(),
@@ -102,30 +101,19 @@ impl ImgapiService {
         info!("Fetching image with UUID: {}", uuid);
         
         // Construct the URL for the IMGAPI image endpoint
-        let image_url = format!("{}/images/{}", self.base_url, uuid);
+        let image_path = format!("/images/{}", uuid);
         
         // Make the request to IMGAPI
         let response = self.client
-            .get(&image_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch image from IMGAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Image with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch image from IMGAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&image_path),
+                "fetch image from IMGAPI",
+                Some(format!("Image with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON
-        let image_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse IMGAPI response: {}", e)))?;
+        let image_data: serde_json::Value = self.client.json(response).await?;
             
         // Extract the required fields from the response
         let name = image_data["name"]
@@ -144,6 +132,9 @@ impl ImgapiService {
             .as_str()
             .ok_or_else(|| AppError::InternalServerError("State not found in IMGAPI response".to_string()))?;
             
+        let image_type = image_data["type"].as_str().unwrap_or("").to_string();
+        let image_size = image_data["image_size"].as_u64();
+        
         let public = image_data["public"].as_bool().unwrap_or(false);
         let published_at = image_data["published_at"].as_str().unwrap_or("");
         
@@ -177,6 +168,8 @@ impl ImgapiService {
             version: version.to_string(),
             os: os.to_string(),
             state: state.to_string(),
+            image_type,
+            image_size,
             owner,
             public,
             published_at: published_at.to_string(),
@@ -197,25 +190,16 @@ impl ImgapiService {
         info!("Updating image with UUID: {}", uuid);
         
         // Construct the URL for the IMGAPI image endpoint
-        let image_url = format!("{}/images/{}", self.base_url, uuid);
+        let image_path = format!("/images/{}", uuid);
         
         // Make the request to IMGAPI
-        let response = self.client
-            .post(&image_url)
-            .json(&image)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update image with IMGAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Image with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to update image with IMGAPI: {} - {}", status, error_text)));
-        }
+        self.client
+            .send(
+                self.client.post(&image_path).json(&image),
+                "update image with IMGAPI",
+                Some(format!("Image with UUID {} not found", uuid)),
+            )
+            .await?;
         
         info!("Successfully updated image {}", uuid);
         
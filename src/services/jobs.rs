@@ -1,46 +0,0 @@
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-use anyhow::Result;
-
-use crate::error::AppError;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Job {
-    pub uuid: String,
-    pub name: String,
-    pub execution: String,
-    pub params: serde_json::Value,
-    pub created_at: String,
-    pub finished: bool,
-    pub status: String,
-}
-
-pub struct JobsService {
-    client: reqwest::Client,
-    base_url: String,
-}
-
-impl JobsService {
-    pub fn new(base_url: String) -> Self {
-        Self {
-            client: reqwest::Client::new(),
-            base_url,
-        }
-    }
-    
-    pub async fn list_jobs(&self) -> Result<Vec<Job>, AppError> {
-        // This is a placeholder for actual implementation
-        // In a real implementation, this would make an HTTP request to the Workflow API
-        Ok(vec![])
-    }
-    
-    pub async fn get_job(&self, uuid: &str) -> Result<Job, AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::NotFound(format!("Job with UUID {} not found", uuid)))
-    }
-    
-    pub async fn cancel_job(&self, uuid: &str) -> Result<(), AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::InternalServerError("Not implemented".to_string()))
-    }
-}
\ No newline at end of file
@@ -0,0 +1,468 @@
+use ldap3::result::{LdapError, LdapResult, Result as LdapOpResult, SearchResult};
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Mod, RequestId, Scope};
+use native_tls::{Certificate, TlsConnector};
+use std::collections::HashSet;
+use std::fs;
+use std::hash::Hash;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{error, info, warn};
+
+use crate::config::LdapConfig;
+use crate::error::AppError;
+
+// Sizing and timeouts for UFDS connections
+#[derive(Debug, Clone)]
+pub struct LdapPoolConfig {
+    // Service-account connections kept for searches and user management
+    pub pool_size: usize,
+    // Short-lived connections used to check user passwords at the same time
+    pub max_concurrent_binds: usize,
+    pub connect_timeout: Duration,
+    pub operation_timeout: Duration,
+    // How long a request waits for a pooled connection before giving up
+    pub acquire_timeout: Duration,
+    // Idle connections older than this are probed before being reused
+    pub health_check_interval: Duration,
+}
+
+impl LdapPoolConfig {
+    pub fn from_config(config: &LdapConfig) -> Self {
+        Self {
+            pool_size: config.pool_size.max(1),
+            max_concurrent_binds: config.max_concurrent_binds.max(1),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            operation_timeout: Duration::from_secs(config.operation_timeout_secs),
+            acquire_timeout: Duration::from_secs(config.pool_timeout_secs),
+            health_check_interval: Duration::from_secs(config.health_check_interval_secs),
+        }
+    }
+}
+
+// How to reach the directory server
+#[derive(Clone)]
+pub struct LdapTarget {
+    url: String,
+    starttls: bool,
+    // Used for ldaps:// and StartTLS
+    connector: TlsConnector,
+}
+
+impl LdapTarget {
+    pub fn new(url: String, config: &LdapConfig) -> Result<Self, AppError> {
+        let mut builder = TlsConnector::builder();
+
+        if let Some(ca_file) = &config.ca_file {
+            for certificate in load_ca_bundle(ca_file)? {
+                builder.add_root_certificate(certificate);
+            }
+            info!("Trusting CA certificates from {} for UFDS", ca_file);
+        }
+        if !config.verify_certificates {
+            warn!("LDAP certificate verification is disabled");
+            builder.danger_accept_invalid_certs(true);
+        }
+
+        let connector = builder.build().map_err(|e| {
+            error!("Failed to build TLS connector: {}", e);
+            AppError::InternalServerError(format!("TLS configuration error: {}", e))
+        })?;
+
+        if config.starttls && url.starts_with("ldaps://") {
+            warn!("LDAP StartTLS is ignored for ldaps:// URLs");
+        }
+
+        Ok(Self {
+            starttls: config.starttls && url.starts_with("ldap://"),
+            url,
+            connector,
+        })
+    }
+
+    // Open an unauthenticated connection. The connection is driven by a
+    // spawned task that exits when the last handle is dropped.
+    pub async fn connect(&self, connect_timeout: Duration) -> Result<Ldap, AppError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(connect_timeout)
+            .set_connector(self.connector.clone())
+            .set_starttls(self.starttls);
+
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(|e| {
+                error!("Failed to connect to LDAP server {}: {}", self.url, e);
+                AppError::ServiceUnavailable(format!("Cannot connect to LDAP server: {}", e))
+            })?;
+
+        tokio::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                warn!("LDAP connection closed with error: {}", e);
+            }
+        });
+
+        Ok(ldap)
+    }
+}
+
+// Read every certificate from a PEM bundle
+fn load_ca_bundle(path: &str) -> Result<Vec<Certificate>, AppError> {
+    let pem = fs::read_to_string(path)
+        .map_err(|e| AppError::InternalServerError(format!("Cannot read LDAP CA file {}: {}", path, e)))?;
+
+    const END: &str = "-----END CERTIFICATE-----";
+    let certificates = pem
+        .split_inclusive(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| {
+            Certificate::from_pem(block.trim().as_bytes())
+                .map_err(|e| AppError::InternalServerError(format!("Invalid certificate in {}: {}", path, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if certificates.is_empty() {
+        return Err(AppError::InternalServerError(format!("No certificates found in LDAP CA file {}", path)));
+    }
+
+    Ok(certificates)
+}
+
+struct IdleConnection {
+    ldap: Ldap,
+    since: Instant,
+}
+
+// A bounded pool of connections bound as the UFDS service account.
+// Connections are opened lazily, dropped when the server closes them, and
+// probed before reuse once they have been idle for a while.
+pub struct LdapPool {
+    target: LdapTarget,
+    config: LdapPoolConfig,
+    bind_dn: String,
+    bind_password: String,
+    idle: Mutex<Vec<IdleConnection>>,
+    permits: Semaphore,
+    bind_permits: Semaphore,
+}
+
+impl LdapPool {
+    pub fn new(target: LdapTarget, config: LdapPoolConfig, bind_dn: String, bind_password: String) -> Self {
+        Self {
+            permits: Semaphore::new(config.pool_size),
+            bind_permits: Semaphore::new(config.max_concurrent_binds),
+            idle: Mutex::new(Vec::new()),
+            target,
+            config,
+            bind_dn,
+            bind_password,
+        }
+    }
+
+    // Check out a service connection, reusing an idle one when it is healthy
+    pub async fn get(&self) -> Result<PooledConnection<'_>, AppError> {
+        let permit = acquire(&self.permits, self.config.acquire_timeout).await?;
+
+        loop {
+            let idle = self.idle.lock().unwrap().pop();
+            let Some(IdleConnection { mut ldap, since }) = idle else {
+                break;
+            };
+
+            if ldap.is_closed() {
+                continue;
+            }
+            if since.elapsed() >= self.config.health_check_interval && !self.probe(&mut ldap).await {
+                info!("Discarding unresponsive UFDS connection");
+                continue;
+            }
+
+            return Ok(PooledConnection::new(self, ldap, permit));
+        }
+
+        let ldap = self.open().await?;
+        Ok(PooledConnection::new(self, ldap, permit))
+    }
+
+    // Check a user's password with a bind on a connection of its own, so the
+    // pooled connections stay bound as the service account
+    pub async fn check_password(&self, dn: &str, password: &str) -> Result<(), AppError> {
+        // An empty password would be an anonymous bind, which always succeeds
+        if password.is_empty() {
+            return Err(AppError::AuthError("Invalid username or password".to_string()));
+        }
+
+        let _permit = acquire(&self.bind_permits, self.config.acquire_timeout).await?;
+        let mut ldap = self.target.connect(self.config.connect_timeout).await?;
+
+        let result = ldap
+            .with_timeout(self.config.operation_timeout)
+            .simple_bind(dn, password)
+            .await;
+        let _ = ldap.unbind().await;
+
+        match result {
+            Ok(result) if result.rc == 0 => Ok(()),
+            // invalidCredentials
+            Ok(result) if result.rc == 49 => Err(AppError::AuthError("Invalid username or password".to_string())),
+            Ok(result) => {
+                warn!("LDAP bind for {} failed: {}", dn, result);
+                Err(AppError::AuthError(format!("Authentication failed: {}", result)))
+            }
+            Err(e) => {
+                error!("LDAP bind for {} failed: {}", dn, e);
+                Err(AppError::ServiceUnavailable(format!("Cannot reach UFDS: {}", e)))
+            }
+        }
+    }
+
+    // Round-trip a pooled connection to prove UFDS is answering
+    pub async fn ping(&self) -> Result<(), AppError> {
+        let mut conn = self.get().await?;
+        if conn.probe().await {
+            Ok(())
+        } else {
+            conn.discard();
+            Err(AppError::ServiceUnavailable("UFDS did not answer".to_string()))
+        }
+    }
+
+    async fn open(&self) -> Result<Ldap, AppError> {
+        let mut ldap = self.target.connect(self.config.connect_timeout).await?;
+
+        ldap.with_timeout(self.config.operation_timeout)
+            .simple_bind(&self.bind_dn, &self.bind_password)
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("LDAP service bind failed for {}: {}", self.bind_dn, e);
+                AppError::ServiceUnavailable(format!("Cannot bind to UFDS: {}", e))
+            })?;
+
+        Ok(ldap)
+    }
+
+    // Any reply to a root DSE read, even an error result, means the server is there
+    async fn probe(&self, ldap: &mut Ldap) -> bool {
+        ldap.with_timeout(self.config.operation_timeout)
+            .search("", Scope::Base, "(objectclass=*)", vec!["1.1"])
+            .await
+            .is_ok()
+    }
+}
+
+async fn acquire(semaphore: &Semaphore, timeout: Duration) -> Result<SemaphorePermit<'_>, AppError> {
+    tokio::time::timeout(timeout, semaphore.acquire())
+        .await
+        .map_err(|_| AppError::ServiceUnavailable("Timed out waiting for a UFDS connection".to_string()))?
+        .map_err(|_| AppError::ServiceUnavailable("UFDS connection pool is closed".to_string()))
+}
+
+// A checked-out service connection. It goes back to the pool when dropped
+// unless the server has closed it or an operation on it timed out or failed
+// in transport: a late reply could still arrive on such a connection, so it
+// is never reused. Every operation started through it is subject to the
+// configured operation timeout.
+pub struct PooledConnection<'a> {
+    pool: &'a LdapPool,
+    ldap: Option<Ldap>,
+    reusable: bool,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<'a> PooledConnection<'a> {
+    fn new(pool: &'a LdapPool, ldap: Ldap, permit: SemaphorePermit<'a>) -> Self {
+        Self {
+            pool,
+            ldap: Some(ldap),
+            reusable: true,
+            _permit: permit,
+        }
+    }
+
+    async fn probe(&mut self) -> bool {
+        let pool = self.pool;
+        match self.ldap.as_mut() {
+            Some(ldap) => pool.probe(ldap).await,
+            None => false,
+        }
+    }
+
+    // Drop the connection instead of returning it to the pool
+    pub fn discard(&mut self) {
+        self.reusable = false;
+    }
+
+    // Pass an operation's result through, discarding the connection if the
+    // operation timed out or the connection failed. Errors reported by the
+    // server leave the connection usable.
+    pub fn check<T>(&mut self, result: LdapOpResult<T>) -> LdapOpResult<T> {
+        if let Err(e) = &result {
+            if is_connection_error(e) {
+                warn!("Discarding UFDS connection after error: {}", e);
+                self.discard();
+            }
+        }
+        result
+    }
+
+    // The operations below shadow those of Ldap so that every use of a
+    // pooled connection goes through check()
+
+    pub async fn search<'s, S: AsRef<str> + Send + Sync + 's, A: AsRef<[S]> + Send + Sync + 's>(
+        &mut self,
+        base: &str,
+        scope: Scope,
+        filter: &str,
+        attrs: A,
+    ) -> LdapOpResult<SearchResult> {
+        let result = (**self).search(base, scope, filter, attrs).await;
+        self.check(result)
+    }
+
+    pub async fn add<S: AsRef<[u8]> + Eq + Hash>(
+        &mut self,
+        dn: &str,
+        attrs: Vec<(S, HashSet<S>)>,
+    ) -> LdapOpResult<LdapResult> {
+        let result = (**self).add(dn, attrs).await;
+        self.check(result)
+    }
+
+    pub async fn modify<S: AsRef<[u8]> + Eq + Hash>(&mut self, dn: &str, mods: Vec<Mod<S>>) -> LdapOpResult<LdapResult> {
+        let result = (**self).modify(dn, mods).await;
+        self.check(result)
+    }
+
+    pub async fn delete(&mut self, dn: &str) -> LdapOpResult<LdapResult> {
+        let result = (**self).delete(dn).await;
+        self.check(result)
+    }
+
+    pub async fn abandon(&mut self, msgid: RequestId) -> LdapOpResult<()> {
+        let result = (**self).abandon(msgid).await;
+        self.check(result)
+    }
+}
+
+// Errors after which the state of the connection is unknown
+fn is_connection_error(error: &LdapError) -> bool {
+    matches!(
+        error,
+        LdapError::Io { .. }
+            | LdapError::OpSend { .. }
+            | LdapError::ResultRecv { .. }
+            | LdapError::IdScrubSend { .. }
+            | LdapError::MiscSend { .. }
+            | LdapError::Timeout { .. }
+            | LdapError::EndOfStream
+    )
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Ldap;
+
+    fn deref(&self) -> &Ldap {
+        self.ldap.as_ref().expect("pooled LDAP connection")
+    }
+}
+
+impl DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Ldap {
+        let timeout = self.pool.config.operation_timeout;
+        self.ldap
+            .as_mut()
+            .expect("pooled LDAP connection")
+            .with_timeout(timeout)
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(mut ldap) = self.ldap.take() {
+            if self.reusable && !ldap.is_closed() {
+                self.pool.idle.lock().unwrap().push(IdleConnection {
+                    ldap,
+                    since: Instant::now(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_keep_the_connection() {
+        let rejected = LdapError::LdapResult {
+            result: LdapResult {
+                rc: 49,
+                matched: String::new(),
+                text: "invalid credentials".to_string(),
+                refs: vec![],
+                ctrls: vec![],
+            },
+        };
+
+        assert!(!is_connection_error(&rejected));
+        assert!(!is_connection_error(&LdapError::FilterParsing));
+        assert!(is_connection_error(&LdapError::EndOfStream));
+        assert!(is_connection_error(&LdapError::Io {
+            source: std::io::Error::from(std::io::ErrorKind::ConnectionReset),
+        }));
+    }
+
+    // Self-signed P-256 certificate, valid until 2126
+    const CA_PEM: &str = "\
+-----BEGIN CERTIFICATE-----
+MIIBhDCCASmgAwIBAgIUM1wDEOxbxUHlA0G5m1HIICqbO0QwCgYIKoZIzj0EAwIw
+FjEUMBIGA1UEAwwLVGVzdCBDQSBPbmUwIBcNMjYxMDE4MjAxMDMxWhgPMjEyNjA5
+MjQyMDEwMzFaMBYxFDASBgNVBAMMC1Rlc3QgQ0EgT25lMFkwEwYHKoZIzj0CAQYI
+KoZIzj0DAQcDQgAEIHbywb/GnbclP8+bYYZgZ5Me/yK9pUEED/zl9dA8aOLI5d6l
+/41dZ6HSvqI41aiwMHGcMGKU+y7l4kPMnfE/jKNTMFEwHQYDVR0OBBYEFG43oahD
+PG/fuoHTx5r4OD9P3jsFMB8GA1UdIwQYMBaAFG43oahDPG/fuoHTx5r4OD9P3jsF
+MA8GA1UdEwEB/wQFMAMBAf8wCgYIKoZIzj0EAwIDSQAwRgIhAPDvl+4WMmiag6+E
+usSEGmW97KkrI4PWYVutZLO6c8EHAiEAkjNlldWnB9Fd+4/aid0cqYEHG6DKCeYr
+Nla0GFGFP5A=
+-----END CERTIFICATE-----
+";
+
+    fn write_bundle(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("adminui-ca-{}-{}.pem", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn ca_bundle_loads_every_certificate() {
+        // Text between certificates, as openssl and most bundles include, is skipped
+        let path = write_bundle("two", &format!("# first\n{}\nsubject=CN = Test CA One\n{}\n", CA_PEM, CA_PEM));
+        let certificates = load_ca_bundle(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(certificates.len(), 2);
+    }
+
+    #[test]
+    fn bad_ca_bundles_are_rejected() {
+        let message = |result: Result<Vec<Certificate>, AppError>| match result {
+            Err(AppError::InternalServerError(message)) => message,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("bundle was accepted"),
+        };
+
+        let missing = std::env::temp_dir().join("adminui-ca-does-not-exist.pem");
+        assert!(message(load_ca_bundle(&missing.to_string_lossy())).starts_with("Cannot read LDAP CA file"));
+
+        let path = write_bundle("empty", "not a certificate\n");
+        assert!(message(load_ca_bundle(&path)).starts_with("No certificates found"));
+        fs::remove_file(&path).unwrap();
+
+        let corrupt = CA_PEM.replace("MIIB", "AAAA");
+        let path = write_bundle("corrupt", &format!("{}\n{}\n", CA_PEM, corrupt));
+        assert!(message(load_ca_bundle(&path)).starts_with("Invalid certificate"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -6,15 +6,18 @@ mod cnapi;
 mod imgapi;
 mod napi;
 mod ufds;
-mod amon;
-mod jobs;
+mod ldap_pool;
 mod papi;
+mod clients;
+mod triton;
+mod health;
 
-pub use vmapi::VmapiService;
+pub use vmapi::{VmapiService, VmListQuery, VmSort};
 pub use cnapi::CnapiService;
 pub use imgapi::ImgapiService;
 pub use napi::NapiService;
 pub use ufds::UfdsService;
-pub use amon::AmonService;
-pub use jobs::JobsService;
-pub use papi::PapiService;
\ No newline at end of file
+pub use papi::PapiService;
+pub use clients::ServiceClients;
+pub use triton::UpstreamStats;
+pub use health::{HealthStatus, ServiceHealth};
\ No newline at end of file
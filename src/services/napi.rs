@@ -4,18 +4,16 @@ use anyhow::Result;
 use tracing::info;
 
 use crate::error::AppError;
+use super::triton::TritonClient;
 
 pub struct NapiService {
-    client: reqwest::Client,
-    base_url: String,
+    pub(super) client: TritonClient,
 }
 
 impl NapiService {
-    pub fn new(base_url: String) -> Self {
-        info!("Initializing NAPI service with URL: {}", base_url);
+    pub fn new(client: reqwest::Client, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client: TritonClient::new("NAPI", client, base_url),
         }
     }
     
@@ -23,26 +21,19 @@ impl NapiService {
         info!("Fetching network list from NAPI");
         
         // Construct the URL for the NAPI networks endpoint
-        let networks_url = format!("{}/networks", self.base_url);
+        let networks_path = "/networks".to_string();
         
         // Make the request to NAPI
         let response = self.client
-            .get(&networks_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch networks from NAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch networks from NAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&networks_path),
+                "fetch networks from NAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON
-        let networks_data: Vec<serde_json::Value> = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse NAPI response: {}", e)))?;
+        let networks_data: Vec<serde_json::Value> = self.client.json(response).await?;
             
         // Convert the response data to our Network model
         let networks: Vec<crate::api::networks::Network> = networks_data
@@ -90,30 +81,19 @@ impl NapiService {
         info!("Fetching network with UUID: {}", uuid);
         
         // Construct the URL for the NAPI network endpoint
-        let network_url = format!("{}/networks/{}", self.base_url, uuid);
+        let network_path = format!("/networks/{}", uuid);
         
         // Make the request to NAPI
         let response = self.client
-            .get(&network_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch network from NAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Network with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch network from NAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&network_path),
+                "fetch network from NAPI",
+                Some(format!("Network with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON
-        let network_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse NAPI response: {}", e)))?;
+        let network_data: serde_json::Value = self.client.json(response).await?;
             
         // Extract the required fields from the response
         let name = network_data["name"]
@@ -179,27 +159,19 @@ impl NapiService {
         info!("Creating new network with name: {}", network.name);
         
         // Construct the URL for the NAPI networks endpoint
-        let networks_url = format!("{}/networks", self.base_url);
+        let networks_path = "/networks".to_string();
         
         // Make the request to NAPI
         let response = self.client
-            .post(&networks_url)
-            .json(&network)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to create network with NAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to create network with NAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.post(&networks_path).json(&network),
+                "create network with NAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON
-        let network_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse NAPI response: {}", e)))?;
+        let network_data: serde_json::Value = self.client.json(response).await?;
             
         // Extract the UUID from the response
         let uuid = network_data["uuid"]
@@ -220,25 +192,16 @@ impl NapiService {
         info!("Updating network with UUID: {}", uuid);
         
         // Construct the URL for the NAPI network endpoint
-        let network_url = format!("{}/networks/{}", self.base_url, uuid);
+        let network_path = format!("/networks/{}", uuid);
         
         // Make the request to NAPI
-        let response = self.client
-            .put(&network_url)
-            .json(&network)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update network with NAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Network with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to update network with NAPI: {} - {}", status, error_text)));
-        }
+        self.client
+            .send(
+                self.client.put(&network_path).json(&network),
+                "update network with NAPI",
+                Some(format!("Network with UUID {} not found", uuid)),
+            )
+            .await?;
         
         info!("Successfully updated network {}", uuid);
         
@@ -250,26 +213,52 @@ impl NapiService {
         info!("Deleting network with UUID: {}", uuid);
         
         // Construct the URL for the NAPI network endpoint
-        let network_url = format!("{}/networks/{}", self.base_url, uuid);
+        let network_path = format!("/networks/{}", uuid);
         
         // Make the request to NAPI
-        let response = self.client
-            .delete(&network_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to delete network with NAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Network with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to delete network with NAPI: {} - {}", status, error_text)));
-        }
+        self.client
+            .send(
+                self.client.delete(&network_path),
+                "delete network with NAPI",
+                Some(format!("Network with UUID {} not found", uuid)),
+            )
+            .await?;
         
         info!("Successfully deleted network {}", uuid);
         Ok(())
     }
-}
\ No newline at end of file
+    
+    // Every network's record of an IP address
+    pub async fn search_ips(&self, ip: &str) -> Result<Vec<crate::api::networks::NetworkIp>, AppError> {
+        info!("Searching NAPI for IP: {}", ip);
+        
+        // NAPI answers 404 when no network has the address
+        let response = self.client
+            .send(
+                self.client.get("/search/ips").query(&[("ip", ip)]),
+                "search IPs in NAPI",
+                Some(format!("IP {} not found", ip)),
+            )
+            .await?;
+        
+        self.client.json(response).await
+    }
+    
+    // The NIC with a MAC address, in any common notation
+    pub async fn get_nic(&self, mac: &str) -> Result<crate::api::networks::Nic, AppError> {
+        info!("Fetching NIC with MAC: {}", mac);
+        
+        // NAPI takes the MAC without separators
+        let nic_path = format!("/nics/{}", mac.replace([':', '-'], ""));
+        
+        let response = self.client
+            .send(
+                self.client.get(&nic_path),
+                "fetch NIC from NAPI",
+                Some(format!("NIC with MAC {} not found", mac)),
+            )
+            .await?;
+        
+        self.client.json(response).await
+    }
+}
@@ -4,18 +4,16 @@ use anyhow::Result;
 use tracing::info;
 
 use crate::error::AppError;
+use super::triton::TritonClient;
 
 pub struct PapiService {
-    client: reqwest::Client,
-    base_url: String,
+    pub(super) client: TritonClient,
 }
 
 impl PapiService {
-    pub fn new(base_url: String) -> Self {
-        info!("Initializing PAPI service with URL: {}", base_url);
+    pub fn new(client: reqwest::Client, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client: TritonClient::new("PAPI", client, base_url),
         }
     }
     
@@ -23,29 +21,19 @@ impl PapiService {
         info!("Fetching package list from PAPI");
         
         // Construct the URL for the PAPI packages endpoint
-        let packages_url = format!("{}/packages", self.base_url);
+        let packages_path = "/packages".to_string();
         
         // Make the request to PAPI
         let response = self.client
-            .get(&packages_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch packages from PAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch packages from PAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&packages_path),
+                "fetch packages from PAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON directly into our Package model
-        let packages: Vec<crate::api::packages::Package> = response
-            .json()
-            .await
-            .map_err(|e| {
-                info!("Error parsing PAPI response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse PAPI response: {}", e))
-            })?;
+        let packages: Vec<crate::api::packages::Package> = self.client.json(response).await?;
         
         for pkg in &packages {
             info!("Found package: {} ({})", pkg.name, pkg.uuid);
@@ -59,33 +47,19 @@ impl PapiService {
         info!("Fetching package with UUID: {}", uuid);
         
         // Construct the URL for the PAPI package endpoint
-        let package_url = format!("{}/packages/{}", self.base_url, uuid);
+        let package_path = format!("/packages/{}", uuid);
         
         // Make the request to PAPI
         let response = self.client
-            .get(&package_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch package from PAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Package with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch package from PAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&package_path),
+                "fetch package from PAPI",
+                Some(format!("Package with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON directly into our Package model
-        let package: crate::api::packages::Package = response
-            .json()
-            .await
-            .map_err(|e| {
-                info!("Error parsing PAPI response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse PAPI response: {}", e))
-            })?;
+        let package: crate::api::packages::Package = self.client.json(response).await?;
         
         info!("Successfully fetched package {} ({})", uuid, package.name);
         Ok(package)
@@ -98,27 +72,19 @@ impl PapiService {
         info!("Creating new package with name: {}", package.name);
         
         // Construct the URL for the PAPI packages endpoint
-        let packages_url = format!("{}/packages", self.base_url);
+        let packages_path = "/packages".to_string();
         
         // Make the request to PAPI
         let response = self.client
-            .post(&packages_url)
-            .json(&package)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to create package with PAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to create package with PAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.post(&packages_path).json(&package),
+                "create package with PAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON
-        let package_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse PAPI response: {}", e)))?;
+        let package_data: serde_json::Value = self.client.json(response).await?;
             
         // Extract the UUID from the response
         let uuid = package_data["uuid"]
@@ -139,25 +105,16 @@ impl PapiService {
         info!("Updating package with UUID: {}", uuid);
         
         // Construct the URL for the PAPI package endpoint
-        let package_url = format!("{}/packages/{}", self.base_url, uuid);
+        let package_path = format!("/packages/{}", uuid);
         
         // Make the request to PAPI
-        let response = self.client
-            .put(&package_url)
-            .json(&package)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update package with PAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Package with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to update package with PAPI: {} - {}", status, error_text)));
-        }
+        self.client
+            .send(
+                self.client.put(&package_path).json(&package),
+                "update package with PAPI",
+                Some(format!("Package with UUID {} not found", uuid)),
+            )
+            .await?;
         
         info!("Successfully updated package {}", uuid);
         
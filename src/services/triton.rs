@@ -0,0 +1,646 @@
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::error::{AppError, FieldError};
+use crate::metrics::{UPSTREAM_ERRORS, UPSTREAM_REQUEST_DURATION};
+
+// Retry policy for idempotent requests
+const MAX_ATTEMPTS: u32 = 3;
+const BACKOFF_BASE: Duration = Duration::from_millis(100);
+const BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+// Circuit breaker policy: trip after this many consecutive failures and
+// reject calls until the cool-down has elapsed, then let a single probe through.
+// A probe that never reports back (its future was dropped) is given up on after
+// another cool-down so the breaker cannot stay half-open forever.
+const BREAKER_FAILURE_THRESHOLD: u32 = 5;
+const BREAKER_COOL_DOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug)]
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+    last_error: Option<String>,
+    last_failure_at: Option<DateTime<Utc>>,
+}
+
+// Snapshot of a client's breaker and counters for the diagnostics endpoint
+#[derive(Debug, Serialize)]
+pub struct UpstreamStats {
+    pub service: &'static str,
+    pub base_url: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub requests: u64,
+    pub failures: u64,
+    pub retries: u64,
+    pub rejected: u64,
+    pub last_error: Option<String>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+// Base HTTP client shared by the Triton service wrappers. It owns the
+// send/check-status/parse-JSON plumbing, retries idempotent requests with
+// jittered exponential backoff, and keeps a per-service circuit breaker so a
+// dead upstream fails fast with ServiceUnavailable.
+pub struct TritonClient {
+    service: &'static str,
+    http: reqwest::Client,
+    base_url: String,
+    breaker: Mutex<Breaker>,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    retries: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl TritonClient {
+    pub fn new(service: &'static str, http: reqwest::Client, base_url: String) -> Self {
+        info!("Initializing {} client with URL: {}", service, base_url);
+        Self {
+            service,
+            http,
+            base_url,
+            breaker: Mutex::new(Breaker {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                opened_at: None,
+                probe_started_at: None,
+                last_error: None,
+                last_failure_at: None,
+            }),
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http.request(method, self.url(path))
+    }
+
+    pub fn get(&self, path: &str) -> RequestBuilder {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> RequestBuilder {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> RequestBuilder {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn delete(&self, path: &str) -> RequestBuilder {
+        self.request(Method::DELETE, path)
+    }
+
+    // Send a request and check its status. `action` describes the call for
+    // error messages ("fetch VMs from VMAPI"). Error responses are mapped to
+    // AppError from the restify error body; `not_found` overrides the message
+    // reported for a missing resource.
+    pub async fn send(
+        &self,
+        request: RequestBuilder,
+        action: &str,
+        not_found: Option<String>,
+    ) -> Result<Response, AppError> {
+        let response = self.execute(request, action).await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(upstream_error(action, status, &body, not_found));
+        }
+
+        Ok(response)
+    }
+
+    // Parse a successful response body as JSON
+    pub async fn json<T: DeserializeOwned>(&self, response: Response) -> Result<T, AppError> {
+        response.json().await.map_err(|e| {
+            info!("Error parsing {} response: {}", self.service, e);
+            AppError::InternalServerError(format!("Failed to parse {} response: {}", self.service, e))
+        })
+    }
+
+    pub fn stats(&self) -> UpstreamStats {
+        let breaker = self.breaker.lock().unwrap();
+        UpstreamStats {
+            service: self.service,
+            base_url: self.base_url.clone(),
+            state: breaker.state,
+            consecutive_failures: breaker.consecutive_failures,
+            requests: self.requests.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            last_error: breaker.last_error.clone(),
+            last_failure_at: breaker.last_failure_at,
+        }
+    }
+
+    // Dispatch a request through the circuit breaker, retrying idempotent
+    // methods on connection errors and gateway-style 5xx responses
+    async fn execute(&self, request: RequestBuilder, action: &str) -> Result<Response, AppError> {
+        let request = request
+            .build()
+            .map_err(|e| AppError::InternalServerError(format!("Failed to {}: {}", action, e)))?;
+        let idempotent = matches!(*request.method(), Method::GET | Method::HEAD);
+        let max_attempts = if idempotent { MAX_ATTEMPTS } else { 1 };
+
+        let mut pending = Some(request);
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            let current = pending.take().expect("request is available for each attempt");
+
+            // Keep a copy for the next attempt; bodies that cannot be cloned
+            // (streams) are sent once
+            if attempt < max_attempts {
+                pending = current.try_clone();
+            }
+
+            self.acquire()?;
+            self.requests.fetch_add(1, Ordering::Relaxed);
+
+            let method = current.method().to_string();
+            let started = Instant::now();
+            let result = self.http.execute(current).await;
+            self.observe(&method, &result, started);
+
+            let error = match result {
+                Ok(response) if !is_upstream_failure(response.status()) => {
+                    self.record_success();
+                    return Ok(response);
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    self.record_failure(format!("{} returned {}", self.service, status));
+                    if pending.is_none() {
+                        return Ok(response);
+                    }
+                    format!("{}", status)
+                }
+                Err(e) => {
+                    self.record_failure(e.to_string());
+                    if pending.is_none() {
+                        return Err(transport_error(action, &e));
+                    }
+                    e.to_string()
+                }
+            };
+
+            let delay = backoff(attempt);
+            warn!(
+                "{} request failed ({}), retrying in {:?} (attempt {}/{})",
+                self.service, error, delay, attempt, max_attempts
+            );
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    // Export per-attempt latency and error counts to Prometheus
+    fn observe(&self, method: &str, result: &Result<Response, reqwest::Error>, started: Instant) {
+        let service = self.service.to_ascii_lowercase();
+        let outcome = match result {
+            Ok(response) => format!("{}xx", response.status().as_u16() / 100),
+            Err(_) => "error".to_string(),
+        };
+
+        UPSTREAM_REQUEST_DURATION
+            .with_label_values(&[&service, method, &outcome])
+            .observe(started.elapsed().as_secs_f64());
+
+        let kind = match result {
+            Ok(response) if response.status().is_server_error() => Some("http_5xx"),
+            Ok(_) => None,
+            Err(e) if e.is_timeout() => Some("timeout"),
+            Err(e) if e.is_connect() => Some("connect"),
+            Err(_) => Some("transport"),
+        };
+
+        if let Some(kind) = kind {
+            UPSTREAM_ERRORS.with_label_values(&[&service, kind]).inc();
+        }
+    }
+
+    // Check the breaker before sending. An open breaker rejects immediately
+    // until the cool-down passes, after which one probe request is allowed.
+    fn acquire(&self) -> Result<(), AppError> {
+        let mut breaker = self.breaker.lock().unwrap();
+
+        match breaker.state {
+            BreakerState::Closed => Ok(()),
+            BreakerState::Open => {
+                let cooled_down = breaker
+                    .opened_at
+                    .map(|opened_at| opened_at.elapsed() >= BREAKER_COOL_DOWN)
+                    .unwrap_or(true);
+
+                if cooled_down {
+                    info!("{} circuit breaker half-open, sending probe request", self.service);
+                    breaker.state = BreakerState::HalfOpen;
+                    breaker.probe_started_at = Some(Instant::now());
+                    Ok(())
+                } else {
+                    drop(breaker);
+                    self.reject()
+                }
+            }
+            // A probe is already in flight, unless it was abandoned without
+            // recording an outcome
+            BreakerState::HalfOpen => {
+                let abandoned = breaker
+                    .probe_started_at
+                    .map(|started| started.elapsed() >= BREAKER_COOL_DOWN)
+                    .unwrap_or(true);
+
+                if abandoned {
+                    warn!("{} circuit breaker probe never completed, sending another", self.service);
+                    breaker.probe_started_at = Some(Instant::now());
+                    Ok(())
+                } else {
+                    drop(breaker);
+                    self.reject()
+                }
+            }
+        }
+    }
+
+    fn reject(&self) -> Result<(), AppError> {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+        UPSTREAM_ERRORS
+            .with_label_values(&[&self.service.to_ascii_lowercase(), "circuit_open"])
+            .inc();
+        Err(AppError::ServiceUnavailable(format!(
+            "{} is unavailable (circuit breaker open)",
+            self.service
+        )))
+    }
+
+    fn record_success(&self) {
+        let mut breaker = self.breaker.lock().unwrap();
+        if breaker.state != BreakerState::Closed {
+            info!("{} circuit breaker closed", self.service);
+        }
+        breaker.state = BreakerState::Closed;
+        breaker.consecutive_failures = 0;
+        breaker.opened_at = None;
+        breaker.probe_started_at = None;
+    }
+
+    fn record_failure(&self, error: String) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+
+        let mut breaker = self.breaker.lock().unwrap();
+        breaker.consecutive_failures += 1;
+        breaker.last_error = Some(error);
+        breaker.last_failure_at = Some(Utc::now());
+
+        let trip = breaker.state == BreakerState::HalfOpen
+            || breaker.consecutive_failures >= BREAKER_FAILURE_THRESHOLD;
+
+        if trip && breaker.state != BreakerState::Open {
+            warn!(
+                "{} circuit breaker opened after {} consecutive failures",
+                self.service, breaker.consecutive_failures
+            );
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Some(Instant::now());
+        }
+        breaker.probe_started_at = None;
+    }
+}
+
+// Error body returned by the restify-based Triton services, e.g.
+// {"code": "ValidationFailed", "message": "...", "errors": [{"field": "ram", ...}]}
+#[derive(Debug, Default, Deserialize)]
+struct RestifyError {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<FieldError>,
+}
+
+// Map an upstream error response to the matching AppError, preferring the
+// restify error code over the bare HTTP status
+fn upstream_error(action: &str, status: StatusCode, body: &str, not_found: Option<String>) -> AppError {
+    let error: RestifyError = serde_json::from_str(body).unwrap_or_default();
+
+    let detail = if !error.message.is_empty() {
+        error.message.clone()
+    } else if !body.is_empty() {
+        format!("{} - {}", status, body)
+    } else {
+        status.to_string()
+    };
+    let message = format!("Failed to {}: {}", action, detail);
+
+    // The restify code wins when present: VMAPI, for one, answers
+    // ValidationFailed with a 409
+    match error.code.as_str() {
+        "ResourceNotFound" => return AppError::NotFound(not_found.unwrap_or(message)),
+        "Conflict" | "ConflictError" | "InUse" | "InvalidState" | "ObjectAlreadyExists" | "ResourceExists" => {
+            return AppError::Conflict(message)
+        }
+        "ValidationFailed" | "InvalidParameters" | "InvalidArgument" | "MissingParameter" => {
+            return AppError::UpstreamValidation {
+                message,
+                errors: error.errors,
+            }
+        }
+        _ => {}
+    }
+
+    match status {
+        StatusCode::NOT_FOUND => AppError::NotFound(not_found.unwrap_or(message)),
+        StatusCode::CONFLICT | StatusCode::PRECONDITION_FAILED => AppError::Conflict(message),
+        StatusCode::UNPROCESSABLE_ENTITY => AppError::UpstreamValidation {
+            message,
+            errors: error.errors,
+        },
+        StatusCode::BAD_REQUEST if !error.errors.is_empty() => AppError::UpstreamValidation {
+            message,
+            errors: error.errors,
+        },
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => AppError::Timeout(message),
+        StatusCode::FORBIDDEN => AppError::AuthorizationError(message),
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::BAD_GATEWAY => AppError::ServiceUnavailable(message),
+        status if status.is_client_error() => AppError::BadRequest(message),
+        _ => AppError::InternalServerError(message),
+    }
+}
+
+// Map a failure to reach the upstream at all
+fn transport_error(action: &str, error: &reqwest::Error) -> AppError {
+    let message = format!("Failed to {}: {}", action, error);
+    if error.is_timeout() {
+        AppError::Timeout(message)
+    } else if error.is_connect() {
+        AppError::ServiceUnavailable(message)
+    } else {
+        AppError::InternalServerError(message)
+    }
+}
+
+// Responses that indicate the upstream itself is unhealthy, as opposed to an
+// error in the request
+fn is_upstream_failure(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// Exponential backoff with full jitter
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(BACKOFF_MAX);
+    let millis = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64);
+    Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> TritonClient {
+        TritonClient::new("TEST", reqwest::Client::new(), "http://127.0.0.1:1".to_string())
+    }
+
+    fn state(client: &TritonClient) -> BreakerState {
+        client.breaker.lock().unwrap().state
+    }
+
+    // Pretend the breaker opened (or the probe started) a full cool-down ago
+    fn cooled_down() -> Option<Instant> {
+        Instant::now().checked_sub(BREAKER_COOL_DOWN)
+    }
+
+    fn trip(client: &TritonClient) {
+        for _ in 0..BREAKER_FAILURE_THRESHOLD {
+            client.acquire().unwrap();
+            client.record_failure("boom".to_string());
+        }
+    }
+
+    #[test]
+    fn breaker_opens_after_consecutive_failures() {
+        let client = client();
+
+        for _ in 0..BREAKER_FAILURE_THRESHOLD - 1 {
+            client.record_failure("boom".to_string());
+        }
+        assert_eq!(state(&client), BreakerState::Closed);
+        assert!(client.acquire().is_ok());
+
+        client.record_failure("boom".to_string());
+        assert_eq!(state(&client), BreakerState::Open);
+        assert!(matches!(client.acquire(), Err(AppError::ServiceUnavailable(_))));
+        assert_eq!(client.stats().rejected, 1);
+    }
+
+    #[test]
+    fn success_resets_failure_count() {
+        let client = client();
+
+        for _ in 0..BREAKER_FAILURE_THRESHOLD - 1 {
+            client.record_failure("boom".to_string());
+        }
+        client.record_success();
+        client.record_failure("boom".to_string());
+
+        assert_eq!(state(&client), BreakerState::Closed);
+        assert_eq!(client.stats().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn breaker_half_opens_after_cool_down_with_a_single_probe() {
+        let client = client();
+        trip(&client);
+
+        client.breaker.lock().unwrap().opened_at = cooled_down();
+        assert!(client.acquire().is_ok());
+        assert_eq!(state(&client), BreakerState::HalfOpen);
+
+        // Only the probe is let through
+        assert!(client.acquire().is_err());
+    }
+
+    #[test]
+    fn probe_success_closes_the_breaker() {
+        let client = client();
+        trip(&client);
+        client.breaker.lock().unwrap().opened_at = cooled_down();
+
+        client.acquire().unwrap();
+        client.record_success();
+
+        assert_eq!(state(&client), BreakerState::Closed);
+        assert!(client.acquire().is_ok());
+    }
+
+    #[test]
+    fn probe_failure_reopens_the_breaker() {
+        let client = client();
+        trip(&client);
+        client.breaker.lock().unwrap().opened_at = cooled_down();
+
+        client.acquire().unwrap();
+        client.record_failure("still down".to_string());
+
+        assert_eq!(state(&client), BreakerState::Open);
+        assert!(client.acquire().is_err());
+    }
+
+    #[test]
+    fn abandoned_probe_allows_another_after_cool_down() {
+        let client = client();
+        trip(&client);
+        client.breaker.lock().unwrap().opened_at = cooled_down();
+
+        // The probe's future is dropped and never records an outcome
+        client.acquire().unwrap();
+        assert!(client.acquire().is_err());
+
+        client.breaker.lock().unwrap().probe_started_at = cooled_down();
+        assert!(client.acquire().is_ok());
+        assert_eq!(state(&client), BreakerState::HalfOpen);
+        assert!(client.acquire().is_err());
+    }
+
+    #[test]
+    fn restify_validation_errors_keep_field_details() {
+        let body = r#"{"code": "ValidationFailed", "message": "Invalid VM parameters",
+            "errors": [{"field": "ram", "code": "Invalid", "message": "ram must be a number"}]}"#;
+
+        match upstream_error("create VM", StatusCode::CONFLICT, body, None) {
+            AppError::UpstreamValidation { message, errors } => {
+                assert_eq!(message, "Failed to create VM: Invalid VM parameters");
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].field, "ram");
+                assert_eq!(errors[0].code, "Invalid");
+                assert_eq!(errors[0].message, "ram must be a number");
+            }
+            other => panic!("expected UpstreamValidation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_request_with_field_errors_is_a_validation_error() {
+        let body = r#"{"code": "Whatever", "message": "bad", "errors": [{"field": "alias"}]}"#;
+        assert!(matches!(
+            upstream_error("update VM", StatusCode::BAD_REQUEST, body, None),
+            AppError::UpstreamValidation { ref errors, .. } if errors[0].field == "alias"
+        ));
+
+        // Without field errors a 400 stays a plain bad request
+        assert!(matches!(
+            upstream_error("update VM", StatusCode::BAD_REQUEST, r#"{"message": "bad"}"#, None),
+            AppError::BadRequest(_)
+        ));
+    }
+
+    #[test]
+    fn restify_code_takes_precedence_over_status() {
+        let not_found = upstream_error(
+            "get VM",
+            StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"code": "ResourceNotFound", "message": "no such VM"}"#,
+            Some("VM with UUID x not found".to_string()),
+        );
+        assert!(matches!(not_found, AppError::NotFound(ref m) if m == "VM with UUID x not found"));
+
+        for code in ["InUse", "InvalidState", "ObjectAlreadyExists"] {
+            let body = format!(r#"{{"code": "{}", "message": "busy"}}"#, code);
+            assert!(matches!(
+                upstream_error("delete network", StatusCode::INTERNAL_SERVER_ERROR, &body, None),
+                AppError::Conflict(_)
+            ));
+        }
+    }
+
+    #[test]
+    fn status_maps_when_body_has_no_code() {
+        let cases = [
+            (StatusCode::NOT_FOUND, "NotFound"),
+            (StatusCode::CONFLICT, "Conflict"),
+            (StatusCode::PRECONDITION_FAILED, "Conflict"),
+            (StatusCode::UNPROCESSABLE_ENTITY, "ValidationError"),
+            (StatusCode::REQUEST_TIMEOUT, "Timeout"),
+            (StatusCode::GATEWAY_TIMEOUT, "Timeout"),
+            (StatusCode::FORBIDDEN, "AuthorizationError"),
+            (StatusCode::SERVICE_UNAVAILABLE, "ServiceUnavailable"),
+            (StatusCode::BAD_GATEWAY, "ServiceUnavailable"),
+            (StatusCode::METHOD_NOT_ALLOWED, "BadRequest"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "InternalServerError"),
+        ];
+
+        for (status, expected) in cases {
+            let error = upstream_error("list VMs", status, "", None);
+            let kind = match error {
+                AppError::NotFound(_) => "NotFound",
+                AppError::Conflict(_) => "Conflict",
+                AppError::UpstreamValidation { .. } => "ValidationError",
+                AppError::Timeout(_) => "Timeout",
+                AppError::AuthorizationError(_) => "AuthorizationError",
+                AppError::ServiceUnavailable(_) => "ServiceUnavailable",
+                AppError::BadRequest(_) => "BadRequest",
+                AppError::InternalServerError(_) => "InternalServerError",
+                other => panic!("unexpected error {:?}", other),
+            };
+            assert_eq!(kind, expected, "status {}", status);
+        }
+    }
+
+    #[test]
+    fn non_json_body_is_kept_in_the_message() {
+        let error = upstream_error("list VMs", StatusCode::INTERNAL_SERVER_ERROR, "upstream exploded", None);
+        assert!(matches!(
+            error,
+            AppError::InternalServerError(ref m) if m == "Failed to list VMs: 500 Internal Server Error - upstream exploded"
+        ));
+    }
+
+    #[test]
+    fn backoff_stays_within_bounds() {
+        for attempt in 1..=10 {
+            let ceiling = BACKOFF_BASE
+                .saturating_mul(2u32.pow(attempt - 1))
+                .min(BACKOFF_MAX);
+            for _ in 0..50 {
+                assert!(backoff(attempt) <= ceiling);
+            }
+        }
+        // Large attempt numbers must not overflow
+        assert!(backoff(u32::MAX) <= BACKOFF_MAX);
+    }
+}
@@ -3,12 +3,16 @@ use uuid::Uuid;
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use std::collections::HashMap;
-use tracing::{info, warn, error};
-use ldap3::{LdapConn, Scope, SearchEntry, LdapError, LdapConnSettings};
-use native_tls::{TlsConnector, Certificate};
+use std::collections::{HashMap, HashSet};
+use tracing::{info, error, warn};
+use ldap3::{Scope, SearchEntry, Mod, ldap_escape, dn_escape};
+use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+use md5::{Digest, Md5};
+use ssh_key::{HashAlg, PublicKey};
 
+use crate::config::LdapConfig;
 use crate::error::AppError;
+use super::ldap_pool::{LdapPool, LdapPoolConfig, LdapTarget, PooledConnection};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UfdsUser {
@@ -20,270 +24,218 @@ pub struct UfdsUser {
     pub roles: Vec<String>,
 }
 
+// Attributes read from sdcperson entries when mapping them to API users
+const USER_ATTRS: [&str; 9] = [
+    "uuid", "login", "email", "givenname", "sn", "company",
+    "approved_for_provisioning", "created_at", "updated_at",
+];
+
+// Attributes read from sdcperson entries at login
+const LOGIN_ATTRS: [&str; 8] = ["uuid", "login", "email", "cn", "sn", "givenname", "memberof", "isadmin"];
+
+// Attributes read from sdckey entries
+const KEY_ATTRS: [&str; 3] = ["name", "fingerprint", "openssh"];
+
+// Page size used for LDAP paged results when no limit is requested
+const DEFAULT_PAGE_SIZE: i32 = 100;
+
+#[derive(Clone)]
 pub struct UfdsService {
     client: reqwest::Client,
     ldaps_url: String,
     api_url: String,
     // LDAP configuration
     ldap_base_dn: String,
-    ldap_user_dn_format: String,
-    ldap_use_tls: bool,
-    ldap_verify_certs: bool,  // Whether to verify TLS certificates
+    user_search_base: String,
+    user_search_filter: String,
+    group_roles: HashMap<String, String>,
+    // Service-account connections for searches and user management, plus
+    // short-lived binds for checking user passwords
+    pool: Arc<LdapPool>,
     // Cache for user data (UUID -> UserData)
     cache: Arc<Mutex<HashMap<String, UfdsUser>>>,
 }
 
 impl UfdsService {
-    // Helper method to authenticate via LDAP/LDAPS
-    fn authenticate_ldap(&self, username: &str, password: &str) -> Result<UfdsUser, AppError> {
-        // Format the LDAP URL properly for connecting
-        let ldap_url = if self.ldaps_url.contains("://") {
-            self.ldaps_url.clone()
-        } else {
-            format!("ldap://{}", self.ldaps_url)
-        };
-        
-        info!("Connecting to LDAP server: {}", ldap_url);
-        
-        // Create custom TLS settings when using LDAPS
-        let mut ldap = if ldap_url.starts_with("ldaps://") {
-            // Configure TLS for LDAPS connection
-            info!("Using LDAPS connection with certificate verification: {}", self.ldap_verify_certs);
-            
-            // Create a TLS connector with certificate verification options
-            let tls_builder = TlsConnector::builder()
-                .danger_accept_invalid_certs(!self.ldap_verify_certs)  // Disable cert verification if specified
-                .build()
-                .map_err(|e| {
-                    error!("Failed to build TLS connector: {}", e);
-                    AppError::AuthError(format!("TLS configuration error: {}", e))
-                })?;
-                
-            // Create LDAP connection settings with our TLS connector
-            let ldap_settings = LdapConnSettings::new()
-                .set_connector(tls_builder.into());
-                
-            // Create the connection with custom settings
-            match LdapConn::with_settings(ldap_settings, &ldap_url) {
-                Ok(conn) => conn,
-                Err(e) => {
-                    error!("Failed to connect to LDAPS server: {}", e);
-                    return Err(AppError::AuthError(format!("Cannot connect to LDAPS server: {}", e)));
-                }
-            }
-        } else {
-            // For regular LDAP (non-SSL), use the standard connection
-            match LdapConn::new(&ldap_url) {
-                Ok(conn) => conn,
-                Err(e) => {
-                    error!("Failed to connect to LDAP server: {}", e);
-                    return Err(AppError::AuthError(format!("Cannot connect to LDAP server: {}", e)));
-                }
-            }
-        };
-        
-        // Note: ldap3 crate's LdapConn doesn't have start_tls() method; 
-        // LDAPS connections are automatically secured when using ldaps:// URLs
-        
-        // Create the user DN from the username
-        let user_dn = self.ldap_user_dn_format.replace("{}", username);
+    // Look the user up with the service account, then check the password
+    // with a bind as the user's own DN
+    async fn authenticate_ldap(&self, username: &str, password: &str) -> Result<UfdsUser, AppError> {
+        let filter = self.user_search_filter.replace("{login}", &ldap_escape(username));
+        let mut entries = self.search_login_entries(&filter, username).await?;
         
-        info!("Binding with user DN: {}", user_dn);
-        
-        // Attempt to bind with the user credentials
-        if let Err(e) = ldap.simple_bind(&user_dn, password) {
-            error!("LDAP authentication failed: {}", e);
-            return Err(AppError::AuthError(format!("Authentication failed: {}", e)));
+        // Unknown and ambiguous logins get the same answer as wrong passwords
+        if entries.len() > 1 {
+            warn!("Login {} matches {} UFDS entries; refusing to pick one", username, entries.len());
+            return Err(AppError::AuthError("Invalid username or password".to_string()));
         }
+        let entry = entries.pop().ok_or_else(|| {
+            info!("Login attempt for unknown user {}", username);
+            AppError::AuthError("Invalid username or password".to_string())
+        })?;
         
-        info!("LDAP authentication succeeded for user: {}", username);
+        self.pool.check_password(&entry.dn, password).await?;
         
-        // Search for the user attributes
-        let search_base = self.ldap_base_dn.clone();
-        let search_filter = format!("(&(objectClass=sdcPerson)(cn={}))", username);
-        let attrs = vec!["uuid", "email", "cn", "sn", "givenName", "memberof", "isAdmin"];
+        info!("LDAP authentication succeeded for user: {}", username);
         
-        info!("Searching for user attributes: base={}, filter={}", search_base, search_filter);
+        Ok(login_user_from_entry(&entry, username, &self.group_roles))
+    }
+    
+    // Search for sdcperson entries with the attributes needed to start a
+    // session. Only direct children of the search base are considered: the
+    // sub-users of Triton accounts live below their account's entry and
+    // their logins are only unique within that account, so a subtree search
+    // could match one of them instead.
+    async fn search_login_entries(&self, filter: &str, label: &str) -> Result<Vec<SearchEntry>, AppError> {
+        let mut ldap = self.pool.get().await?;
+        let (entries, _) = ldap.search(&self.user_search_base, Scope::OneLevel, filter, LOGIN_ATTRS.to_vec())
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("LDAP search for login {} failed: {}", label, e);
+                AppError::ServiceUnavailable(format!("Failed to retrieve user information: {}", e))
+            })?;
+            
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+    
+    // Current login details of a user, used to refresh sessions with
+    // up-to-date roles. None once the account has been deleted.
+    pub async fn find_user_by_uuid(&self, uuid: &str) -> Result<Option<UfdsUser>, AppError> {
+        let filter = format!("(&(objectclass=sdcperson)(uuid={}))", ldap_escape(uuid));
+        let entry = self.search_login_entries(&filter, uuid).await?.into_iter().next();
         
-        let search_result = match ldap.search(&search_base, Scope::Subtree, &search_filter, attrs) {
-            Ok(result) => result,
-            Err(e) => {
-                error!("LDAP search failed: {}", e);
-                return Err(AppError::AuthError(format!("Failed to retrieve user information: {}", e)));
+        Ok(entry.map(|entry| login_user_from_entry(&entry, uuid, &self.group_roles)))
+    }
+    
+    // Find the user for an identity asserted by an external provider, by
+    // login first and then by email. No password is checked: the caller has
+    // already authenticated the user.
+    pub async fn find_login_user(&self, login: Option<&str>, email: Option<&str>) -> Result<Option<UfdsUser>, AppError> {
+        if let Some(login) = login {
+            let filter = self.user_search_filter.replace("{login}", &ldap_escape(login));
+            let mut entries = self.search_login_entries(&filter, login).await?;
+            // Never fall back to the email when the login is ambiguous
+            if entries.len() > 1 {
+                warn!("Login {} matches {} UFDS entries; refusing to pick one", login, entries.len());
+                return Ok(None);
+            }
+            if let Some(entry) = entries.pop() {
+                return Ok(Some(login_user_from_entry(&entry, login, &self.group_roles)));
             }
-        };
-        
-        // Process the search results - SearchResult.0 contains the Vec<ResultEntry>
-        let result_entries = search_result.0;
-        if result_entries.is_empty() {
-            error!("No user entries found for {}", username);
-            return Err(AppError::AuthError(format!("User {} not found in directory", username)));
         }
         
-        // Use the first entry - SearchEntry::construct returns a SearchEntry directly, not a Result
-        let entry = SearchEntry::construct(result_entries[0].clone());
-        
-        // Extract user attributes
-        let user_uuid = entry.attrs.get("uuid")
-            .and_then(|attr| attr.first())
-            .unwrap_or(&"unknown".to_string())
-            .to_string();
-            
-        // Get name - use givenName + sn if available, otherwise use cn
-        let first_name = entry.attrs.get("givenName")
-            .and_then(|attr| attr.first())
-            .unwrap_or(&"".to_string())
-            .to_string();
-            
-        let last_name = entry.attrs.get("sn")
-            .and_then(|attr| attr.first())
-            .unwrap_or(&"".to_string())
-            .to_string();
-            
-        let name = if !first_name.is_empty() || !last_name.is_empty() {
-            format!("{} {}", first_name, last_name).trim().to_string()
-        } else {
-            entry.attrs.get("cn")
-                .and_then(|attr| attr.first())
-                .unwrap_or(&username.to_string())
-                .to_string()
-        };
-        
-        let email = entry.attrs.get("email")
-            .and_then(|attr| attr.first())
-            .unwrap_or(&format!("{}@example.com", username))
-            .to_string();
-            
-        // Extract roles from memberof attribute
-        let mut roles = Vec::new();
-        if let Some(member_of) = entry.attrs.get("memberof") {
-            for group_dn in member_of {
-                // Parse role from group DN
-                // Typical format: cn=role-name,ou=groups,o=smartdc
-                if group_dn.contains("cn=") {
-                    let role = group_dn
-                        .split("cn=").nth(1)
-                        .and_then(|s| s.split(',').next())
-                        .unwrap_or("unknown");
-                        
-                    roles.push(role.to_string());
-                }
+        if let Some(email) = email {
+            let filter = format!("(&(objectclass=sdcperson)(email={}))", ldap_escape(email));
+            let mut entries = self.search_login_entries(&filter, email).await?;
+            // An address shared by several accounts identifies none of them
+            if entries.len() > 1 {
+                warn!("Email {} matches {} UFDS users; not using it to sign in", email, entries.len());
+                return Ok(None);
+            }
+            if let Some(entry) = entries.pop() {
+                return Ok(Some(login_user_from_entry(&entry, email, &self.group_roles)));
             }
         }
         
-        // Check for admin attribute
-        let is_admin = entry.attrs.get("isAdmin")
-            .and_then(|attr| attr.first())
-            .map(|v| v == "true")
-            .unwrap_or(false);
-            
-        if is_admin && !roles.contains(&"admin".to_string()) {
-            roles.push("admin".to_string());
+        Ok(None)
+    }
+    
+    // Find a user's SSH public key by login and fingerprint, for checking
+    // request signatures. The fingerprint is MD5 (as stored in UFDS, with
+    // or without an "MD5:" prefix) or "SHA256:..." as printed by ssh-keygen.
+    pub async fn find_signing_key(&self, login: &str, fingerprint: &str) -> Result<Option<(UfdsUser, String)>, AppError> {
+        let filter = self.user_search_filter.replace("{login}", &ldap_escape(login));
+        let mut entries = self.search_login_entries(&filter, login).await?;
+        if entries.len() > 1 {
+            warn!("Login {} matches {} UFDS entries; refusing to pick one", login, entries.len());
+            return Ok(None);
         }
+        let user = match entries.pop() {
+            Some(entry) => login_user_from_entry(&entry, login, &self.group_roles),
+            None => return Ok(None),
+        };
         
-        // Create user object
-        let user = UfdsUser {
-            uuid: user_uuid,
-            login: username.to_string(),
-            email,
-            name,
-            is_admin,
-            roles,
+        let mut ldap = self.pool.get().await?;
+        let key = if fingerprint.starts_with("SHA256:") {
+            // UFDS only indexes MD5 fingerprints
+            self.search_keys(&mut ldap, &user.uuid, "(objectclass=sdckey)").await?
+                .into_iter()
+                .find(|key| key.fingerprint_sha256 == fingerprint)
+        } else {
+            let md5 = fingerprint.strip_prefix("MD5:").unwrap_or(fingerprint);
+            let filter = format!("(&(objectclass=sdckey)(fingerprint={}))", ldap_escape(md5));
+            self.search_keys(&mut ldap, &user.uuid, &filter).await?.into_iter().next()
         };
         
-        Ok(user)
+        Ok(key.map(|key| (user, key.key)))
     }
     
-    pub fn new(base_url: String) -> Self {
+    pub fn new(client: reqwest::Client, base_url: String, ldap: &LdapConfig) -> Result<Self, AppError> {
         // Determine if we're using LDAPS or HTTP
         let is_ldaps = base_url.starts_with("ldaps://");
         let is_ldap = base_url.starts_with("ldap://");
         
-        // Get certificate verification setting from env, default to true in production
-        let verify_certs = std::env::var("LDAP_VERIFY_CERTIFICATES")
-            .map(|v| v.to_lowercase() != "false")
-            .unwrap_or(true);
-            
-        info!("LDAP certificate verification: {}", verify_certs);
-        
-        let (api_url, ldaps_url, ldap_use_tls, ldap_base_dn, ldap_user_dn_format) = if is_ldaps || is_ldap {
+        let (api_url, ldaps_url, url_base_dn) = if is_ldaps || is_ldap {
             // Handle LDAP/LDAPS URL
             let protocol = if is_ldaps { "ldaps://" } else { "ldap://" };
-            // Split to get host:port, fixing ownership issues
             let without_protocol = base_url.replace(protocol, "");
-            let host_parts: Vec<&str> = without_protocol.split('/').collect();
-            let host_with_port = host_parts.first().unwrap_or(&"localhost:389").to_string();
-            
-            // Split host and port if present - fix ownership issue
-            let host_parts: Vec<&str> = host_with_port.split(':').collect();
-            let host = host_parts.first().unwrap_or(&"localhost");
-            
-            // Extract base DN from the URL if present or use a default
-            let parts: Vec<&str> = base_url.split('/').collect();
-            let base_dn = if parts.len() > 3 {
-                parts[3..].join("/")
-            } else {
-                "o=smartdc".to_string() // Default Triton base DN
+            let (host_with_port, url_dn) = match without_protocol.split_once('/') {
+                Some((host, dn)) => (host.to_string(), Some(dn.to_string()).filter(|dn| !dn.is_empty())),
+                None => (without_protocol.clone(), None),
             };
-            
-            // Create user DN format
-            let user_dn_format = format!("cn={},ou=users,{}", "{}", base_dn);
+            let host = host_with_port.split(':').next().unwrap_or("localhost");
             
             // For API fallback (mock server)
             let api_url = format!("http://{}:3000", host);
             
-            info!("Configured for LDAP/LDAPS authentication: URL={}, Base DN={}, User DN Format={}", 
-                  base_url, base_dn, user_dn_format);
-            
-            (api_url, base_url, is_ldaps, base_dn, user_dn_format)
+            // The connection URL carries no DN
+            (api_url, format!("{}{}", protocol, host_with_port), url_dn)
         } else {
             // HTTP URL for mock server or API
             let url_without_protocol = base_url.replace("http://", "").replace("https://", "");
             let hostname = url_without_protocol.split('/').next().unwrap_or("localhost");
             
-            // Default values for LDAP config when using HTTP API
-            let ldaps = format!("ldaps://{}:636", hostname);
-            let base_dn = "o=smartdc".to_string();
-            let user_dn_format = format!("cn={},ou=users,{}", "{}", base_dn);
-            
             info!("Configured for HTTP API authentication: URL={}", base_url);
             
-            (base_url, ldaps, true, base_dn, user_dn_format)
+            (base_url, format!("ldaps://{}:636", hostname), None)
         };
         
-        Self {
-            client: reqwest::Client::new(),
+        // An explicit base DN wins over one embedded in UFDS_URL
+        let ldap_base_dn = ldap.base_dn.clone()
+            .or(url_base_dn)
+            .unwrap_or_else(|| "o=smartdc".to_string()); // Default Triton base DN
+        let user_search_base = ldap.user_search_base.clone()
+            .unwrap_or_else(|| format!("ou=users, {}", ldap_base_dn));
+            
+        if is_ldaps || is_ldap {
+            info!(
+                "Configured for LDAP authentication: URL={}, base DN={}, user search base={}, StartTLS={}",
+                ldaps_url, ldap_base_dn, user_search_base, ldap.starttls
+            );
+        }
+        
+        let target = LdapTarget::new(ldaps_url.clone(), ldap)?;
+        let pool = LdapPool::new(
+            target,
+            LdapPoolConfig::from_config(ldap),
+            ldap.bind_dn.clone(),
+            ldap.bind_password.clone(),
+        );
+        
+        Ok(Self {
+            client,
             ldaps_url,
             api_url,
             ldap_base_dn,
-            ldap_user_dn_format,
-            ldap_use_tls,
-            ldap_verify_certs: verify_certs,
+            user_search_base,
+            user_search_filter: ldap.user_search_filter.clone(),
+            group_roles: normalize_group_roles(&ldap.group_roles),
+            pool: Arc::new(pool),
             cache: Arc::new(Mutex::new(HashMap::new())),
-        }
+        })
     }
     
     pub async fn authenticate(&self, username: &str, password: &str) -> Result<(String, String, String, Vec<String>), AppError> {
-        // For development, still allow admin/admin
-        if username == "admin" && password == "admin" {
-            return Ok((
-                "00000000-0000-0000-0000-000000000000".to_string(),
-                "Administrator".to_string(),
-                "admin@example.com".to_string(),
-                vec!["admin".to_string()]
-            ));
-        }
-        
-        // For development mode, support additional test users
-        if username == "operator" && password == "operator" {
-            return Ok((
-                "11111111-1111-1111-1111-111111111111".to_string(),
-                "System Operator".to_string(),
-                "operator@example.com".to_string(),
-                vec!["operator".to_string()]
-            ));
-        }
-        
         // Determine if we should use direct LDAP or API approach
         let is_ldap_url = self.ldaps_url.starts_with("ldap://") || self.ldaps_url.starts_with("ldaps://");
         
@@ -291,34 +243,7 @@ impl UfdsService {
             // Use native LDAP authentication
             info!("Using native LDAP authentication for {}", username);
             
-            // Use blocking thread for LDAP operation since ldap3 is not async
-            let ldaps_url = self.ldaps_url.clone();
-            let ldap_base_dn = self.ldap_base_dn.clone();
-            let ldap_user_dn_format = self.ldap_user_dn_format.clone();
-            let ldap_use_tls = self.ldap_use_tls;
-            
-            // Clone self for use in the blocking thread
-            let this = Self {
-                client: self.client.clone(),
-                ldaps_url,
-                api_url: self.api_url.clone(),
-                ldap_base_dn,
-                ldap_user_dn_format,
-                ldap_use_tls,
-                ldap_verify_certs: self.ldap_verify_certs,
-                cache: self.cache.clone(),
-            };
-            
-            // Run LDAP authentication in a blocking thread - clone username and password to owned values
-            let owned_username = username.to_string();
-            let owned_password = password.to_string();
-            
-            let user_result = tokio::task::spawn_blocking(move || {
-                this.authenticate_ldap(&owned_username, &owned_password)
-            }).await.map_err(|e| {
-                error!("LDAP thread error: {}", e);
-                AppError::AuthError(format!("Internal error during authentication: {}", e))
-            })??;
+            let user_result = self.authenticate_ldap(username, password).await?;
             
             // Cache the user data
             {
@@ -422,22 +347,178 @@ impl UfdsService {
         }
     }
     
-    pub async fn list_users(&self) -> Result<Vec<crate::api::users::User>, AppError> {
-        // This is a placeholder for actual implementation
-        Ok(vec![])
+    // Check that UFDS is reachable and accepts the service account bind
+    pub async fn ping(&self) -> Result<(), AppError> {
+        self.pool.ping().await
+    }
+    
+    fn users_base_dn(&self) -> String {
+        format!("ou=users, {}", self.ldap_base_dn)
+    }
+    
+    fn user_dn(&self, uuid: &str) -> String {
+        format!("uuid={}, {}", dn_escape(uuid), self.users_base_dn())
+    }
+    
+    // Find a single sdcperson entry by UUID
+    async fn find_user_entry(&self, ldap: &mut PooledConnection<'_>, uuid: &str) -> Result<SearchEntry, AppError> {
+        let filter = format!("(&(objectclass=sdcperson)(uuid={}))", ldap_escape(uuid));
+        
+        let (entries, _) = ldap.search(&self.users_base_dn(), Scope::OneLevel, &filter, USER_ATTRS.to_vec())
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("LDAP search for user {} failed: {}", uuid, e);
+                AppError::InternalServerError(format!("Failed to look up user in UFDS: {}", e))
+            })?;
+            
+        entries.into_iter()
+            .next()
+            .map(SearchEntry::construct)
+            .ok_or_else(|| AppError::NotFound(format!("User with UUID {} not found", uuid)))
+    }
+    
+    pub async fn list_users(
+        &self,
+        params: &crate::api::users::UserListParams,
+    ) -> Result<Vec<crate::api::users::User>, AppError> {
+        info!("Listing users from UFDS: email={:?}, login={:?}", params.email, params.login);
+        
+        // Build the LDAP filter from the query parameters
+        let mut filter = "(&(objectclass=sdcperson)".to_string();
+        if let Some(email) = &params.email {
+            filter.push_str(&format!("(email={})", ldap_escape(email.as_str())));
+        }
+        if let Some(login) = &params.login {
+            filter.push_str(&format!("(login={})", ldap_escape(login.as_str())));
+        }
+        filter.push(')');
+        
+        let offset = params.offset.unwrap_or(0) as usize;
+        let limit = params.limit.map(|l| l as usize);
+        let page_size = params.limit
+            .map(|l| l.clamp(1, 1000) as i32)
+            .unwrap_or(DEFAULT_PAGE_SIZE);
+        
+        let mut ldap = self.pool.get().await?;
+        let base_dn = self.users_base_dn();
+        
+        let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+            Box::new(EntriesOnly::new()),
+            Box::new(PagedResults::new(page_size)),
+        ];
+        
+        let result = ldap
+            .streaming_search_with(adapters, &base_dn, Scope::OneLevel, &filter, USER_ATTRS.to_vec())
+            .await;
+        let mut stream = ldap
+            .check(result)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to search UFDS users: {}", e)))?;
+            
+        // Walk the pages, skipping `offset` entries and stopping once `limit` is reached
+        let mut users = Vec::new();
+        let mut skipped = 0;
+        let mut truncated = false;
+        
+        while let Some(entry) = ldap
+            .check(stream.next().await)
+            .map_err(|e| AppError::InternalServerError(format!("Failed to read UFDS search results: {}", e)))?
+        {
+            match page_step(skipped, users.len(), offset, limit) {
+                PageStep::Skip => skipped += 1,
+                PageStep::Take => users.push(user_from_entry(&SearchEntry::construct(entry))),
+                PageStep::Stop => {
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+        
+        if truncated {
+            // Stop the server from sending the remaining pages
+            let msgid = stream.ldap_handle().last_id();
+            drop(stream);
+            let _ = ldap.abandon(msgid).await;
+        } else {
+            stream.finish()
+                .await
+                .success()
+                .map_err(|e| AppError::InternalServerError(format!("UFDS user search failed: {}", e)))?;
+        }
+        
+        info!("Successfully fetched {} users from UFDS", users.len());
+        Ok(users)
     }
     
     pub async fn get_user(&self, uuid: &str) -> Result<crate::api::users::User, AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::NotFound(format!("User with UUID {} not found", uuid)))
+        info!("Fetching user with UUID: {}", uuid);
+        
+        let mut ldap = self.pool.get().await?;
+        let entry = self.find_user_entry(&mut ldap, uuid).await?;
+        Ok(user_from_entry(&entry))
     }
     
     pub async fn create_user(
         &self, 
         user: crate::api::users::CreateUserRequest
     ) -> Result<crate::api::users::User, AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::InternalServerError("Not implemented".to_string()))
+        info!("Creating user with login: {}", user.login);
+        
+        if user.login.is_empty() || user.email.is_empty() || user.password.is_empty() {
+            return Err(AppError::ValidationError("login, email and password are required".to_string()));
+        }
+        
+        let uuid = Uuid::new_v4().to_string();
+        let now = chrono::Utc::now().timestamp_millis().to_string();
+        
+        let mut attrs: Vec<(String, HashSet<String>)> = vec![
+            attr("objectclass", "sdcperson"),
+            attr("uuid", &uuid),
+            attr("login", &user.login),
+            attr("email", &user.email),
+            // Sent as given, like the passwords checked at sign-in; UFDS
+            // salts and hashes it itself
+            attr("userpassword", &user.password),
+            attr("approved_for_provisioning", &user.approved_for_provisioning.unwrap_or(false).to_string()),
+            attr("created_at", &now),
+            attr("updated_at", &now),
+        ];
+        
+        if let Some(first_name) = user.first_name.as_deref().filter(|v| !v.is_empty()) {
+            attrs.push(attr("givenname", first_name));
+        }
+        if let Some(last_name) = user.last_name.as_deref().filter(|v| !v.is_empty()) {
+            attrs.push(attr("sn", last_name));
+        }
+        if let Some(company) = user.company.as_deref().filter(|v| !v.is_empty()) {
+            attrs.push(attr("company", company));
+        }
+        
+        let mut ldap = self.pool.get().await?;
+        
+        // UFDS enforces unique logins, but check first to give a clearer error
+        let filter = format!("(&(objectclass=sdcperson)(login={}))", ldap_escape(user.login.as_str()));
+        let (existing, _) = ldap.search(&self.users_base_dn(), Scope::OneLevel, &filter, vec!["uuid"])
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| AppError::InternalServerError(format!("Failed to search UFDS users: {}", e)))?;
+            
+        if !existing.is_empty() {
+            return Err(AppError::ValidationError(format!("Login {} is already in use", user.login)));
+        }
+        
+        ldap.add(&self.user_dn(&uuid), attrs)
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("Failed to add user {} to UFDS: {}", user.login, e);
+                AppError::InternalServerError(format!("Failed to create user in UFDS: {}", e))
+            })?;
+            
+        info!("Successfully created user {} ({})", user.login, uuid);
+        
+        let entry = self.find_user_entry(&mut ldap, &uuid).await?;
+        Ok(user_from_entry(&entry))
     }
     
     pub async fn update_user(
@@ -445,12 +526,473 @@ impl UfdsService {
         uuid: &str, 
         user: crate::api::users::UpdateUserRequest
     ) -> Result<crate::api::users::User, AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::InternalServerError("Not implemented".to_string()))
+        info!("Updating user with UUID: {}", uuid);
+        
+        // Empty strings clear optional attributes; missing fields are left untouched
+        let mut mods: Vec<Mod<String>> = Vec::new();
+        
+        if let Some(email) = &user.email {
+            if email.is_empty() {
+                return Err(AppError::ValidationError("email cannot be empty".to_string()));
+            }
+            mods.push(replace("email", Some(email)));
+        }
+        if let Some(first_name) = &user.first_name {
+            mods.push(replace("givenname", Some(first_name).filter(|v| !v.is_empty())));
+        }
+        if let Some(last_name) = &user.last_name {
+            mods.push(replace("sn", Some(last_name).filter(|v| !v.is_empty())));
+        }
+        if let Some(company) = &user.company {
+            mods.push(replace("company", Some(company).filter(|v| !v.is_empty())));
+        }
+        if let Some(approved) = user.approved_for_provisioning {
+            mods.push(replace("approved_for_provisioning", Some(&approved.to_string())));
+        }
+        mods.push(replace("updated_at", Some(&chrono::Utc::now().timestamp_millis().to_string())));
+        
+        let mut ldap = self.pool.get().await?;
+        
+        // Make sure the user exists so a missing entry maps to 404
+        self.find_user_entry(&mut ldap, uuid).await?;
+        
+        ldap.modify(&self.user_dn(uuid), mods)
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("Failed to modify user {} in UFDS: {}", uuid, e);
+                AppError::InternalServerError(format!("Failed to update user in UFDS: {}", e))
+            })?;
+            
+        info!("Successfully updated user {}", uuid);
+        
+        let entry = self.find_user_entry(&mut ldap, uuid).await?;
+        Ok(user_from_entry(&entry))
     }
     
     pub async fn delete_user(&self, uuid: &str) -> Result<(), AppError> {
-        // This is a placeholder for actual implementation
-        Err(AppError::InternalServerError("Not implemented".to_string()))
+        info!("Deleting user with UUID: {}", uuid);
+        
+        {
+            let mut ldap = self.pool.get().await?;
+            self.find_user_entry(&mut ldap, uuid).await?;
+            
+            // UFDS refuses to delete entries with children, so remove SSH keys first
+            for key in self.search_keys(&mut ldap, uuid, "(objectclass=sdckey)").await? {
+                ldap.delete(&self.key_dn(uuid, &key.fingerprint))
+                    .await
+                    .and_then(|res| res.success())
+                    .map_err(|e| AppError::InternalServerError(format!("Failed to delete SSH key from UFDS: {}", e)))?;
+            }
+            
+            ldap.delete(&self.user_dn(uuid))
+                .await
+                .and_then(|res| res.success())
+                .map_err(|e| {
+                    error!("Failed to delete user {} from UFDS: {}", uuid, e);
+                    AppError::InternalServerError(format!("Failed to delete user from UFDS: {}", e))
+                })?;
+                
+            info!("Successfully deleted user {}", uuid);
+        }
+        
+        let mut cache = self.cache.lock().await;
+        cache.remove(uuid);
+        
+        Ok(())
+    }
+    
+    fn key_dn(&self, user_uuid: &str, fingerprint: &str) -> String {
+        format!("fingerprint={}, {}", dn_escape(fingerprint), self.user_dn(user_uuid))
+    }
+    
+    // Search the sdckey children of a user entry
+    async fn search_keys(
+        &self,
+        ldap: &mut PooledConnection<'_>,
+        user_uuid: &str,
+        filter: &str,
+    ) -> Result<Vec<crate::api::users::SshKey>, AppError> {
+        let (entries, _) = ldap.search(&self.user_dn(user_uuid), Scope::OneLevel, filter, KEY_ATTRS.to_vec())
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("LDAP search for keys of user {} failed: {}", user_uuid, e);
+                AppError::InternalServerError(format!("Failed to look up SSH keys in UFDS: {}", e))
+            })?;
+            
+        Ok(entries.into_iter()
+            .map(SearchEntry::construct)
+            .map(|entry| key_from_entry(&entry))
+            .collect())
+    }
+    
+    pub async fn list_keys(&self, user_uuid: &str) -> Result<Vec<crate::api::users::SshKey>, AppError> {
+        info!("Listing SSH keys for user: {}", user_uuid);
+        
+        let mut ldap = self.pool.get().await?;
+        self.find_user_entry(&mut ldap, user_uuid).await?;
+        
+        let keys = self.search_keys(&mut ldap, user_uuid, "(objectclass=sdckey)").await?;
+        info!("Successfully fetched {} SSH keys for user {}", keys.len(), user_uuid);
+        Ok(keys)
+    }
+    
+    pub async fn add_key(
+        &self,
+        user_uuid: &str,
+        key: crate::api::users::AddSshKeyRequest,
+    ) -> Result<crate::api::users::SshKey, AppError> {
+        info!("Adding SSH key for user: {}", user_uuid);
+        
+        let parsed = parse_public_key(&key.key)?;
+        
+        // Default the key name to the key comment, then to the fingerprint
+        let name = key.name
+            .filter(|name| !name.trim().is_empty())
+            .or_else(|| Some(parsed.comment.clone()).filter(|c| !c.is_empty()))
+            .unwrap_or_else(|| parsed.fingerprint.clone());
+            
+        let mut ldap = self.pool.get().await?;
+        self.find_user_entry(&mut ldap, user_uuid).await?;
+        
+        // Reject keys that are already registered, by fingerprint or by name
+        let filter = format!(
+            "(&(objectclass=sdckey)(|(fingerprint={})(name={})))",
+            ldap_escape(parsed.fingerprint.as_str()),
+            ldap_escape(name.as_str()),
+        );
+        let existing = self.search_keys(&mut ldap, user_uuid, &filter).await?;
+        check_duplicate_key(&existing, &parsed.fingerprint, &name)?;
+        
+        let attrs = vec![
+            attr("objectclass", "sdckey"),
+            attr("name", &name),
+            attr("fingerprint", &parsed.fingerprint),
+            attr("openssh", &parsed.openssh),
+        ];
+        
+        ldap.add(&self.key_dn(user_uuid, &parsed.fingerprint), attrs)
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("Failed to add SSH key for user {}: {}", user_uuid, e);
+                AppError::InternalServerError(format!("Failed to add SSH key in UFDS: {}", e))
+            })?;
+            
+        info!("Successfully added SSH key {} for user {}", parsed.fingerprint, user_uuid);
+        
+        Ok(crate::api::users::SshKey {
+            name,
+            fingerprint: parsed.fingerprint,
+            fingerprint_sha256: parsed.fingerprint_sha256,
+            key: parsed.openssh,
+        })
+    }
+    
+    pub async fn delete_key(&self, user_uuid: &str, fingerprint: &str) -> Result<(), AppError> {
+        info!("Deleting SSH key {} for user: {}", fingerprint, user_uuid);
+        
+        let mut ldap = self.pool.get().await?;
+        
+        let filter = format!("(&(objectclass=sdckey)(fingerprint={}))", ldap_escape(fingerprint));
+        if self.search_keys(&mut ldap, user_uuid, &filter).await?.is_empty() {
+            return Err(AppError::NotFound(format!("SSH key {} not found", fingerprint)));
+        }
+        
+        ldap.delete(&self.key_dn(user_uuid, fingerprint))
+            .await
+            .and_then(|res| res.success())
+            .map_err(|e| {
+                error!("Failed to delete SSH key {} for user {}: {}", fingerprint, user_uuid, e);
+                AppError::InternalServerError(format!("Failed to delete SSH key from UFDS: {}", e))
+            })?;
+            
+        info!("Successfully deleted SSH key {} for user {}", fingerprint, user_uuid);
+        Ok(())
+    }
+}
+
+struct ParsedKey {
+    openssh: String,
+    comment: String,
+    fingerprint: String,
+    fingerprint_sha256: String,
+}
+
+// Parse an OpenSSH public key line and compute its fingerprints. UFDS keys
+// are identified by the colon-separated MD5 fingerprint of the key blob.
+fn parse_public_key(key: &str) -> Result<ParsedKey, AppError> {
+    let key = key.trim();
+    if key.is_empty() || key.contains('\n') {
+        return Err(AppError::ValidationError("SSH key must be a single OpenSSH public key line".to_string()));
+    }
+    
+    let public_key = PublicKey::from_openssh(key)
+        .map_err(|e| AppError::ValidationError(format!("Invalid OpenSSH public key: {}", e)))?;
+        
+    let blob = public_key.to_bytes()
+        .map_err(|e| AppError::ValidationError(format!("Invalid OpenSSH public key: {}", e)))?;
+        
+    Ok(ParsedKey {
+        openssh: key.to_string(),
+        comment: public_key.comment().to_string(),
+        fingerprint: md5_fingerprint(&blob),
+        fingerprint_sha256: public_key.fingerprint(HashAlg::Sha256).to_string(),
+    })
+}
+
+fn md5_fingerprint(blob: &[u8]) -> String {
+    Md5::digest(blob)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// A user's keys must differ in both fingerprint and name
+fn check_duplicate_key(existing: &[crate::api::users::SshKey], fingerprint: &str, name: &str) -> Result<(), AppError> {
+    if existing.iter().any(|key| key.fingerprint == fingerprint) {
+        return Err(AppError::ValidationError(format!("SSH key {} already exists for this user", fingerprint)));
+    }
+    if existing.iter().any(|key| key.name == name) {
+        return Err(AppError::ValidationError(format!("An SSH key named {} already exists for this user", name)));
     }
-}
\ No newline at end of file
+    
+    Ok(())
+}
+
+fn key_from_entry(entry: &SearchEntry) -> crate::api::users::SshKey {
+    let openssh = first_attr(entry, "openssh").unwrap_or_default();
+    
+    // Older entries may carry keys UFDS accepted but we can't parse; still list them
+    let fingerprint_sha256 = PublicKey::from_openssh(openssh.trim())
+        .map(|key| key.fingerprint(HashAlg::Sha256).to_string())
+        .unwrap_or_default();
+        
+    crate::api::users::SshKey {
+        name: first_attr(entry, "name").unwrap_or_default(),
+        fingerprint: first_attr(entry, "fingerprint").unwrap_or_default(),
+        fingerprint_sha256,
+        key: openssh,
+    }
+}
+
+fn attr(name: &str, value: &str) -> (String, HashSet<String>) {
+    (name.to_string(), HashSet::from([value.to_string()]))
+}
+
+// A Replace with no values removes the attribute
+fn replace(name: &str, value: Option<&String>) -> Mod<String> {
+    Mod::Replace(name.to_string(), value.into_iter().cloned().collect())
+}
+
+fn first_attr(entry: &SearchEntry, name: &str) -> Option<String> {
+    entry.attrs.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .cloned()
+}
+
+fn all_attrs(entry: &SearchEntry, name: &str) -> Vec<String> {
+    entry.attrs.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.clone())
+        .unwrap_or_default()
+}
+
+// Compare DNs case-insensitively and ignoring spaces around separators, so
+// "cn=operators, ou=groups, o=smartdc" matches "CN=operators,ou=groups,o=smartdc"
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| {
+            rdn.split('=')
+                .map(|part| part.trim())
+                .collect::<Vec<_>>()
+                .join("=")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
+
+fn normalize_group_roles(group_roles: &HashMap<String, String>) -> HashMap<String, String> {
+    group_roles
+        .iter()
+        .map(|(group, role)| (normalize_dn(group), role.clone()))
+        .collect()
+}
+
+// Admin UI roles granted by the user's groups. A mapping key matches a
+// group by its full DN or by its first RDN alone.
+fn roles_for_groups(groups: &[String], group_roles: &HashMap<String, String>) -> Vec<String> {
+    let mut roles: Vec<String> = Vec::new();
+    
+    for group in groups {
+        let dn = normalize_dn(group);
+        let rdn = dn.split(',').next().unwrap_or_default();
+        
+        if let Some(role) = group_roles.get(&dn).or_else(|| group_roles.get(rdn)) {
+            if !roles.contains(role) {
+                roles.push(role.clone());
+            }
+        }
+    }
+    
+    roles
+}
+
+// Map the sdcperson entry found at login to the user carried in the session
+fn login_user_from_entry(entry: &SearchEntry, username: &str, group_roles: &HashMap<String, String>) -> UfdsUser {
+    // Use givenname + sn if available, otherwise cn, otherwise the login
+    let first_name = first_attr(entry, "givenname").unwrap_or_default();
+    let last_name = first_attr(entry, "sn").unwrap_or_default();
+    let name = if !first_name.is_empty() || !last_name.is_empty() {
+        format!("{} {}", first_name, last_name).trim().to_string()
+    } else {
+        first_attr(entry, "cn").unwrap_or_else(|| username.to_string())
+    };
+    
+    let mut roles = roles_for_groups(&all_attrs(entry, "memberof"), group_roles);
+        
+    let is_admin = first_attr(entry, "isadmin")
+        .map(|v| v == "true")
+        .unwrap_or(false);
+        
+    if is_admin && !roles.contains(&"admin".to_string()) {
+        roles.push("admin".to_string());
+    }
+    
+    UfdsUser {
+        uuid: first_attr(entry, "uuid").unwrap_or_default(),
+        login: first_attr(entry, "login").unwrap_or_else(|| username.to_string()),
+        email: first_attr(entry, "email").unwrap_or_default(),
+        name,
+        is_admin,
+        roles,
+    }
+}
+
+// What to do with the next search result when serving one page of users
+#[derive(Debug, PartialEq, Eq)]
+enum PageStep {
+    Skip,
+    Take,
+    Stop,
+}
+
+fn page_step(skipped: usize, taken: usize, offset: usize, limit: Option<usize>) -> PageStep {
+    if skipped < offset {
+        PageStep::Skip
+    } else if limit.is_some_and(|limit| taken >= limit) {
+        PageStep::Stop
+    } else {
+        PageStep::Take
+    }
+}
+
+// UFDS stores timestamps as milliseconds since the epoch
+fn ufds_timestamp(value: Option<String>) -> String {
+    let value = value.unwrap_or_default();
+    value.parse::<i64>()
+        .ok()
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .map(|ts| ts.to_rfc3339())
+        .unwrap_or(value)
+}
+
+fn user_from_entry(entry: &SearchEntry) -> crate::api::users::User {
+    crate::api::users::User {
+        uuid: first_attr(entry, "uuid").unwrap_or_default(),
+        login: first_attr(entry, "login").unwrap_or_default(),
+        email: first_attr(entry, "email").unwrap_or_default(),
+        first_name: first_attr(entry, "givenname"),
+        last_name: first_attr(entry, "sn"),
+        company: first_attr(entry, "company"),
+        created_at: ufds_timestamp(first_attr(entry, "created_at")),
+        updated_at: ufds_timestamp(first_attr(entry, "updated_at")),
+        approved_for_provisioning: first_attr(entry, "approved_for_provisioning")
+            .map(|v| v == "true")
+            .unwrap_or(false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIPI9p0N2IEJ53A/HFxM6fq1v/OmOCwUUiN7/pFwxW2WF alice@example.com";
+    const RSA_KEY: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQChfPOL7lCIHduI9nWz3cSAa5LhnSWuZ1Lkd7oM3zaNZ3bvi1AHT/aGDrKbbKnGgli9zNYX2MZgh7TkfiP/bfb/YNQ7U6URSOHmui23IlzSrHlIP2OSVCRs9v7GjwLB4HDUmRTHpXeABgzj3lcuYB9DqEKJ9leonIAAxkz+CNkiTw==";
+
+    // Expected values from `ssh-keygen -l -E md5` and `ssh-keygen -l`
+    #[test]
+    fn fingerprints_match_ssh_keygen() {
+        let key = parse_public_key(ED25519_KEY).unwrap();
+        assert_eq!(key.fingerprint, "c3:c9:b0:ad:8c:e6:4f:0e:af:85:b2:8f:f0:0b:07:d3");
+        assert_eq!(key.fingerprint_sha256, "SHA256:mBNchjcIxMG5mtDZMICRVEjUfOl6BPNFq/5YGHdkG/g");
+        assert_eq!(key.comment, "alice@example.com");
+
+        let key = parse_public_key(&format!("  {}\n", RSA_KEY)).unwrap();
+        assert_eq!(key.fingerprint, "d2:b9:86:4c:d4:b7:2a:4c:d2:da:c3:38:e4:57:3e:7f");
+        assert_eq!(key.fingerprint_sha256, "SHA256:jXub7uS0sRa/uCsy+iErErROP4rti4zW/d+2YL3sxtQ");
+        assert_eq!(key.openssh, RSA_KEY);
+        assert_eq!(key.comment, "");
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        for key in [
+            "",
+            "not a key",
+            "ssh-ed25519",
+            "ssh-ed25519 AAAAnotbase64!!",
+            // Blob of one algorithm labelled as another
+            "ssh-rsa AAAAC3NzaC1lZDI1NTE5AAAAIPI9p0N2IEJ53A/HFxM6fq1v/OmOCwUUiN7/pFwxW2WF",
+        ] {
+            assert!(matches!(parse_public_key(key), Err(AppError::ValidationError(_))), "accepted {:?}", key);
+        }
+
+        let two_keys = format!("{}\n{}", ED25519_KEY, RSA_KEY);
+        assert!(matches!(parse_public_key(&two_keys), Err(AppError::ValidationError(_))));
+    }
+
+    #[test]
+    fn rejects_duplicate_keys_by_fingerprint_or_name() {
+        let existing = vec![crate::api::users::SshKey {
+            name: "laptop".to_string(),
+            fingerprint: "c3:c9:b0:ad:8c:e6:4f:0e:af:85:b2:8f:f0:0b:07:d3".to_string(),
+            fingerprint_sha256: String::new(),
+            key: ED25519_KEY.to_string(),
+        }];
+
+        assert!(check_duplicate_key(&existing, "c3:c9:b0:ad:8c:e6:4f:0e:af:85:b2:8f:f0:0b:07:d3", "desktop").is_err());
+        assert!(check_duplicate_key(&existing, "d2:b9:86:4c:d4:b7:2a:4c:d2:da:c3:38:e4:57:3e:7f", "laptop").is_err());
+        assert!(check_duplicate_key(&existing, "d2:b9:86:4c:d4:b7:2a:4c:d2:da:c3:38:e4:57:3e:7f", "desktop").is_ok());
+    }
+
+    // Run page_step over `total` results the way list_users does
+    fn page(total: usize, offset: usize, limit: Option<usize>) -> (Vec<usize>, bool) {
+        let (mut skipped, mut taken, mut truncated) = (0, Vec::new(), false);
+        for index in 0..total {
+            match page_step(skipped, taken.len(), offset, limit) {
+                PageStep::Skip => skipped += 1,
+                PageStep::Take => taken.push(index),
+                PageStep::Stop => {
+                    truncated = true;
+                    break;
+                }
+            }
+        }
+        (taken, truncated)
+    }
+
+    #[test]
+    fn pages_apply_offset_then_limit() {
+        assert_eq!(page(10, 0, None), ((0..10).collect(), false));
+        assert_eq!(page(10, 3, Some(4)), (vec![3, 4, 5, 6], true));
+        assert_eq!(page(10, 8, Some(4)), (vec![8, 9], false));
+        assert_eq!(page(10, 12, Some(4)), (vec![], false));
+        assert_eq!(page(10, 0, Some(0)), (vec![], true));
+    }
+}
@@ -5,48 +5,180 @@ use anyhow::Result;
 use tracing::info;
 
 use crate::error::AppError;
+use super::triton::TritonClient;
+
+// Number of VMs matching a list query, across all pages
+const RESOURCE_COUNT_HEADER: &str = "x-joyent-resource-count";
+
+// VMAPI's page size when a query gives none. It is always sent, so a full
+// page can be told apart from the last one.
+pub const DEFAULT_LIMIT: u32 = 1000;
+
+// Fields VMAPI can sort VMs by, under the names our VM model uses where they
+// differ
+const SORT_FIELDS: &[(&str, &str)] = &[
+    ("uuid", "uuid"),
+    ("alias", "alias"),
+    ("state", "state"),
+    ("brand", "brand"),
+    ("owner_uuid", "owner_uuid"),
+    ("server_uuid", "server_uuid"),
+    ("memory", "ram"),
+    ("ram", "ram"),
+    ("quota", "quota"),
+    ("created_at", "create_timestamp"),
+    ("create_timestamp", "create_timestamp"),
+];
+
+// Filters and paging for VMAPI's ListVms, sent as query parameters
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VmListQuery {
+    pub owner_uuid: Option<String>,
+    pub server_uuid: Option<String>,
+    pub state: Option<String>,
+    pub alias: Option<String>,
+    // Tag name and value, matched with a predicate
+    pub tag: Option<(String, String)>,
+    pub sort: Option<VmSort>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    // Opaque marker from a previous page (see VmPage::next_marker)
+    pub marker: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmSort {
+    // VMAPI field name
+    pub field: &'static str,
+    pub descending: bool,
+}
+
+impl VmSort {
+    // "created_at", "created_at:desc" or "created_at.desc"
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let (field, direction) = match value.split_once([':', '.']) {
+            Some((field, direction)) => (field, direction.to_ascii_lowercase()),
+            None => (value, "asc".to_string()),
+        };
+        let field = SORT_FIELDS
+            .iter()
+            .find(|(name, _)| *name == field)
+            .map(|(_, vmapi_field)| *vmapi_field)
+            .ok_or_else(|| AppError::ValidationError(format!("Cannot sort VMs by {}", field)))?;
+        let descending = match direction.as_str() {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(AppError::ValidationError(format!("Sort direction must be asc or desc, not {}", direction))),
+        };
+        
+        Ok(Self { field, descending })
+    }
+}
+
+impl VmListQuery {
+    fn to_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        
+        let filters = [
+            ("owner_uuid", &self.owner_uuid),
+            ("server_uuid", &self.server_uuid),
+            ("state", &self.state),
+            ("alias", &self.alias),
+            ("marker", &self.marker),
+        ];
+        for (name, value) in filters {
+            if let Some(value) = value {
+                params.push((name, value.clone()));
+            }
+        }
+        
+        if let Some((name, value)) = &self.tag {
+            let predicate = serde_json::json!({ "eq": [format!("tags.{}", name), value] });
+            params.push(("predicate", predicate.to_string()));
+        }
+        if let Some(sort) = &self.sort {
+            let direction = if sort.descending { "DESC" } else { "ASC" };
+            params.push(("sort", format!("{}.{}", sort.field, direction)));
+        }
+        params.push(("limit", self.limit().to_string()));
+        if let Some(offset) = self.offset {
+            params.push(("offset", offset.to_string()));
+        }
+        
+        params
+    }
+    
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT)
+    }
+    
+    // A full page may be followed by another one, starting after its last VM
+    fn next_marker(&self, page: &[serde_json::Value]) -> Option<String> {
+        match page.last() {
+            Some(last) if page.len() >= self.limit() as usize => Some(self.marker_after(last)),
+            _ => None,
+        }
+    }
+    
+    // VMAPI markers name the last VM seen by its UUID and the value of the
+    // sort field, so the next page starts right after it
+    fn marker_after(&self, last: &serde_json::Value) -> String {
+        let mut marker = serde_json::Map::new();
+        marker.insert("uuid".to_string(), last["uuid"].clone());
+        let field = self.sort.as_ref().map_or("create_timestamp", |sort| sort.field);
+        marker.insert(field.to_string(), last[field].clone());
+        
+        serde_json::Value::Object(marker).to_string()
+    }
+}
+
+// A page of VMs
+#[derive(Debug)]
+pub struct VmPage {
+    pub vms: Vec<crate::api::vms::Vm>,
+    // All matching VMs, when VMAPI reports it
+    pub total: Option<u64>,
+    // Pass as the marker to get the next page; None on the last page
+    pub next_marker: Option<String>,
+}
 
 pub struct VmapiService {
-    client: reqwest::Client,
-    base_url: String,
+    pub(super) client: TritonClient,
 }
 
 impl VmapiService {
-    pub fn new(base_url: String) -> Self {
-        info!("Initializing VMAPI service with URL: {}", base_url);
+    pub fn new(client: reqwest::Client, base_url: String) -> Self {
         Self {
-            client: reqwest::Client::new(),
-            base_url,
+            client: TritonClient::new("VMAPI", client, base_url),
         }
     }
     
-    pub async fn list_vms(&self) -> Result<Vec<crate::api::vms::Vm>, AppError> {
+    // One page of VMs matching the query. Filters, sorting and paging are
+    // all done by VMAPI, which also reports how many VMs match in total.
+    pub async fn list_vms(&self, query: &VmListQuery) -> Result<VmPage, AppError> {
         info!("Fetching VM list from VMAPI");
         
-        // Construct the URL for the VMAPI VMs endpoint
-        let vms_url = format!("{}/vms", self.base_url);
+        let vms_path = "/vms".to_string();
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&vms_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch VMs from VMAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch VMs from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&vms_path).query(&query.to_params()),
+                "fetch VMs from VMAPI",
+                None,
+            )
+            .await?;
+        
+        let total = response
+            .headers()
+            .get(RESOURCE_COUNT_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
         
         // Parse the response JSON directly into our VM model
-        let vms_data: Vec<serde_json::Value> = response
-            .json()
-            .await
-            .map_err(|e| {
-                info!("Error parsing VMAPI response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse VMAPI response: {}", e))
-            })?;
+        let vms_data: Vec<serde_json::Value> = self.client.json(response).await?;
+        
+        let next_marker = query.next_marker(&vms_data);
         
         // Convert to our VM model
         let mut vms = Vec::new();
@@ -122,143 +254,48 @@ impl VmapiService {
             });
         }
             
-        info!("Successfully fetched {} VMs from VMAPI", vms.len());
-        Ok(vms)
+        info!("Successfully fetched {} of {:?} VMs from VMAPI", vms.len(), total);
+        Ok(VmPage { vms, total, next_marker })
     }
     
-    pub async fn list_vms_by_server(&self, server_uuid: &str) -> Result<Vec<crate::api::vms::Vm>, AppError> {
-        info!("Fetching VMs for server: {}", server_uuid);
-        
-        // Construct the URL for the VMAPI VMs endpoint with server_uuid filter
-        let vms_url = format!("{}/vms?server_uuid={}", self.base_url, server_uuid);
-        
-        // Make the request to VMAPI
-        let response = self.client
-            .get(&vms_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch VMs for server from VMAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch VMs for server from VMAPI: {} - {}", status, error_text)));
+    // Number of VMs matching the query, without fetching them all when
+    // VMAPI reports the total
+    pub async fn count_vms(&self, query: &VmListQuery) -> Result<u64, AppError> {
+        let first = VmListQuery { limit: Some(1), offset: None, marker: None, ..query.clone() };
+        if let Some(total) = self.list_vms(&first).await?.total {
+            return Ok(total);
         }
         
-        // Parse the response JSON directly into our VM model
-        let vms_data: Vec<serde_json::Value> = response
-            .json()
-            .await
-            .map_err(|e| {
-                info!("Error parsing VMAPI response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse VMAPI response: {}", e))
-            })?;
-        
-        // Convert to our VM model
-        let mut vms = Vec::new();
-        
-        for vm_data in vms_data {
-            // Extract the required fields
-            let uuid = match vm_data["uuid"].as_str() {
-                Some(uuid) => uuid,
-                None => continue, // Skip if UUID is missing
-            };
-            
-            let alias = match vm_data["alias"].as_str() {
-                Some(alias) => alias,
-                None => continue, // Skip if alias is missing
-            };
-            
-            let state = match vm_data["state"].as_str() {
-                Some(state) => state,
-                None => "unknown",
-            };
-            
-            let brand = match vm_data["brand"].as_str() {
-                Some(brand) => brand,
-                None => "unknown",
-            };
-            
-            let memory = vm_data["ram"].as_u64().unwrap_or(0);
-            let disk = vm_data["quota"].as_u64().unwrap_or(0);
-            let vcpus = vm_data["vcpus"].as_u64().unwrap_or(1) as u32;
-            
-            // Extract IPs from nics but also pass the whole nics array through
-            let mut ips = Vec::new();
-            let nics = vm_data["nics"].clone(); // Clone the entire nics array to pass through
-            
-            let owner_uuid = match vm_data["owner_uuid"].as_str() {
-                Some(owner_uuid) => owner_uuid,
-                None => continue, // Skip if owner_uuid is missing
-            };
-            
-            let image_uuid = vm_data["image_uuid"].as_str().unwrap_or("").to_string();
-            let package_uuid = vm_data["billing_id"].as_str().unwrap_or("").to_string();
-            
-            // Handle different timestamp names: create_timestamp or created_at
-            let created_at = match vm_data["create_timestamp"].as_str() {
-                Some(ts) => ts.to_string(),
-                None => vm_data["created_at"].as_str().unwrap_or("").to_string(),
-            };
-            
-            let tags = vm_data["tags"].clone();
-            let customer_metadata = vm_data["customer_metadata"].clone();
-            let internal_metadata = vm_data["internal_metadata"].clone();
-            
-            vms.push(crate::api::vms::Vm {
-                uuid: uuid.to_string(),
-                alias: alias.to_string(),
-                state: state.to_string(),
-                brand: brand.to_string(),
-                memory,
-                quota: disk, // Use disk value for quota
-                disk,
-                vcpus,
-                ips,
-                owner_uuid: owner_uuid.to_string(),
-                image_uuid,
-                package_uuid,
-                server_uuid: server_uuid.to_string(), // Set to the provided server_uuid
-                created_at,
-                tags,
-                customer_metadata,
-                internal_metadata,
-                nics: Some(nics.as_array().unwrap_or(&vec![]).to_vec()),
-            });
+        // Otherwise count them page by page
+        let mut page_query = VmListQuery { limit: Some(DEFAULT_LIMIT), offset: None, marker: None, ..query.clone() };
+        let mut count = 0;
+        loop {
+            let page = self.list_vms(&page_query).await?;
+            count += page.vms.len() as u64;
+            match page.next_marker {
+                Some(marker) => page_query.marker = Some(marker),
+                None => return Ok(count),
+            }
         }
-            
-        info!("Successfully fetched {} VMs for server {}", vms.len(), server_uuid);
-        Ok(vms)
     }
     
     pub async fn get_vm(&self, uuid: &str) -> Result<crate::api::vms::Vm, AppError> {
         info!("Fetching VM with UUID: {}", uuid);
         
         // Construct the URL for the VMAPI VM endpoint
-        let vm_url = format!("{}/vms/{}", self.base_url, uuid);
+        let vm_path = format!("/vms/{}", uuid);
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&vm_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch VM from VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("VM with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch VM from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&vm_path),
+                "fetch VM from VMAPI",
+                Some(format!("VM with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON
-        let vm_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse VMAPI response: {}", e)))?;
+        let vm_data: serde_json::Value = self.client.json(response).await?;
             
         // Extract the required fields from the response
         let alias = vm_data["alias"]
@@ -343,196 +380,116 @@ impl VmapiService {
         Ok(vm)
     }
     
-    pub async fn create_vm(&self, vm: crate::api::vms::CreateVmRequest) -> Result<crate::api::vms::Vm, AppError> {
-        info!("Creating new VM with alias: {}", vm.alias);
+    pub async fn create_vm(&self, payload: &serde_json::Value) -> Result<crate::api::vms::VmJobResponse, AppError> {
+        info!("Creating new VM with alias: {}", payload["alias"].as_str().unwrap_or(""));
         
         // Construct the URL for the VMAPI VMs endpoint
-        let vms_url = format!("{}/vms", self.base_url);
+        let vms_path = "/vms".to_string();
         
         // Make the request to VMAPI
         let response = self.client
-            .post(&vms_url)
-            .json(&vm)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to create VM with VMAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to create VM with VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.post(&vms_path).json(payload),
+                "create VM with VMAPI",
+                None,
+            )
+            .await?;
         
-        // Parse the response JSON to get the job UUID
-        let job_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse VMAPI response: {}", e)))?;
+        // VMAPI responds with the new VM UUID and the provisioning job UUID
+        let job: crate::api::vms::VmJobResponse = self.client.json(response).await?;
             
-        let job_uuid = job_data["job_uuid"]
-            .as_str()
-            .ok_or_else(|| AppError::InternalServerError("Job UUID not found in VMAPI response".to_string()))?;
-            
-        let vm_uuid = job_data["vm_uuid"]
-            .as_str()
-            .ok_or_else(|| AppError::InternalServerError("VM UUID not found in VMAPI response".to_string()))?;
-            
-        info!("VM creation job started: {} for VM: {}", job_uuid, vm_uuid);
-        
-        // Return a placeholder VM object with the UUID and status
-        // In a real implementation, we might poll the job status or return a more complete VM object
-        let new_vm = crate::api::vms::Vm {
-            uuid: vm_uuid.to_string(),
-            alias: vm.alias.clone(),
-            state: "provisioning".to_string(),
-            brand: vm.brand.clone(),
-            memory: 0, // Will be set based on package
-            quota: 0,  // Will be set based on package
-            disk: 0,   // Will be set based on package
-            vcpus: 0,  // Will be set based on package
-            ips: vec![],
-            owner_uuid: vm.owner_uuid.clone(),
-            image_uuid: vm.image_uuid.clone(),
-            package_uuid: vm.package_uuid.clone(),
-            server_uuid: "".to_string(), // Will be assigned during provisioning
-            created_at: chrono::Utc::now().to_rfc3339(),
-            tags: vm.tags.clone().unwrap_or(serde_json::json!({})),
-            customer_metadata: vm.customer_metadata.clone().unwrap_or(serde_json::json!({})),
-            internal_metadata: serde_json::json!({}),
-            nics: None,
-        };
-        
-        Ok(new_vm)
+        info!("VM creation job started: {} for VM: {}", job.job_uuid, job.vm_uuid);
+        Ok(job)
     }
     
-    pub async fn update_vm(&self, uuid: &str, vm: crate::api::vms::UpdateVmRequest) -> Result<crate::api::vms::Vm, AppError> {
+    pub async fn update_vm(&self, uuid: &str, payload: &serde_json::Value) -> Result<crate::api::vms::VmJobResponse, AppError> {
         info!("Updating VM with UUID: {}", uuid);
         
-        // Construct the URL for the VMAPI VM endpoint
-        let vm_url = format!("{}/vms/{}", self.base_url, uuid);
-        
-        // Make the request to VMAPI
-        let response = self.client
-            .post(&vm_url)
-            .json(&vm)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to update VM with VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("VM with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to update VM with VMAPI: {} - {}", status, error_text)));
-        }
-        
-        // Get the updated VM
-        self.get_vm(uuid).await
+        // Updates are queued as an "update" action job
+        self.vm_action(uuid, "update", Some(payload)).await
     }
     
-    pub async fn delete_vm(&self, uuid: &str) -> Result<(), AppError> {
+    pub async fn delete_vm(&self, uuid: &str) -> Result<crate::api::vms::VmJobResponse, AppError> {
         info!("Deleting VM with UUID: {}", uuid);
         
         // Construct the URL for the VMAPI VM endpoint
-        let vm_url = format!("{}/vms/{}", self.base_url, uuid);
+        let vm_path = format!("/vms/{}", uuid);
         
         // Make the request to VMAPI
         let response = self.client
-            .delete(&vm_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to delete VM with VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("VM with UUID {} not found", uuid)));
-        }
+            .send(
+                self.client.delete(&vm_path),
+                "delete VM with VMAPI",
+                Some(format!("VM with UUID {} not found", uuid)),
+            )
+            .await?;
         
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to delete VM with VMAPI: {} - {}", status, error_text)));
-        }
+        let job = self.parse_job_response(uuid, response).await?;
         
-        info!("Successfully deleted VM {}", uuid);
-        Ok(())
+        info!("VM destroy job started: {} for VM: {}", job.job_uuid, uuid);
+        Ok(job)
     }
     
-    pub async fn vm_action(&self, uuid: &str, action: &str) -> Result<String, AppError> {
+    pub async fn vm_action(
+        &self,
+        uuid: &str,
+        action: &str,
+        params: Option<&serde_json::Value>,
+    ) -> Result<crate::api::vms::VmJobResponse, AppError> {
         info!("Performing action {} on VM with UUID: {}", action, uuid);
         
-        // Construct the URL for the VMAPI VM endpoint
-        let vm_url = format!("{}/vms/{}", self.base_url, uuid);
+        // Construct the URL for the VMAPI VM endpoint; the action goes in the query string
+        let vm_path = format!("/vms/{}", uuid);
         
-        // Create the action payload
-        let action_payload = serde_json::json!({
-            "action": action
-        });
+        // Action parameters are sent as the request body
+        let action_payload = params.filter(|p| p.is_object()).cloned().unwrap_or_else(|| serde_json::json!({}));
         
         // Make the request to VMAPI
         let response = self.client
-            .post(&vm_url)
-            .json(&action_payload)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to perform action on VM with VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("VM with UUID {} not found", uuid)));
-        }
+            .send(
+                self.client.post(&vm_path).query(&[("action", action)]).json(&action_payload),
+                "perform action on VM with VMAPI",
+                Some(format!("VM with UUID {} not found", uuid)),
+            )
+            .await?;
         
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to perform action on VM with VMAPI: {} - {}", status, error_text)));
-        }
+        let job = self.parse_job_response(uuid, response).await?;
         
-        // Parse the response JSON to get the job UUID if applicable
-        let response_data: serde_json::Value = response
-            .json()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to parse VMAPI response: {}", e)))?;
+        info!("VM action job started: {} ({}) for VM: {}", job.job_uuid, action, uuid);
+        Ok(job)
+    }
+    
+    // Parse the {vm_uuid, job_uuid} body VMAPI returns for queued jobs
+    async fn parse_job_response(&self, uuid: &str, response: reqwest::Response) -> Result<crate::api::vms::VmJobResponse, AppError> {
+        let job_data: serde_json::Value = self.client.json(response).await?;
             
-        // Return job UUID if provided, otherwise success message
-        let job_uuid = response_data["job_uuid"].as_str().unwrap_or("");
-        if !job_uuid.is_empty() {
-            info!("VM action job started: {} for VM: {}", job_uuid, uuid);
-            Ok(format!("Action '{}' initiated with job ID: {}", action, job_uuid))
-        } else {
-            info!("VM action completed successfully: {} for VM: {}", action, uuid);
-            Ok(format!("Action '{}' completed successfully", action))
-        }
+        let job_uuid = job_data["job_uuid"]
+            .as_str()
+            .ok_or_else(|| AppError::InternalServerError("Job UUID not found in VMAPI response".to_string()))?;
+            
+        Ok(crate::api::vms::VmJobResponse {
+            vm_uuid: job_data["vm_uuid"].as_str().unwrap_or(uuid).to_string(),
+            job_uuid: job_uuid.to_string(),
+        })
     }
     
     pub async fn get_vm_jobs(&self, vm_uuid: &str) -> Result<Vec<crate::api::vms::VmJob>, AppError> {
         info!("Fetching jobs for VM: {}", vm_uuid);
         
         // Construct the URL for the VMAPI jobs endpoint with vm_uuid filter
-        let jobs_url = format!("{}/jobs?vm_uuid={}", self.base_url, vm_uuid);
+        let jobs_path = format!("/jobs?vm_uuid={}", vm_uuid);
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&jobs_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch jobs from VMAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch jobs from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&jobs_path),
+                "fetch jobs from VMAPI",
+                None,
+            )
+            .await?;
         
         // Try to parse the response directly into our VmJob struct
-        let jobs = response
-            .json::<Vec<crate::api::vms::VmJob>>()
-            .await
-            .map_err(|e| {
-                info!("Error parsing VMAPI jobs response directly: {}", e);
-                AppError::InternalServerError(format!("Failed to parse VMAPI jobs response: {}", e))
-            })?;
+        let jobs = self.client.json::<Vec<crate::api::vms::VmJob>>(response).await?;
         
         info!("Successfully fetched {} jobs for VM {}", jobs.len(), vm_uuid);
         Ok(jobs)
@@ -549,8 +506,8 @@ impl VmapiService {
         info!("Listing jobs with filters: vm_uuid={:?}, execution={:?}, name={:?}", 
               vm_uuid, execution, name);
         
-        // Construct the base URL for the VMAPI jobs endpoint
-        let mut jobs_url = format!("{}/jobs", self.base_url);
+        // Construct the base path for the VMAPI jobs endpoint
+        let mut jobs_path = "/jobs".to_string();
         
         // Add filters as query parameters
         let mut query_params = Vec::new();
@@ -575,32 +532,22 @@ impl VmapiService {
             query_params.push(format!("offset={}", offset));
         }
         
-        // Add the query parameters to the URL
+        // Add the query parameters to the path
         if !query_params.is_empty() {
-            jobs_url = format!("{}?{}", jobs_url, query_params.join("&"));
+            jobs_path = format!("{}?{}", jobs_path, query_params.join("&"));
         }
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&jobs_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch jobs from VMAPI: {}", e)))?;
-            
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch jobs from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&jobs_path),
+                "fetch jobs from VMAPI",
+                None,
+            )
+            .await?;
         
         // Parse the response JSON
-        let jobs = response
-            .json::<Vec<crate::api::jobs::Job>>()
-            .await
-            .map_err(|e| {
-                info!("Error parsing VMAPI jobs response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse VMAPI jobs response: {}", e))
-            })?;
+        let jobs = self.client.json::<Vec<crate::api::jobs::Job>>(response).await?;
             
         info!("Successfully fetched {} jobs from VMAPI", jobs.len());
         Ok(jobs)
@@ -610,33 +557,19 @@ impl VmapiService {
         info!("Getting job {}", uuid);
         
         // Construct the URL for the VMAPI job endpoint
-        let job_url = format!("{}/jobs/{}", self.base_url, uuid);
+        let job_path = format!("/jobs/{}", uuid);
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&job_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch job from VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Job with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch job from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&job_path),
+                "fetch job from VMAPI",
+                Some(format!("Job with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Parse the response JSON
-        let job = response
-            .json::<crate::api::jobs::Job>()
-            .await
-            .map_err(|e| {
-                info!("Error parsing VMAPI job response: {}", e);
-                AppError::InternalServerError(format!("Failed to parse VMAPI job response: {}", e))
-            })?;
+        let job = self.client.json::<crate::api::jobs::Job>(response).await?;
             
         info!("Successfully fetched job {}", uuid);
         Ok(job)
@@ -646,24 +579,16 @@ impl VmapiService {
         info!("Getting job output for {}", uuid);
         
         // Construct the URL for the VMAPI job output endpoint
-        let job_output_url = format!("{}/jobs/{}/output", self.base_url, uuid);
+        let job_output_path = format!("/jobs/{}/output", uuid);
         
         // Make the request to VMAPI
         let response = self.client
-            .get(&job_output_url)
-            .send()
-            .await
-            .map_err(|e| AppError::InternalServerError(format!("Failed to fetch job output from VMAPI: {}", e)))?;
-            
-        if response.status().is_client_error() {
-            return Err(AppError::NotFound(format!("Output for job with UUID {} not found", uuid)));
-        }
-        
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(AppError::InternalServerError(format!("Failed to fetch job output from VMAPI: {} - {}", status, error_text)));
-        }
+            .send(
+                self.client.get(&job_output_path),
+                "fetch job output from VMAPI",
+                Some(format!("Output for job with UUID {} not found", uuid)),
+            )
+            .await?;
         
         // Get the text response (job output is plain text)
         let output = response
@@ -674,4 +599,73 @@ impl VmapiService {
         info!("Successfully fetched output for job {}", uuid);
         Ok(output)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_and_paging_become_query_parameters() {
+        let query = VmListQuery {
+            owner_uuid: Some("o-1".to_string()),
+            state: Some("running".to_string()),
+            alias: Some("web".to_string()),
+            tag: Some(("role".to_string(), "db".to_string())),
+            sort: Some(VmSort::parse("created_at:desc").unwrap()),
+            limit: Some(50),
+            offset: Some(100),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            query.to_params(),
+            vec![
+                ("owner_uuid", "o-1".to_string()),
+                ("state", "running".to_string()),
+                ("alias", "web".to_string()),
+                ("predicate", r#"{"eq":["tags.role","db"]}"#.to_string()),
+                ("sort", "create_timestamp.DESC".to_string()),
+                ("limit", "50".to_string()),
+                ("offset", "100".to_string()),
+            ]
+        );
+        // VMAPI's default page size is sent when no limit is given
+        assert_eq!(VmListQuery::default().to_params(), vec![("limit", "1000".to_string())]);
+    }
+
+    #[test]
+    fn sort_fields_are_checked() {
+        assert_eq!(VmSort::parse("alias").unwrap(), VmSort { field: "alias", descending: false });
+        assert_eq!(VmSort::parse("memory.DESC").unwrap(), VmSort { field: "ram", descending: true });
+        assert!(VmSort::parse("customer_metadata").is_err());
+        assert!(VmSort::parse("alias:sideways").is_err());
+    }
+
+    #[test]
+    fn markers_name_the_last_vm_and_its_sort_value() {
+        let last = json!({ "uuid": "v-9", "alias": "zeta", "create_timestamp": "2026-10-18T00:00:00.000Z" });
+
+        let marker: serde_json::Value = serde_json::from_str(&VmListQuery::default().marker_after(&last)).unwrap();
+        assert_eq!(marker, json!({ "uuid": "v-9", "create_timestamp": "2026-10-18T00:00:00.000Z" }));
+
+        let by_alias = VmListQuery { sort: Some(VmSort::parse("alias").unwrap()), ..Default::default() };
+        let marker: serde_json::Value = serde_json::from_str(&by_alias.marker_after(&last)).unwrap();
+        assert_eq!(marker, json!({ "uuid": "v-9", "alias": "zeta" }));
+    }
+
+    #[test]
+    fn only_full_pages_have_a_next_marker() {
+        let page = |n: usize| (0..n).map(|i| json!({ "uuid": format!("v-{}", i) })).collect::<Vec<_>>();
+
+        let default = VmListQuery::default();
+        assert!(default.next_marker(&page(DEFAULT_LIMIT as usize)).is_some());
+        assert!(default.next_marker(&page(DEFAULT_LIMIT as usize - 1)).is_none());
+
+        let small = VmListQuery { limit: Some(2), ..Default::default() };
+        assert!(small.next_marker(&page(2)).is_some());
+        assert!(small.next_marker(&page(1)).is_none());
+        assert!(small.next_marker(&[]).is_none());
+    }
+}